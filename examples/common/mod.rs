@@ -3,7 +3,7 @@ use std::panic::UnwindSafe;
 use std::sync::Arc;
 use raw_window_handle::HasRawDisplayHandle;
 use agnaji::vulkan::AgnajiVulkan;
-use agnaji::vulkan::init::AgnajiVulkanInitializer;
+use agnaji::vulkan::init::{AgnajiVulkanInitializer, DeviceSelection};
 use agnaji::vulkan::output::SurfaceOutput;
 use agnaji::winit::{Window, WinitBackend};
 
@@ -32,12 +32,12 @@ pub fn run_with_window<F>(name: &str, f: F) where F: FnOnce(Arc<WinitBackend>, A
         }
 
         if let Some(selected) = selected {
-            let (agnaji, mut surfaces) = initializer.build(selected).unwrap();
+            let (agnaji, mut surfaces) = initializer.build(DeviceSelection::Report(selected)).unwrap();
             let surface = surfaces.remove(0).1;
 
             f(backend, window, surface, agnaji);
         } else {
             log::error!("Failed to find suitable device");
         }
-    })
+    }).unwrap()
 }
\ No newline at end of file
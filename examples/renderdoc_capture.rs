@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use agnaji::debug::FrameCapture;
+use agnaji::winit::{ButtonState, InputEvent};
+
+mod common;
+
+/// The scancode of the C key on windows and linux.
+const CAPTURE_SCANCODE: u32 = 46;
+
+/// Run from RenderDoc and press Ctrl+C in the window to capture the next frame.
+fn main() {
+    common::run_with_window("RenderDoc capture", |_backend, window, surface, _agnaji| {
+        let frame_capture = FrameCapture::get();
+        if !frame_capture.is_available() {
+            log::warn!("RenderDoc is not available. Launch this example from RenderDoc to capture frames");
+        }
+
+        while !window.is_close_requested() {
+            for event in window.poll_input_events() {
+                if let InputEvent::KeyboardInput { scancode: CAPTURE_SCANCODE, state: ButtonState::Pressed, modifiers } = event {
+                    if modifiers.ctrl {
+                        match frame_capture.capture_next_frame(&surface) {
+                            Ok(()) => log::info!("Capturing next frame"),
+                            Err(err) => log::warn!("Failed to capture frame: {:?}", err),
+                        }
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(16));
+        }
+    })
+}
@@ -0,0 +1,16 @@
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+mod common;
+
+/// Runs the cube example and prints the duration of every span when it closes.
+fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::TRACE)
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+
+    common::run_with_window("Cube (tracing)", |_backend, _window, _surface, _agnaji| {
+
+    })
+}
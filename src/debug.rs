@@ -0,0 +1,1200 @@
+use std::collections::{HashSet, VecDeque};
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use ash::vk;
+
+use crate::vulkan::InstanceContext;
+use crate::vulkan::device::MainDeviceContext;
+use crate::vulkan::instance::{APIVersion, DebugMessage, DebugMessageHandler};
+use crate::vulkan::memory::MemoryStatisticsSnapshot;
+use crate::vulkan::pipeline_statistics::GpuPipelineStatisticsQuery;
+use crate::vulkan::timestamp::{GpuTimestampPool, TimestampHandle};
+
+/// Wrapper around `VK_EXT_debug_utils` used to attach debug information to vulkan objects.
+///
+/// If the extension is not enabled all functions do nothing so call sites never need to check
+/// for its presence.
+#[derive(Clone)]
+pub struct DebugUtils {
+    ext_debug_utils: Option<ash::extensions::ext::DebugUtils>,
+}
+
+impl DebugUtils {
+    pub fn new(instance: &InstanceContext) -> Self {
+        Self {
+            ext_debug_utils: instance.get_ext_debug_utils().cloned(),
+        }
+    }
+
+    /// Creates a instance which does nothing.
+    pub fn disabled() -> Self {
+        Self {
+            ext_debug_utils: None,
+        }
+    }
+
+    /// Returns true if `VK_EXT_debug_utils` is enabled. Can be used to avoid formatting names
+    /// which would be discarded anyway.
+    pub fn is_enabled(&self) -> bool {
+        self.ext_debug_utils.is_some()
+    }
+
+    /// Sets the name of a vulkan object used by debugging tools and in validation messages.
+    ///
+    /// Names cannot contain nul bytes so `name` is truncated at the first one. Failures are
+    /// logged and otherwise ignored.
+    pub fn set_object_name(&self, device: vk::Device, handle: u64, object_type: vk::ObjectType, name: &str) {
+        if let Some(debug_utils) = &self.ext_debug_utils {
+            let name = CString::new(name.split('\0').next().unwrap()).unwrap();
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(object_type)
+                .object_handle(handle)
+                .object_name(&name);
+
+            if let Err(err) = unsafe { debug_utils.set_debug_utils_object_name(device, &name_info) } {
+                log::warn!("Failed to set name {:?} of {:?} object: {:?}", name, object_type, err);
+            }
+        }
+    }
+
+    /// Sets the name of any vulkan object. See [`DebugUtils::set_object_name`].
+    pub fn set_name<H: vk::Handle>(&self, device: vk::Device, handle: H, name: &str) {
+        self.set_object_name(device, handle.as_raw(), H::TYPE, name);
+    }
+
+    pub fn set_buffer_name(&self, device: vk::Device, buffer: vk::Buffer, name: &str) {
+        self.set_name(device, buffer, name);
+    }
+
+    pub fn set_image_name(&self, device: vk::Device, image: vk::Image, name: &str) {
+        self.set_name(device, image, name);
+    }
+
+    pub fn set_semaphore_name(&self, device: vk::Device, semaphore: vk::Semaphore, name: &str) {
+        self.set_name(device, semaphore, name);
+    }
+
+    pub fn set_queue_name(&self, device: vk::Device, queue: vk::Queue, name: &str) {
+        self.set_name(device, queue, name);
+    }
+}
+
+impl DebugUtils {
+    /// Returns true if command buffer and queue labels are recorded. Labels are disabled if
+    /// `VK_EXT_debug_utils` is not enabled or if the `disable-gpu-labels` feature is set.
+    pub fn are_labels_enabled(&self) -> bool {
+        !cfg!(feature = "disable-gpu-labels") && self.is_enabled()
+    }
+
+    /// Opens a label region in `queue` which is closed when the returned scope is dropped. The
+    /// queue must be externally synchronized for the whole lifetime of the scope.
+    pub fn queue_label_scope(&self, queue: vk::Queue, name: &str, color: [f32; 4]) -> QueueLabelScope<'_> {
+        let debug_utils = self.get_label_ext();
+        if let Some(debug_utils) = debug_utils {
+            let name = label_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            unsafe {
+                debug_utils.queue_begin_debug_utils_label(queue, &label);
+            }
+        }
+
+        QueueLabelScope {
+            debug_utils,
+            queue,
+        }
+    }
+
+    fn get_label_ext(&self) -> Option<&ash::extensions::ext::DebugUtils> {
+        if self.are_labels_enabled() {
+            self.ext_debug_utils.as_ref()
+        } else {
+            None
+        }
+    }
+}
+
+fn label_name(name: &str) -> CString {
+    CString::new(name.split('\0').next().unwrap()).unwrap()
+}
+
+/// A label region in a command buffer. The region is opened by [`CmdLabelScope::begin`] and
+/// closed when the scope is dropped. Does nothing if [`DebugUtils::are_labels_enabled`] is false.
+#[must_use]
+pub struct CmdLabelScope<'a> {
+    debug_utils: Option<&'a ash::extensions::ext::DebugUtils>,
+    cmd: vk::CommandBuffer,
+}
+
+impl<'a> CmdLabelScope<'a> {
+    /// Records the beginning of a label region into `cmd`. The command buffer must be in the
+    /// recording state until the scope is dropped.
+    pub fn begin(debug_utils: &'a DebugUtils, cmd: vk::CommandBuffer, name: &str, color: [f32; 4]) -> Self {
+        let debug_utils = debug_utils.get_label_ext();
+        if let Some(debug_utils) = debug_utils {
+            let name = label_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            unsafe {
+                debug_utils.cmd_begin_debug_utils_label(cmd, &label);
+            }
+        }
+
+        Self {
+            debug_utils,
+            cmd,
+        }
+    }
+}
+
+impl<'a> Drop for CmdLabelScope<'a> {
+    fn drop(&mut self) {
+        if let Some(debug_utils) = self.debug_utils {
+            unsafe {
+                debug_utils.cmd_end_debug_utils_label(self.cmd);
+            }
+        }
+    }
+}
+
+/// A label region in a queue created by [`DebugUtils::queue_label_scope`].
+#[must_use]
+pub struct QueueLabelScope<'a> {
+    debug_utils: Option<&'a ash::extensions::ext::DebugUtils>,
+    queue: vk::Queue,
+}
+
+impl<'a> Drop for QueueLabelScope<'a> {
+    fn drop(&mut self) {
+        if let Some(debug_utils) = self.debug_utils {
+            unsafe {
+                debug_utils.queue_end_debug_utils_label(self.queue);
+            }
+        }
+    }
+}
+
+/// A validation message recorded by a [`ValidationCapture`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CapturedMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_id_name: Option<String>,
+    pub message_id_number: i32,
+    pub message: String,
+}
+
+/// Records validation messages so automated tests can fail if validation errors occur.
+///
+/// The capture is installed as the debug message handler of a [`InstanceContext`] and records
+/// every message with a severity of at least the threshold unless its message id is allowlisted.
+/// Cloning the capture creates a new handle to the same recorded messages.
+#[derive(Clone)]
+pub struct ValidationCapture {
+    inner: Arc<CaptureInner>,
+}
+
+struct CaptureInner {
+    threshold: vk::DebugUtilsMessageSeverityFlagsEXT,
+    allowlist: HashSet<String>,
+    messages: Mutex<Vec<CapturedMessage>>,
+}
+
+impl ValidationCapture {
+    /// Creates a capture recording all messages with a severity of at least `threshold`.
+    pub fn new(threshold: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        Self::with_allowlist(threshold, std::iter::empty::<&str>())
+    }
+
+    /// Creates a capture which ignores messages with any of the message id names in `allowlist`.
+    pub fn with_allowlist<I, S>(threshold: vk::DebugUtilsMessageSeverityFlagsEXT, allowlist: I) -> Self where I: IntoIterator<Item=S>, S: Into<String> {
+        Self {
+            inner: Arc::new(CaptureInner {
+                threshold,
+                allowlist: allowlist.into_iter().map(Into::into).collect(),
+                messages: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    /// Installs this capture as the debug message handler of `instance`, replacing any previously
+    /// set handler. Messages emitted while the instance is being created cannot be captured this
+    /// way. To capture them pass [`ValidationCapture::create_handler`] to
+    /// [`AgnajiVulkanInitializer::new_with_debug_handler`](crate::vulkan::init::AgnajiVulkanInitializer::new_with_debug_handler).
+    pub fn install(&self, instance: &InstanceContext) {
+        instance.set_debug_message_handler(self.create_handler());
+    }
+
+    /// Creates a handler which records messages into this capture.
+    pub fn create_handler(&self) -> DebugMessageHandler {
+        let inner = self.inner.clone();
+        Box::new(move |message| inner.record(message))
+    }
+
+    /// Removes and returns all recorded messages.
+    pub fn take_messages(&self) -> Vec<CapturedMessage> {
+        std::mem::take(&mut *self.inner.messages.lock().unwrap())
+    }
+
+    /// Returns true if any recorded message has error severity.
+    pub fn has_errors(&self) -> bool {
+        self.inner.messages.lock().unwrap().iter().any(|message| message.severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+    }
+
+    /// Panics if any recorded message has error severity. The panic message lists all errors.
+    pub fn assert_no_errors(&self) {
+        let guard = self.inner.messages.lock().unwrap();
+        let errors: Vec<_> = guard.iter().filter(|message| message.severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR).collect();
+        if !errors.is_empty() {
+            let errors = format!("{:#?}", errors);
+            drop(guard);
+            panic!("Validation errors occurred: {}", errors);
+        }
+    }
+}
+
+impl CaptureInner {
+    fn record(&self, message: &DebugMessage) {
+        if message.severity.as_raw() < self.threshold.as_raw() {
+            return;
+        }
+        if message.message_id_name.map(|name| self.allowlist.contains(name)).unwrap_or(false) {
+            return;
+        }
+
+        self.messages.lock().unwrap().push(CapturedMessage {
+            severity: message.severity,
+            message_id_name: message.message_id_name.map(String::from),
+            message_id_number: message.message_id_number,
+            message: String::from(message.message),
+        });
+    }
+}
+
+/// The gpu duration of a named phase of a frame measured by a [`GpuProfiler`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub duration: Duration,
+}
+
+/// The work done by the graphics pipeline during a frame measured by a [`GpuProfiler`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub fragment_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+}
+
+impl PipelineStatistics {
+    /// Creates the statistics from the results of a query. The results must be in the order
+    /// vulkan writes them which is the order of the corresponding flag bits.
+    pub(crate) fn from_results(results: &[u64; 6]) -> Self {
+        Self {
+            input_assembly_vertices: results[0],
+            input_assembly_primitives: results[1],
+            vertex_shader_invocations: results[2],
+            clipping_invocations: results[3],
+            clipping_primitives: results[4],
+            fragment_shader_invocations: results[5],
+        }
+    }
+}
+
+/// The gpu timings of a single frame measured by a [`GpuProfiler`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct FrameStatistics {
+    /// The timings of all phases in the order they were started.
+    pub phases: Vec<PhaseTiming>,
+    /// The pipeline statistics of the frame. [`None`] if the device does not support pipeline
+    /// statistics queries or they were not collected for the frame.
+    pub pipeline: Option<PipelineStatistics>,
+}
+
+impl FrameStatistics {
+    /// Returns the duration of the first phase called `name`.
+    pub fn get_phase_duration(&self, name: &str) -> Option<Duration> {
+        self.phases.iter().find(|phase| phase.name == name).map(|phase| phase.duration)
+    }
+}
+
+/// Measures the gpu duration of named phases of frames using timestamp queries.
+///
+/// Every frame in flight has its own query pool. The results of a frame are only resolved once
+/// its pool is reused by [`GpuProfiler::begin_frame`] so reading them never stalls. Frames whose
+/// results are still not available at that point are discarded.
+///
+/// If the device supports pipeline statistics queries the work done between
+/// [`GpuProfiler::begin_pipeline_statistics`] and [`GpuProfiler::end_pipeline_statistics`] is
+/// counted as well.
+///
+/// All command buffers must be submitted to the main queue of the device.
+pub struct GpuProfiler {
+    frames: Box<[ProfiledFrame]>,
+    next_frame: usize,
+    /// The index of the frame currently being recorded.
+    current_frame: Option<usize>,
+    timestamp_valid_bits: u32,
+    nanoseconds_per_tick: f64,
+}
+
+struct ProfiledFrame {
+    pool: GpuTimestampPool,
+    /// [`None`] if the device does not support pipeline statistics queries.
+    pipeline_statistics: Option<GpuPipelineStatisticsQuery>,
+    /// All phases in the order they were started.
+    phases: Vec<ProfiledPhase>,
+    /// Set if the frame has been recorded but its results have not been resolved yet.
+    pending: bool,
+}
+
+struct ProfiledPhase {
+    name: String,
+    /// [`None`] if the pool did not have enough space left for the phase.
+    start: Option<TimestampHandle>,
+    end: Option<TimestampHandle>,
+    open: bool,
+}
+
+impl GpuProfiler {
+    /// Creates a profiler for `frame_count` frames in flight with up to `max_phases` phases per
+    /// frame.
+    ///
+    /// Returns [`None`] if the main queue does not support timestamps or the query pools could
+    /// not be created.
+    pub fn new(device: Arc<MainDeviceContext>, frame_count: usize, max_phases: u32) -> Option<Self> {
+        let timestamp_valid_bits = device.timestamp_valid_bits(device.get_main_queue().get_queue_family());
+        if timestamp_valid_bits == 0 {
+            log::info!("Main queue does not support timestamps. Gpu profiling is disabled.");
+            return None;
+        }
+
+        let frames = (0..frame_count).map(|_| {
+            GpuTimestampPool::new(device.clone(), max_phases * 2).map(|pool| ProfiledFrame {
+                pool,
+                pipeline_statistics: GpuPipelineStatisticsQuery::new(device.clone()).ok(),
+                phases: Vec::new(),
+                pending: false,
+            })
+        }).collect::<Result<Vec<_>, _>>().ok()?;
+
+        Some(Self {
+            frames: frames.into_boxed_slice(),
+            next_frame: 0,
+            current_frame: None,
+            timestamp_valid_bits,
+            nanoseconds_per_tick: device.get_limits().timestamp_period as f64,
+        })
+    }
+
+    /// Starts recording a new frame into `cmd`.
+    ///
+    /// Frames rotate through the query pools so this must only be called once the frame which
+    /// was recorded `frame_count` frames earlier has completed. Returns the statistics of that
+    /// frame if they are available.
+    pub fn begin_frame(&mut self, cmd: vk::CommandBuffer) -> Option<FrameStatistics> {
+        if self.current_frame.is_some() {
+            self.end_frame();
+        }
+
+        let index = self.next_frame;
+        self.next_frame = (index + 1) % self.frames.len();
+        self.current_frame = Some(index);
+
+        let statistics = if self.frames[index].pending {
+            self.resolve(&self.frames[index])
+        } else {
+            None
+        };
+
+        let frame = &mut self.frames[index];
+        frame.pending = false;
+        frame.phases.clear();
+        frame.pool.reset(cmd);
+        if let Some(query) = &mut frame.pipeline_statistics {
+            query.reset(cmd);
+        }
+
+        statistics
+    }
+
+    /// Ends the current frame. Phases which are still open are discarded.
+    ///
+    /// # Panics
+    /// If pipeline statistics are still being collected.
+    pub fn end_frame(&mut self) {
+        let index = self.current_frame.take().expect("GpuProfiler::end_frame called without a frame being recorded");
+        let frame = &mut self.frames[index];
+        if frame.pipeline_statistics.as_ref().is_some_and(GpuPipelineStatisticsQuery::is_active) {
+            panic!("GpuProfiler::end_frame called while collecting pipeline statistics");
+        }
+        for phase in frame.phases.iter_mut().filter(|phase| phase.open) {
+            log::warn!("Gpu profiler phase {:?} was not ended", phase.name);
+            phase.open = false;
+            phase.start = None;
+        }
+        frame.pending = true;
+    }
+
+    /// Starts a new phase in `cmd`. Phases can be nested and are ended by
+    /// [`GpuProfiler::end_phase`] in reverse order.
+    ///
+    /// If the query pool of the frame is full the phase is not measured.
+    ///
+    /// # Panics
+    /// If no frame is being recorded.
+    pub fn begin_phase(&mut self, cmd: vk::CommandBuffer, name: &str) {
+        let frame = self.get_current_frame();
+
+        // Make sure every open phase can still record its end
+        let open_count = frame.phases.iter().filter(|phase| phase.open).count() as u32;
+        let start = if frame.pool.get_recorded_count() + open_count + 2 <= frame.pool.get_capacity() {
+            Some(frame.pool.record_timestamp(cmd, vk::PipelineStageFlags2KHR::TOP_OF_PIPE))
+        } else {
+            log::debug!("Gpu profiler query pool is full. Phase {:?} is not measured.", name);
+            None
+        };
+
+        frame.phases.push(ProfiledPhase {
+            name: String::from(name),
+            start,
+            end: None,
+            open: true,
+        });
+    }
+
+    /// Ends the most recently started phase which has not been ended yet.
+    ///
+    /// # Panics
+    /// If no frame is being recorded or no phase is open.
+    pub fn end_phase(&mut self, cmd: vk::CommandBuffer) {
+        let frame = self.get_current_frame();
+        let phase = frame.phases.iter_mut().rev().find(|phase| phase.open).expect("GpuProfiler::end_phase called without an open phase");
+        phase.open = false;
+        if phase.start.is_some() {
+            phase.end = Some(frame.pool.record_timestamp(cmd, vk::PipelineStageFlags2KHR::BOTTOM_OF_PIPE));
+        }
+    }
+
+    /// Starts collecting pipeline statistics in `cmd`. Does nothing if the device does not
+    /// support pipeline statistics queries.
+    ///
+    /// Pipeline statistics can only be collected once per frame and must be ended by
+    /// [`GpuProfiler::end_pipeline_statistics`] before the frame ends.
+    ///
+    /// # Panics
+    /// If no frame is being recorded or pipeline statistics have already been collected for the
+    /// frame.
+    pub fn begin_pipeline_statistics(&mut self, cmd: vk::CommandBuffer) {
+        if let Some(query) = &mut self.get_current_frame().pipeline_statistics {
+            query.begin(cmd);
+        }
+    }
+
+    /// Stops collecting pipeline statistics in `cmd`.
+    ///
+    /// # Panics
+    /// If no frame is being recorded or pipeline statistics are not being collected.
+    pub fn end_pipeline_statistics(&mut self, cmd: vk::CommandBuffer) {
+        if let Some(query) = &mut self.get_current_frame().pipeline_statistics {
+            query.end(cmd);
+        }
+    }
+
+    fn get_current_frame(&mut self) -> &mut ProfiledFrame {
+        let index = self.current_frame.expect("GpuProfiler used without a frame being recorded");
+        &mut self.frames[index]
+    }
+
+    fn resolve(&self, frame: &ProfiledFrame) -> Option<FrameStatistics> {
+        let timestamps = match frame.pool.try_read_timestamps() {
+            Ok(Some(timestamps)) => timestamps,
+            Ok(None) => {
+                log::debug!("Gpu profiler results are not available yet. Discarding frame.");
+                return None;
+            }
+            Err(err) => {
+                log::warn!("Failed to read gpu profiler timestamps: {:?}", err);
+                return None;
+            }
+        };
+
+        let phases = frame.phases.iter().filter_map(|phase| {
+            let start = timestamps[phase.start?.get_index() as usize];
+            let end = timestamps[phase.end?.get_index() as usize];
+            Some(PhaseTiming {
+                name: phase.name.clone(),
+                duration: ticks_to_duration(start, end, self.timestamp_valid_bits, self.nanoseconds_per_tick),
+            })
+        }).collect();
+
+        let pipeline = frame.pipeline_statistics.as_ref().and_then(|query| {
+            query.try_read().unwrap_or_else(|err| {
+                log::warn!("Failed to read gpu profiler pipeline statistics: {:?}", err);
+                None
+            })
+        });
+
+        Some(FrameStatistics {
+            phases,
+            pipeline,
+        })
+    }
+}
+
+/// Converts the difference between two timestamps to a duration. Only the lower
+/// `timestamp_valid_bits` of the timestamps are valid so the difference wraps around at that
+/// bit.
+fn ticks_to_duration(start: u64, end: u64, timestamp_valid_bits: u32, nanoseconds_per_tick: f64) -> Duration {
+    let mask = if timestamp_valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << timestamp_valid_bits) - 1
+    };
+    let ticks = end.wrapping_sub(start) & mask;
+
+    Duration::from_nanos((ticks as f64 * nanoseconds_per_tick) as u64)
+}
+
+/// The number of entries kept by [`Breadcrumbs`].
+const BREADCRUMB_CAPACITY: usize = 64;
+
+/// The type of operation recorded by a [`Breadcrumb`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BreadcrumbKind {
+    /// A queue submission.
+    Submit,
+    /// A checkpoint recorded into a command buffer. Only recorded if
+    /// `VK_NV_device_diagnostic_checkpoints` is enabled.
+    Checkpoint,
+    /// A swapchain has been created.
+    SwapchainCreated,
+    /// A large device memory allocation.
+    Allocation,
+}
+
+/// A major operation recorded by [`Breadcrumbs`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Breadcrumb {
+    /// Unique and increasing in recording order.
+    pub id: u64,
+    pub kind: BreadcrumbKind,
+    pub message: String,
+    pub time: Instant,
+}
+
+/// Ring buffer of the most recent major operations of a device used to diagnose device loss.
+pub struct Breadcrumbs {
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<Breadcrumb>>,
+}
+
+impl Breadcrumbs {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            entries: Mutex::new(VecDeque::with_capacity(BREADCRUMB_CAPACITY)),
+        }
+    }
+
+    /// Records a new breadcrumb dropping the oldest one if the buffer is full. Returns the id of
+    /// the new breadcrumb.
+    pub fn record<S: Into<String>>(&self, kind: BreadcrumbKind, message: S) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let breadcrumb = Breadcrumb {
+            id,
+            kind,
+            message: message.into(),
+            time: Instant::now(),
+        };
+
+        let mut guard = self.entries.lock().unwrap();
+        if guard.len() >= BREADCRUMB_CAPACITY {
+            guard.pop_front();
+        }
+        guard.push_back(breadcrumb);
+
+        id
+    }
+
+    /// Returns the breadcrumb with the specified id if it is still stored.
+    pub fn get(&self, id: u64) -> Option<Breadcrumb> {
+        self.entries.lock().unwrap().iter().find(|breadcrumb| breadcrumb.id == id).cloned()
+    }
+
+    /// Returns all stored breadcrumbs from oldest to newest.
+    pub fn snapshot(&self) -> Vec<Breadcrumb> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Default for Breadcrumbs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The last checkpoint executed by the gpu in some pipeline stage as reported by
+/// `VK_NV_device_diagnostic_checkpoints`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GpuCheckpoint {
+    pub stage: vk::PipelineStageFlags,
+    /// The id of the [`Breadcrumb`] recorded together with the checkpoint.
+    pub breadcrumb_id: u64,
+    /// The message of the breadcrumb or [`None`] if it has been dropped from the ring buffer.
+    pub message: Option<String>,
+}
+
+/// Information collected after a device has been lost.
+///
+/// The [`Display`] implementation formats the report for logging.
+#[derive(Clone, Debug)]
+pub struct DeviceLostReport {
+    pub device_name: String,
+    pub api_version: APIVersion,
+    pub enabled_extensions: Vec<CString>,
+    pub memory: MemoryStatisticsSnapshot,
+    /// The most recent breadcrumbs from oldest to newest.
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Empty if `VK_NV_device_diagnostic_checkpoints` is not enabled.
+    pub gpu_checkpoints: Vec<GpuCheckpoint>,
+    pub time: Instant,
+}
+
+impl Display for DeviceLostReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Device lost: {} (API version {:?})", self.device_name, self.api_version)?;
+        writeln!(f, "Enabled extensions: {:?}", self.enabled_extensions)?;
+        writeln!(f, "Memory: {} bytes in {} pooled allocations, {} bytes in {} dedicated allocations",
+                 self.memory.pool_bytes, self.memory.pool_allocation_count, self.memory.dedicated_bytes, self.memory.dedicated_allocation_count)?;
+
+        if !self.gpu_checkpoints.is_empty() {
+            writeln!(f, "Last gpu checkpoints:")?;
+            for checkpoint in &self.gpu_checkpoints {
+                writeln!(f, "    {:?}: #{} {}", checkpoint.stage, checkpoint.breadcrumb_id, checkpoint.message.as_deref().unwrap_or("<dropped>"))?;
+            }
+        }
+
+        write!(f, "Recent operations (oldest first):")?;
+        for breadcrumb in &self.breadcrumbs {
+            write!(f, "\n    #{} {:?} ago {:?}: {}", breadcrumb.id, self.time.saturating_duration_since(breadcrumb.time), breadcrumb.kind, breadcrumb.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A statistic which only ever increases, for example the number of presented frames. Cloning
+/// creates a new handle to the same counter.
+#[derive(Clone, Debug)]
+pub struct Counter {
+    value: Arc<AtomicU64>,
+}
+
+impl Counter {
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// A statistic which can increase and decrease, for example the number of live components.
+/// Cloning creates a new handle to the same gauge.
+#[derive(Clone, Debug)]
+pub struct Gauge {
+    value: Arc<AtomicU64>,
+}
+
+impl Gauge {
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, value: u64) {
+        self.value.fetch_sub(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Computes the value of a gauge registered with [`Statistics::register_gauge_fn`].
+pub type GaugeFn = dyn Fn() -> u64 + Send + Sync;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum StatisticKind {
+    Counter,
+    Gauge,
+}
+
+enum StatisticSource {
+    Atomic(StatisticKind, Arc<AtomicU64>),
+    Fn(Box<GaugeFn>),
+}
+
+struct Statistic {
+    name: String,
+    source: StatisticSource,
+}
+
+/// A registry of named counters and gauges.
+///
+/// Subsystems register their statistics once at startup and update them through the returned
+/// [`Counter`] and [`Gauge`] handles which only perform a single atomic operation. Every
+/// [`crate::vulkan::AgnajiVulkan`] owns its own registry so multiple instances in the same
+/// process never mix their numbers.
+pub struct Statistics {
+    statistics: Mutex<Vec<Statistic>>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self {
+            statistics: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns a handle to the counter called `name` creating it if it does not exist yet.
+    ///
+    /// # Panics
+    /// If `name` is already registered as a different kind of statistic.
+    pub fn register_counter<S: Into<String>>(&self, name: S) -> Counter {
+        Counter {
+            value: self.register_atomic(name.into(), StatisticKind::Counter),
+        }
+    }
+
+    /// Returns a handle to the gauge called `name` creating it if it does not exist yet.
+    ///
+    /// # Panics
+    /// If `name` is already registered as a different kind of statistic.
+    pub fn register_gauge<S: Into<String>>(&self, name: S) -> Gauge {
+        Gauge {
+            value: self.register_atomic(name.into(), StatisticKind::Gauge),
+        }
+    }
+
+    /// Registers a gauge whose value is computed by `gauge_fn` every time a snapshot is taken.
+    /// Useful if the value is already tracked elsewhere.
+    ///
+    /// `gauge_fn` is called while the registry is locked and must not access the registry.
+    ///
+    /// # Panics
+    /// If `name` is already registered.
+    pub fn register_gauge_fn<S: Into<String>>(&self, name: S, gauge_fn: Box<GaugeFn>) {
+        let name = name.into();
+        let mut guard = self.statistics.lock().unwrap();
+        if guard.iter().any(|statistic| statistic.name == name) {
+            panic!("Statistic {} is already registered", name);
+        }
+
+        guard.push(Statistic {
+            name,
+            source: StatisticSource::Fn(gauge_fn),
+        });
+    }
+
+    fn register_atomic(&self, name: String, kind: StatisticKind) -> Arc<AtomicU64> {
+        let mut guard = self.statistics.lock().unwrap();
+        if let Some(statistic) = guard.iter().find(|statistic| statistic.name == name) {
+            return match &statistic.source {
+                StatisticSource::Atomic(existing, value) if *existing == kind => value.clone(),
+                _ => panic!("Statistic {} is already registered as a different kind", name),
+            };
+        }
+
+        let value = Arc::new(AtomicU64::new(0));
+        guard.push(Statistic {
+            name,
+            source: StatisticSource::Atomic(kind, value.clone()),
+        });
+
+        value
+    }
+
+    /// Returns the current value of the statistic called `name`.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.statistics.lock().unwrap().iter()
+            .find(|statistic| statistic.name == name)
+            .map(Statistic::get_value)
+    }
+
+    /// Returns the name and current value of all statistics in registration order.
+    pub fn snapshot(&self) -> Vec<(String, u64)> {
+        self.statistics.lock().unwrap().iter()
+            .map(|statistic| (statistic.name.clone(), statistic.get_value()))
+            .collect()
+    }
+
+    /// Logs a snapshot of all statistics in a single line at info level.
+    pub fn log_snapshot(&self) {
+        let snapshot = self.snapshot();
+        let formatted = snapshot.iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        log::info!("Statistics: {}", formatted);
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Statistic {
+    fn get_value(&self) -> u64 {
+        match &self.source {
+            StatisticSource::Atomic(_, value) => value.load(Ordering::Relaxed),
+            StatisticSource::Fn(gauge_fn) => gauge_fn(),
+        }
+    }
+}
+
+/// Builds a log target by appending `label` in square brackets to `base`. Used to tell apart the
+/// logs of multiple engine instances, windows or outputs in one process. Returns `base`
+/// unchanged if `label` is [`None`].
+///
+/// For example `log_target("agnaji::winit", Some("editor"))` returns `"agnaji::winit[editor]"`.
+pub fn log_target(base: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{}[{}]", base, label),
+        None => String::from(base),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(severity: vk::DebugUtilsMessageSeverityFlagsEXT, message_id_name: Option<&str>) -> DebugMessage<'_> {
+        DebugMessage {
+            severity,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            message_id_name,
+            message_id_number: 7,
+            message: "test message",
+        }
+    }
+
+    #[test]
+    fn validation_capture() {
+        let capture = ValidationCapture::with_allowlist(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING, ["noisy"]);
+        let handler = capture.create_handler();
+
+        handler(&message(vk::DebugUtilsMessageSeverityFlagsEXT::INFO, Some("info")));
+        handler(&message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING, Some("warning")));
+        capture.assert_no_errors();
+
+        handler(&message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR, Some("noisy")));
+        assert!(!capture.has_errors());
+        handler(&message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR, None));
+        assert!(capture.has_errors());
+
+        let messages = capture.take_messages();
+        assert_eq!(messages.iter().map(|message| message.message_id_name.as_deref()).collect::<Vec<_>>(), vec![Some("warning"), None]);
+        assert_eq!(messages[1].message_id_number, 7);
+        assert!(!capture.has_errors());
+        assert!(capture.take_messages().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Validation errors occurred")]
+    fn assert_no_errors() {
+        let capture = ValidationCapture::new(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR);
+        capture.create_handler()(&message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR, Some("error")));
+        capture.assert_no_errors();
+    }
+
+    #[test]
+    fn ticks_conversion() {
+        assert_eq!(ticks_to_duration(100, 350, 64, 1.0), Duration::from_nanos(250));
+        assert_eq!(ticks_to_duration(100, 350, 64, 2.5), Duration::from_nanos(625));
+        assert_eq!(ticks_to_duration(7, 7, 36, 1.0), Duration::ZERO);
+
+        // Timestamps wrap around at the valid bits
+        assert_eq!(ticks_to_duration(0xFFFF_FFF0, 0x10, 32, 1.0), Duration::from_nanos(0x20));
+        assert_eq!(ticks_to_duration(u64::MAX - 4, 5, 64, 1.0), Duration::from_nanos(10));
+    }
+
+    #[test]
+    fn frame_statistics_phase_lookup() {
+        let statistics = FrameStatistics {
+            phases: vec![
+                PhaseTiming { name: String::from("frame"), duration: Duration::from_micros(300) },
+                PhaseTiming { name: String::from("clear"), duration: Duration::from_micros(20) },
+            ],
+            pipeline: None,
+        };
+        assert_eq!(statistics.get_phase_duration("clear"), Some(Duration::from_micros(20)));
+        assert_eq!(statistics.get_phase_duration("present"), None);
+    }
+
+    #[test]
+    fn pipeline_statistics_from_results() {
+        let statistics = PipelineStatistics::from_results(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(statistics, PipelineStatistics {
+            input_assembly_vertices: 1,
+            input_assembly_primitives: 2,
+            vertex_shader_invocations: 3,
+            fragment_shader_invocations: 6,
+            clipping_invocations: 4,
+            clipping_primitives: 5,
+        });
+    }
+
+    #[test]
+    fn log_target_label() {
+        assert_eq!(log_target("agnaji::winit", None), "agnaji::winit");
+        assert_eq!(log_target("agnaji::winit", Some("editor")), "agnaji::winit[editor]");
+
+        let output = log_target(&log_target("agnaji::vulkan::output", Some("editor")), Some("main"));
+        assert_eq!(output, "agnaji::vulkan::output[editor][main]");
+        assert_eq!(log_target(&log_target("agnaji::vulkan::output", None), Some("main")), "agnaji::vulkan::output[main]");
+    }
+
+    #[test]
+    fn breadcrumb_ring_buffer() {
+        let breadcrumbs = Breadcrumbs::new();
+        let first = breadcrumbs.record(BreadcrumbKind::Submit, "first");
+        assert_eq!(breadcrumbs.get(first).unwrap().message, "first");
+
+        for index in 0..BREADCRUMB_CAPACITY {
+            breadcrumbs.record(BreadcrumbKind::Checkpoint, format!("checkpoint {}", index));
+        }
+        assert_eq!(breadcrumbs.get(first), None);
+
+        let snapshot = breadcrumbs.snapshot();
+        assert_eq!(snapshot.len(), BREADCRUMB_CAPACITY);
+        assert_eq!(snapshot[0].message, "checkpoint 0");
+        assert_eq!(snapshot.last().unwrap().message, format!("checkpoint {}", BREADCRUMB_CAPACITY - 1));
+        assert!(snapshot.windows(2).all(|pair| pair[0].id < pair[1].id));
+    }
+
+    #[test]
+    fn device_lost_report_display() {
+        let breadcrumbs = Breadcrumbs::new();
+        let id = breadcrumbs.record(BreadcrumbKind::Checkpoint, "clear");
+        breadcrumbs.record(BreadcrumbKind::Submit, "agnaji output frame");
+
+        let report = DeviceLostReport {
+            device_name: String::from("Test Device"),
+            api_version: APIVersion::VERSION_1_2,
+            enabled_extensions: Vec::new(),
+            memory: MemoryStatisticsSnapshot::default(),
+            breadcrumbs: breadcrumbs.snapshot(),
+            gpu_checkpoints: vec![GpuCheckpoint {
+                stage: vk::PipelineStageFlags::TRANSFER,
+                breadcrumb_id: id,
+                message: Some(String::from("clear")),
+            }],
+            time: Instant::now(),
+        };
+        let formatted = report.to_string();
+        assert!(formatted.starts_with("Device lost: Test Device"));
+        assert!(formatted.contains(&format!("TRANSFER: #{} clear", id)));
+        assert!(formatted.contains("Submit: agnaji output frame"));
+    }
+
+    #[test]
+    fn statistics_registry() {
+        let statistics = Statistics::new();
+        let frames = statistics.register_counter("frames");
+        let components = statistics.register_gauge("components");
+        statistics.register_gauge_fn("constant", Box::new(|| 42));
+
+        frames.increment();
+        statistics.register_counter("frames").add(2);
+        components.add(5);
+        components.sub(2);
+
+        assert_eq!(statistics.snapshot(), vec![
+            ("frames".to_string(), 3),
+            ("components".to_string(), 3),
+            ("constant".to_string(), 42),
+        ]);
+        assert_eq!(statistics.get("components"), Some(3));
+        assert_eq!(statistics.get("missing"), None);
+
+        // Registries are independent of each other
+        assert_eq!(Statistics::new().register_counter("frames").get(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn statistics_kind_mismatch() {
+        let statistics = Statistics::new();
+        statistics.register_counter("frames");
+        statistics.register_gauge("frames");
+    }
+}
+
+#[cfg(feature = "renderdoc")]
+mod frame_capture {
+    use std::ffi::{c_int, c_void};
+    use std::sync::OnceLock;
+
+    use crate::vulkan::output::SurfaceOutput;
+
+    /// The RenderDoc in-app API version requested. 1.1.2 is supported by all RenderDoc releases
+    /// since 1.1.
+    const RENDERDOC_API_VERSION: c_int = 10102;
+
+    type GetApiFn = unsafe extern "C" fn(version: c_int, out_api_pointers: *mut *mut c_void) -> c_int;
+
+    /// The prefix of the `RENDERDOC_API_1_1_2` function table up to the functions used here.
+    #[repr(C)]
+    struct RenderDocApiTable {
+        _unused: [*const c_void; 19],
+        start_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void),
+        is_frame_capturing: unsafe extern "C" fn() -> u32,
+        end_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32,
+    }
+
+    struct RenderDocApi {
+        _library: libloading::Library,
+        table: *const RenderDocApiTable,
+    }
+
+    // The RenderDoc API may be used from any thread
+    unsafe impl Send for RenderDocApi {}
+    unsafe impl Sync for RenderDocApi {}
+
+    impl RenderDocApi {
+        /// Loads the API if RenderDoc has been injected into the process. RenderDoc is never
+        /// loaded by this function.
+        fn load() -> Option<Self> {
+            let library = open_injected_library()?;
+            let get_api = unsafe { library.get::<GetApiFn>(b"RENDERDOC_GetAPI\0") }.ok()?;
+
+            let mut table = std::ptr::null_mut();
+            if unsafe { get_api(RENDERDOC_API_VERSION, &mut table) } != 1 || table.is_null() {
+                log::warn!("RenderDoc is loaded but does not support in-app API version {}", RENDERDOC_API_VERSION);
+                return None;
+            }
+
+            Some(Self {
+                _library: library,
+                table: table as *const RenderDocApiTable,
+            })
+        }
+
+        fn table(&self) -> &RenderDocApiTable {
+            unsafe { &*self.table }
+        }
+    }
+
+    #[cfg(unix)]
+    fn open_injected_library() -> Option<libloading::Library> {
+        unsafe {
+            libloading::os::unix::Library::open(Some("librenderdoc.so"), libc::RTLD_NOW | libc::RTLD_NOLOAD)
+        }.ok().map(Into::into)
+    }
+
+    #[cfg(windows)]
+    fn open_injected_library() -> Option<libloading::Library> {
+        libloading::os::windows::Library::open_already_loaded("renderdoc.dll").ok().map(Into::into)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn open_injected_library() -> Option<libloading::Library> {
+        None
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+    pub enum FrameCaptureError {
+        /// RenderDoc has not been injected into the process.
+        Unavailable,
+    }
+
+    /// Triggers RenderDoc captures from within the application. Only available if the process
+    /// has been launched from RenderDoc (or RenderDoc has been injected otherwise).
+    pub struct FrameCapture {
+        api: Option<RenderDocApi>,
+    }
+
+    static FRAME_CAPTURE: OnceLock<FrameCapture> = OnceLock::new();
+
+    impl FrameCapture {
+        /// Returns the global instance. RenderDoc is detected the first time this is called.
+        pub fn get() -> &'static FrameCapture {
+            FRAME_CAPTURE.get_or_init(|| {
+                let api = RenderDocApi::load();
+                log::info!("RenderDoc in-app API available: {}", api.is_some());
+                FrameCapture {
+                    api,
+                }
+            })
+        }
+
+        pub fn is_available(&self) -> bool {
+            self.api.is_some()
+        }
+
+        /// Captures the next frame presented by `output`. The capture begins before the
+        /// swapchain image is acquired and ends after it has been presented.
+        pub fn capture_next_frame(&self, output: &SurfaceOutput) -> Result<(), FrameCaptureError> {
+            if self.api.is_none() {
+                return Err(FrameCaptureError::Unavailable);
+            }
+            output.request_frame_capture();
+
+            Ok(())
+        }
+
+        /// Returns true if a capture is currently in progress.
+        pub fn is_capturing(&self) -> bool {
+            self.api.as_ref().map(|api| unsafe { (api.table().is_frame_capturing)() } != 0).unwrap_or(false)
+        }
+
+        /// Starts a capture of all vulkan devices and windows which ends when the returned scope
+        /// is dropped. Returns [`None`] if RenderDoc is not available.
+        pub(crate) fn begin_capture(&'static self) -> Option<FrameCaptureScope> {
+            let api = self.api.as_ref()?;
+            unsafe {
+                (api.table().start_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut());
+            }
+
+            Some(FrameCaptureScope {
+                api,
+            })
+        }
+    }
+
+    pub(crate) struct FrameCaptureScope {
+        api: &'static RenderDocApi,
+    }
+
+    impl Drop for FrameCaptureScope {
+        fn drop(&mut self) {
+            if unsafe { (self.api.table().end_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+                log::warn!("RenderDoc frame capture failed");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "renderdoc")]
+pub use frame_capture::{FrameCapture, FrameCaptureError};
@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
-use crate::scene::Scene;
+use crate::scene::{Scene, SceneComponent, SceneData, SceneError};
 
 pub mod vulkan;
 pub mod debug;
+pub mod math;
 pub mod output;
 pub mod scene;
 pub mod utils;
@@ -12,6 +13,21 @@ pub mod prelude;
 #[cfg(feature = "winit")]
 pub mod winit;
 
+/// A scene together with the components loaded into it by [`Agnaji::create_scene_from_data`].
+pub type LoadedScene = (Arc<dyn Scene>, Vec<Arc<dyn SceneComponent>>);
+
 pub trait Agnaji: Send + Sync {
     fn create_scene(&self) -> Arc<dyn Scene>;
+
+    /// Creates a new scene containing the components of a snapshot created by
+    /// [`Scene::serialize`]. Loaded components receive new ids and are returned in the order of
+    /// [`SceneData::components`].
+    fn create_scene_from_data(&self, data: SceneData) -> Result<LoadedScene, SceneError> {
+        let scene = self.create_scene();
+        let update = scene.begin_update().map_err(|_| SceneError::UpdateInProgress)?;
+        let components = data.load_into(update.as_ref())?;
+        drop(update);
+
+        Ok((scene, components))
+    }
 }
\ No newline at end of file
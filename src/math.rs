@@ -0,0 +1,148 @@
+use crate::prelude::*;
+use crate::scene::ComponentId;
+
+/// A axis aligned bounding box.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3f32,
+    pub max: Vec3f32,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3f32, max: Vec3f32) -> Self {
+        Self {
+            min,
+            max,
+        }
+    }
+
+    pub fn from_center_extent(center: Vec3f32, half_extent: Vec3f32) -> Self {
+        Self {
+            min: center - half_extent,
+            max: center + half_extent,
+        }
+    }
+}
+
+/// A view frustum defined by 6 planes.
+///
+/// Each plane is stored as `(normal, distance)` with the normal pointing into the frustum. A point
+/// `p` is inside a plane if `dot(normal, p) + distance >= 0`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Frustum {
+    planes: [Vec4f32; 6],
+}
+
+impl Frustum {
+    /// Extracts the frustum planes from a view projection matrix. The matrix must map into the
+    /// vulkan clip space, i.e. a depth range of `[0, 1]`.
+    pub fn from_view_projection(matrix: &Mat4f32) -> Self {
+        let row = |i: usize| -> Vec4f32 {
+            matrix.row(i).transpose()
+        };
+
+        let planes = [
+            row(3) + row(0), // Left
+            row(3) - row(0), // Right
+            row(3) + row(1), // Bottom
+            row(3) - row(1), // Top
+            row(2),          // Near
+            row(3) - row(2), // Far
+        ];
+
+        Self {
+            planes: planes.map(|plane| {
+                let length = plane.xyz().norm();
+                if length > 0.0 {
+                    plane / length
+                } else {
+                    plane
+                }
+            }),
+        }
+    }
+
+    pub fn get_planes(&self) -> &[Vec4f32; 6] {
+        &self.planes
+    }
+
+    /// Tests if the box is at least partially inside the frustum.
+    ///
+    /// This is a conservative test. Some boxes close to the frustum corners may be reported as
+    /// intersecting even though they are not.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        for plane in &self.planes {
+            // The corner of the box furthest along the plane normal
+            let positive = Vec3f32::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+
+            if plane.xyz().dot(&positive) + plane.w < 0.0 {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Tests all boxes against the frustum and returns the ids of all boxes which are at least
+    /// partially inside the frustum. The order of the ids matches the order in `aabbs`.
+    ///
+    /// Using this function is preferred over calling [`Frustum::intersects_aabb`] for each
+    /// component since the planes only need to be loaded once and the loop is simple enough to be
+    /// vectorized by the compiler.
+    pub fn cull_aabbs(&self, aabbs: &[(ComponentId, Aabb)]) -> Vec<ComponentId> {
+        aabbs.iter().filter_map(|(id, aabb)| {
+            if self.intersects_aabb(aabb) {
+                Some(*id)
+            } else {
+                None
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// With a identity view projection matrix the frustum is the vulkan clip space box.
+    fn clip_space_frustum() -> Frustum {
+        Frustum::from_view_projection(&Mat4f32::identity())
+    }
+
+    #[test]
+    fn intersects_aabb_clip_space() {
+        let frustum = clip_space_frustum();
+
+        assert!(frustum.intersects_aabb(&Aabb::from_center_extent(Vec3f32::new(0.0, 0.0, 0.5), Vec3f32::new(0.1, 0.1, 0.1))));
+        assert!(frustum.intersects_aabb(&Aabb::new(Vec3f32::new(-5.0, -5.0, -5.0), Vec3f32::new(5.0, 5.0, 5.0))));
+        assert!(frustum.intersects_aabb(&Aabb::new(Vec3f32::new(0.9, 0.0, 0.5), Vec3f32::new(1.5, 0.1, 0.6))));
+
+        assert!(!frustum.intersects_aabb(&Aabb::from_center_extent(Vec3f32::new(5.0, 0.0, 0.5), Vec3f32::new(0.1, 0.1, 0.1))));
+        assert!(!frustum.intersects_aabb(&Aabb::from_center_extent(Vec3f32::new(0.0, -5.0, 0.5), Vec3f32::new(0.1, 0.1, 0.1))));
+        assert!(!frustum.intersects_aabb(&Aabb::from_center_extent(Vec3f32::new(0.0, 0.0, -0.5), Vec3f32::new(0.1, 0.1, 0.1))));
+        assert!(!frustum.intersects_aabb(&Aabb::from_center_extent(Vec3f32::new(0.0, 0.0, 1.5), Vec3f32::new(0.1, 0.1, 0.1))));
+    }
+
+    #[test]
+    fn cull_aabbs_returns_survivors_in_order() {
+        let frustum = clip_space_frustum();
+        let extent = Vec3f32::new(0.1, 0.1, 0.1);
+
+        let inside_1 = ComponentId::new();
+        let outside = ComponentId::new();
+        let inside_2 = ComponentId::new();
+
+        let aabbs = [
+            (inside_1, Aabb::from_center_extent(Vec3f32::new(0.5, 0.5, 0.5), extent)),
+            (outside, Aabb::from_center_extent(Vec3f32::new(3.0, 0.0, 0.5), extent)),
+            (inside_2, Aabb::from_center_extent(Vec3f32::new(-0.5, 0.0, 0.2), extent)),
+        ];
+
+        assert_eq!(frustum.cull_aabbs(&aabbs), vec![inside_1, inside_2]);
+        assert!(frustum.cull_aabbs(&[]).is_empty());
+    }
+}
@@ -1,13 +1,24 @@
 use std::sync::Arc;
-use crate::scene::CameraComponent;
+use crate::scene::{CameraComponent, Color};
 
 /// A output target defines the ultimate destination of rendered images. To render a output target
 /// uses a camera component which defines the scene and draw settings to be used for rendering. Any
 /// rendering is ultimately initiated by a output target.
-pub trait OutputTarget: Send {
+pub trait OutputTarget: Send + Sync {
 
     /// Configures the camera that should be used for rendering.
     ///
     /// If `camera` is [`None`] the camera is cleared.
     fn set_source_camera(&self, camera: Option<Arc<dyn CameraComponent>>);
+
+    /// Sets the priority used to order multiple outputs of the same renderer. Outputs with a lower
+    /// priority value are processed first. Defaults to 0.
+    fn set_render_priority(&self, priority: i32);
+
+    fn get_render_priority(&self) -> i32;
+
+    /// Returns the color the output clears its images to at the start of every frame or [`None`]
+    /// if the output does not clear. Cameras using
+    /// [`crate::scene::CameraClearMode::InheritFromOutput`] apply the same clear.
+    fn get_clear_color(&self) -> Option<Color>;
 }
\ No newline at end of file
@@ -38,4 +38,7 @@ pub type Quatf32 = nalgebra::geometry::UnitQuaternion<f32>;
 pub type Vec2f64 = nalgebra::Vector2<f64>;
 pub type Vec3f64 = nalgebra::Vector3<f64>;
 pub type Vec4f64 = nalgebra::Vector4<f64>;
-pub type Quatf64 = nalgebra::geometry::UnitQuaternion<f64>;
\ No newline at end of file
+pub type Quatf64 = nalgebra::geometry::UnitQuaternion<f64>;
+
+pub type Mat4f32 = nalgebra::Matrix4<f32>;
+pub type Mat4f64 = nalgebra::Matrix4<f64>;
\ No newline at end of file
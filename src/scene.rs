@@ -1,10 +1,170 @@
 use std::any::Any;
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
 use crate::utils::define_counting_id_type;
 
 define_counting_id_type!(pub, SceneId);
 define_counting_id_type!(pub, ComponentId);
 
+/// A rgba color in linear color space.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SceneUpdateError {
+    /// The scene already contains a component of which only one may exist per scene.
+    AlreadyExists,
+
+    /// Another update of the scene is currently in progress.
+    UpdateInProgress,
+}
+
+/// Errors returned when serializing or loading a [`Scene`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SceneError {
+    /// Another update of the scene is currently in progress.
+    UpdateInProgress,
+
+    /// The [`SceneData`] has been created by an unsupported version.
+    UnsupportedVersion(u32),
+
+    /// Multiple components of the [`SceneData`] have the same id.
+    DuplicateComponentId(u64),
+
+    /// The parent of a component is not part of the [`SceneData`].
+    UnknownParent { id: u64, parent: u64 },
+
+    /// The properties of a component could not be encoded or decoded.
+    InvalidProperties { id: u64, message: String },
+
+    /// The component is of a type that cannot be serialized.
+    UnsupportedComponent(u64),
+
+    /// Creating a component failed.
+    Update(SceneUpdateError),
+}
+
+impl From<SceneUpdateError> for SceneError {
+    fn from(err: SceneUpdateError) -> Self {
+        SceneError::Update(err)
+    }
+}
+
+/// Statistics about the current state of a [`Scene`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SceneStatistics {
+    /// The number of live components in the scene.
+    pub component_count: usize,
+}
+
+/// The type of a [`SceneComponent`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum ComponentType {
+    Camera,
+    BackgroundColor,
+}
+
+/// The property of a component that was modified.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ModifiedField {
+    /// See [`CameraComponent::set_clear_mode`].
+    ClearMode,
+
+    /// See [`CameraComponent::set_render_order`].
+    RenderOrder,
+
+    /// The color or gradient of a [`BackgroundColorComponent`].
+    Background,
+}
+
+/// A change of a [`Scene`] reported to [`ChangeReceiver`]s.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SceneChange {
+    ComponentAdded(ComponentId, ComponentType),
+    ComponentRemoved(ComponentId),
+    ComponentModified(ComponentId, ModifiedField),
+}
+
+/// Receives all changes of a scene in the order they were made. Created by
+/// [`Scene::subscribe_changes`].
+///
+/// Changes are reported as soon as they are made and not when the [`SceneUpdate`] is committed.
+/// Changes are queued until received so receivers that are not needed anymore should be dropped.
+pub struct ChangeReceiver {
+    receiver: Receiver<SceneChange>,
+}
+
+impl ChangeReceiver {
+    /// Returns the next change if one is available without blocking.
+    pub fn try_recv(&self) -> Option<SceneChange> {
+        match self.receiver.try_recv() {
+            Ok(change) => Some(change),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Blocks until the next change. Returns [`None`] if the scene has been dropped and all
+    /// changes have been received.
+    pub fn wait_change(&self) -> Option<SceneChange> {
+        self.receiver.recv().ok()
+    }
+
+    /// Blocks until the next change or the timeout elapsed.
+    pub fn wait_change_timeout(&self, timeout: Duration) -> Option<SceneChange> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(change) => Some(change),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+/// Distributes scene changes to all [`ChangeReceiver`]s of a scene. Used by scene
+/// implementations.
+pub(crate) struct SceneChangeBroadcast {
+    subscribers: Mutex<Vec<Sender<SceneChange>>>,
+}
+
+impl SceneChangeBroadcast {
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> ChangeReceiver {
+        let (send, receiver) = std::sync::mpsc::channel();
+        self.subscribers.lock().unwrap().push(send);
+
+        ChangeReceiver {
+            receiver,
+        }
+    }
+
+    pub(crate) fn broadcast(&self, change: SceneChange) {
+        // Dropped receivers are removed
+        self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(change).is_ok());
+    }
+}
+
 /// A scene is a collection of components defining a world to be rendered. [`SceneComponent`]s are
 /// organized into a hierarchy which is called the scene graph.
 ///
@@ -25,6 +185,49 @@ pub trait Scene: Send + Sync {
     /// instance is dropped.
     fn begin_update(&self) -> Result<Box<dyn SceneUpdate>, ()>;
 
+    /// Returns how long the commit of the last [`SceneUpdate`] took (i.e. how long its drop
+    /// blocked) or [`None`] if no update has been committed yet.
+    ///
+    /// Long commit durations indicate that scene updates are submitted faster than the renderer
+    /// can consume them.
+    fn last_commit_duration(&self) -> Option<Duration>;
+
+    /// Returns the average commit duration of the last `window` updates. If fewer updates have
+    /// been committed all of them are used. Only a limited number of durations is stored so
+    /// large windows may be clamped.
+    fn average_commit_duration(&self, window: usize) -> Option<Duration>;
+
+    /// Destroys all components of the scene in a single update.
+    ///
+    /// If another update is currently in progress [`SceneUpdateError::UpdateInProgress`] is
+    /// returned and the scene is not modified.
+    fn clear(&self) -> Result<(), SceneUpdateError>;
+
+    /// Returns the component with the provided id if it is part of this scene and has not been
+    /// destroyed.
+    fn get_component_by_id(&self, id: ComponentId) -> Option<Arc<dyn SceneComponent>>;
+
+    fn get_statistics(&self) -> SceneStatistics;
+
+    /// Returns an iterator over all live cameras of the scene in creation order. The iterator
+    /// operates on a snapshot taken when this function is called so it is not affected by
+    /// concurrent modifications.
+    fn iter_cameras(&self) -> Box<dyn Iterator<Item=Arc<dyn CameraComponent>> + Send>;
+
+    /// Returns the number of live cameras in the scene.
+    fn camera_count(&self) -> usize;
+
+    /// Returns a receiver which is sent every change made to this scene from now on.
+    fn subscribe_changes(&self) -> ChangeReceiver;
+
+    /// Returns a snapshot of all live components of the scene in creation order. The snapshot can
+    /// be loaded again with [`crate::Agnaji::create_scene_from_data`].
+    ///
+    /// If an update is currently in progress [`SceneError::UpdateInProgress`] is returned since
+    /// the scene may be in an incomplete state. If the scene contains a component that cannot be
+    /// serialized [`SceneError::UnsupportedComponent`] is returned.
+    fn serialize(&self) -> Result<SceneData, SceneError>;
+
     fn as_any(&self) -> &(dyn Any + Send + Sync + 'static);
 
     fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static>;
@@ -52,6 +255,17 @@ pub trait SceneUpdate: Send + Sync {
 
     fn create_camera_component(&self) -> Arc<dyn CameraComponent>;
 
+    /// Destroys all components of the scene. Equivalent to calling [`SceneComponent::destroy`]
+    /// on every component.
+    fn destroy_all_components(&self);
+
+    /// Creates the background of the scene. The background is drawn behind all other geometry.
+    ///
+    /// Only one background may exist per scene at a time. If the scene already has a background
+    /// [`SceneUpdateError::AlreadyExists`] is returned. Once the existing background is destroyed
+    /// a new one may be created.
+    fn create_background_color(&self) -> Result<Arc<dyn BackgroundColorComponent>, SceneUpdateError>;
+
     fn as_any(&self) -> &(dyn Any + Send + Sync + 'static);
 
     fn as_any_box(self: Box<Self>) -> Box<dyn Any + Send + Sync + 'static>;
@@ -94,5 +308,181 @@ pub trait TransformComponent: SceneComponent {
     fn set_scale(&self, update: &dyn SceneUpdate, scale: ());
 }*/
 
+/// Defines which attachments a camera clears before rendering. When multiple cameras render to
+/// the same output (for example split screen) usually only the first camera clears everything.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum CameraClearMode {
+    /// Clears color and depth. This is the default for new cameras. Color is cleared to the clear
+    /// color of the output or [`Color::BLACK`] if the output does not clear.
+    ClearAll,
+
+    /// Clears only depth keeping the color written by previous cameras.
+    ClearDepthOnly,
+
+    /// Clears nothing. Both color and depth of previous cameras are kept.
+    NoClear,
+
+    /// Uses the clear settings of the output the camera renders to. Color and depth are cleared
+    /// only if the output clears (see [`crate::output::OutputTarget::get_clear_color`]).
+    InheritFromOutput,
+}
+
 pub trait CameraComponent: SceneComponent {
-}
\ No newline at end of file
+    /// Sets which attachments are cleared before this camera renders.
+    fn set_clear_mode(&self, update: &dyn SceneUpdate, mode: CameraClearMode);
+
+    /// Sets the order in which cameras rendering to the same output are processed. Cameras with a
+    /// lower render order render first. Defaults to 0.
+    fn set_render_order(&self, update: &dyn SceneUpdate, render_order: i32);
+}
+
+/// Fills the background of a [`Scene`] with a solid color or a vertical gradient. Newly created
+/// backgrounds are [`Color::BLACK`].
+///
+/// For simple 2D applications this is an alternative to a clear color which can be changed as
+/// part of a scene update.
+///
+/// Every camera of the scene which clears color (see [`CameraClearMode`]) draws the background
+/// instead of clearing.
+pub trait BackgroundColorComponent: SceneComponent {
+    /// Fills the whole background with a single color.
+    fn set_color(&self, update: &dyn SceneUpdate, color: Color);
+
+    /// Fills the background with a vertical gradient linearly interpolated from `top` at the top
+    /// edge of the output to `bottom` at the bottom edge.
+    fn set_gradient(&self, update: &dyn SceneUpdate, top: Color, bottom: Color);
+}
+
+/// A serializable snapshot of the components of a [`Scene`]. Created by [`Scene::serialize`] and
+/// loaded by [`crate::Agnaji::create_scene_from_data`].
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct SceneData {
+    /// The version of the format. Must be [`SceneData::VERSION`].
+    pub version: u32,
+    pub components: Vec<SerializedComponent>,
+}
+
+impl SceneData {
+    pub const VERSION: u32 = 1;
+
+    pub fn new(components: Vec<SerializedComponent>) -> Self {
+        Self {
+            version: Self::VERSION,
+            components,
+        }
+    }
+
+    /// Creates all components of this snapshot using `update`. The data is fully validated before
+    /// any component is created so on error the scene is left unchanged.
+    ///
+    /// Returns the created components in the order of [`SceneData::components`]. Like any other
+    /// component they are destroyed once dropped.
+    pub fn load_into(&self, update: &dyn SceneUpdate) -> Result<Vec<Arc<dyn SceneComponent>>, SceneError> {
+        if self.version != Self::VERSION {
+            return Err(SceneError::UnsupportedVersion(self.version));
+        }
+
+        let mut ids = HashSet::new();
+        for component in &self.components {
+            if !ids.insert(component.id) {
+                return Err(SceneError::DuplicateComponentId(component.id));
+            }
+        }
+
+        let mut decoded = Vec::with_capacity(self.components.len());
+        for component in &self.components {
+            if let Some(parent) = component.parent.filter(|parent| !ids.contains(parent)) {
+                return Err(SceneError::UnknownParent { id: component.id, parent });
+            }
+            decoded.push(match component.component_type {
+                ComponentType::Camera => DecodedProperties::Camera(component.get_properties()?),
+                ComponentType::BackgroundColor => DecodedProperties::Background(component.get_properties()?),
+            });
+        }
+
+        // The scene graph only consists of the root so far so parents are only validated
+        let mut components: Vec<Arc<dyn SceneComponent>> = Vec::with_capacity(decoded.len());
+        for properties in decoded {
+            match properties {
+                DecodedProperties::Camera(properties) => {
+                    let camera = update.create_camera_component();
+                    camera.set_clear_mode(update, properties.clear_mode);
+                    camera.set_render_order(update, properties.render_order);
+                    components.push(camera);
+                }
+                DecodedProperties::Background(properties) => {
+                    let background = update.create_background_color()?;
+                    match properties {
+                        BackgroundProperties::Color(color) => background.set_color(update, color),
+                        BackgroundProperties::Gradient { top, bottom } => background.set_gradient(update, top, bottom),
+                    }
+                    components.push(background);
+                }
+            }
+        }
+
+        Ok(components)
+    }
+}
+
+/// A single component of a [`SceneData`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct SerializedComponent {
+    pub component_type: ComponentType,
+
+    /// The id of the component when it was serialized. Loaded components receive new ids so this
+    /// is only used to reference components inside the [`SceneData`].
+    pub id: u64,
+
+    /// The id of the parent component or [`None`] if the parent is the scene root.
+    pub parent: Option<u64>,
+
+    /// The JSON encoded properties. See [`CameraProperties`] and [`BackgroundProperties`].
+    pub properties: String,
+}
+
+impl SerializedComponent {
+    pub fn new<P: Serialize>(component_type: ComponentType, id: u64, parent: Option<u64>, properties: &P) -> Result<Self, SceneError> {
+        let properties = serde_json::to_string(properties).map_err(|err| SceneError::InvalidProperties {
+            id,
+            message: err.to_string(),
+        })?;
+
+        Ok(Self {
+            component_type,
+            id,
+            parent,
+            properties,
+        })
+    }
+
+    /// Decodes the properties of the component.
+    pub fn get_properties<P: DeserializeOwned>(&self) -> Result<P, SceneError> {
+        serde_json::from_str(&self.properties).map_err(|err| SceneError::InvalidProperties {
+            id: self.id,
+            message: err.to_string(),
+        })
+    }
+}
+
+/// The serialized properties of a [`CameraComponent`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct CameraProperties {
+    pub clear_mode: CameraClearMode,
+    pub render_order: i32,
+}
+
+/// The serialized properties of a [`BackgroundColorComponent`].
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BackgroundProperties {
+    Color(Color),
+    Gradient {
+        top: Color,
+        bottom: Color,
+    },
+}
+
+enum DecodedProperties {
+    Camera(CameraProperties),
+    Background(BackgroundProperties),
+}
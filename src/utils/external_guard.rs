@@ -41,6 +41,19 @@ impl<I: Eq + Clone, G: ExternalGuard<I>, T> ExternallyGuarded<I, G, T> {
         unsafe { self.payload.get().as_mut().unwrap_unchecked() }
     }
 
+    /// Runs `f` with mutable access to the payload and returns its result. Unlike
+    /// [`ExternallyGuarded::get_mut`] the reference cannot escape the closure so the borrow of
+    /// `guard` ends as soon as `f` returns.
+    pub fn with_guard<R, F: FnOnce(&mut T) -> R>(&self, guard: &mut MutexGuard<G>, f: F) -> R {
+        f(self.get_mut(guard))
+    }
+
+    /// Runs `f` with shared access to the payload and returns its result. See
+    /// [`ExternallyGuarded::with_guard`].
+    pub fn with_guard_ref<R, F: FnOnce(&T) -> R>(&self, guard: &MutexGuard<G>, f: F) -> R {
+        f(self.get(guard))
+    }
+
     pub fn borrow_mut(&mut self) -> &mut T {
         unsafe { self.payload.get().as_mut().unwrap_unchecked() }
     }
@@ -49,4 +62,46 @@ impl<I: Eq + Clone, G: ExternalGuard<I>, T> ExternallyGuarded<I, G, T> {
 unsafe impl<I: Eq + Clone, G: ExternalGuard<I>, T> Send for ExternallyGuarded<I, G, T> where I: Send, T: Send {
 }
 unsafe impl<I: Eq + Clone, G: ExternalGuard<I>, T> Sync for ExternallyGuarded<I, G, T> where I: Send, T: Send {
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    struct TestGuard(u32);
+
+    impl ExternalGuard<u32> for TestGuard {
+        fn get_guard_id(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn with_guard() {
+        let mutex = Mutex::new(TestGuard(1));
+        let guarded = unsafe { ExternallyGuarded::new(&*mutex.lock().unwrap(), vec![1, 2]) };
+
+        let mut guard = mutex.lock().unwrap();
+        let len = guarded.with_guard(&mut guard, |value| {
+            value.push(3);
+            value.len()
+        });
+        assert_eq!(len, 3);
+        assert_eq!(guarded.with_guard_ref(&guard, |value| value.iter().sum::<i32>()), 6);
+
+        // The guard can be used again right away
+        guard.0 = 1;
+    }
+
+    #[test]
+    #[should_panic(expected = "guard_id check failed")]
+    fn with_guard_wrong_guard() {
+        let mutex = Mutex::new(TestGuard(1));
+        let other = Mutex::new(TestGuard(2));
+        let guarded = unsafe { ExternallyGuarded::new(&*mutex.lock().unwrap(), 0u32) };
+
+        guarded.with_guard(&mut other.lock().unwrap(), |value| *value += 1);
+    }
+}
@@ -1,4 +1,5 @@
 mod external_guard;
+pub mod tlsf;
 
 pub use external_guard::ExternalGuard;
 pub use external_guard::ExternallyGuarded;
@@ -10,6 +11,8 @@ macro_rules! define_counting_id_type {
             value: ::std::num::NonZeroU64,
         }
 
+        // Every call creates a new unique id which would be surprising for a Default impl
+        #[allow(clippy::new_without_default)]
         impl $name {
             $v fn new() -> Self {
                 use std::sync::atomic::{AtomicU64, Ordering};
@@ -43,4 +46,17 @@ macro_rules! define_counting_id_type {
     };
 }
 
-pub(crate) use define_counting_id_type;
\ No newline at end of file
+pub(crate) use define_counting_id_type;
+
+/// Enters a trace level [`tracing`] span which is exited at the end of the enclosing scope. The
+/// arguments are passed to `tracing::trace_span!`.
+///
+/// Expands to nothing if the `tracing` feature is disabled.
+macro_rules! trace_span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::trace_span!($name $(, $($fields)*)?).entered();
+    };
+}
+
+pub(crate) use trace_span;
@@ -1,26 +1,163 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::num::NonZeroUsize;
 use std::ptr::{NonNull, null_mut};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
 
 pub struct Allocation<T> {
     header: NonNull<BlockHeader<T>>,
+    /// The value of [`TLSF::get_generation`] when the allocation was created. Used to detect
+    /// allocations used after [`TLSF::reset`].
+    #[cfg(debug_assertions)]
+    generation: u64,
 }
 
 impl<T> Allocation<T> {
+    /// # Safety
+    /// The allocation must not have been freed and its allocator must still be alive.
     pub unsafe fn get_offset(&self) -> usize {
         self.header.as_ref().base_offset
     }
 
+    /// # Safety
+    /// The allocation must not have been freed and its allocator must still be alive.
     pub unsafe fn get_pool(&self) -> &T {
         self.header.as_ref().pool.as_ref().unwrap()
     }
+
+    /// Returns the size of the block backing this allocation. May be larger than the requested
+    /// size.
+    ///
+    /// # Safety
+    /// The allocation must not have been freed and its allocator must still be alive.
+    pub unsafe fn get_block_size(&self) -> usize {
+        self.header.as_ref().get_size()
+    }
+}
+
+/// A block that has been moved by [`TLSF::defragment`]. The caller is responsible for copying
+/// `size` bytes from `src_offset` to `dst_offset` inside the page `pool`.
+///
+/// The destination is always lower than the source but the two ranges may overlap. Moves must be
+/// executed in the order they were returned.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlockMove<T> {
+    pub pool: *const T,
+    pub src_offset: usize,
+    pub dst_offset: usize,
+    pub size: usize,
+}
+
+/// A used block returned by [`TLSF::iter_allocated_blocks`].
+#[derive(Copy, Clone, Debug)]
+pub struct AllocatedBlock<'a, T> {
+    pub pool: &'a T,
+    pub offset: usize,
+    /// The size of the block. May be larger than the requested size.
+    pub size: usize,
+    pub debug_name: Option<&'a str>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AllocateAtError {
+    /// The offset is not a multiple of [`TLSF::MIN_BLOCK_SIZE`].
+    UnalignedOffset,
+
+    /// The pool is not a page of the allocator.
+    UnknownPool,
+
+    /// The range extends past the end of the page.
+    OutOfRange,
+
+    /// Parts of the range are already allocated.
+    Occupied,
+}
+
+/// The reason a page size was rejected by [`TLSF::new_page`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PageSizeError {
+    /// The size is smaller than [`TLSF::MIN_BLOCK_SIZE`].
+    TooSmall,
+
+    /// The size is larger than the max block size of the allocator.
+    TooLarge,
+
+    /// The size is not a multiple of [`TLSF::MIN_BLOCK_SIZE`]. Block sizes share their lower
+    /// bits with flags so they cannot be stored.
+    Unaligned,
+}
+
+/// An inconsistency found by [`TLSF::validate`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ValidationError {
+    /// The bit of a first level in the first level mask does not match whether the second level
+    /// mask is empty.
+    FirstLevelMaskMismatch { first_level: u32 },
+
+    /// The bit of a list in the second level mask does not match whether the list is empty.
+    SecondLevelMaskMismatch { first_level: u32, second_level: u32 },
+
+    /// The `prev_free` pointer or the first free block flag of a block does not match its
+    /// position in a free list.
+    FreeListLinkBroken { first_level: u32, second_level: u32 },
+
+    /// The `prev_free` pointer or the first free block flag of a header does not match its
+    /// position in the header free list.
+    HeaderFreeListLinkBroken,
+
+    /// A block is stored in a free list that does not match its size.
+    FreeBlockInWrongList { first_level: u32, second_level: u32, size: usize },
+
+    /// The free block flag of a block does not match whether it is part of a free list.
+    FreeFlagMismatch { offset: usize },
+
+    /// A header appears in more than one list or more than once in the same list.
+    DuplicateHeader,
+
+    /// A pointer does not point to a header owned by the allocator.
+    InvalidPointer,
+
+    /// The `prev_physical` pointer or the pool of a block does not match its physical list.
+    PhysicalListBroken { offset: usize },
+
+    /// A block does not start where the previous block of its physical list ended.
+    OffsetDiscontinuity { expected: usize, found: usize },
+
+    /// A block size is 0 or not a multiple of [`TLSF::MIN_BLOCK_SIZE`].
+    InvalidBlockSize { offset: usize, size: usize },
+
+    /// The blocks of a page do not add up to the size of the page.
+    PageSizeMismatch { expected: usize, found: usize },
+
+    /// A page does not have exactly one physical list, a physical list references an unknown
+    /// page or a header is neither free nor part of a physical list.
+    InvalidPageList,
+
+    /// An allocation tracked while debug validation is enabled is not a used block anymore.
+    DeadAllocation,
 }
 
 pub struct TLSF<T> {
     free_first_level_mask: usize,
     segregated_lists: Box<[Box<SecondLevel<T>>]>,
-    header_free_list: *mut BlockHeader<T>,
+    /// Boxed so that the first header of the list (which points back to the head pointer) stays
+    /// valid when the allocator is moved.
+    header_free_list: Box<*mut BlockHeader<T>>,
     header_pool: Vec<Box<[BlockHeader<T>]>>,
-    page_pool: Vec<Box<T>>,
+    page_pool: Vec<Page<T>>,
+    /// Pages whose blocks must not be moved by [`TLSF::defragment`].
+    pinned_pages: HashSet<*const T>,
+    /// If set [`TLSF::validate`] is called after every mutation.
+    debug_validation: bool,
+    /// Headers of all outstanding allocations. Only tracked while debug validation is enabled.
+    tracked_allocations: HashSet<*const BlockHeader<T>>,
+    /// Names set using [`TLSF::set_debug_name`].
+    debug_names: HashMap<*const BlockHeader<T>, String>,
+    /// log2 of the number of second level lists per first level.
+    second_level_log2: u32,
+    /// The largest page size accepted by [`TLSF::new_page`].
+    max_block_size: usize,
+    /// Incremented by every call to [`TLSF::reset`].
+    generation: u64,
 }
 
 impl<T> TLSF<T> {
@@ -28,27 +165,72 @@ impl<T> TLSF<T> {
     const MISSING_MIN_BLOCKS: u32 = 5;
     const MIN_BLOCK_MASK: usize = (1usize << Self::MISSING_MIN_BLOCKS) - 1;
 
-    /// The minimum block size
+    /// The minimum block size. All block sizes and offsets are multiples of this which keeps the
+    /// low bits of the block size free for the flags stored alongside it.
     pub const MIN_BLOCK_SIZE: usize = 1 << Self::MISSING_MIN_BLOCKS;
 
-    const SECOND_LEVEL_INDEX: u32 = 5;
+    /// The log2 of the number of second level lists used by [`TLSF::new_for_max_size`].
+    pub const DEFAULT_SECOND_LEVEL_LOG2: u32 = 5;
 
     pub fn new_for_max_size(max_block_size: usize) -> Self {
-        let first_level_index = usize::BITS - max_block_size.trailing_zeros();
-        let segregated_lists: Box<_> = std::iter::repeat_with(|| Box::new(SecondLevel::new()))
-            .take((first_level_index - Self::MISSING_MIN_BLOCKS) as usize)
+        Self::new_with_config(max_block_size, Self::DEFAULT_SECOND_LEVEL_LOG2)
+    }
+
+    /// Creates an allocator which splits every first level into `1 << second_level_log2` second
+    /// level lists. More lists reduce fragmentation at the cost of memory and slower searches
+    /// when many lists are empty.
+    ///
+    /// `max_block_size` does not need to be a power of 2 but only pages of up to
+    /// `max_block_size` bytes can be added.
+    ///
+    /// # Panics
+    /// If `max_block_size` is smaller than [`Self::MIN_BLOCK_SIZE`] or `1 << second_level_log2`
+    /// is larger than the number of bits of a `usize`.
+    pub fn new_with_config(max_block_size: usize, second_level_log2: u32) -> Self {
+        assert!(second_level_log2 < usize::BITS && (1u32 << second_level_log2) <= usize::BITS, "Invalid second level log2 {}", second_level_log2);
+        assert!(max_block_size >= Self::MIN_BLOCK_SIZE, "Max block size {} is smaller than the min block size", max_block_size);
+
+        // Enough first levels to contain the list of max_block_size
+        let last_bit = usize::BITS - 1 - max_block_size.leading_zeros();
+        let linear_bits = Self::MISSING_MIN_BLOCKS + second_level_log2;
+        let first_level_count = last_bit.saturating_sub(linear_bits - 1) + 1;
+
+        let segregated_lists: Box<_> = std::iter::repeat_with(|| Box::new(SecondLevel::new(1 << second_level_log2)))
+            .take(first_level_count as usize)
             .collect();
 
         Self {
             free_first_level_mask: 0,
             segregated_lists,
-            header_free_list: null_mut(),
+            header_free_list: Box::new(null_mut()),
             header_pool: Vec::with_capacity(4),
             page_pool: Vec::with_capacity(4),
+            pinned_pages: HashSet::new(),
+            debug_validation: false,
+            tracked_allocations: HashSet::new(),
+            debug_names: HashMap::new(),
+            second_level_log2,
+            max_block_size,
+            generation: 0,
         }
     }
 
+    /// If enabled the allocator is validated after every mutation and panics if it is found to be
+    /// inconsistent. This is very slow and only intended for debugging.
+    ///
+    /// While enabled all new allocations are tracked and verified by [`TLSF::validate`]. Freeing
+    /// an allocation which is not valid anymore (for example a double free) panics.
+    pub fn set_debug_validation(&mut self, enabled: bool) {
+        self.debug_validation = enabled;
+        if !enabled {
+            self.tracked_allocations.clear();
+        }
+    }
+
+    /// # Safety
+    /// The allocator must be in a valid state.
     pub unsafe fn allocate(&mut self, size: NonZeroUsize) -> Option<Allocation<T>> {
+        let rounded_size = Self::round_size(size)?;
         let (first_level, second_level) = self.find_free_block_index(size)?;
 
         let mut header = self.take_block(
@@ -56,32 +238,211 @@ impl<T> TLSF<T> {
             second_level as usize
         ).unwrap();
 
-        let header_ref = header.as_mut();
-        header_ref.clear_free_block_flag();
+        header.as_mut().clear_free_block_flag();
 
-        let rounded_size = (size.get() + Self::MIN_BLOCK_MASK) & !Self::MIN_BLOCK_MASK;
-        let split_size = header_ref.get_size() - rounded_size;
-        if split_size > 0 {
-            let mut split_block = self.allocate_block_header();
-            let split_block_ref = split_block.as_mut();
-            split_block_ref.set_free_block_flag();
+        self.split_tail(header, rounded_size);
+        self.track_allocation(header);
 
-            header_ref.set_size(rounded_size);
-            split_block_ref.set_size(split_size);
-            split_block_ref.base_offset = header_ref.base_offset + rounded_size;
-            split_block_ref.pool = header_ref.pool;
+        Some(self.new_allocation(header))
+    }
 
-            // This also modifies header!!!
-            split_block_ref.insert_to_physical_list_after(header);
-            self.return_block_no_merge(split_block);
+    /// Allocates a block whose offset is a multiple of `alignment`.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state.
+    ///
+    /// # Panics
+    /// If `alignment` is not a power of 2.
+    pub unsafe fn allocate_aligned(&mut self, size: NonZeroUsize, alignment: NonZeroUsize) -> Option<Allocation<T>> {
+        assert!(alignment.is_power_of_two(), "Alignment must be a power of 2 but is {}", alignment);
+
+        // All block offsets are multiples of the min block size
+        if alignment.get() <= Self::MIN_BLOCK_SIZE {
+            return self.allocate(size);
         }
 
-        Some(Allocation {
-            header
-        })
+        // In the worst case the block starts just after an aligned offset
+        let rounded_size = Self::round_size(size)?;
+        let search_size = NonZeroUsize::new(rounded_size.checked_add(alignment.get() - Self::MIN_BLOCK_SIZE)?).unwrap();
+        let (first_level, second_level) = self.find_free_block_index(search_size)?;
+
+        let mut header = self.take_block(
+            first_level as usize,
+            second_level as usize
+        ).unwrap();
+
+        let base_offset = header.as_ref().base_offset;
+        let aligned_offset = (base_offset + alignment.get() - 1) & !(alignment.get() - 1);
+        header = self.split_head(header, aligned_offset - base_offset);
+
+        header.as_mut().clear_free_block_flag();
+        self.split_tail(header, rounded_size);
+        self.track_allocation(header);
+
+        Some(self.new_allocation(header))
+    }
+
+    /// Allocates the range starting at `offset` inside the page `pool`. The size is rounded up to
+    /// a multiple of [`Self::MIN_BLOCK_SIZE`] and the whole range must be free.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state.
+    pub unsafe fn allocate_at(&mut self, offset: usize, size: NonZeroUsize, pool: &T) -> Result<Allocation<T>, AllocateAtError> {
+        if offset & Self::MIN_BLOCK_MASK != 0 {
+            return Err(AllocateAtError::UnalignedOffset);
+        }
+
+        let pool = pool as *const T;
+        let page = self.page_pool.iter().find(|page| page.as_ptr() == pool).ok_or(AllocateAtError::UnknownPool)?;
+        let rounded_size = Self::round_size(size).ok_or(AllocateAtError::OutOfRange)?;
+        let end = offset.checked_add(rounded_size)
+            .filter(|end| *end <= page.size)
+            .ok_or(AllocateAtError::OutOfRange)?;
+
+        // Every page has exactly one physical list
+        let mut block = self.collect_physical_list_starts().into_iter()
+            .find(|start| start.as_ref().pool == pool)
+            .unwrap();
+        while block.as_ref().base_offset + block.as_ref().get_size() <= offset {
+            // The range lies inside the page so the containing block must exist
+            block = NonNull::new(block.as_ref().next_physical).unwrap();
+        }
+
+        let block_ref = block.as_ref();
+        // Adjacent free blocks are always merged so the range must lie inside a single free block
+        if !block_ref.is_free_block() || end > block_ref.base_offset + block_ref.get_size() {
+            return Err(AllocateAtError::Occupied);
+        }
+        let head_size = offset - block_ref.base_offset;
+
+        self.remove_free_block(block);
+        let mut header = self.split_head(block, head_size);
+
+        header.as_mut().clear_free_block_flag();
+        self.split_tail(header, rounded_size);
+        self.track_allocation(header);
+
+        Ok(self.new_allocation(header))
     }
 
+    /// Like [`TLSF::allocate_aligned`] but never returns a block inside one of the `excluded`
+    /// pages.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state.
+    ///
+    /// # Panics
+    /// If `alignment` is not a power of 2.
+    pub unsafe fn allocate_aligned_excluding(&mut self, size: NonZeroUsize, alignment: NonZeroUsize, excluded: &[*const T]) -> Option<Allocation<T>> {
+        if excluded.is_empty() {
+            return self.allocate_aligned(size, alignment);
+        }
+
+        // Temporarily hide the free blocks of the excluded pages. Allocating never merges the
+        // taken block with its neighbours so the hidden blocks are not touched.
+        let mut hidden = Vec::new();
+        for start in self.collect_physical_list_starts() {
+            if !excluded.contains(&start.as_ref().pool) {
+                continue;
+            }
+            let mut block = Some(start);
+            while let Some(current) = block {
+                if current.as_ref().is_free_block() {
+                    hidden.push(current);
+                }
+                block = NonNull::new(current.as_ref().next_physical);
+            }
+        }
+        for block in &hidden {
+            self.remove_free_block(*block);
+        }
+
+        // The allocator is inconsistent until the hidden blocks are returned
+        let debug_validation = std::mem::replace(&mut self.debug_validation, false);
+        let allocation = self.allocate_aligned(size, alignment);
+        self.debug_validation = debug_validation;
+
+        for block in hidden {
+            self.return_block_no_merge(block);
+        }
+        match &allocation {
+            Some(allocation) => self.track_allocation(allocation.header),
+            None => self.validate_if_enabled(),
+        }
+
+        allocation
+    }
+
+    /// Grows the allocation to `new_size` without changing its offset. Succeeds if the block
+    /// already is large enough or if the physically next block is free and large enough to cover
+    /// the difference. Returns false if the allocation could not be grown, in which case it is
+    /// left unchanged.
+    ///
+    /// # Safety
+    /// The allocation must have been created by this allocator and must not have been freed or
+    /// invalidated by [`TLSF::reset`] already.
+    pub unsafe fn try_grow(&mut self, allocation: &Allocation<T>, new_size: NonZeroUsize) -> bool {
+        self.check_generation(allocation);
+        let header = allocation.header;
+        let Some(rounded_size) = Self::round_size(new_size) else {
+            return false;
+        };
+        let size = header.as_ref().get_size();
+        if rounded_size <= size {
+            return true;
+        }
+
+        match header.as_ref().next_physical.as_ref() {
+            Some(next) if next.is_free_block() && size + next.get_size() >= rounded_size => {}
+            _ => return false,
+        }
+
+        self.absorb_next_free_block(header);
+        self.split_tail(header, rounded_size);
+        self.validate_if_enabled();
+
+        true
+    }
+
+    /// Shrinks the allocation to `new_size` without changing its offset and returns the tail to
+    /// the free lists.
+    ///
+    /// # Safety
+    /// The allocation must have been created by this allocator and must not have been freed or
+    /// invalidated by [`TLSF::reset`] already.
+    ///
+    /// # Panics
+    /// If `new_size` is larger than the block of the allocation.
+    pub unsafe fn shrink(&mut self, allocation: &Allocation<T>, new_size: NonZeroUsize) {
+        self.check_generation(allocation);
+        let header = allocation.header;
+        let size = header.as_ref().get_size();
+        let rounded_size = Self::round_size(new_size)
+            .filter(|rounded_size| *rounded_size <= size)
+            .unwrap_or_else(|| panic!("Cannot shrink block of size {} to {}", size, new_size));
+        if rounded_size == size {
+            return;
+        }
+
+        // Merge first so the tail ends up as a single free block
+        self.absorb_next_free_block(header);
+        self.split_tail(header, rounded_size);
+        self.validate_if_enabled();
+    }
+
+    /// # Safety
+    /// The allocation must have been created by this allocator and must not have been freed or
+    /// invalidated by [`TLSF::reset`] already.
     pub unsafe fn free(&mut self, allocation: Allocation<T>) {
+        self.check_generation(&allocation);
+        if self.debug_validation {
+            assert!(self.verify_allocation(&allocation), "Freeing an allocation which is not valid anymore");
+            self.tracked_allocations.remove(&(allocation.header.as_ptr() as *const BlockHeader<T>));
+        }
+        if !self.debug_names.is_empty() {
+            self.debug_names.remove(&(allocation.header.as_ptr() as *const BlockHeader<T>));
+        }
+
         let mut header = allocation.header;
 
         let header_ref = header.as_ref();
@@ -90,7 +451,7 @@ impl<T> TLSF<T> {
 
         if let Some(prev) = header_ref.prev_physical.as_mut() {
             if prev.is_free_block() {
-                prev.remove_from_free_list();
+                self.remove_free_block(NonNull::from(&mut *prev));
                 prev.remove_from_physical_list();
 
                 size += prev.get_size();
@@ -103,7 +464,7 @@ impl<T> TLSF<T> {
         // Need to reborrow because potential write
         if let Some(next) = header.as_ref().next_physical.as_mut() {
             if next.is_free_block() {
-                next.remove_from_free_list();
+                self.remove_free_block(NonNull::from(&mut *next));
                 next.remove_from_physical_list();
 
                 size += next.get_size();
@@ -114,29 +475,600 @@ impl<T> TLSF<T> {
 
         // Need to reborrow because potential write
         let header_ref = header.as_mut();
+        header_ref.set_free_block_flag();
         header_ref.set_size(size);
         header_ref.base_offset = base_offset;
 
-        self.return_block_no_merge(header)
+        self.return_block_no_merge(header);
+        self.validate_if_enabled();
     }
 
-    pub unsafe fn new_page(&mut self, page: Box<T>, size: usize) {
-        // TODO validate size range
+    /// Adds a new page of `size` bytes. The size must be a multiple of [`Self::MIN_BLOCK_SIZE`]
+    /// and must not exceed the max block size of this allocator. Sizes are never rounded, if the
+    /// size is invalid an error is returned and the page is dropped.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state.
+    pub unsafe fn new_page(&mut self, page: Box<T>, size: usize) -> Result<(), PageSizeError> {
+        self.check_page_size(size)?;
 
         let ptr = page.as_ref() as *const T;
 
-        self.page_pool.push(page);
-        let mut header = self.allocate_block_header();
+        self.page_pool.push(Page {
+            page,
+            size,
+        });
+        self.insert_page_block(ptr, size);
+        self.validate_if_enabled();
+
+        Ok(())
+    }
+
+    /// Frees all allocations at once. Every block header is returned to the header free list and
+    /// each page is rebuilt as a single free block. Neither the pages nor the header pools are
+    /// released so this is much cheaper than freeing every allocation individually.
+    ///
+    /// All outstanding [`Allocation`]s are invalidated. In debug builds passing one of them to the
+    /// allocator afterwards panics.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state. None of the outstanding allocations may be used
+    /// after the reset.
+    pub unsafe fn reset(&mut self) {
+        self.free_first_level_mask = 0;
+        for second_level in self.segregated_lists.iter_mut() {
+            second_level.free_mask = 0;
+            second_level.list_headers.fill(null_mut());
+        }
+
+        *self.header_free_list = null_mut();
+        let headers: Vec<_> = self.header_pool.iter_mut()
+            .flat_map(|pool| pool.iter_mut().map(NonNull::from))
+            .collect();
+        for header in headers {
+            self.free_block_header(header);
+        }
+
+        let pages: Vec<_> = self.page_pool.iter().map(|page| (page.as_ptr(), page.size)).collect();
+        for (ptr, size) in pages {
+            self.insert_page_block(ptr, size);
+        }
+
+        self.tracked_allocations.clear();
+        self.debug_names.clear();
+        self.generation = self.generation.wrapping_add(1);
+        self.validate_if_enabled();
+    }
+
+    /// Returns the number of times [`TLSF::reset`] has been called.
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns true if `size` can be passed to [`TLSF::new_page`].
+    pub fn is_valid_page_size(&self, size: usize) -> bool {
+        self.check_page_size(size).is_ok()
+    }
+
+    fn check_page_size(&self, size: usize) -> Result<(), PageSizeError> {
+        if size < Self::MIN_BLOCK_SIZE {
+            Err(PageSizeError::TooSmall)
+        } else if size > self.max_block_size {
+            Err(PageSizeError::TooLarge)
+        } else if size & Self::MIN_BLOCK_MASK != 0 {
+            Err(PageSizeError::Unaligned)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn get_max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    /// Prevents all blocks in the page from being moved by [`TLSF::defragment`]. Pinning a page
+    /// multiple times has no additional effect.
+    pub fn pin_page(&mut self, page_ptr: *const T) {
+        self.pinned_pages.insert(page_ptr);
+    }
+
+    /// Allows blocks in a page previously pinned with [`TLSF::pin_page`] to be moved again.
+    pub fn unpin_page(&mut self, page_ptr: *const T) {
+        self.pinned_pages.remove(&page_ptr);
+    }
+
+    pub fn is_page_pinned(&self, page_ptr: *const T) -> bool {
+        self.pinned_pages.contains(&page_ptr)
+    }
+
+    /// Returns all pages which are not pinned.
+    pub fn iter_unpinned_pages(&self) -> impl Iterator<Item=*const T> + '_ {
+        self.page_pool.iter()
+            .map(Page::as_ptr)
+            .filter(|page| !self.pinned_pages.contains(page))
+    }
+
+    /// Removes all unpinned pages which contain no allocations and for which `f` returns true.
+    /// The removed pages are returned so the caller can destroy them.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state.
+    pub unsafe fn try_release_empty_pages(&mut self, mut f: impl FnMut(&T) -> bool) -> Vec<Box<T>> {
+        let mut empty_blocks = Vec::new();
+        for second_level in self.segregated_lists.iter() {
+            for head in second_level.list_headers.iter() {
+                let mut block = *head;
+                while let Some(block_ref) = block.as_ref() {
+                    // A empty page has collapsed into a single block spanning the whole page
+                    if block_ref.prev_physical.is_null() && block_ref.next_physical.is_null() && !self.pinned_pages.contains(&block_ref.pool) {
+                        empty_blocks.push(NonNull::from(block_ref));
+                    }
+                    block = block_ref.next_free;
+                }
+            }
+        }
+
+        let mut released = Vec::new();
+        for block in empty_blocks {
+            let pool = block.as_ref().pool;
+            let index = match self.page_pool.iter().position(|page| page.as_ptr() == pool) {
+                Some(index) => index,
+                None => continue,
+            };
+            let page = &self.page_pool[index];
+            debug_assert_eq!(block.as_ref().get_size(), page.size);
+
+            if !f(page.page.as_ref()) {
+                continue;
+            }
+
+            self.remove_free_block(block);
+            self.free_block_header(block);
+            released.push(self.page_pool.swap_remove(index).page);
+        }
+        self.validate_if_enabled();
+
+        released
+    }
+
+    /// Compacts all unpinned pages by moving used blocks towards the start of their page so that
+    /// the free space of each page is merged into a single block at its end. Blocks are never
+    /// moved across pages.
+    ///
+    /// Existing [`Allocation`]s stay valid but their offset may change. The returned moves describe
+    /// the copies the caller has to perform on the page memory.
+    ///
+    /// # Safety
+    /// All outstanding allocations must have been created by this allocator and no allocation may
+    /// be accessed by anyone until the returned moves have been performed.
+    pub unsafe fn defragment(&mut self) -> Vec<BlockMove<T>> {
+        let mut moves = Vec::new();
+
+        for first in self.collect_defragment_candidates() {
+            let mut current = first;
+            while let Some(mut next) = NonNull::new(current.as_ref().next_physical) {
+                if !current.as_ref().is_free_block() {
+                    current = next;
+                    continue;
+                }
+
+                if next.as_ref().is_free_block() {
+                    self.merge_free_blocks(current, next);
+                    continue;
+                }
+
+                // Swap the free block with the used block after it. The size of the free block does
+                // not change so it can stay in its current free list.
+                let current_ref = current.as_mut();
+                let next_ref = next.as_mut();
+
+                moves.push(BlockMove {
+                    pool: next_ref.pool,
+                    src_offset: next_ref.base_offset,
+                    dst_offset: current_ref.base_offset,
+                    size: next_ref.get_size(),
+                });
+
+                next_ref.base_offset = current_ref.base_offset;
+                current_ref.base_offset += next_ref.get_size();
+
+                current_ref.remove_from_physical_list();
+                current_ref.insert_to_physical_list_after(next);
+            }
+        }
+        self.validate_if_enabled();
+
+        moves
+    }
+
+    /// Checks the internal consistency of the allocator.
+    ///
+    /// Walks every free list, the header free list and every physical list and verifies that
+    /// offsets are continuous, that free block flags match free list membership, that the level
+    /// masks agree with the list contents and that no header appears in two lists. Only pointers
+    /// to headers owned by this allocator are dereferenced so this is safe to call even if the
+    /// allocator has been corrupted.
+    ///
+    /// This walks the entire data structure and is intended for debugging only.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let headers: HashSet<*const BlockHeader<T>> = self.header_pool.iter()
+            .flat_map(|pool| pool.iter())
+            .map(|header| header as *const BlockHeader<T>)
+            .collect();
+        let get = |ptr: *const BlockHeader<T>| -> Result<&BlockHeader<T>, ValidationError> {
+            if headers.contains(&ptr) {
+                // All headers in the pool stay valid as long as the allocator is alive
+                Ok(unsafe { &*ptr })
+            } else {
+                Err(ValidationError::InvalidPointer)
+            }
+        };
+
+        // Headers in any free list. Used to detect headers appearing in multiple lists.
+        let mut listed = HashSet::new();
+        let mut free_blocks = HashSet::new();
+
+        if let Some(first_level) = Self::first_one_after_at(self.free_first_level_mask, self.segregated_lists.len() as u32) {
+            return Err(ValidationError::FirstLevelMaskMismatch { first_level });
+        }
+        for (first_level, second_level_info) in self.segregated_lists.iter().enumerate() {
+            let first_level = first_level as u32;
+            if (self.free_first_level_mask & (1 << first_level) != 0) != (second_level_info.free_mask != 0) {
+                return Err(ValidationError::FirstLevelMaskMismatch { first_level });
+            }
+
+            for (second_level, head) in second_level_info.list_headers.iter().enumerate() {
+                let second_level = second_level as u32;
+                if (second_level_info.free_mask & (1 << second_level) != 0) == head.is_null() {
+                    return Err(ValidationError::SecondLevelMaskMismatch { first_level, second_level });
+                }
+
+                let head_ptr = head as *const *mut BlockHeader<T> as *mut *mut BlockHeader<T>;
+                let mut prev_free = head_ptr;
+                let mut block = *head;
+                while !block.is_null() {
+                    let block_ref = get(block)?;
+                    if !listed.insert(block as *const BlockHeader<T>) {
+                        return Err(ValidationError::DuplicateHeader);
+                    }
+                    if block_ref.prev_free != prev_free || block_ref.is_first_free_block() != (prev_free == head_ptr) {
+                        return Err(ValidationError::FreeListLinkBroken { first_level, second_level });
+                    }
+                    if !block_ref.is_free_block() {
+                        return Err(ValidationError::FreeFlagMismatch { offset: block_ref.base_offset });
+                    }
+
+                    let size = block_ref.get_size();
+                    if NonZeroUsize::new(size).map(|size| self.map_block_size(size)) != Some((first_level, second_level)) {
+                        return Err(ValidationError::FreeBlockInWrongList { first_level, second_level, size });
+                    }
+
+                    free_blocks.insert(block as *const BlockHeader<T>);
+                    prev_free = block as *mut *mut BlockHeader<T>;
+                    block = block_ref.next_free;
+                }
+            }
+        }
+
+        let head_ptr = self.header_free_list.as_ref() as *const *mut BlockHeader<T> as *mut *mut BlockHeader<T>;
+        let mut prev_free = head_ptr;
+        let mut header = *self.header_free_list;
+        while !header.is_null() {
+            let header_ref = get(header)?;
+            if !listed.insert(header as *const BlockHeader<T>) {
+                return Err(ValidationError::DuplicateHeader);
+            }
+            if header_ref.prev_free != prev_free || header_ref.is_first_free_block() != (prev_free == head_ptr) {
+                return Err(ValidationError::HeaderFreeListLinkBroken);
+            }
+            prev_free = header as *mut *mut BlockHeader<T>;
+            header = header_ref.next_free;
+        }
+
+        // Every header which is not in the header free list must be part of a physical list
+        let live: Vec<_> = headers.iter().copied()
+            .filter(|header| free_blocks.contains(header) || !listed.contains(header))
+            .collect();
+        let mut visited = HashSet::new();
+        let mut used_blocks = HashSet::new();
+        let mut page_lists = HashMap::new();
+        for start in live.iter().copied() {
+            let start_ref = get(start)?;
+            if !start_ref.prev_physical.is_null() {
+                continue;
+            }
+
+            let pool = start_ref.pool;
+            let page = self.page_pool.iter().find(|page| page.as_ptr() == pool).ok_or(ValidationError::InvalidPageList)?;
+            if page_lists.insert(pool, start).is_some() {
+                return Err(ValidationError::InvalidPageList);
+            }
+
+            let mut expected_offset = 0;
+            let mut prev: *const BlockHeader<T> = std::ptr::null();
+            let mut block = start;
+            while !block.is_null() {
+                let block_ref = get(block)?;
+                if !visited.insert(block) {
+                    return Err(ValidationError::DuplicateHeader);
+                }
+
+                let offset = block_ref.base_offset;
+                if !std::ptr::eq(block_ref.prev_physical, prev) || block_ref.pool != pool {
+                    return Err(ValidationError::PhysicalListBroken { offset });
+                }
+                if offset != expected_offset {
+                    return Err(ValidationError::OffsetDiscontinuity { expected: expected_offset, found: offset });
+                }
+                let size = block_ref.get_size();
+                if size == 0 || size & Self::MIN_BLOCK_MASK != 0 {
+                    return Err(ValidationError::InvalidBlockSize { offset, size });
+                }
+                if block_ref.is_free_block() != free_blocks.contains(&block) {
+                    return Err(ValidationError::FreeFlagMismatch { offset });
+                }
+                if !block_ref.is_free_block() {
+                    used_blocks.insert(block);
+                }
+
+                expected_offset += size;
+                prev = block;
+                block = block_ref.next_physical;
+            }
+
+            if expected_offset != page.size {
+                return Err(ValidationError::PageSizeMismatch { expected: page.size, found: expected_offset });
+            }
+        }
 
+        if visited.len() != live.len() || page_lists.len() != self.page_pool.len() {
+            return Err(ValidationError::InvalidPageList);
+        }
+
+        // The physical lists have been fully checked above so this is equivalent to calling
+        // verify_allocation for every tracked allocation
+        if !self.tracked_allocations.is_subset(&used_blocks) {
+            return Err(ValidationError::DeadAllocation);
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the allocation has not been freed.
+    ///
+    /// Walks the physical list of the page referenced by the allocation and checks that the block
+    /// of the allocation is part of it, is not free and that its offset matches its position in
+    /// the list.
+    ///
+    /// # Safety
+    /// The allocation must have been created by this allocator. It may have been freed already.
+    pub unsafe fn verify_allocation(&self, allocation: &Allocation<T>) -> bool {
+        #[cfg(debug_assertions)]
+        if allocation.generation != self.generation {
+            return false;
+        }
+
+        let header = allocation.header.as_ptr() as *const BlockHeader<T>;
+        // Headers are never deallocated while the allocator is alive so this is always readable
+        let pool = allocation.header.as_ref().pool;
+        if !self.page_pool.iter().any(|page| page.as_ptr() == pool) {
+            return false;
+        }
+
+        let start = self.collect_physical_list_starts().into_iter()
+            .find(|start| start.as_ref().pool == pool);
+
+        let mut offset = 0;
+        let mut block = start.map_or(std::ptr::null(), |start| start.as_ptr() as *const BlockHeader<T>);
+        while let Some(block_ref) = block.as_ref() {
+            if block == header {
+                return !block_ref.is_free_block() && block_ref.base_offset == offset;
+            }
+            offset += block_ref.get_size();
+            block = block_ref.next_physical;
+        }
+
+        false
+    }
+
+    /// Sets the name of an allocation reported by [`TLSF::iter_allocated_blocks`]. The name is
+    /// removed when the allocation is freed.
+    pub fn set_debug_name(&mut self, allocation: &Allocation<T>, name: Option<String>) {
+        self.check_generation(allocation);
+        let header = allocation.header.as_ptr() as *const BlockHeader<T>;
+        match name {
+            Some(name) => self.debug_names.insert(header, name),
+            None => self.debug_names.remove(&header),
+        };
+    }
+
+    pub fn get_debug_name(&self, allocation: &Allocation<T>) -> Option<&str> {
+        self.debug_names.get(&(allocation.header.as_ptr() as *const BlockHeader<T>)).map(String::as_str)
+    }
+
+    /// Returns all used blocks by walking the physical list of every page. Useful to report leaked
+    /// allocations.
+    ///
+    /// # Safety
+    /// The allocator must be in a valid state.
+    pub unsafe fn iter_allocated_blocks(&self) -> impl Iterator<Item=AllocatedBlock<'_, T>> + '_ {
+        self.collect_physical_list_starts().into_iter().flat_map(move |start| {
+            let mut block = start.as_ptr() as *const BlockHeader<T>;
+            std::iter::from_fn(move || {
+                // Valid since the allocator is in a valid state
+                let block_ref = unsafe { block.as_ref() }?;
+                let current = block;
+                block = block_ref.next_physical;
+                Some((current, block_ref))
+            })
+        }).filter(|(_, block_ref)| !block_ref.is_free_block()).map(move |(header, block_ref)| {
+            AllocatedBlock {
+                pool: unsafe { &*block_ref.pool },
+                offset: block_ref.base_offset,
+                size: block_ref.get_size(),
+                debug_name: self.debug_names.get(&header).map(String::as_str),
+            }
+        })
+    }
+
+    /// Returns the first block of every physical list by searching all headers not in the header
+    /// free list.
+    unsafe fn collect_physical_list_starts(&self) -> Vec<NonNull<BlockHeader<T>>> {
+        let mut free_headers = HashSet::new();
+        let mut free_header = *self.header_free_list;
+        while let Some(free_header_ref) = free_header.as_ref() {
+            free_headers.insert(free_header as *const BlockHeader<T>);
+            free_header = free_header_ref.next_free;
+        }
+
+        self.header_pool.iter()
+            .flat_map(|headers| headers.iter())
+            .filter(|header| header.prev_physical.is_null() && !free_headers.contains(&(*header as *const BlockHeader<T>)))
+            .map(NonNull::from)
+            .collect()
+    }
+
+    /// Records a new allocation if debug validation is enabled and validates the allocator.
+    fn track_allocation(&mut self, header: NonNull<BlockHeader<T>>) {
+        if self.debug_validation {
+            self.tracked_allocations.insert(header.as_ptr() as *const BlockHeader<T>);
+        }
+        self.validate_if_enabled();
+    }
+
+    /// Calls [`TLSF::validate`] if debug validation is enabled.
+    ///
+    /// # Panics
+    /// If debug validation is enabled and the allocator is inconsistent.
+    fn validate_if_enabled(&self) {
+        if self.debug_validation {
+            if let Err(err) = self.validate() {
+                panic!("TLSF validation failed: {:?}", err);
+            }
+        }
+    }
+
+    /// Returns the first block of every unpinned page which contains at least one free block.
+    /// Pages without any free block are already as compact as possible.
+    unsafe fn collect_defragment_candidates(&self) -> Vec<NonNull<BlockHeader<T>>> {
+        let mut visited = HashSet::new();
+        let mut candidates = Vec::new();
+
+        for second_level in self.segregated_lists.iter() {
+            for head in second_level.list_headers.iter() {
+                let mut block = *head;
+                while let Some(block_ref) = block.as_ref() {
+                    if !self.pinned_pages.contains(&block_ref.pool) && visited.insert(block_ref.pool) {
+                        let mut first = NonNull::from(block_ref);
+                        while let Some(prev) = NonNull::new(first.as_ref().prev_physical) {
+                            first = prev;
+                        }
+                        candidates.push(first);
+                    }
+                    block = block_ref.next_free;
+                }
+            }
+        }
+
+        candidates
+    }
+
+    /// Merges the free block `next` into the physically preceding free block `block`.
+    unsafe fn merge_free_blocks(&mut self, mut block: NonNull<BlockHeader<T>>, mut next: NonNull<BlockHeader<T>>) {
+        self.remove_free_block(block);
+        self.remove_free_block(next);
+
+        let size = block.as_ref().get_size() + next.as_ref().get_size();
+        next.as_mut().remove_from_physical_list();
+        self.free_block_header(next);
+
+        block.as_mut().set_size(size);
+        self.return_block_no_merge(block);
+    }
+
+    /// Removes a free block from its free list and updates the level masks if the list becomes
+    /// empty.
+    unsafe fn remove_free_block(&mut self, mut block: NonNull<BlockHeader<T>>) {
+        let (first_level, second_level) = self.map_block_size(NonZeroUsize::new(block.as_ref().get_size()).unwrap());
+        block.as_mut().remove_from_free_list();
+
+        let second_level_info = self.segregated_lists.get_mut(first_level as usize).unwrap();
+        if second_level_info.list_headers.get(second_level as usize).unwrap().is_null() {
+            second_level_info.free_mask &= !(1 << second_level);
+
+            if second_level_info.free_mask == 0 {
+                self.free_first_level_mask &= !(1 << first_level);
+            }
+        }
+    }
+
+    /// If the physically next block of the used block `header` is free it is removed and its size
+    /// added to `header`.
+    unsafe fn absorb_next_free_block(&mut self, mut header: NonNull<BlockHeader<T>>) {
+        if let Some(mut next) = NonNull::new(header.as_ref().next_physical) {
+            if next.as_ref().is_free_block() {
+                let size = header.as_ref().get_size() + next.as_ref().get_size();
+                self.remove_free_block(next);
+                next.as_mut().remove_from_physical_list();
+                self.free_block_header(next);
+
+                header.as_mut().set_size(size);
+            }
+        }
+    }
+
+    /// Splits off the first `head_size` bytes of a block which has been taken from the free
+    /// lists. The taken header stays in place as the free head remainder and is returned to the
+    /// free lists. Returns the header of the remaining block or `header` if `head_size` is 0.
+    unsafe fn split_head(&mut self, mut header: NonNull<BlockHeader<T>>, head_size: usize) -> NonNull<BlockHeader<T>> {
+        if head_size == 0 {
+            return header;
+        }
+
+        let mut block = self.allocate_block_header();
+        let block_ref = block.as_mut();
         let header_ref = header.as_mut();
-        header_ref.make_new_physical_list();
-        header_ref.set_free_block_flag();
 
-        header_ref.set_size(size);
-        header_ref.base_offset = 0;
-        header_ref.pool = ptr;
+        block_ref.set_size(header_ref.get_size() - head_size);
+        block_ref.base_offset = header_ref.base_offset + head_size;
+        block_ref.pool = header_ref.pool;
+        header_ref.set_size(head_size);
 
+        // This also modifies header!!!
+        block_ref.insert_to_physical_list_after(header);
         self.return_block_no_merge(header);
+
+        block
+    }
+
+    /// Splits off everything after the first `size` bytes of a used block and returns it to the
+    /// free lists.
+    unsafe fn split_tail(&mut self, mut header: NonNull<BlockHeader<T>>, size: usize) {
+        let header_ref = header.as_mut();
+        let split_size = header_ref.get_size() - size;
+        if split_size > 0 {
+            let mut split_block = self.allocate_block_header();
+            let split_block_ref = split_block.as_mut();
+            split_block_ref.set_free_block_flag();
+
+            header_ref.set_size(size);
+            split_block_ref.set_size(split_size);
+            split_block_ref.base_offset = header_ref.base_offset + size;
+            split_block_ref.pool = header_ref.pool;
+
+            // This also modifies header!!!
+            split_block_ref.insert_to_physical_list_after(header);
+            self.return_block_no_merge(split_block);
+        }
+    }
+
+    /// Rounds a size up to the next multiple of [`Self::MIN_BLOCK_SIZE`]. Returns [`None`] if the
+    /// rounded size does not fit into a `usize`.
+    ///
+    /// Every block size must pass through here (or [`Self::check_page_size`] for pages) since the
+    /// low bits of the size field of a [`BlockHeader`] store its flags.
+    fn round_size(size: NonZeroUsize) -> Option<usize> {
+        size.get().checked_add(Self::MIN_BLOCK_MASK).map(|size| size & !Self::MIN_BLOCK_MASK)
     }
 
     unsafe fn take_block(&mut self, first_level_index: usize, second_level_index: usize) -> Option<NonNull<BlockHeader<T>>> {
@@ -165,7 +1097,7 @@ impl<T> TLSF<T> {
 
     unsafe fn return_block_no_merge(&mut self, mut block: NonNull<BlockHeader<T>>) {
         let size = block.as_ref().get_size();
-        let (first_level, second_level) = Self::map_block_size(NonZeroUsize::new(size).unwrap());
+        let (first_level, second_level) = self.map_block_size(NonZeroUsize::new(size).unwrap());
 
         self.free_first_level_mask |= 1 << first_level;
         let second_level_info = self.segregated_lists.get_mut(first_level as usize).unwrap();
@@ -176,14 +1108,14 @@ impl<T> TLSF<T> {
     }
 
     unsafe fn allocate_block_header(&mut self) -> NonNull<BlockHeader<T>> {
-        if let Some(header) = self.header_free_list.as_mut() {
+        if let Some(header) = (*self.header_free_list).as_mut() {
             header.remove_from_free_list();
             NonNull::from(header)
         } else {
             let mut pool: Box<_> = std::iter::repeat_with(BlockHeader::new).take(64).collect();
 
             for header in &mut pool[1..] {
-                header.insert_to_free_list_head(NonNull::from(&mut self.header_free_list));
+                header.insert_to_free_list_head(NonNull::from(&mut *self.header_free_list));
             }
             let header = NonNull::from(&mut pool[0]);
 
@@ -194,11 +1126,42 @@ impl<T> TLSF<T> {
     }
 
     unsafe fn free_block_header(&mut self, mut header: NonNull<BlockHeader<T>>) {
-        header.as_mut().insert_to_free_list_head(NonNull::from(&mut self.header_free_list));
+        header.as_mut().insert_to_free_list_head(NonNull::from(&mut *self.header_free_list));
+    }
+
+    /// Inserts a free block spanning the entire page `ptr` of `size` bytes.
+    unsafe fn insert_page_block(&mut self, ptr: *const T, size: usize) {
+        let mut header = self.allocate_block_header();
+
+        let header_ref = header.as_mut();
+        header_ref.make_new_physical_list();
+        header_ref.set_free_block_flag();
+
+        header_ref.set_size(size);
+        header_ref.base_offset = 0;
+        header_ref.pool = ptr;
+
+        self.return_block_no_merge(header);
+    }
+
+    fn new_allocation(&self, header: NonNull<BlockHeader<T>>) -> Allocation<T> {
+        Allocation {
+            header,
+            #[cfg(debug_assertions)]
+            generation: self.generation,
+        }
+    }
+
+    /// Panics if the allocation was created before the last call to [`TLSF::reset`]. Only checked
+    /// in debug builds.
+    #[inline(always)]
+    fn check_generation(&self, _allocation: &Allocation<T>) {
+        #[cfg(debug_assertions)]
+        assert_eq!(_allocation.generation, self.generation, "Allocation used after TLSF::reset");
     }
 
     fn find_free_block_index(&self, size: NonZeroUsize) -> Option<(u32, u32)> {
-        let (first_level, second_level) = Self::map_request_size(size);
+        let (first_level, second_level) = self.map_request_size(size);
 
         let mut selected_first_level = Self::first_one_after_at(
             self.free_first_level_mask,
@@ -208,7 +1171,7 @@ impl<T> TLSF<T> {
         let selected_second_level;
         if first_level == selected_first_level {
             if let Some(free_second_level) = Self::first_one_after_at(
-                self.segregated_lists.get(selected_first_level as usize).unwrap().free_mask as usize,
+                self.segregated_lists.get(selected_first_level as usize).unwrap().free_mask,
                 second_level
             ) {
                 selected_second_level = free_second_level;
@@ -217,7 +1180,7 @@ impl<T> TLSF<T> {
                     selected_first_level = new_first_level;
 
                     selected_second_level = Self::first_one_after_at(
-                        self.segregated_lists.get(new_first_level as usize).unwrap().free_mask as usize,
+                        self.segregated_lists.get(new_first_level as usize).unwrap().free_mask,
                         0
                     ).unwrap(); // Must succeed because otherwise the first level bit would've been cleared
                 } else {
@@ -227,60 +1190,539 @@ impl<T> TLSF<T> {
 
         } else {
             selected_second_level = Self::first_one_after_at(
-                self.segregated_lists.get(selected_first_level as usize).unwrap().free_mask as usize,
+                self.segregated_lists.get(selected_first_level as usize).unwrap().free_mask,
                 0
             ).unwrap(); // Must succeed because otherwise the first level bit would've been cleared
         }
 
-        Some((selected_first_level, selected_second_level))
+        Some((selected_first_level, selected_second_level))
+    }
+
+    /// Returns the index of the smallest list whose blocks are all at least `size` bytes large.
+    /// Used when searching for a free block.
+    fn map_request_size(&self, size: NonZeroUsize) -> (u32, u32) {
+        let (first_level, _) = self.map_block_size(size);
+
+        // Round up to the start of the next list unless the size already is the start of a list
+        let list_size_mask = Self::list_size(first_level) - 1;
+        match size.get().checked_add(list_size_mask) {
+            Some(rounded) => self.map_block_size(NonZeroUsize::new(rounded & !list_size_mask).unwrap()),
+            // Can never be satisfied
+            None => (usize::BITS, 0),
+        }
+    }
+
+    /// Returns the index of the list containing blocks of size `size`.
+    ///
+    /// The first first level list contains blocks up to `MIN_BLOCK_SIZE << second_level_log2`
+    /// bytes with second level lists spaced [`Self::MIN_BLOCK_SIZE`] bytes apart. Every following
+    /// first level list covers twice the range of the previous one.
+    fn map_block_size(&self, size: NonZeroUsize) -> (u32, u32) {
+        let last_bit = usize::BITS - 1 - size.leading_zeros();
+        let linear_bits = Self::MISSING_MIN_BLOCKS + self.second_level_log2;
+
+        if last_bit < linear_bits {
+            (0, (size.get() >> Self::MISSING_MIN_BLOCKS) as u32)
+        } else {
+            let first_level = last_bit - linear_bits + 1;
+            let second_level = (size.get() >> (last_bit - self.second_level_log2)) as u32 & ((1 << self.second_level_log2) - 1);
+            (first_level, second_level)
+        }
+    }
+
+    /// Returns the size range covered by each second level list of a first level.
+    fn list_size(first_level: u32) -> usize {
+        if first_level == 0 {
+            Self::MIN_BLOCK_SIZE
+        } else {
+            Self::MIN_BLOCK_SIZE << (first_level - 1)
+        }
+    }
+
+    /// Returns the index of the first set bit in `mask` at or after the bit at index `after_at`.
+    #[inline(always)]
+    fn first_one_after_at(mask: usize, after_at: u32) -> Option<u32> {
+        if after_at >= usize::BITS {
+            return None;
+        }
+
+        let trailing_zeros = (mask & (usize::MAX << after_at)).trailing_zeros();
+        if trailing_zeros < usize::BITS {
+            Some(trailing_zeros)
+        } else {
+            None
+        }
+    }
+}
+
+// The raw pointers only reference memory owned by the allocator itself
+unsafe impl<T: Send> Send for TLSF<T> {
+}
+
+/// Safe wrapper around a [`TLSF`] allocator.
+///
+/// The allocator is stored behind a mutex and every allocation keeps it alive, so a
+/// [`PoolAllocation`] can never reference freed headers. Blocks are never moved in place since
+/// [`TLSF::defragment`] is not exposed. Instead allocations created with
+/// [`PoolAllocator::allocate_movable`] may be relocated to a new block by
+/// [`PoolAllocator::begin_defrag`].
+pub struct PoolAllocator<T> {
+    inner: Arc<PoolAllocatorInner<T>>,
+    defrag: Mutex<DefragState<T>>,
+}
+
+impl<T> PoolAllocator<T> {
+    pub fn new(max_block_size: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolAllocatorInner {
+                tlsf: Mutex::new(PoolAllocatorGuarded {
+                    tlsf: TLSF::new_for_max_size(max_block_size),
+                    allocation_count: 0,
+                }),
+            }),
+            defrag: Mutex::new(DefragState {
+                movable: Vec::new(),
+                pending: Vec::new(),
+                retired: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Adds a new page of `size` bytes to the allocator.
+    ///
+    /// # Panics
+    /// If `size` is not a multiple of [`TLSF::MIN_BLOCK_SIZE`] or exceeds the max block size of
+    /// the allocator.
+    pub fn add_page(&self, page: Box<T>, size: usize) {
+        let mut guard = self.inner.tlsf.lock().unwrap();
+
+        // The tlsf is heap allocated inside the arc
+        if let Err(err) = unsafe { guard.tlsf.new_page(page, size) } {
+            // Dont poison the mutex
+            drop(guard);
+            panic!("Invalid page size {}: {:?}", size, err);
+        }
+    }
+
+    pub fn allocate(&self, size: NonZeroUsize) -> Option<PoolAllocation<T>> {
+        let mut guard = self.inner.tlsf.lock().unwrap();
+        let allocation = unsafe { guard.tlsf.allocate(size) }?;
+        guard.allocation_count += 1;
+        let generation = guard.tlsf.get_generation();
+        drop(guard);
+
+        Some(PoolAllocation::new(self.inner.clone(), allocation, size.get(), generation))
+    }
+
+    /// # Panics
+    /// If `alignment` is not a power of 2.
+    pub fn allocate_aligned(&self, size: NonZeroUsize, alignment: NonZeroUsize) -> Option<PoolAllocation<T>> {
+        let mut guard = self.inner.tlsf.lock().unwrap();
+        let allocation = unsafe { guard.tlsf.allocate_aligned(size, alignment) }?;
+        guard.allocation_count += 1;
+        let generation = guard.tlsf.get_generation();
+        drop(guard);
+
+        Some(PoolAllocation::new(self.inner.clone(), allocation, size.get(), generation))
+    }
+
+    /// Allocates a block which may be relocated by [`PoolAllocator::begin_defrag`].
+    ///
+    /// # Panics
+    /// If `alignment` is not a power of 2.
+    pub fn allocate_movable(&self, size: NonZeroUsize, alignment: NonZeroUsize) -> Option<MovablePoolAllocation<T>> {
+        let allocation = self.allocate_aligned(size, alignment)?;
+        let shared = Arc::new(MovableShared {
+            allocation: Mutex::new(allocation),
+            alignment,
+        });
+        self.defrag.lock().unwrap().movable.push(Arc::downgrade(&shared));
+
+        Some(MovablePoolAllocation {
+            shared,
+        })
+    }
+
+    /// Proposes moves of movable allocations to free up contiguous space. The pages with the
+    /// least used bytes are evacuated first by allocating a new block for every movable allocation
+    /// in a different page. No page that is evacuated receives new blocks. At most
+    /// `budget_bytes` bytes are moved.
+    ///
+    /// The caller must copy the contents of every move and then call
+    /// [`PoolAllocator::commit_moves`] or [`PoolAllocator::cancel_defrag`]. Until then the
+    /// moved allocations must not be written to and their location does not change.
+    ///
+    /// # Panics
+    /// If the moves of a previous call have neither been committed nor cancelled.
+    pub fn begin_defrag(&self, budget_bytes: usize) -> Vec<PoolMove<'_, T>> {
+        let mut defrag = self.defrag.lock().unwrap();
+        if !defrag.pending.is_empty() {
+            drop(defrag);
+            panic!("Called begin_defrag while moves of a previous defragmentation are pending");
+        }
+
+        defrag.movable.retain(|movable| movable.strong_count() != 0);
+        let movable: Vec<_> = defrag.movable.iter().filter_map(Weak::upgrade).collect();
+
+        let mut page_usage: HashMap<*const T, usize> = HashMap::new();
+        self.for_each_allocated_block(|block| {
+            *page_usage.entry(block.pool as *const T).or_default() += block.size;
+        });
+
+        // Group the movable allocations by page keeping the allocation order of each page
+        let mut pages: Vec<(*const T, Vec<&Arc<MovableShared<T>>>)> = Vec::new();
+        for shared in &movable {
+            let pool = shared.allocation.lock().unwrap().get_pool() as *const T;
+            match pages.iter_mut().find(|(page, _)| *page == pool) {
+                Some((_, allocations)) => allocations.push(shared),
+                None => pages.push((pool, vec![shared])),
+            }
+        }
+        pages.sort_by_key(|(page, _)| page_usage.get(page).copied().unwrap_or(0));
+
+        let mut moves = Vec::new();
+        let mut remaining = budget_bytes;
+        let mut excluded = Vec::new();
+        let mut received = HashSet::new();
+
+        'pages: for (page, allocations) in pages {
+            // Evacuating a page which just received blocks would undo the previous moves
+            if received.contains(&page) {
+                continue;
+            }
+            excluded.push(page);
+
+            for shared in allocations {
+                let src = shared.allocation.lock().unwrap();
+                let size = src.get_size();
+                if size > remaining {
+                    break 'pages;
+                }
+
+                let dst = {
+                    let mut guard = self.inner.tlsf.lock().unwrap();
+                    // Only modified through the safe api so always valid
+                    let allocation = unsafe {
+                        guard.tlsf.allocate_aligned_excluding(NonZeroUsize::new(size).unwrap(), shared.alignment, &excluded)
+                    };
+                    if allocation.is_some() {
+                        guard.allocation_count += 1;
+                    }
+                    allocation.map(|allocation| (allocation, guard.tlsf.get_generation()))
+                };
+                let Some((dst, generation)) = dst else {
+                    continue;
+                };
+                let dst = PoolAllocation::new(self.inner.clone(), dst, size, generation);
+                remaining -= size;
+                received.insert(dst.pool.as_ptr() as *const T);
+
+                moves.push(PoolMove {
+                    // Pages are never released so they live as long as the allocator
+                    src_pool: unsafe { src.pool.as_ref() },
+                    src_offset: src.get_offset(),
+                    dst_pool: unsafe { dst.pool.as_ref() },
+                    dst_offset: dst.get_offset(),
+                    size,
+                });
+                drop(src);
+                defrag.pending.push((Arc::downgrade(shared), dst));
+            }
+        }
+
+        moves
+    }
+
+    /// Rebinds all allocations moved by the last call to [`PoolAllocator::begin_defrag`] to
+    /// their new blocks. The old blocks are freed by [`PoolAllocator::recycle`] once
+    /// `timeline_value` has been reached, i.e. once the copies have completed.
+    ///
+    /// Moves of allocations which have been dropped or resized in the meantime are discarded.
+    pub fn commit_moves(&self, timeline_value: u64) {
+        let mut defrag = self.defrag.lock().unwrap();
+        let pending = std::mem::take(&mut defrag.pending);
+
+        for (shared, dst) in pending {
+            let retired = match shared.upgrade() {
+                Some(shared) => {
+                    let mut allocation = shared.allocation.lock().unwrap();
+                    if allocation.get_size() == dst.get_size() {
+                        std::mem::replace(&mut *allocation, dst)
+                    } else {
+                        dst
+                    }
+                }
+                None => dst,
+            };
+            defrag.retired.push_back((timeline_value, retired));
+        }
+    }
+
+    /// Discards all moves proposed by the last call to [`PoolAllocator::begin_defrag`]. The
+    /// allocations keep their current location.
+    ///
+    /// The caller must ensure that no copies into the new blocks are still executing.
+    pub fn cancel_defrag(&self) {
+        let pending = std::mem::take(&mut self.defrag.lock().unwrap().pending);
+        drop(pending);
+    }
+
+    /// Frees all old blocks of committed moves whose timeline value is less or equal to
+    /// `completed_value`.
+    pub fn recycle(&self, completed_value: u64) {
+        let mut defrag = self.defrag.lock().unwrap();
+        let mut freed = Vec::new();
+        while let Some((timeline_value, _)) = defrag.retired.front() {
+            if *timeline_value > completed_value {
+                break;
+            }
+            freed.push(defrag.retired.pop_front().unwrap().1);
+        }
+        drop(defrag);
+
+        drop(freed);
+    }
+
+    /// Frees all allocations at once without releasing any pages. See [`TLSF::reset`].
+    ///
+    /// All outstanding [`PoolAllocation`]s and [`MovablePoolAllocation`]s are invalidated.
+    /// Dropping them afterwards does nothing while resizing them or setting their debug name
+    /// panics. Pending and retired moves are discarded so no copies of a defragmentation may still
+    /// be executing.
+    pub fn reset(&self) {
+        let mut defrag = self.defrag.lock().unwrap();
+        let pending = std::mem::take(&mut defrag.pending);
+        let retired = std::mem::take(&mut defrag.retired);
+        defrag.movable.clear();
+        drop(defrag);
+
+        let mut guard = self.inner.tlsf.lock().unwrap();
+        // Only modified through the safe api so always valid
+        unsafe { guard.tlsf.reset() };
+        guard.allocation_count = 0;
+        drop(guard);
+
+        // Invalidated allocations must be dropped without holding the lock
+        drop(pending);
+        drop(retired);
+    }
+
+    /// Returns the number of live allocations.
+    pub fn get_allocation_count(&self) -> usize {
+        self.inner.tlsf.lock().unwrap().allocation_count
+    }
+
+    /// Calls `f` for every live allocation. The allocator is locked while `f` runs so it must not
+    /// allocate or free.
+    pub fn for_each_allocated_block(&self, mut f: impl FnMut(AllocatedBlock<T>)) {
+        let guard = self.inner.tlsf.lock().unwrap();
+        // Only modified through the safe api so always valid
+        for block in unsafe { guard.tlsf.iter_allocated_blocks() } {
+            f(block);
+        }
+    }
+}
+
+impl<T> Drop for PoolAllocator<T> {
+    fn drop(&mut self) {
+        // The device must be idle at this point so pending and retired blocks can be freed
+        let defrag = self.defrag.get_mut().unwrap();
+        defrag.pending.clear();
+        defrag.retired.clear();
+
+        // Outliving allocations keep the memory valid but almost certainly indicate a bug
+        if !std::thread::panicking() {
+            debug_assert_eq!(self.get_allocation_count(), 0, "PoolAllocator destroyed with live allocations");
+        }
+    }
+}
+
+struct PoolAllocatorInner<T> {
+    tlsf: Mutex<PoolAllocatorGuarded<T>>,
+}
+
+struct PoolAllocatorGuarded<T> {
+    tlsf: TLSF<T>,
+    allocation_count: usize,
+}
+
+struct DefragState<T> {
+    /// Every allocation created by [`PoolAllocator::allocate_movable`]. Dropped allocations are
+    /// removed lazily.
+    movable: Vec<Weak<MovableShared<T>>>,
+    /// The moved allocations and their new blocks proposed by [`PoolAllocator::begin_defrag`].
+    pending: Vec<(Weak<MovableShared<T>>, PoolAllocation<T>)>,
+    /// Blocks which are freed once the timeline has reached the associated value.
+    retired: VecDeque<(u64, PoolAllocation<T>)>,
+}
+
+/// A move proposed by [`PoolAllocator::begin_defrag`]. The caller has to copy `size` bytes from
+/// `src_offset` in `src_pool` to `dst_offset` in `dst_pool`. The two ranges never overlap.
+#[derive(Copy, Clone, Debug)]
+pub struct PoolMove<'a, T> {
+    pub src_pool: &'a T,
+    pub src_offset: usize,
+    pub dst_pool: &'a T,
+    pub dst_offset: usize,
+    pub size: usize,
+}
+
+/// A allocation made by a [`PoolAllocator`]. The allocation is freed when dropped.
+pub struct PoolAllocation<T> {
+    allocator: Arc<PoolAllocatorInner<T>>,
+    allocation: Option<Allocation<T>>,
+    pool: NonNull<T>,
+    offset: usize,
+    size: usize,
+    /// The generation of the allocator when this allocation was created. If it differs from the
+    /// current generation the allocation has been invalidated by [`PoolAllocator::reset`].
+    generation: u64,
+}
+
+impl<T> PoolAllocation<T> {
+    fn new(allocator: Arc<PoolAllocatorInner<T>>, allocation: Allocation<T>, size: usize, generation: u64) -> Self {
+        // Valid since the allocation has just been created and blocks are never moved
+        let (pool, offset) = unsafe {
+            (NonNull::from(allocation.get_pool()), allocation.get_offset())
+        };
+
+        Self {
+            allocator,
+            allocation: Some(allocation),
+            pool,
+            offset,
+            size,
+            generation,
+        }
+    }
+
+    /// Returns false if the allocation has been invalidated by [`PoolAllocator::reset`].
+    pub fn is_live(&self) -> bool {
+        self.allocator.tlsf.lock().unwrap().tlsf.get_generation() == self.generation
+    }
+
+    /// Returns the page this allocation is part of.
+    pub fn get_pool(&self) -> &T {
+        // Pages can only be released once they are empty so the page outlives this allocation
+        unsafe { self.pool.as_ref() }
+    }
+
+    /// Returns the offset of the allocation into its page.
+    pub fn get_offset(&self) -> usize {
+        self.offset
     }
 
-    fn map_request_size(size: NonZeroUsize) -> (u32, u32) {
-        let last_bit = usize::BITS - size.trailing_zeros();
-        let first_level = last_bit.saturating_sub(Self::MISSING_MIN_BLOCKS);
+    /// Returns the requested size of the allocation.
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
 
-        let masked_size = size.get() & !(1 << last_bit);
-        let second_level = (masked_size >> last_bit.saturating_sub(Self::SECOND_LEVEL_INDEX)) as u32;
+    /// Grows the allocation to `new_size` bytes without changing its offset. Returns false if the
+    /// space after the allocation is not free in which case the allocation is left unchanged.
+    pub fn try_grow(&mut self, new_size: NonZeroUsize) -> bool {
+        let mut guard = self.lock_live();
+        let grown = unsafe { guard.tlsf.try_grow(self.allocation.as_ref().unwrap(), new_size) };
+        drop(guard);
+        if grown {
+            self.size = std::cmp::max(self.size, new_size.get());
+        }
+        grown
+    }
 
-        (first_level, second_level)
+    /// Shrinks the allocation to `new_size` bytes without changing its offset.
+    ///
+    /// # Panics
+    /// If `new_size` is larger than the current size.
+    pub fn shrink(&mut self, new_size: NonZeroUsize) {
+        assert!(new_size.get() <= self.size, "Cannot shrink allocation of size {} to {}", self.size, new_size);
+
+        let mut guard = self.lock_live();
+        unsafe { guard.tlsf.shrink(self.allocation.as_ref().unwrap(), new_size) };
+        drop(guard);
+        self.size = new_size.get();
     }
 
-    fn map_block_size(size: NonZeroUsize) -> (u32, u32) {
-        let (first_level, second_level) = Self::map_request_size(size);
+    /// Sets the name reported by [`PoolAllocator::for_each_allocated_block`].
+    pub fn set_debug_name(&self, name: impl Into<String>) {
+        let mut guard = self.lock_live();
+        guard.tlsf.set_debug_name(self.allocation.as_ref().unwrap(), Some(name.into()));
+    }
 
-        let end_range = (1usize << (first_level + Self::SECOND_LEVEL_INDEX)) + ((1usize << first_level) * (second_level as usize));
-        if size.get() == end_range {
-            (first_level, second_level)
-        } else {
-            if second_level + 1 >= (1 << Self::SECOND_LEVEL_INDEX) {
-                (first_level + 1, 0)
-            } else {
-                (first_level, second_level + 1)
-            }
+    /// Locks the allocator.
+    ///
+    /// # Panics
+    /// If the allocation has been invalidated by [`PoolAllocator::reset`].
+    fn lock_live(&self) -> MutexGuard<'_, PoolAllocatorGuarded<T>> {
+        let guard = self.allocator.tlsf.lock().unwrap();
+        if guard.tlsf.get_generation() != self.generation {
+            // Dont poison the mutex
+            drop(guard);
+            panic!("PoolAllocation used after PoolAllocator::reset");
         }
+        guard
     }
+}
 
-    #[inline(always)]
-    fn first_one_after_at(mask: usize, after_at: u32) -> Option<u32> {
-        let leading_zeros = (mask & ((1 << after_at) - 1)).leading_zeros();
-        if leading_zeros < 32 {
-            Some(leading_zeros)
-        } else {
-            None
+impl<T> Drop for PoolAllocation<T> {
+    fn drop(&mut self) {
+        let mut guard = self.allocator.tlsf.lock().unwrap();
+        if guard.tlsf.get_generation() != self.generation {
+            // The block has already been freed by the reset
+            return;
         }
+        unsafe { guard.tlsf.free(self.allocation.take().unwrap()) };
+        guard.allocation_count -= 1;
+    }
+}
+
+// The allocation only hands out shared references to the page
+unsafe impl<T: Send + Sync> Send for PoolAllocation<T> {
+}
+
+unsafe impl<T: Send + Sync> Sync for PoolAllocation<T> {
+}
+
+/// A [`PoolAllocation`] which may be relocated by [`PoolAllocator::begin_defrag`]. The allocation
+/// is freed when dropped.
+pub struct MovablePoolAllocation<T> {
+    shared: Arc<MovableShared<T>>,
+}
+
+impl<T> MovablePoolAllocation<T> {
+    /// Locks the current location of the allocation. The location only changes in
+    /// [`PoolAllocator::commit_moves`] so it must not be cached across commits.
+    pub fn lock(&self) -> MutexGuard<'_, PoolAllocation<T>> {
+        self.shared.allocation.lock().unwrap()
+    }
+}
+
+struct MovableShared<T> {
+    allocation: Mutex<PoolAllocation<T>>,
+    alignment: NonZeroUsize,
+}
+
+struct Page<T> {
+    page: Box<T>,
+    /// The size passed to [`TLSF::new_page`].
+    size: usize,
+}
+
+impl<T> Page<T> {
+    fn as_ptr(&self) -> *const T {
+        self.page.as_ref() as *const T
     }
 }
 
 struct SecondLevel<T> {
-    free_mask: u32,
-    list_headers: [*mut BlockHeader<T>; 32],
+    free_mask: usize,
+    list_headers: Box<[*mut BlockHeader<T>]>,
 }
 
 impl<T> SecondLevel<T> {
-    fn new() -> Self {
+    fn new(list_count: usize) -> Self {
         Self {
             free_mask: 0,
-            list_headers: [null_mut(); 32],
+            list_headers: vec![null_mut(); list_count].into_boxed_slice(),
         }
     }
 }
@@ -470,9 +1912,11 @@ impl<T> BlockHeader<T> {
     /// Sets the size of this header. The size must be a multiple of 4.
     ///
     /// # Safety
-    /// The size must not have any of the bits set in [`Self::BLOCK_SIZE_MASK`].
+    /// The 2 least significant bits of the size must be cleared, otherwise the flags are
+    /// clobbered.
     #[inline(always)]
     unsafe fn set_size(&mut self, size: usize) {
+        debug_assert_eq!(size & !Self::BLOCK_SIZE_MASK, 0, "Block size {} would clobber the header flags", size);
         self.size_and_flags = size | (self.size_and_flags & !Self::BLOCK_SIZE_MASK);
     }
 
@@ -605,4 +2049,1085 @@ mod tests {
             assert_eq!(list_header, null_mut());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn iter_unpinned_pages() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        let page_a = Box::new(0u32);
+        let page_b = Box::new(1u32);
+        let ptr_a = page_a.as_ref() as *const u32;
+        let ptr_b = page_b.as_ref() as *const u32;
+
+        tlsf.page_pool.push(Page { page: page_a, size: 1024 });
+        tlsf.page_pool.push(Page { page: page_b, size: 1024 });
+
+        tlsf.pin_page(ptr_a);
+        assert!(tlsf.is_page_pinned(ptr_a));
+        assert_eq!(tlsf.iter_unpinned_pages().collect::<Vec<_>>(), vec![ptr_b]);
+
+        tlsf.unpin_page(ptr_a);
+        assert!(!tlsf.is_page_pinned(ptr_a));
+        assert_eq!(tlsf.iter_unpinned_pages().collect::<Vec<_>>(), vec![ptr_a, ptr_b]);
+    }
+
+    #[test]
+    fn defragment_skips_pinned_pages() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        let page = Box::new(0u32);
+        let page_ptr = page.as_ref() as *const u32;
+
+        unsafe {
+            tlsf.new_page(page, 1024).unwrap();
+
+            let a = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let b = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let c = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let b_offset = b.get_offset();
+            let c_offset = c.get_offset();
+            tlsf.free(a);
+
+            tlsf.pin_page(page_ptr);
+            assert!(tlsf.defragment().is_empty());
+            assert_eq!(b.get_offset(), b_offset);
+            assert_eq!(c.get_offset(), c_offset);
+
+            tlsf.unpin_page(page_ptr);
+            let moves = tlsf.defragment();
+            assert_eq!(moves, vec![
+                BlockMove { pool: page_ptr, src_offset: b_offset, dst_offset: 0, size: 64 },
+                BlockMove { pool: page_ptr, src_offset: c_offset, dst_offset: 64, size: 64 },
+            ]);
+            assert_eq!(b.get_offset(), 0);
+            assert_eq!(c.get_offset(), 64);
+
+            // All free space has been merged so the rest of the page can be allocated at once
+            let d = tlsf.allocate(NonZeroUsize::new(1024 - 128).unwrap()).unwrap();
+            assert_eq!(d.get_offset(), 128);
+        }
+    }
+
+    #[test]
+    fn allocate_aligned_head_split() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+
+            // Moves the start of the free block away from any large alignment
+            let _first = tlsf.allocate(NonZeroUsize::new(32).unwrap()).unwrap();
+
+            let aligned = tlsf.allocate_aligned(NonZeroUsize::new(64).unwrap(), NonZeroUsize::new(256).unwrap()).unwrap();
+            assert_eq!(aligned.get_offset() % 256, 0);
+            assert_eq!(aligned.get_offset(), 256);
+
+            // The head remainder (32..256) must be available again
+            let head = tlsf.allocate(NonZeroUsize::new(256 - 32).unwrap()).unwrap();
+            assert_eq!(head.get_offset(), 32);
+
+            // As does the tail remainder after the aligned block
+            let tail = tlsf.allocate(NonZeroUsize::new(4096 - 320).unwrap()).unwrap();
+            assert_eq!(tail.get_offset(), 320);
+        }
+    }
+
+    #[test]
+    fn allocate_aligned_larger_than_position() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(8192);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 8192).unwrap();
+
+            for _ in 0..4 {
+                let _ = tlsf.allocate(NonZeroUsize::new(96).unwrap()).unwrap();
+            }
+            for alignment in [64, 512, 2048] {
+                let allocation = tlsf.allocate_aligned(NonZeroUsize::new(32).unwrap(), NonZeroUsize::new(alignment).unwrap()).unwrap();
+                assert_eq!(allocation.get_offset() % alignment, 0);
+            }
+
+            // Small alignments are always satisfied by the min block size
+            let small = tlsf.allocate_aligned(NonZeroUsize::new(8).unwrap(), NonZeroUsize::new(16).unwrap()).unwrap();
+            assert_eq!(small.get_offset() % 16, 0);
+        }
+    }
+
+    #[test]
+    fn release_empty_pages() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 1024).unwrap();
+            tlsf.new_page(Box::new(1u32), 1024).unwrap();
+            tlsf.new_page(Box::new(2u32), 1024).unwrap();
+
+            // Fills one page completely so it can't be released
+            let used = tlsf.allocate(NonZeroUsize::new(1024).unwrap()).unwrap();
+            let used_page = *used.get_pool();
+
+            // The filter is only called for empty pages and may keep some of them
+            let keep = if used_page == 0 { 1 } else { 0 };
+            let mut seen = Vec::new();
+            let released = tlsf.try_release_empty_pages(|page| {
+                seen.push(*page);
+                *page != keep
+            });
+            seen.sort();
+            assert_eq!(seen, (0..3).filter(|page| *page != used_page).collect::<Vec<_>>());
+            assert_eq!(released.len(), 1);
+            assert_ne!(*released[0], keep);
+            assert_eq!(tlsf.iter_unpinned_pages().count(), 2);
+
+            tlsf.free(used);
+            let released = tlsf.try_release_empty_pages(|_| true);
+            assert!(released.iter().any(|page| **page == used_page));
+            assert_eq!(tlsf.iter_unpinned_pages().count(), 0);
+            assert!(tlsf.allocate(NonZeroUsize::new(32).unwrap()).is_none());
+        }
+    }
+
+    #[test]
+    fn first_one_after_at_small_masks() {
+        for mask in 0usize..(1 << 10) {
+            for after_at in 0..12 {
+                let expected = (after_at..usize::BITS).find(|bit| mask & (1 << bit) != 0);
+                assert_eq!(TLSF::<()>::first_one_after_at(mask, after_at), expected, "mask: {:#b} after_at: {}", mask, after_at);
+            }
+        }
+
+        assert_eq!(TLSF::<()>::first_one_after_at(1 << (usize::BITS - 1), usize::BITS - 1), Some(usize::BITS - 1));
+        assert_eq!(TLSF::<()>::first_one_after_at(usize::MAX, usize::BITS), None);
+    }
+
+    #[test]
+    fn size_mapping() {
+        for second_level_log2 in [4, 5, 6] {
+            size_mapping_with(second_level_log2);
+        }
+    }
+
+    fn size_mapping_with(second_level_log2: u32) {
+        type Tlsf = TLSF<()>;
+        let tlsf = Tlsf::new_with_config(1 << 20, second_level_log2);
+
+        // Returns the smallest block size stored in a list
+        let list_start = |(first_level, second_level): (u32, u32)| -> usize {
+            if first_level == 0 {
+                second_level as usize * Tlsf::MIN_BLOCK_SIZE
+            } else {
+                (Tlsf::MIN_BLOCK_SIZE << (first_level - 1 + second_level_log2)) + second_level as usize * Tlsf::list_size(first_level)
+            }
+        };
+
+        let mut previous = (0, 0);
+        for size in (Tlsf::MIN_BLOCK_SIZE..(1 << 20)).step_by(Tlsf::MIN_BLOCK_SIZE) {
+            let block = tlsf.map_block_size(NonZeroUsize::new(size).unwrap());
+            assert!(block.1 < (1 << second_level_log2));
+            assert!(list_start(block) <= size);
+            assert!(block >= previous);
+            previous = block;
+
+            // Every block in the list found for a request must be large enough
+            let request = tlsf.map_request_size(NonZeroUsize::new(size).unwrap());
+            assert!(list_start(request) >= size);
+            assert!(request >= block);
+        }
+    }
+
+    #[test]
+    fn page_size_bounds() {
+        const MIN: usize = TLSF::<u32>::MIN_BLOCK_SIZE;
+
+        for max_block_size in [1 << 12, 3008, 1 << 40, MIN] {
+            let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(max_block_size);
+            tlsf.set_debug_validation(true);
+            assert_eq!(tlsf.get_max_block_size(), max_block_size);
+
+            // The list of the max block size must exist
+            let (first_level, _) = tlsf.map_block_size(NonZeroUsize::new(max_block_size).unwrap());
+            assert_eq!(first_level as usize, tlsf.segregated_lists.len() - 1);
+
+            unsafe {
+                assert_eq!(tlsf.new_page(Box::new(0), 0), Err(PageSizeError::TooSmall));
+                assert_eq!(tlsf.new_page(Box::new(0), MIN - 1), Err(PageSizeError::TooSmall));
+                if max_block_size > MIN {
+                    assert_eq!(tlsf.new_page(Box::new(0), MIN + 1), Err(PageSizeError::Unaligned));
+                    assert_eq!(tlsf.new_page(Box::new(0), max_block_size - 1), Err(PageSizeError::Unaligned));
+                }
+                assert_eq!(tlsf.new_page(Box::new(0), max_block_size + 1), Err(PageSizeError::TooLarge));
+                assert_eq!(tlsf.new_page(Box::new(0), max_block_size + MIN), Err(PageSizeError::TooLarge));
+                assert!(tlsf.page_pool.is_empty());
+
+                tlsf.new_page(Box::new(1), MIN).unwrap();
+                if max_block_size <= 1 << 16 {
+                    tlsf.new_page(Box::new(2), max_block_size).unwrap();
+                    let allocation = tlsf.allocate(NonZeroUsize::new(max_block_size).unwrap()).unwrap();
+                    assert_eq!(*allocation.get_pool(), 2);
+                    tlsf.free(allocation);
+                }
+                assert!(tlsf.allocate(NonZeroUsize::new(max_block_size + 1).unwrap()).is_none());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn max_block_size_too_small() {
+        TLSF::<u32>::new_for_max_size(TLSF::<u32>::MIN_BLOCK_SIZE - 1);
+    }
+
+    #[test]
+    fn second_level_configs() {
+        const PAGE_SIZE: usize = 1 << 14;
+
+        for second_level_log2 in [4, 5, 6] {
+            let mut tlsf: TLSF<u32> = TLSF::new_with_config(PAGE_SIZE, second_level_log2);
+            tlsf.set_debug_validation(true);
+            assert!(tlsf.segregated_lists.iter().all(|lists| lists.list_headers.len() == 1 << second_level_log2));
+
+            unsafe {
+                tlsf.new_page(Box::new(0u32), PAGE_SIZE).unwrap();
+                let pages = [(tlsf.page_pool[0].as_ptr(), PAGE_SIZE)];
+
+                let mut allocations = Vec::new();
+                let mut size = 1;
+                while let Some(allocation) = tlsf.allocate(NonZeroUsize::new(size).unwrap()) {
+                    allocations.push(allocation);
+                    size = size * 3 % 997 + 1;
+                }
+                assert!(allocations.len() > 20);
+
+                // Free every other allocation first so freed blocks end up in many different lists
+                let (even, odd): (Vec<_>, Vec<_>) = allocations.into_iter().enumerate().partition(|(index, _)| index % 2 == 0);
+                for (_, allocation) in even.into_iter().chain(odd) {
+                    tlsf.free(allocation);
+                }
+                assert_pages_merged(&tlsf, &pages);
+            }
+        }
+    }
+
+    /// Deterministic lcg so randomized tests are reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn new(seed: u64) -> Self {
+            Self(seed)
+        }
+
+        fn next(&mut self) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize
+        }
+
+        /// Returns an allocation size. Small sizes are more likely than large sizes.
+        fn next_size(&mut self) -> usize {
+            match self.next() % 4 {
+                0 => 1 + self.next() % 64,
+                1 => 1 + self.next() % 1024,
+                2 => 1024 + self.next() % 4096,
+                _ => 4096 + self.next() % 16384,
+            }
+        }
+    }
+
+    #[test]
+    fn allocate_free_cycles() {
+        const PAGE_SIZE: usize = 1 << 16;
+
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(PAGE_SIZE);
+        let mut live: Vec<(Allocation<u32>, usize)> = Vec::new();
+
+        let mut rng = Lcg::new(0x2545F4914F6CDD1D);
+
+        tlsf.set_debug_validation(true);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), PAGE_SIZE).unwrap();
+            tlsf.new_page(Box::new(1u32), PAGE_SIZE).unwrap();
+
+            for _ in 0..10000 {
+                if live.is_empty() || rng.next() % 3 != 0 {
+                    let size = rng.next_size();
+                    if let Some(allocation) = tlsf.allocate(NonZeroUsize::new(size).unwrap()) {
+                        live.push((allocation, size));
+                    }
+                } else {
+                    let (allocation, _) = live.swap_remove(rng.next() % live.len());
+                    tlsf.free(allocation);
+                }
+
+                let mut ranges: Vec<_> = live.iter().map(|(allocation, size)| (*allocation.get_pool(), allocation.get_offset(), *size)).collect();
+                ranges.sort();
+                for window in ranges.windows(2) {
+                    let (pool_a, offset_a, size_a) = window[0];
+                    let (pool_b, offset_b, _) = window[1];
+                    assert!(pool_a != pool_b || offset_a + size_a <= offset_b, "Overlapping allocations: {:?}", window);
+                }
+                for (_, offset, size) in ranges {
+                    assert!(offset + size <= PAGE_SIZE);
+                }
+            }
+
+            for (allocation, _) in live.drain(..) {
+                tlsf.free(allocation);
+            }
+
+            // All blocks must have been merged again
+            let a = tlsf.allocate(NonZeroUsize::new(PAGE_SIZE).unwrap()).unwrap();
+            let b = tlsf.allocate(NonZeroUsize::new(PAGE_SIZE).unwrap()).unwrap();
+            assert_eq!(a.get_offset(), 0);
+            assert_eq!(b.get_offset(), 0);
+            assert!(tlsf.allocate(NonZeroUsize::new(1).unwrap()).is_none());
+        }
+    }
+
+    #[test]
+    fn defragment_cycles() {
+        const PAGE_SIZE: usize = 1 << 14;
+
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(PAGE_SIZE);
+        tlsf.set_debug_validation(true);
+        let mut live = Vec::new();
+
+        let mut rng = Lcg::new(0x9E3779B97F4A7C15);
+
+        unsafe {
+            for page in 0..3 {
+                tlsf.new_page(Box::new(page), PAGE_SIZE).unwrap();
+            }
+
+            for round in 0..200 {
+                for _ in 0..16 {
+                    let size = NonZeroUsize::new(1 + rng.next() % 512).unwrap();
+                    let allocation = if rng.next() % 4 == 0 {
+                        tlsf.allocate_aligned(size, NonZeroUsize::new(256).unwrap())
+                    } else {
+                        tlsf.allocate(size)
+                    };
+                    live.extend(allocation);
+                }
+                for _ in 0..(rng.next() % 16) {
+                    if !live.is_empty() {
+                        tlsf.free(live.swap_remove(rng.next() % live.len()));
+                    }
+                }
+
+                if round % 10 == 0 {
+                    tlsf.defragment();
+                }
+                if round % 50 == 0 {
+                    tlsf.try_release_empty_pages(|_| false);
+                }
+            }
+
+            for allocation in live.drain(..) {
+                tlsf.free(allocation);
+            }
+            assert_eq!(tlsf.try_release_empty_pages(|_| true).len(), 3);
+        }
+        assert_eq!(tlsf.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_detects_corruption() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        assert_eq!(tlsf.validate(), Ok(()));
+
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+            let mut a = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            assert_eq!(tlsf.validate(), Ok(()));
+
+            let mask = tlsf.free_first_level_mask;
+            tlsf.free_first_level_mask |= 1;
+            assert_eq!(tlsf.validate(), Err(ValidationError::FirstLevelMaskMismatch { first_level: 0 }));
+            tlsf.free_first_level_mask = mask;
+
+            a.header.as_mut().set_free_block_flag();
+            assert_eq!(tlsf.validate(), Err(ValidationError::FreeFlagMismatch { offset: 0 }));
+            a.header.as_mut().clear_free_block_flag();
+
+            a.header.as_mut().base_offset = 32;
+            assert_eq!(tlsf.validate(), Err(ValidationError::OffsetDiscontinuity { expected: 0, found: 32 }));
+            a.header.as_mut().base_offset = 0;
+
+            // Make the used block appear in the header free list as well
+            a.header.as_mut().insert_to_free_list_head(NonNull::from(&mut *tlsf.header_free_list));
+            assert_eq!(tlsf.validate(), Err(ValidationError::InvalidPageList));
+            a.header.as_mut().remove_from_free_list();
+
+            assert_eq!(tlsf.validate(), Ok(()));
+            tlsf.free(a);
+        }
+        assert_eq!(tlsf.validate(), Ok(()));
+    }
+
+    #[test]
+    fn verify_allocation() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        tlsf.set_debug_validation(true);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+            tlsf.new_page(Box::new(1u32), 4096).unwrap();
+
+            let a = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let b = tlsf.allocate(NonZeroUsize::new(4096).unwrap()).unwrap();
+            let c = tlsf.allocate_aligned(NonZeroUsize::new(64).unwrap(), NonZeroUsize::new(512).unwrap()).unwrap();
+            assert!(tlsf.verify_allocation(&a));
+            assert!(tlsf.verify_allocation(&b));
+            assert!(tlsf.verify_allocation(&c));
+
+            let stale = tlsf.new_allocation(a.header);
+            tlsf.free(a);
+            assert!(!tlsf.verify_allocation(&stale));
+            assert!(tlsf.verify_allocation(&c));
+
+            // The tracked allocation is checked by validate
+            let mut c_header = c.header;
+            c_header.as_mut().set_free_block_flag();
+            assert_eq!(tlsf.validate(), Err(ValidationError::FreeFlagMismatch { offset: c.get_offset() }));
+            c_header.as_mut().clear_free_block_flag();
+            tlsf.tracked_allocations.insert(stale.header.as_ptr());
+            assert_eq!(tlsf.validate(), Err(ValidationError::DeadAllocation));
+            tlsf.tracked_allocations.remove(&(stale.header.as_ptr() as *const BlockHeader<u32>));
+
+            tlsf.free(b);
+            tlsf.free(c);
+        }
+        assert_eq!(tlsf.validate(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn double_free_panics() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        tlsf.set_debug_validation(true);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+            let _keep = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let a = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let stale = tlsf.new_allocation(a.header);
+            tlsf.free(a);
+            tlsf.free(stale);
+        }
+    }
+
+    #[test]
+    fn iter_allocated_blocks() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+            tlsf.new_page(Box::new(1u32), 4096).unwrap();
+            assert_eq!(tlsf.iter_allocated_blocks().count(), 0);
+
+            let a = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            let b = tlsf.allocate(NonZeroUsize::new(100).unwrap()).unwrap();
+            let c = tlsf.allocate(NonZeroUsize::new(4096).unwrap()).unwrap();
+            tlsf.set_debug_name(&b, Some(String::from("b")));
+            assert_eq!(tlsf.get_debug_name(&b), Some("b"));
+
+            let mut blocks: Vec<_> = tlsf.iter_allocated_blocks()
+                .map(|block| (*block.pool, block.offset, block.size, block.debug_name.map(String::from)))
+                .collect();
+            blocks.sort();
+            let expected_page = *a.get_pool();
+            let mut expected = vec![
+                (expected_page, a.get_offset(), 64, None),
+                (expected_page, b.get_offset(), 128, Some(String::from("b"))),
+                (*c.get_pool(), 0, 4096, None),
+            ];
+            expected.sort();
+            assert_eq!(blocks, expected);
+
+            // Freeing removes the name so a new allocation in the same header is unnamed
+            tlsf.free(b);
+            let d = tlsf.allocate(NonZeroUsize::new(100).unwrap()).unwrap();
+            assert_eq!(tlsf.get_debug_name(&d), None);
+            assert_eq!(tlsf.iter_allocated_blocks().count(), 3);
+            assert!(tlsf.iter_allocated_blocks().all(|block| block.debug_name.is_none()));
+
+            tlsf.free(a);
+            tlsf.free(c);
+            tlsf.free(d);
+            assert_eq!(tlsf.iter_allocated_blocks().count(), 0);
+        }
+    }
+
+    fn allocate_at_tlsf() -> TLSF<u32> {
+        let mut tlsf = TLSF::new_for_max_size(4096);
+        tlsf.set_debug_validation(true);
+        unsafe { tlsf.new_page(Box::new(0u32), 1024).unwrap() };
+        tlsf
+    }
+
+    #[test]
+    fn allocate_aligned_excluding() {
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        unsafe {
+            let mut tlsf = allocate_at_tlsf();
+            tlsf.new_page(Box::new(1u32), 1024).unwrap();
+            let page_a = &*tlsf.page_pool[0].page as *const u32;
+            let page_b = &*tlsf.page_pool[1].page as *const u32;
+
+            let in_b = tlsf.allocate_aligned_excluding(size(100), size(64), &[page_a]).unwrap();
+            assert!(std::ptr::eq(in_b.get_pool(), page_b));
+            assert_eq!(in_b.get_offset() % 64, 0);
+            let in_a = tlsf.allocate_aligned_excluding(size(100), size(1), &[page_b]).unwrap();
+            assert!(std::ptr::eq(in_a.get_pool(), page_a));
+            assert!(tlsf.allocate_aligned_excluding(size(32), size(1), &[page_a, page_b]).is_none());
+
+            // The hidden free blocks must still be available
+            let rest_a = tlsf.allocate_at(128, size(896), &*page_a).unwrap();
+            assert!(tlsf.allocate_aligned_excluding(size(1024), size(1), &[page_b]).is_none());
+
+            for allocation in [in_a, in_b, rest_a] {
+                tlsf.free(allocation);
+            }
+            assert_eq!(tlsf.allocate(size(1024)).unwrap().get_block_size(), 1024);
+        }
+    }
+
+    #[test]
+    fn allocate_at_splits() {
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        unsafe {
+            let mut tlsf = allocate_at_tlsf();
+            let page = &*tlsf.page_pool[0].page as *const u32;
+
+            // Exact fit
+            let full = tlsf.allocate_at(0, size(1024), &*page).unwrap();
+            assert_eq!(full.get_offset(), 0);
+            assert_eq!(full.get_block_size(), 1024);
+            assert_eq!(tlsf.allocate_at(0, size(32), &*page).err(), Some(AllocateAtError::Occupied));
+            tlsf.free(full);
+
+            // Tail split
+            let head = tlsf.allocate_at(0, size(100), &*page).unwrap();
+            assert_eq!(head.get_block_size(), 128);
+
+            // Head split
+            let tail = tlsf.allocate_at(512, size(512), &*page).unwrap();
+            assert_eq!(tail.get_offset(), 512);
+
+            // Both
+            let middle = tlsf.allocate_at(256, size(64), &*page).unwrap();
+            assert_eq!(middle.get_offset(), 256);
+
+            // The remainders must be available again
+            let before = tlsf.allocate_at(128, size(128), &*page).unwrap();
+            let after = tlsf.allocate_at(320, size(192), &*page).unwrap();
+            assert!(tlsf.allocate(size(1)).is_none());
+
+            for allocation in [head, tail, middle, before, after] {
+                tlsf.free(allocation);
+            }
+            assert_eq!(tlsf.allocate(size(1024)).unwrap().get_offset(), 0);
+        }
+    }
+
+    #[test]
+    fn allocate_at_errors() {
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        unsafe {
+            let mut tlsf = allocate_at_tlsf();
+            let page = &*tlsf.page_pool[0].page as *const u32;
+            let other = 0u32;
+
+            assert_eq!(tlsf.allocate_at(16, size(32), &*page).err(), Some(AllocateAtError::UnalignedOffset));
+            assert_eq!(tlsf.allocate_at(0, size(32), &other).err(), Some(AllocateAtError::UnknownPool));
+            assert_eq!(tlsf.allocate_at(1024 - 32, size(64), &*page).err(), Some(AllocateAtError::OutOfRange));
+            assert_eq!(tlsf.allocate_at(usize::MAX & !31, size(32), &*page).err(), Some(AllocateAtError::OutOfRange));
+
+            let used = tlsf.allocate_at(256, size(64), &*page).unwrap();
+            // Overlaps the start, the end and the entire used block
+            assert_eq!(tlsf.allocate_at(224, size(64), &*page).err(), Some(AllocateAtError::Occupied));
+            assert_eq!(tlsf.allocate_at(288, size(64), &*page).err(), Some(AllocateAtError::Occupied));
+            assert_eq!(tlsf.allocate_at(0, size(512), &*page).err(), Some(AllocateAtError::Occupied));
+            tlsf.free(used);
+        }
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        tlsf.set_debug_validation(true);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 1024).unwrap();
+
+            let a = tlsf.allocate(size(64)).unwrap();
+            let b = tlsf.allocate(size(64)).unwrap();
+            assert_eq!(b.get_offset(), 64);
+
+            // Growing within the block is always possible
+            assert!(tlsf.try_grow(&a, size(60)));
+            // The next block is used
+            assert!(!tlsf.try_grow(&a, size(65)));
+            assert_eq!(a.get_block_size(), 64);
+
+            // Consumes part of the free tail
+            assert!(tlsf.try_grow(&b, size(200)));
+            assert_eq!(b.get_offset(), 64);
+            assert_eq!(b.get_block_size(), 224);
+            // Consumes the entire free tail
+            assert!(tlsf.try_grow(&b, size(1024 - 64)));
+            assert!(!tlsf.try_grow(&b, size(1024)));
+            assert!(tlsf.allocate(size(1)).is_none());
+
+            // The tail is returned and merged into a single block
+            tlsf.shrink(&b, size(100));
+            assert_eq!(b.get_offset(), 64);
+            assert_eq!(b.get_block_size(), 128);
+            tlsf.shrink(&b, size(32));
+            let c = tlsf.allocate(size(1024 - 96)).unwrap();
+            assert_eq!(c.get_offset(), 96);
+
+            tlsf.free(a);
+            tlsf.free(b);
+            tlsf.free(c);
+            assert_eq!(tlsf.allocate(size(1024)).unwrap().get_offset(), 0);
+        }
+    }
+
+    /// Sizes which are not multiples of the min block size must never end up in a block header
+    /// where they would clobber the flags.
+    #[test]
+    fn adversarial_sizes() {
+        const MIN: usize = TLSF::<u32>::MIN_BLOCK_SIZE;
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        // The first free block flag is undefined for used blocks
+        let is_free = |allocation: &Allocation<u32>| unsafe { allocation.header.as_ref().is_free_block() };
+
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(1 << 16);
+        tlsf.set_debug_validation(true);
+        unsafe {
+            for page_size in [MIN + 1, MIN + 2, MIN + 3, 4095, (1 << 16) - 1] {
+                assert_eq!(tlsf.new_page(Box::new(0), page_size), Err(PageSizeError::Unaligned));
+            }
+            tlsf.new_page(Box::new(0), 4096).unwrap();
+            let page = &*tlsf.page_pool[0].page as *const u32;
+
+            let mut allocations = Vec::new();
+            for requested in [1, 2, 3, MIN - 1, MIN + 1, MIN + 2, MIN + 3, 2 * MIN + 1, 255, 257] {
+                let allocation = tlsf.allocate(size(requested)).unwrap();
+                assert_eq!(allocation.get_block_size() % MIN, 0);
+                assert_eq!(allocation.get_offset() % MIN, 0);
+                assert!(!is_free(&allocation));
+                allocations.push(allocation);
+            }
+
+            let aligned = tlsf.allocate_aligned(size(MIN + 3), size(256)).unwrap();
+            assert_eq!(aligned.get_block_size(), 2 * MIN);
+            assert!(!is_free(&aligned));
+
+            let at = tlsf.allocate_at(3072, size(MIN + 1), &*page).unwrap();
+            assert_eq!(at.get_block_size(), 2 * MIN);
+            assert!(tlsf.try_grow(&at, size(3 * MIN + 1)));
+            assert_eq!(at.get_block_size(), 4 * MIN);
+            tlsf.shrink(&at, size(MIN + 3));
+            assert_eq!(at.get_block_size(), 2 * MIN);
+            assert!(!is_free(&at));
+
+            // Sizes which overflow when rounded are rejected instead of wrapping around
+            assert!(tlsf.allocate(size(usize::MAX)).is_none());
+            assert!(tlsf.allocate(size(usize::MAX - MIN)).is_none());
+            assert!(tlsf.allocate_aligned(size(usize::MAX - 1), size(1024)).is_none());
+            assert_eq!(tlsf.allocate_at(0, size(usize::MAX), &*page).err(), Some(AllocateAtError::OutOfRange));
+            assert!(!tlsf.try_grow(&at, size(usize::MAX)));
+            tlsf.validate().unwrap();
+
+            for allocation in allocations {
+                tlsf.free(allocation);
+            }
+            tlsf.free(aligned);
+            tlsf.free(at);
+            assert_eq!(tlsf.allocate(size(4096)).unwrap().get_offset(), 0);
+        }
+    }
+
+    /// Asserts that every page consists of a single free block covering the entire page.
+    unsafe fn assert_pages_merged(tlsf: &TLSF<u32>, pages: &[(*const u32, usize)]) {
+        let starts = tlsf.collect_physical_list_starts();
+        assert_eq!(starts.len(), pages.len());
+        for (pool, size) in pages {
+            let start = starts.iter().find(|start| std::ptr::eq(start.as_ref().pool, *pool)).unwrap().as_ref();
+            assert!(start.is_free_block());
+            assert_eq!(start.base_offset, 0);
+            assert_eq!(start.get_size(), *size);
+            assert!(start.next_physical.is_null());
+        }
+    }
+
+    struct LiveBlock {
+        allocation: Allocation<u32>,
+        pool: *const u32,
+        offset: usize,
+        block_size: usize,
+    }
+
+    impl LiveBlock {
+        unsafe fn new(allocation: Allocation<u32>) -> Self {
+            Self {
+                pool: allocation.get_pool() as *const u32,
+                offset: allocation.get_offset(),
+                block_size: allocation.get_block_size(),
+                allocation,
+            }
+        }
+    }
+
+    /// Shadows the allocator with a list of occupied ranges and checks every operation against it.
+    #[test]
+    fn randomized_stress() {
+        const MAX_PAGE_SIZE: usize = 1 << 16;
+        const PAGE_SIZES: [usize; 4] = [MAX_PAGE_SIZE, 1 << 15, 3 << 12, MAX_PAGE_SIZE];
+        const ALIGNMENTS: [usize; 7] = [1, 16, 32, 64, 256, 1024, 4096];
+
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        let round = |size| TLSF::<u32>::round_size(NonZeroUsize::new(size).unwrap()).unwrap();
+
+        let mut rng = Lcg::new(0x9E3779B97F4A7C15);
+
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(MAX_PAGE_SIZE);
+        let mut live: Vec<LiveBlock> = Vec::new();
+
+        // Checks the free and physical lists after every operation
+        tlsf.set_debug_validation(true);
+        unsafe {
+            for (index, page_size) in PAGE_SIZES.iter().enumerate() {
+                tlsf.new_page(Box::new(index as u32), *page_size).unwrap();
+            }
+            let pages: Vec<_> = tlsf.collect_physical_list_starts().iter()
+                .map(|start| (start.as_ref().pool, start.as_ref().get_size()))
+                .collect();
+            let page_size = |pool: *const u32| pages.iter().find(|(page, _)| std::ptr::eq(*page, pool)).unwrap().1;
+
+            // The start of the next occupied range or the end of the page
+            let next_occupied = |live: &[LiveBlock], pool: *const u32, offset: usize| {
+                live.iter()
+                    .filter(|block| std::ptr::eq(block.pool, pool) && block.offset > offset)
+                    .map(|block| block.offset)
+                    .min()
+                    .unwrap_or_else(|| page_size(pool))
+            };
+
+            let check_new_block = |live: &[LiveBlock], block: &LiveBlock, requested: usize, alignment: usize| {
+                assert!(block.block_size >= requested);
+                assert_eq!(block.offset % alignment, 0, "Misaligned block at {} (Alignment: {})", block.offset, alignment);
+                assert!(block.offset + block.block_size <= page_size(block.pool));
+                for other in live.iter().filter(|other| std::ptr::eq(other.pool, block.pool)) {
+                    assert!(
+                        other.offset + other.block_size <= block.offset || block.offset + block.block_size <= other.offset,
+                        "Block {}+{} overlaps {}+{}", block.offset, block.block_size, other.offset, other.block_size
+                    );
+                }
+            };
+
+            for _ in 0..10000 {
+                match rng.next() % 20 {
+                    // Allocate
+                    0..=8 => {
+                        let requested = rng.next_size();
+                        let alignment = ALIGNMENTS[rng.next() % ALIGNMENTS.len()];
+                        if let Some(allocation) = tlsf.allocate_aligned(size(requested), size(alignment)) {
+                            let block = LiveBlock::new(allocation);
+                            check_new_block(&live, &block, requested, alignment);
+                            live.push(block);
+                        }
+                    },
+                    // Free and check the range can be reused
+                    9..=14 if !live.is_empty() => {
+                        let block = live.swap_remove(rng.next() % live.len());
+                        let (pool, offset, block_size) = (block.pool, block.offset, block.block_size);
+                        tlsf.free(block.allocation);
+
+                        if rng.next() % 2 == 0 {
+                            let allocation = tlsf.allocate_at(offset, size(block_size), &*pool).unwrap();
+                            let block = LiveBlock::new(allocation);
+                            assert_eq!(block.offset, offset);
+                            assert_eq!(block.block_size, block_size);
+                            check_new_block(&live, &block, block_size, 1);
+                            live.push(block);
+                        }
+                    },
+                    // Grow
+                    15..=17 if !live.is_empty() => {
+                        let index = rng.next() % live.len();
+                        let block = &live[index];
+                        let new_size = block.block_size + 1 + rng.next() % 4096;
+                        let available = next_occupied(&live, block.pool, block.offset) - block.offset;
+
+                        let grown = tlsf.try_grow(&block.allocation, size(new_size));
+                        assert_eq!(grown, round(new_size) <= available, "Unexpected grow result at {}+{} to {} (Available: {})", block.offset, block.block_size, new_size, available);
+
+                        let block = &mut live[index];
+                        if grown {
+                            block.block_size = round(new_size);
+                        }
+                        assert_eq!(block.allocation.get_offset(), block.offset);
+                        assert_eq!(block.allocation.get_block_size(), block.block_size);
+                    },
+                    // Shrink
+                    18..=19 if !live.is_empty() => {
+                        let block = live.swap_remove(rng.next() % live.len());
+                        let new_size = 1 + rng.next() % block.block_size;
+                        tlsf.shrink(&block.allocation, size(new_size));
+
+                        assert_eq!(block.allocation.get_offset(), block.offset);
+                        assert_eq!(block.allocation.get_block_size(), round(new_size));
+                        live.push(LiveBlock::new(block.allocation));
+                    },
+                    _ => {},
+                }
+
+                assert_eq!(tlsf.iter_allocated_blocks().count(), live.len());
+            }
+
+            tlsf.validate().unwrap();
+            for block in live.drain(..) {
+                tlsf.free(block.allocation);
+            }
+            tlsf.validate().unwrap();
+            assert_pages_merged(&tlsf, &pages);
+        }
+    }
+
+    #[test]
+    fn free_all_merges_pages() {
+        const PAGE_SIZE: usize = 1 << 14;
+
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(PAGE_SIZE);
+        tlsf.set_debug_validation(true);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), PAGE_SIZE).unwrap();
+            tlsf.new_page(Box::new(1u32), PAGE_SIZE / 2).unwrap();
+            let pages: Vec<_> = tlsf.collect_physical_list_starts().iter()
+                .map(|start| (start.as_ref().pool, start.as_ref().get_size()))
+                .collect();
+
+            // Free in allocation order, in reverse and alternating to exercise all merge paths
+            for order in 0..3 {
+                let mut allocations = Vec::new();
+                while let Some(allocation) = tlsf.allocate(NonZeroUsize::new(96).unwrap()) {
+                    allocations.push(allocation);
+                }
+                assert!(allocations.len() >= (PAGE_SIZE + PAGE_SIZE / 2) / 128);
+
+                match order {
+                    0 => {},
+                    1 => allocations.reverse(),
+                    _ => {
+                        let (even, odd): (Vec<_>, Vec<_>) = allocations.into_iter().enumerate().partition(|(index, _)| index % 2 == 0);
+                        allocations = even.into_iter().chain(odd).map(|(_, allocation)| allocation).collect();
+                    }
+                }
+                for allocation in allocations {
+                    tlsf.free(allocation);
+                }
+
+                assert_pages_merged(&tlsf, &pages);
+            }
+        }
+    }
+
+    #[test]
+    fn tlsf_can_be_moved() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(1 << 16);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 1 << 16).unwrap();
+            let a = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+
+            // Moving the allocator must not invalidate the header free list
+            let mut moved = Box::new(tlsf);
+            let b = moved.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            assert_ne!(a.get_offset(), b.get_offset());
+            moved.free(a);
+            moved.free(b);
+        }
+    }
+
+    #[test]
+    fn pool_allocator() {
+        const PAGE_SIZE: usize = 1 << 16;
+
+        let allocator = PoolAllocator::new(PAGE_SIZE);
+        assert!(allocator.allocate(NonZeroUsize::new(1).unwrap()).is_none());
+        allocator.add_page(Box::new(7u32), PAGE_SIZE);
+
+        let a = allocator.allocate(NonZeroUsize::new(100).unwrap()).unwrap();
+        let b = allocator.allocate_aligned(NonZeroUsize::new(100).unwrap(), NonZeroUsize::new(256).unwrap()).unwrap();
+        assert_eq!(*a.get_pool(), 7);
+        assert_eq!(a.get_size(), 100);
+        assert_eq!(b.get_offset() % 256, 0);
+        assert!(a.get_offset() + a.get_size() <= b.get_offset() || b.get_offset() + b.get_size() <= a.get_offset());
+        assert_eq!(allocator.get_allocation_count(), 2);
+
+        let mut b = b;
+        assert!(b.try_grow(NonZeroUsize::new(200).unwrap()));
+        assert_eq!(b.get_size(), 200);
+        b.shrink(NonZeroUsize::new(50).unwrap());
+        assert_eq!(b.get_size(), 50);
+
+        b.set_debug_name("b");
+        let mut names = Vec::new();
+        allocator.for_each_allocated_block(|block| names.push(block.debug_name.map(String::from)));
+        names.sort();
+        assert_eq!(names, vec![None, Some(String::from("b"))]);
+
+        drop(a);
+        drop(b);
+        assert_eq!(allocator.get_allocation_count(), 0);
+
+        // All blocks must have been returned
+        let full = allocator.allocate(NonZeroUsize::new(PAGE_SIZE).unwrap()).unwrap();
+        assert_eq!(full.get_offset(), 0);
+    }
+
+    #[test]
+    fn reset() {
+        let size = |size| NonZeroUsize::new(size).unwrap();
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        tlsf.set_debug_validation(true);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+            tlsf.new_page(Box::new(1u32), 1024).unwrap();
+            let pages: Vec<_> = tlsf.page_pool.iter().map(|page| (page.as_ptr(), page.size)).collect();
+
+            for _ in 0..3 {
+                let mut allocations = Vec::new();
+                while let Some(allocation) = tlsf.allocate(size(96)) {
+                    allocations.push(allocation);
+                }
+                tlsf.set_debug_name(&allocations[0], Some(String::from("first")));
+                let header_pools = tlsf.header_pool.len();
+                let generation = tlsf.get_generation();
+
+                tlsf.reset();
+                assert_eq!(tlsf.get_generation(), generation + 1);
+                assert_eq!(tlsf.header_pool.len(), header_pools);
+                assert_eq!(tlsf.page_pool.len(), 2);
+                assert!(tlsf.tracked_allocations.is_empty());
+                assert!(tlsf.debug_names.is_empty());
+                assert_pages_merged(&tlsf, &pages);
+                if cfg!(debug_assertions) {
+                    assert!(!tlsf.verify_allocation(&allocations[0]));
+                }
+                std::mem::forget(allocations);
+            }
+
+            // All headers are back in the free list and get reused
+            let header_pools = tlsf.header_pool.len();
+            let a = tlsf.allocate(size(4096)).unwrap();
+            let b = tlsf.allocate(size(1024)).unwrap();
+            assert_eq!(tlsf.header_pool.len(), header_pools);
+            tlsf.free(a);
+            tlsf.free(b);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Allocation used after TLSF::reset")]
+    fn use_after_reset() {
+        let mut tlsf: TLSF<u32> = TLSF::new_for_max_size(4096);
+        unsafe {
+            tlsf.new_page(Box::new(0u32), 4096).unwrap();
+            let allocation = tlsf.allocate(NonZeroUsize::new(64).unwrap()).unwrap();
+            tlsf.reset();
+            tlsf.free(allocation);
+        }
+    }
+
+    #[test]
+    fn pool_allocator_reset() {
+        const PAGE_SIZE: usize = 1024;
+        let size = |size| NonZeroUsize::new(size).unwrap();
+
+        let allocator = PoolAllocator::new(PAGE_SIZE);
+        allocator.add_page(Box::new(0u32), PAGE_SIZE);
+
+        let a = allocator.allocate(size(512)).unwrap();
+        let mut b = allocator.allocate(size(256)).unwrap();
+        let movable = allocator.allocate_movable(size(64), size(64)).unwrap();
+        assert!(a.is_live());
+
+        allocator.reset();
+        assert_eq!(allocator.get_allocation_count(), 0);
+        assert!(!a.is_live() && !b.is_live() && !movable.lock().is_live());
+        assert!(allocator.begin_defrag(usize::MAX).is_empty());
+
+        let full = allocator.allocate(size(PAGE_SIZE)).unwrap();
+        assert_eq!(full.get_offset(), 0);
+        assert!(full.is_live());
+
+        // Invalidated allocations must not free the blocks they used to own
+        drop(a);
+        drop(movable);
+        assert_eq!(allocator.get_allocation_count(), 1);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| b.shrink(size(32))));
+        assert!(result.is_err());
+        drop(b);
+
+        drop(full);
+        assert_eq!(allocator.get_allocation_count(), 0);
+    }
+
+    #[test]
+    fn pool_allocator_defrag() {
+        const PAGE_SIZE: usize = 1024;
+        const BLOCK_SIZE: usize = 64;
+        let size = |size| NonZeroUsize::new(size).unwrap();
+
+        let allocator = PoolAllocator::new(PAGE_SIZE);
+        allocator.add_page(Box::new(1u32), PAGE_SIZE);
+        allocator.add_page(Box::new(2u32), PAGE_SIZE);
+
+        // Fill both pages and free every other block so no two free blocks are adjacent
+        let all: Vec<_> = (0..(2 * PAGE_SIZE / BLOCK_SIZE)).map(|_| {
+            allocator.allocate_movable(size(BLOCK_SIZE), size(1)).unwrap()
+        }).collect();
+        let live: Vec<_> = all.into_iter().step_by(2).collect();
+        assert_eq!(allocator.get_allocation_count(), 16);
+        assert!(allocator.allocate(size(PAGE_SIZE / 2)).is_none());
+
+        // Cancelled moves dont change anything
+        let moves = allocator.begin_defrag(3 * BLOCK_SIZE);
+        assert_eq!(moves.len(), 3);
+        allocator.cancel_defrag();
+        assert_eq!(allocator.get_allocation_count(), 16);
+
+        let moves = allocator.begin_defrag(usize::MAX);
+        assert_eq!(moves.len(), 8);
+        let evacuated = moves[0].src_pool as *const u32;
+        for pool_move in &moves {
+            assert!(std::ptr::eq(pool_move.src_pool, evacuated));
+            assert!(!std::ptr::eq(pool_move.dst_pool, evacuated));
+            assert_eq!(pool_move.size, BLOCK_SIZE);
+            assert_eq!(pool_move.dst_offset % BLOCK_SIZE, 0);
+        }
+        assert_eq!(allocator.get_allocation_count(), 24);
+
+        allocator.commit_moves(1);
+        for allocation in &live {
+            assert!(!std::ptr::eq(allocation.lock().get_pool(), evacuated));
+        }
+
+        // The old blocks stay allocated until the copies have completed
+        allocator.recycle(0);
+        assert_eq!(allocator.get_allocation_count(), 24);
+        assert!(allocator.allocate(size(PAGE_SIZE / 2)).is_none());
+
+        allocator.recycle(1);
+        assert_eq!(allocator.get_allocation_count(), 16);
+        let large = allocator.allocate(size(PAGE_SIZE)).unwrap();
+        assert!(std::ptr::eq(large.get_pool(), evacuated));
+
+        drop(large);
+        drop(live);
+        assert_eq!(allocator.get_allocation_count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn pool_allocator_invalid_page_size() {
+        let allocator: PoolAllocator<u32> = PoolAllocator::new(1 << 16);
+        allocator.add_page(Box::new(0), 100);
+    }
+}
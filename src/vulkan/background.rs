@@ -0,0 +1,271 @@
+//! Drawing of the [`BackgroundColorComponent`](crate::scene::BackgroundColorComponent) of a scene.
+//!
+//! The background is drawn as a full-screen triangle using the shaders in `shaders/`. The SPIR-V
+//! files contain the code of the GLSL sources next to them.
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::AgnajiVulkan;
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+use crate::vulkan::scene::Background;
+use crate::vulkan::shader::{CompiledSpirv, include_spirv, ShaderError, ShaderModule};
+
+/// The size of the push constants of the fragment shader. Two linear rgba colors.
+const PUSH_CONSTANTS_SIZE: u32 = 32;
+
+/// Returns the push constants of the fragment shader for `background`.
+fn get_push_constants(background: Background) -> [f32; 8] {
+    let (top, bottom) = match background {
+        Background::Color(color) => (color, color),
+        Background::Gradient { top, bottom } => (top, bottom),
+    };
+    [top.r, top.g, top.b, top.a, bottom.r, bottom.g, bottom.b, bottom.a]
+}
+
+/// A graphics pipeline drawing a background into a fixed set of color images.
+///
+/// The images must all have the same format, sample count and extent. They are drawn to in
+/// [`vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL`] and their previous content is discarded.
+pub struct BackgroundPipeline {
+    device: Arc<MainDeviceContext>,
+    extent: vk::Extent2D,
+    /// Owned by the [`crate::vulkan::pipeline_layout::PipelineLayoutCache`] of the instance.
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    /// One view and framebuffer per image.
+    framebuffers: Vec<(vk::ImageView, vk::Framebuffer)>,
+}
+
+impl BackgroundPipeline {
+    /// Creates a pipeline drawing into `images` which have been created with `format`, `samples`
+    /// and `extent` and support color attachment usage.
+    pub fn new(agnaji: &AgnajiVulkan, format: vk::Format, samples: vk::SampleCountFlags, extent: vk::Extent2D, images: &[vk::Image]) -> Result<Self, vk::Result> {
+        let device = agnaji.get_device().clone();
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: PUSH_CONSTANTS_SIZE,
+        };
+        let layout = agnaji.get_pipeline_layout_cache().get_or_create(&[], std::slice::from_ref(&push_constant_range));
+
+        // From here on drop cleans up everything created so far
+        let mut pipeline = Self {
+            device,
+            extent,
+            layout,
+            render_pass: vk::RenderPass::null(),
+            pipeline: vk::Pipeline::null(),
+            framebuffers: Vec::with_capacity(images.len()),
+        };
+        pipeline.render_pass = pipeline.create_render_pass(format, samples)?;
+        pipeline.pipeline = pipeline.create_pipeline(samples)?;
+        for image in images {
+            let framebuffer = pipeline.create_framebuffer(*image, format)?;
+            pipeline.framebuffers.push(framebuffer);
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Records a render pass drawing `background` into the image at `index` of the images the
+    /// pipeline has been created with.
+    pub fn record(&self, cmd: vk::CommandBuffer, index: usize, background: Background) {
+        let vk_device = self.device.get_device();
+
+        let begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[index].1)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent: self.extent,
+            });
+        let push_constants = get_push_constants(background);
+
+        unsafe {
+            vk_device.cmd_begin_render_pass(cmd, &begin_info, vk::SubpassContents::INLINE);
+            vk_device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            vk_device.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::FRAGMENT, 0, bytemuck::cast_slice(&push_constants));
+            vk_device.cmd_draw(cmd, 3, 1, 0, 0);
+            vk_device.cmd_end_render_pass(cmd);
+        }
+    }
+
+    fn create_render_pass(&self, format: vk::Format, samples: vk::SampleCountFlags) -> Result<vk::RenderPass, vk::Result> {
+        // The render graph performs all layout transitions and synchronization
+        let attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(samples)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let color_reference = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(std::slice::from_ref(&color_reference));
+
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(std::slice::from_ref(&attachment))
+            .subpasses(std::slice::from_ref(&subpass));
+
+        unsafe {
+            self.device.get_device().create_render_pass(&create_info, None)
+        }
+    }
+
+    fn create_pipeline(&self, samples: vk::SampleCountFlags) -> Result<vk::Pipeline, vk::Result> {
+        let vertex = create_shader_module(&self.device, &include_spirv!("shaders/background.vert.spv"))?;
+        let fragment = create_shader_module(&self.device, &include_spirv!("shaders/background.frag.spv"))?;
+
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex.get_handle())
+                .name(c"main")
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment.get_handle())
+                .name(c"main")
+                .build(),
+        ];
+
+        // The vertices are generated from the vertex index
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent: self.extent,
+        };
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(std::slice::from_ref(&viewport))
+            .scissors(std::slice::from_ref(&scissor));
+
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+        let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(samples);
+
+        let blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(std::slice::from_ref(&blend_attachment));
+
+        let create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .color_blend_state(&color_blend)
+            .layout(self.layout)
+            .render_pass(self.render_pass)
+            .subpass(0);
+
+        let pipelines = unsafe {
+            self.device.get_device().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&create_info), None)
+        }.map_err(|(_, err)| err)?;
+
+        Ok(pipelines[0])
+    }
+
+    fn create_framebuffer(&self, image: vk::Image, format: vk::Format) -> Result<(vk::ImageView, vk::Framebuffer), vk::Result> {
+        let vk_device = self.device.get_device();
+
+        let view_create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        let view = unsafe { vk_device.create_image_view(&view_create_info, None) }?;
+
+        let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass)
+            .attachments(std::slice::from_ref(&view))
+            .width(self.extent.width)
+            .height(self.extent.height)
+            .layers(1);
+        let framebuffer = unsafe { vk_device.create_framebuffer(&create_info, None) }
+            .inspect_err(|_| unsafe { vk_device.destroy_image_view(view, None) })?;
+
+        Ok((view, framebuffer))
+    }
+}
+
+impl Drop for BackgroundPipeline {
+    fn drop(&mut self) {
+        let vk_device = self.device.get_device();
+        unsafe {
+            for (view, framebuffer) in self.framebuffers.drain(..) {
+                vk_device.destroy_framebuffer(framebuffer, None);
+                vk_device.destroy_image_view(view, None);
+            }
+            // Destroying null handles is a no-op so a partially created pipeline can be dropped
+            vk_device.destroy_pipeline(self.pipeline, None);
+            vk_device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+fn create_shader_module(device: &Arc<MainDeviceContext>, spirv: &CompiledSpirv) -> Result<ShaderModule, vk::Result> {
+    ShaderModule::from_compiled(device.clone(), spirv).map_err(|err| match err {
+        ShaderError::Vulkan(result) => result,
+        // The embedded code has already been validated by include_spirv
+        err => panic!("Invalid background shader: {:?}", err),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scene::Color;
+    use crate::vulkan::reflection::SpvReflection;
+
+    use super::*;
+
+    #[test]
+    fn embedded_shaders() {
+        for spirv in [include_spirv!("shaders/background.vert.spv"), include_spirv!("shaders/background.frag.spv")] {
+            // The background uses push constants only
+            let reflection = SpvReflection::from_spirv(spirv.get_words()).unwrap();
+            assert!(reflection.get_bindings().is_empty());
+        }
+    }
+
+    #[test]
+    fn push_constants() {
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let blue = Color::new(0.0, 0.0, 1.0, 0.5);
+        assert_eq!(get_push_constants(Background::Color(red)), [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(get_push_constants(Background::Gradient { top: red, bottom: blue }), [1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.5]);
+        assert_eq!(std::mem::size_of::<[f32; 8]>(), PUSH_CONSTANTS_SIZE as usize);
+    }
+}
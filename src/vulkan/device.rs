@@ -2,11 +2,18 @@ use std::collections::HashSet;
 use std::ffi::{CStr, CString};
 use std::fmt::Formatter;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 use ash::vk;
 
+use crate::debug::{BreadcrumbKind, Breadcrumbs, DebugUtils, DeviceLostReport, GpuCheckpoint};
+use crate::utils::trace_span;
 use crate::vulkan::device::DeviceCreateError::Vulkan;
+use crate::vulkan::error::{VkError, VkResultExt};
 use crate::vulkan::instance::APIVersion;
+use crate::vulkan::memory::{DeviceAllocator, MemoryStatistics};
+use crate::vulkan::output::MsaaSamples;
 
 use crate::vulkan::InstanceContext;
 
@@ -44,28 +51,86 @@ impl DeviceQueue {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum DeviceCreateError {
     NotSupported,
-    Vulkan(vk::Result),
+    /// A queue priority is outside of the range `[0.0, 1.0]`.
+    InvalidQueuePriority,
+    Vulkan(VkError),
 }
 
-impl From<vk::Result> for DeviceCreateError {
-    fn from(err: vk::Result) -> Self {
+impl From<VkError> for DeviceCreateError {
+    fn from(err: VkError) -> Self {
         Vulkan(err)
     }
 }
 
+impl std::fmt::Display for DeviceCreateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceCreateError::NotSupported => write!(f, "Device is not supported"),
+            DeviceCreateError::InvalidQueuePriority => write!(f, "Queue priority outside of [0.0, 1.0]"),
+            Vulkan(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// The priorities of the device queues. Lower priorities prevent the queue from starving queues
+/// with higher priorities. All priorities must be in the range `[0.0, 1.0]`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DeviceQueuePriority {
+    pub main: f32,
+    pub compute: f32,
+    pub transfer: f32,
+}
+
+impl DeviceQueuePriority {
+    /// Returns true if all priorities are in the range `[0.0, 1.0]`.
+    pub fn is_valid(&self) -> bool {
+        [self.main, self.compute, self.transfer].iter().all(|priority| (0.0..=1.0).contains(priority))
+    }
+}
+
+impl Default for DeviceQueuePriority {
+    fn default() -> Self {
+        Self {
+            main: 1.0,
+            compute: 0.8,
+            transfer: 0.5,
+        }
+    }
+}
+
+/// Called with the report created by [`MainDeviceContext::report_device_lost`].
+pub type DeviceLostReportFn = dyn Fn(&DeviceLostReport) + Send + Sync;
+
+/// Allocations of at least this size are recorded as breadcrumbs.
+const LARGE_ALLOCATION_SIZE: u64 = 16 * 1024 * 1024;
+
 pub struct MainDeviceContext {
     instance: Arc<InstanceContext>,
     physical_device: vk::PhysicalDevice,
+    name: String,
+    api_version: APIVersion,
+    uuid: [u8; vk::UUID_SIZE],
     device: ash::Device,
     khr_buffer_device_address: ash::extensions::khr::BufferDeviceAddress,
     khr_synchronization_2: ash::extensions::khr::Synchronization2,
     khr_timeline_semaphore: ash::extensions::khr::TimelineSemaphore,
     khr_maintenance_4: Option<ash::extensions::khr::Maintenance4>,
     khr_swapchain: Option<ash::extensions::khr::Swapchain>,
+    nv_device_diagnostic_checkpoints: Option<ash::extensions::nv::DeviceDiagnosticCheckpoints>,
+    ext_full_screen_exclusive: Option<ash::extensions::ext::FullScreenExclusive>,
     enabled_extensions: HashSet<CString>,
+    shader_draw_parameters: bool,
+    pipeline_statistics_query: bool,
+    limits: vk::PhysicalDeviceLimits,
+    queue_families: Box<[vk::QueueFamilyProperties]>,
+    allocator: DeviceAllocator,
+    debug_utils: DebugUtils,
+    breadcrumbs: Breadcrumbs,
+    device_lost_reported: AtomicBool,
+    on_device_lost: Mutex<Option<Arc<DeviceLostReportFn>>>,
     main_queue: DeviceQueue,
     compute_queue: Option<DeviceQueue>,
     transfer_queue: Option<DeviceQueue>,
@@ -75,6 +140,226 @@ impl MainDeviceContext {
     pub fn get_main_queue(&self) -> &DeviceQueue {
         &self.main_queue
     }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_api_version(&self) -> APIVersion {
+        self.api_version
+    }
+
+    /// Returns the uuid of the physical device as reported by [`MainDeviceReport::get_uuid`].
+    pub fn get_uuid(&self) -> &[u8; vk::UUID_SIZE] {
+        &self.uuid
+    }
+
+    /// Returns the limits of the physical device. The limits are queried once when the device
+    /// report is generated.
+    pub fn get_limits(&self) -> &vk::PhysicalDeviceLimits {
+        &self.limits
+    }
+
+    pub fn max_uniform_buffer_range(&self) -> u64 {
+        self.limits.max_uniform_buffer_range as u64
+    }
+
+    pub fn min_uniform_buffer_offset_alignment(&self) -> u64 {
+        self.limits.min_uniform_buffer_offset_alignment
+    }
+
+    pub fn min_storage_buffer_offset_alignment(&self) -> u64 {
+        self.limits.min_storage_buffer_offset_alignment
+    }
+
+    pub fn max_push_constants_size(&self) -> u32 {
+        self.limits.max_push_constants_size
+    }
+
+    /// Returns the number of valid bits in timestamps written on queues of the specified family.
+    /// If timestamps are not supported by the queue family 0 is returned.
+    ///
+    /// # Panics
+    /// If `queue_family` is not a valid queue family index.
+    pub fn timestamp_valid_bits(&self, queue_family: u32) -> u32 {
+        self.queue_families[queue_family as usize].timestamp_valid_bits
+    }
+
+    /// Returns the statistics of all memory allocated through [`crate::vulkan::memory`].
+    pub fn get_memory_statistics(&self) -> &MemoryStatistics {
+        self.allocator.get_statistics()
+    }
+
+    pub fn get_allocator(&self) -> &DeviceAllocator {
+        &self.allocator
+    }
+
+    /// Queries the current heap budgets from `VK_EXT_memory_budget` and updates the soft budgets
+    /// of the allocator. The budgets may change at any time so this should be called regularly
+    /// (for example once per frame). Does nothing if the extension is not enabled.
+    pub fn refresh_memory_budget(&self) {
+        if !self.is_extension_enabled(vk::ExtMemoryBudgetFn::name()) {
+            return;
+        }
+
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = vk::PhysicalDeviceMemoryProperties2::builder()
+            .push_next(&mut budget);
+        unsafe {
+            self.instance.get_instance().get_physical_device_memory_properties2(self.physical_device, &mut properties);
+        }
+        let heap_count = properties.memory_properties.memory_heap_count as usize;
+
+        self.allocator.update_reported_budgets(&budget.heap_budget[0..heap_count]);
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_extensions.contains(name)
+    }
+
+    pub fn get_enabled_extensions(&self) -> impl Iterator<Item=&CString> {
+        self.enabled_extensions.iter()
+    }
+
+    pub fn get_debug_utils(&self) -> &DebugUtils {
+        &self.debug_utils
+    }
+
+    /// Returns true if objects can be named using [`MainDeviceContext::set_object_name`]. Can be
+    /// used to avoid formatting names which would be discarded anyway.
+    pub fn is_object_naming_enabled(&self) -> bool {
+        self.debug_utils.is_enabled()
+    }
+
+    /// Sets the name of a vulkan object of this device. See [`DebugUtils::set_object_name`].
+    pub fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        self.debug_utils.set_name(self.device.handle(), handle, name);
+    }
+
+    /// Returns the most recent major operations of this device. See
+    /// [`MainDeviceContext::report_device_lost`].
+    pub fn get_breadcrumbs(&self) -> &Breadcrumbs {
+        &self.breadcrumbs
+    }
+
+    /// Records a breadcrumb. Returns the id of the breadcrumb.
+    pub fn add_breadcrumb<S: Into<String>>(&self, kind: BreadcrumbKind, message: S) -> u64 {
+        self.breadcrumbs.record(kind, message)
+    }
+
+    /// Records a checkpoint into `cmd` which is reported by [`MainDeviceContext::report_device_lost`]
+    /// if it is the last checkpoint the gpu executed. Does nothing if
+    /// `VK_NV_device_diagnostic_checkpoints` is not enabled.
+    pub fn cmd_checkpoint(&self, cmd: vk::CommandBuffer, message: &str) {
+        if let Some(checkpoints) = &self.nv_device_diagnostic_checkpoints {
+            let id = self.breadcrumbs.record(BreadcrumbKind::Checkpoint, message);
+            // The marker is an opaque pointer sized value which is never dereferenced
+            unsafe {
+                checkpoints.cmd_set_checkpoint(cmd, id as usize as *const std::ffi::c_void);
+            }
+        }
+    }
+
+    /// Sets a function called by [`MainDeviceContext::report_device_lost`].
+    pub fn set_device_lost_report_handler<F>(&self, handler: F) where F: Fn(&DeviceLostReport) + Send + Sync + 'static {
+        *self.on_device_lost.lock().unwrap() = Some(Arc::new(handler));
+    }
+
+    /// Collects the breadcrumbs, memory usage and last gpu checkpoints of this device.
+    pub fn create_device_lost_report(&self) -> DeviceLostReport {
+        DeviceLostReport {
+            device_name: self.name.clone(),
+            api_version: self.api_version,
+            enabled_extensions: self.enabled_extensions.iter().cloned().collect(),
+            memory: self.get_memory_statistics().snapshot(),
+            breadcrumbs: self.breadcrumbs.snapshot(),
+            gpu_checkpoints: self.get_gpu_checkpoints(),
+            time: Instant::now(),
+        }
+    }
+
+    /// Must be called once the device has been lost. Logs a [`DeviceLostReport`] and passes it
+    /// to the handler set by [`MainDeviceContext::set_device_lost_report_handler`].
+    ///
+    /// Only the first call creates a report so this can be called by every user of the device
+    /// which observes the loss.
+    pub fn report_device_lost(&self) {
+        if self.device_lost_reported.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let report = self.create_device_lost_report();
+        log::error!("{}", report);
+
+        // Clone the handler so that it can call functions on the device without deadlocking
+        let handler = self.on_device_lost.lock().unwrap().clone();
+        if let Some(handler) = handler {
+            handler(&report);
+        }
+    }
+
+    fn get_gpu_checkpoints(&self) -> Vec<GpuCheckpoint> {
+        let checkpoints = match &self.nv_device_diagnostic_checkpoints {
+            Some(checkpoints) => checkpoints,
+            None => return Vec::new(),
+        };
+
+        let queue = self.main_queue.lock().unwrap();
+        let data = unsafe {
+            let mut data = vec![vk::CheckpointDataNV::default(); checkpoints.get_queue_checkpoint_data_len(*queue)];
+            checkpoints.get_queue_checkpoint_data(*queue, &mut data);
+            data
+        };
+        drop(queue);
+
+        data.into_iter().map(|data| {
+            let breadcrumb_id = data.p_checkpoint_marker as usize as u64;
+            GpuCheckpoint {
+                stage: data.stage,
+                breadcrumb_id,
+                message: self.breadcrumbs.get(breadcrumb_id).map(|breadcrumb| breadcrumb.message),
+            }
+        }).collect()
+    }
+
+    /// Allocates device memory through the [`DeviceAllocator`] recording a breadcrumb for large
+    /// allocations.
+    ///
+    /// # Safety
+    /// `allocate_info` must be a valid allocate info for this device.
+    pub(in crate::vulkan) unsafe fn allocate_memory(&self, allocate_info: &vk::MemoryAllocateInfo) -> Result<vk::DeviceMemory, vk::Result> {
+        if allocate_info.allocation_size >= LARGE_ALLOCATION_SIZE {
+            self.breadcrumbs.record(BreadcrumbKind::Allocation, format!("Allocating {} bytes of memory type {}", allocate_info.allocation_size, allocate_info.memory_type_index));
+        }
+        self.allocator.allocate_memory(&self.device, allocate_info)
+    }
+
+    /// Returns true if the `shader_draw_parameters` feature of `VK_KHR_shader_draw_parameters`
+    /// is enabled. Shaders may only use `gl_DrawID`, `gl_BaseVertex` and `gl_BaseInstance` if
+    /// this is true.
+    pub fn supports_shader_draw_parameters(&self) -> bool {
+        self.shader_draw_parameters
+    }
+
+    /// Returns true if the `pipeline_statistics_query` feature is enabled. Pipeline statistics
+    /// query pools may only be created if this is true.
+    pub fn supports_pipeline_statistics_query(&self) -> bool {
+        self.pipeline_statistics_query
+    }
+
+    pub fn get_khr_buffer_device_address(&self) -> &ash::extensions::khr::BufferDeviceAddress {
+        &self.khr_buffer_device_address
+    }
+
+    pub fn get_khr_synchronization_2(&self) -> &ash::extensions::khr::Synchronization2 {
+        &self.khr_synchronization_2
+    }
+
+    /// Returns the `VK_EXT_full_screen_exclusive` functions or [`None`] if the extension is not
+    /// enabled.
+    pub fn get_ext_full_screen_exclusive(&self) -> Option<&ash::extensions::ext::FullScreenExclusive> {
+        self.ext_full_screen_exclusive.as_ref()
+    }
 }
 
 impl DeviceProvider for MainDeviceContext {
@@ -97,19 +382,55 @@ impl SwapchainProvider for MainDeviceContext {
     }
 }
 
+/// Summary of the memory heaps and types of a physical device. See
+/// [`MainDeviceReport::get_memory_info`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemoryInfo {
+    pub largest_device_local_heap_mb: u64,
+    pub has_host_visible_coherent: bool,
+}
+
+impl MemoryInfo {
+    pub fn from_memory_properties(memory_properties: &vk::PhysicalDeviceMemoryProperties) -> Self {
+        let heaps = &memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize];
+        let types = &memory_properties.memory_types[..memory_properties.memory_type_count as usize];
+
+        let largest_device_local_heap = heaps.iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .max()
+            .unwrap_or(0);
+        let has_host_visible_coherent = types.iter()
+            .any(|memory_type| memory_type.property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT));
+
+        Self {
+            largest_device_local_heap_mb: largest_device_local_heap / (1024 * 1024),
+            has_host_visible_coherent,
+        }
+    }
+}
+
+/// Devices whose largest device local heap is smaller than this get a warning in their report.
+const MIN_DEVICE_LOCAL_HEAP_MB: u64 = 256;
+
 pub struct MainDeviceReport {
     name: String,
     api_version: APIVersion,
     uuid: [u8; vk::UUID_SIZE],
     physical_device: vk::PhysicalDevice,
+    device_type: vk::PhysicalDeviceType,
+    limits: vk::PhysicalDeviceLimits,
+    queue_families: Box<[vk::QueueFamilyProperties]>,
+    memory_info: MemoryInfo,
     config: Option<MainDeviceConfig>,
     warnings: Box<[String]>,
     errors: Box<[String]>,
 }
 
 impl MainDeviceReport {
-    pub fn generate_for(instance: &InstanceContext, physical_device: vk::PhysicalDevice, surface_support: &[bool]) -> Result<Self, vk::Result> {
+    pub fn generate_for(instance: &InstanceContext, physical_device: vk::PhysicalDevice, surface_support: &[bool]) -> Result<Self, VkError> {
         let khr_surface = instance.get_khr_surface();
+        let khr_get_surface_capabilities_2 = instance.is_extension_enabled(vk::KhrGetSurfaceCapabilities2Fn::name());
         let instance = instance.get_instance();
 
         let mut warnings = Vec::new();
@@ -121,6 +442,10 @@ impl MainDeviceReport {
 
         let name = String::from(unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_str().unwrap());
 
+        let memory_properties = unsafe {
+            instance.get_physical_device_memory_properties(physical_device)
+        };
+
         let api_version = APIVersion::from_raw(properties.api_version);
         if api_version.get_variant() != 0 {
             errors.push(String::from("Device API variant is not 0"));
@@ -139,6 +464,10 @@ impl MainDeviceReport {
                 api_version,
                 uuid: properties.pipeline_cache_uuid,
                 physical_device,
+                device_type: properties.device_type,
+                limits: properties.limits,
+                queue_families: Box::new([]),
+                memory_info: MemoryInfo::from_memory_properties(&memory_properties),
                 config: None,
                 warnings: warnings.into_boxed_slice(),
                 errors: errors.into_boxed_slice(),
@@ -147,9 +476,8 @@ impl MainDeviceReport {
 
         let supported_extensions: HashSet<_> = unsafe {
             instance.enumerate_device_extension_properties(physical_device)
-        }.map_err(|err| {
-            log::error!("Failed to enumerate device ({}) extension properties: {:?}", name, err);
-            err
+        }.with_details("enumerate device extension properties", || format!("Device: {}", name)).inspect_err(|err| {
+            log::error!("{}", err);
         })?.into_iter().map(|ext| CString::from(unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } )).collect();
 
         let mut vk_11_features = vk::PhysicalDeviceVulkan11Features::builder();
@@ -167,6 +495,9 @@ impl MainDeviceReport {
         let mut khr_maintenance_4_features_properties = supported_extensions.get(ash::extensions::khr::Maintenance4::name()).map(|_| {
             (vk::PhysicalDeviceMaintenance4FeaturesKHR::builder(), vk::PhysicalDeviceMaintenance4PropertiesKHR::builder())
         });
+        let mut khr_shader_draw_parameters_features = supported_extensions.get(vk::KhrShaderDrawParametersFn::name()).map(|_| {
+            vk::PhysicalDeviceShaderDrawParametersFeatures::builder()
+        });
         let mut khr_portability_subset_features_properties = supported_extensions.get(CStr::from_bytes_with_nul(b"VK_KHR_portability_subset\0").unwrap()).map(|_| {
             (vk::PhysicalDevicePortabilitySubsetFeaturesKHR::builder(), vk::PhysicalDevicePortabilitySubsetPropertiesKHR::builder())
         });
@@ -190,6 +521,9 @@ impl MainDeviceReport {
             features2 = features2.push_next(f);
             properties2 = properties2.push_next(p);
         }
+        if let Some(f) = &mut khr_shader_draw_parameters_features {
+            features2 = features2.push_next(f);
+        }
         if let Some((f, p)) = &mut khr_portability_subset_features_properties {
             features2 = features2.push_next(f);
             properties2 = properties2.push_next(p);
@@ -211,7 +545,9 @@ impl MainDeviceReport {
         let khr_synchronization_2 = Self::process_khr_synchronization_2(&mut warnings, &mut errors, khr_synchronization_2_features.as_ref());
         let khr_timeline_semaphore = Self::process_khr_timeline_semaphore(&mut warnings, &mut errors, khr_timeline_semaphore_features_properties.as_ref());
         let khr_maintenance_4 = Self::process_khr_maintenance_4(&mut warnings, &mut errors, khr_maintenance_4_features_properties.as_ref());
+        let khr_shader_draw_parameters = Self::process_khr_shader_draw_parameters(&mut warnings, &mut errors, khr_shader_draw_parameters_features.as_ref());
         let khr_portability_subset = Self::process_khr_portability_subset(&mut warnings, &mut errors, khr_portability_subset_features_properties.as_ref());
+        let memory_info = Self::process_memory(&mut warnings, &mut errors, &memory_properties);
 
         let queue_properties = unsafe {
             instance.get_physical_device_queue_family_properties(physical_device)
@@ -279,12 +615,26 @@ impl MainDeviceReport {
         if khr_maintenance_4.is_some() {
             enabled_extensions.insert(CString::from(ash::extensions::khr::Maintenance4::name()));
         }
+        if khr_shader_draw_parameters.is_some() {
+            enabled_extensions.insert(CString::from(vk::KhrShaderDrawParametersFn::name()));
+        }
         if khr_portability_subset.is_some() {
             enabled_extensions.insert(CString::from(CStr::from_bytes_with_nul(b"VK_KHR_portability_subset\0").unwrap()));
         }
+        if supported_extensions.contains(vk::ExtMemoryBudgetFn::name()) {
+            enabled_extensions.insert(CString::from(vk::ExtMemoryBudgetFn::name()));
+        }
+        if supported_extensions.contains(ash::extensions::nv::DeviceDiagnosticCheckpoints::name()) {
+            enabled_extensions.insert(CString::from(ash::extensions::nv::DeviceDiagnosticCheckpoints::name()));
+        }
         if supported_extensions.contains(ash::extensions::khr::Swapchain::name()) && khr_surface.is_some() {
             enabled_extensions.insert(CString::from(ash::extensions::khr::Swapchain::name()));
         }
+        if supported_extensions.contains(ash::extensions::ext::FullScreenExclusive::name())
+            && enabled_extensions.contains(ash::extensions::khr::Swapchain::name())
+            && khr_get_surface_capabilities_2 {
+            enabled_extensions.insert(CString::from(ash::extensions::ext::FullScreenExclusive::name()));
+        }
 
         let config = if errors.is_empty() {
             let features = MainDeviceFeatures {
@@ -294,6 +644,7 @@ impl MainDeviceReport {
                 khr_synchronization_2: khr_synchronization_2.unwrap(),
                 khr_timeline_semaphore: khr_timeline_semaphore.unwrap(),
                 khr_maintenance_4,
+                khr_shader_draw_parameters,
                 khr_portability_subset,
             };
 
@@ -313,27 +664,51 @@ impl MainDeviceReport {
             api_version,
             uuid: properties.pipeline_cache_uuid,
             physical_device,
+            device_type: properties.device_type,
+            limits: properties.limits,
+            queue_families: queue_properties.into_boxed_slice(),
+            memory_info,
             config,
             warnings: warnings.into_boxed_slice(),
             errors: errors.into_boxed_slice(),
         })
     }
 
+    /// Creates the device with a priority of 1.0 for all queues.
+    #[must_use = "the device is only usable if creation succeeded"]
     pub fn create_device(&self, instance: Arc<InstanceContext>) -> Result<MainDeviceContext, DeviceCreateError> {
+        self.create_device_with_priorities(instance, DeviceQueuePriority {
+            main: 1.0,
+            compute: 1.0,
+            transfer: 1.0,
+        })
+    }
+
+    #[must_use = "the device is only usable if creation succeeded"]
+    pub fn create_device_with_priorities(&self, instance: Arc<InstanceContext>, priorities: DeviceQueuePriority) -> Result<MainDeviceContext, DeviceCreateError> {
+        trace_span!("create_device", device = %self.name);
+        if !priorities.is_valid() {
+            return Err(DeviceCreateError::InvalidQueuePriority);
+        }
+
         if let Some(config) = &self.config {
-            let priorities = [1f32];
+            // The create infos reference these so they must outlive device creation
+            let main_priorities = [priorities.main];
+            let compute_priorities = [priorities.compute];
+            let transfer_priorities = [priorities.transfer];
+
             let mut queue_create_infos = Vec::with_capacity(3);
             queue_create_infos.push({
                 vk::DeviceQueueCreateInfo::builder()
                     .queue_family_index(config.main_queue)
-                    .queue_priorities(&priorities)
+                    .queue_priorities(&main_priorities)
                     .build()
             });
             if let Some((index, _)) = &config.compute_queue {
                 queue_create_infos.push({
                     vk::DeviceQueueCreateInfo::builder()
                         .queue_family_index(*index)
-                        .queue_priorities(&priorities)
+                        .queue_priorities(&compute_priorities)
                         .build()
                 })
             }
@@ -341,7 +716,7 @@ impl MainDeviceReport {
                 queue_create_infos.push({
                     vk::DeviceQueueCreateInfo::builder()
                         .queue_family_index(*index)
-                        .queue_priorities(&priorities)
+                        .queue_priorities(&transfer_priorities)
                         .build()
                 })
             }
@@ -355,6 +730,11 @@ impl MainDeviceReport {
 
             let mut vk_11_features = config.features.vk_11.clone();
             vk_11_features.p_next = std::ptr::null_mut();
+            // The standalone feature struct must not be chained together with the vulkan 1.1
+            // features so the feature is enabled through them instead
+            if let Some(f) = &config.features.khr_shader_draw_parameters {
+                vk_11_features.shader_draw_parameters = f.shader_draw_parameters;
+            }
             create_info = create_info.push_next(&mut vk_11_features);
 
             let mut khr_buffer_device_address_features = config.features.khr_buffer_device_address.clone();
@@ -383,9 +763,8 @@ impl MainDeviceReport {
 
             let device = unsafe {
                 instance.get_instance().create_device(self.physical_device, &create_info, None)
-            }.map_err(|err| {
-                log::info!("Failed to create physical device: {:?}", err);
-                err
+            }.with_details("create device", || format!("Device: {}", self.name)).inspect_err(|err| {
+                log::info!("{}", err);
             })?;
 
             let main_queue = DeviceQueue::new(unsafe { device.get_device_queue(config.main_queue, 0) }, config.main_queue);
@@ -405,21 +784,57 @@ impl MainDeviceReport {
             let khr_swapchain = config.extensions.get(ash::extensions::khr::Swapchain::name()).map(|_| {
                 ash::extensions::khr::Swapchain::new(instance.get_instance(), &device)
             });
+            let nv_device_diagnostic_checkpoints = config.extensions.get(ash::extensions::nv::DeviceDiagnosticCheckpoints::name()).map(|_| {
+                ash::extensions::nv::DeviceDiagnosticCheckpoints::new(instance.get_instance(), &device)
+            });
+            let ext_full_screen_exclusive = config.extensions.get(ash::extensions::ext::FullScreenExclusive::name()).map(|_| {
+                ash::extensions::ext::FullScreenExclusive::new(instance.get_instance(), &device)
+            });
+
+            let debug_utils = DebugUtils::new(&instance);
+            debug_utils.set_queue_name(device.handle(), *main_queue.lock().unwrap(), "agnaji main queue");
+            if let Some(queue) = &compute_queue {
+                debug_utils.set_queue_name(device.handle(), *queue.lock().unwrap(), "agnaji compute queue");
+            }
+            if let Some(queue) = &transfer_queue {
+                debug_utils.set_queue_name(device.handle(), *queue.lock().unwrap(), "agnaji transfer queue");
+            }
+
+            let memory_properties = unsafe {
+                instance.get_instance().get_physical_device_memory_properties(self.physical_device)
+            };
 
-            Ok(MainDeviceContext {
+            let context = MainDeviceContext {
                 instance,
                 physical_device: self.physical_device,
+                name: self.name.clone(),
+                api_version: self.api_version,
+                uuid: self.uuid,
                 device,
                 khr_buffer_device_address,
                 khr_synchronization_2,
                 khr_timeline_semaphore,
                 khr_maintenance_4,
                 khr_swapchain,
+                nv_device_diagnostic_checkpoints,
+                ext_full_screen_exclusive,
                 enabled_extensions: config.extensions.clone(),
+                shader_draw_parameters: config.features.khr_shader_draw_parameters.is_some(),
+                pipeline_statistics_query: config.features.vk_10.pipeline_statistics_query == vk::TRUE,
+                limits: self.limits,
+                queue_families: self.queue_families.clone(),
+                allocator: DeviceAllocator::new(&memory_properties),
+                debug_utils,
+                breadcrumbs: Breadcrumbs::new(),
+                device_lost_reported: AtomicBool::new(false),
+                on_device_lost: Mutex::new(None),
                 main_queue,
                 compute_queue,
                 transfer_queue,
-            })
+            };
+            context.refresh_memory_budget();
+
+            Ok(context)
         } else {
             Err(DeviceCreateError::NotSupported)
         }
@@ -433,10 +848,42 @@ impl MainDeviceReport {
         &self.uuid
     }
 
+    pub fn get_device_type(&self) -> vk::PhysicalDeviceType {
+        self.device_type
+    }
+
+    #[must_use]
     pub fn is_suitable(&self) -> bool {
         self.config.is_some()
     }
 
+    /// Returns a score used to pick the best device if multiple are suitable. Higher is better.
+    /// Returns [`None`] if the device is not suitable.
+    pub fn get_score(&self) -> Option<u32> {
+        if !self.is_suitable() {
+            return None;
+        }
+
+        let score = match self.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 1,
+            _ => 0,
+        };
+        Some(score)
+    }
+
+    /// Returns all msaa sample counts supported by the device for color attachments.
+    pub fn get_supported_msaa_samples(&self) -> Vec<MsaaSamples> {
+        MsaaSamples::supported_modes(self.limits.framebuffer_color_sample_counts)
+    }
+
+    /// Returns a summary of the memory heaps and types of the device.
+    pub fn get_memory_info(&self) -> MemoryInfo {
+        self.memory_info
+    }
+
     pub fn get_warnings(&self) -> Option<&[String]> {
         if !self.warnings.is_empty() {
             Some(&self.warnings)
@@ -453,6 +900,19 @@ impl MainDeviceReport {
         }
     }
 
+    fn process_memory(warnings: &mut Vec<String>, _errors: &mut Vec<String>, memory_properties: &vk::PhysicalDeviceMemoryProperties) -> MemoryInfo {
+        let memory_info = MemoryInfo::from_memory_properties(memory_properties);
+
+        if memory_info.largest_device_local_heap_mb < MIN_DEVICE_LOCAL_HEAP_MB {
+            warnings.push(format!("Device local heap is only {}MB", memory_info.largest_device_local_heap_mb));
+        }
+        if !memory_info.has_host_visible_coherent {
+            warnings.push(String::from("No host-visible coherent memory type available"));
+        }
+
+        memory_info
+    }
+
     fn process_vk_10(warnings: &mut Vec<String>, errors: &mut Vec<String>, features: &vk::PhysicalDeviceFeatures, _properties: &vk::PhysicalDeviceProperties) -> vk::PhysicalDeviceFeatures {
         let mut enabled = vk::PhysicalDeviceFeatures::builder();
 
@@ -474,6 +934,12 @@ impl MainDeviceReport {
             warnings.push(String::from("Feature `sampler_anisotropy` is not supported"));
         }
 
+        if features.pipeline_statistics_query == vk::TRUE {
+            enabled.pipeline_statistics_query = vk::TRUE;
+        } else {
+            warnings.push(String::from("Feature `pipeline_statistics_query` is not supported"));
+        }
+
         if features.fragment_stores_and_atomics == vk::TRUE {
             enabled.fragment_stores_and_atomics = vk::TRUE;
         } else {
@@ -604,6 +1070,29 @@ impl MainDeviceReport {
         }
     }
 
+    fn process_khr_shader_draw_parameters(warnings: &mut Vec<String>, _errors: &mut Vec<String>, ext: Option<&vk::PhysicalDeviceShaderDrawParametersFeaturesBuilder>) -> Option<vk::PhysicalDeviceShaderDrawParametersFeatures> {
+        if let Some(f) = ext {
+            let mut ok = true;
+            let mut enabled = vk::PhysicalDeviceShaderDrawParametersFeatures::builder();
+
+            if f.shader_draw_parameters == vk::TRUE {
+                enabled.shader_draw_parameters = vk::TRUE;
+            } else {
+                warnings.push(String::from("Feature `shader_draw_parameters` is not supported"));
+                ok = false;
+            }
+
+            if ok {
+                Some(enabled.build())
+            } else {
+                None
+            }
+        } else {
+            warnings.push(String::from("Extension `VK_KHR_shader_draw_parameters` is not supported"));
+            None
+        }
+    }
+
     fn process_khr_portability_subset(_warnings: &mut Vec<String>, errors: &mut Vec<String>, ext: Option<&(vk::PhysicalDevicePortabilitySubsetFeaturesKHRBuilder, vk::PhysicalDevicePortabilitySubsetPropertiesKHRBuilder)>) -> Option<vk::PhysicalDevicePortabilitySubsetFeaturesKHR> {
         if let Some((f, _p)) = ext {
             let mut ok = true;
@@ -634,12 +1123,23 @@ impl MainDeviceReport {
     }
 }
 
+impl std::fmt::Debug for MainDeviceContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MainDeviceContext")
+            .field("device_name", &self.name)
+            .field("api_version", &self.api_version)
+            .finish_non_exhaustive()
+    }
+}
+
 impl std::fmt::Debug for MainDeviceReport {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MainDeviceReport")
             .field("device_name", &self.name)
             .field("api_version", &self.api_version)
+            .field("device_type", &self.device_type)
             .field("suitable", &self.is_suitable())
+            .field("memory_info", &self.memory_info)
             .field("warnings", &self.warnings.as_ref())
             .field("errors", &self.errors.as_ref())
             .finish()
@@ -661,5 +1161,70 @@ struct MainDeviceFeatures {
     khr_synchronization_2: vk::PhysicalDeviceSynchronization2FeaturesKHR,
     khr_timeline_semaphore: vk::PhysicalDeviceTimelineSemaphoreFeaturesKHR,
     khr_maintenance_4: Option<vk::PhysicalDeviceMaintenance4FeaturesKHR>,
+    khr_shader_draw_parameters: Option<vk::PhysicalDeviceShaderDrawParametersFeatures>,
     khr_portability_subset: Option<vk::PhysicalDevicePortabilitySubsetFeaturesKHR>,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_priority_range() {
+        assert!(DeviceQueuePriority::default().is_valid());
+        assert!(DeviceQueuePriority { main: 0.0, compute: 1.0, transfer: 0.5 }.is_valid());
+        assert!(!DeviceQueuePriority { main: 1.5, ..Default::default() }.is_valid());
+        assert!(!DeviceQueuePriority { transfer: -0.1, ..Default::default() }.is_valid());
+        assert!(!DeviceQueuePriority { compute: f32::NAN, ..Default::default() }.is_valid());
+    }
+
+    #[test]
+    fn shader_draw_parameters_optional() {
+        let mut warnings = Vec::new();
+        let mut errors = Vec::new();
+
+        assert!(MainDeviceReport::process_khr_shader_draw_parameters(&mut warnings, &mut errors, None).is_none());
+        assert_eq!(warnings.len(), 1);
+
+        let unsupported = vk::PhysicalDeviceShaderDrawParametersFeatures::builder();
+        assert!(MainDeviceReport::process_khr_shader_draw_parameters(&mut warnings, &mut errors, Some(&unsupported)).is_none());
+        assert_eq!(warnings.len(), 2);
+
+        let supported = vk::PhysicalDeviceShaderDrawParametersFeatures::builder()
+            .shader_draw_parameters(true);
+        let enabled = MainDeviceReport::process_khr_shader_draw_parameters(&mut warnings, &mut errors, Some(&supported)).unwrap();
+        assert_eq!(enabled.shader_draw_parameters, vk::TRUE);
+        assert_eq!(warnings.len(), 2);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn memory_warnings() {
+        const MB: u64 = 1024 * 1024;
+
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 2,
+            memory_heap_count: 2,
+            ..Default::default()
+        };
+        properties.memory_heaps[0] = vk::MemoryHeap { size: 128 * MB, flags: vk::MemoryHeapFlags::DEVICE_LOCAL };
+        properties.memory_heaps[1] = vk::MemoryHeap { size: 4096 * MB, flags: vk::MemoryHeapFlags::empty() };
+        properties.memory_types[0] = vk::MemoryType { property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL, heap_index: 0 };
+        properties.memory_types[1] = vk::MemoryType { property_flags: vk::MemoryPropertyFlags::HOST_VISIBLE, heap_index: 1 };
+        // Heaps and types past the counts are ignored
+        properties.memory_heaps[2] = vk::MemoryHeap { size: 8192 * MB, flags: vk::MemoryHeapFlags::DEVICE_LOCAL };
+
+        let mut warnings = Vec::new();
+        let mut errors = Vec::new();
+        let info = MainDeviceReport::process_memory(&mut warnings, &mut errors, &properties);
+        assert_eq!(info, MemoryInfo { largest_device_local_heap_mb: 128, has_host_visible_coherent: false });
+        assert_eq!(warnings, vec![String::from("Device local heap is only 128MB"), String::from("No host-visible coherent memory type available")]);
+
+        properties.memory_heap_count = 3;
+        properties.memory_types[1].property_flags |= vk::MemoryPropertyFlags::HOST_COHERENT;
+        warnings.clear();
+        let info = MainDeviceReport::process_memory(&mut warnings, &mut errors, &properties);
+        assert_eq!(info, MemoryInfo { largest_device_local_heap_mb: 8192, has_host_visible_coherent: true });
+        assert!(warnings.is_empty());
+        assert!(errors.is_empty());
+    }
+}
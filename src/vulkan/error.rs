@@ -0,0 +1,132 @@
+//! Vulkan errors annotated with the operation that failed.
+
+use std::backtrace::Backtrace;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+use ash::vk;
+
+/// A [`vk::Result`] error annotated with the operation that failed and optional details like the
+/// arguments of the call or the output it happened on.
+///
+/// In debug builds a backtrace is captured when the error is created and included in the
+/// [`Display`] output.
+#[derive(Clone)]
+pub struct VkError {
+    pub result: vk::Result,
+    /// The operation that failed, for example `"create swapchain"`.
+    pub context: &'static str,
+    /// Additional information about the failed operation. May be empty.
+    pub details: String,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl VkError {
+    pub fn new<S: Into<String>>(result: vk::Result, context: &'static str, details: S) -> Self {
+        let backtrace = if cfg!(debug_assertions) {
+            Some(Arc::new(Backtrace::force_capture()))
+        } else {
+            None
+        };
+
+        Self {
+            result,
+            context,
+            details: details.into(),
+            backtrace,
+        }
+    }
+
+    /// Returns the backtrace captured when the error was created. Always [`None`] in release
+    /// builds.
+    pub fn get_backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl PartialEq for VkError {
+    fn eq(&self, other: &Self) -> bool {
+        self.result == other.result && self.context == other.context && self.details == other.details
+    }
+}
+
+impl Eq for VkError {
+}
+
+impl Display for VkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed with {:?}", self.context, self.result)?;
+        if !self.details.is_empty() {
+            write!(f, " ({})", self.details)?;
+        }
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, "\n{}", backtrace)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for VkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VkError")
+            .field("result", &self.result)
+            .field("context", &self.context)
+            .field("details", &self.details)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::error::Error for VkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.result)
+    }
+}
+
+impl From<VkError> for vk::Result {
+    fn from(error: VkError) -> Self {
+        error.result
+    }
+}
+
+/// Attaches context to the error of a vulkan call.
+pub trait VkResultExt<T> {
+    /// Converts the error into a [`VkError`] with `context` describing the failed operation.
+    fn context(self, context: &'static str) -> Result<T, VkError>;
+
+    /// Like [`VkResultExt::context`] but also attaches details. `details` is only called if the
+    /// result is an error.
+    fn with_details<F, S>(self, context: &'static str, details: F) -> Result<T, VkError> where F: FnOnce() -> S, S: Into<String>;
+}
+
+impl<T> VkResultExt<T> for Result<T, vk::Result> {
+    fn context(self, context: &'static str) -> Result<T, VkError> {
+        self.map_err(|result| VkError::new(result, context, String::new()))
+    }
+
+    fn with_details<F, S>(self, context: &'static str, details: F) -> Result<T, VkError> where F: FnOnce() -> S, S: Into<String> {
+        self.map_err(|result| VkError::new(result, context, details()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context() {
+        let result: Result<(), vk::Result> = Err(vk::Result::ERROR_OUT_OF_DATE_KHR);
+        let error = result.with_details("create swapchain", || "Output: main").unwrap_err();
+        assert_eq!(error, VkError::new(vk::Result::ERROR_OUT_OF_DATE_KHR, "create swapchain", "Output: main"));
+        assert_eq!(error.get_backtrace().is_some(), cfg!(debug_assertions));
+
+        let formatted = error.to_string();
+        assert!(formatted.starts_with("create swapchain failed with ERROR_OUT_OF_DATE_KHR (Output: main)"));
+
+        let error = Err::<(), _>(vk::Result::ERROR_DEVICE_LOST).context("submit frame").unwrap_err();
+        assert!(error.to_string().starts_with("submit frame failed with ERROR_DEVICE_LOST"));
+        assert_eq!(vk::Result::from(error), vk::Result::ERROR_DEVICE_LOST);
+
+        assert_eq!(Ok::<u32, vk::Result>(1).context("unused"), Ok(1));
+    }
+}
@@ -0,0 +1,240 @@
+//! Typed handles into a [`HandleArena`].
+//!
+//! Handles are small `Copy` values that can be passed around freely without the atomic reference
+//! counting overhead of a [`std::sync::Arc`]. Every slot of the arena carries a generation
+//! counter which is incremented when the slot is freed, so a stale handle will never alias a
+//! newer value stored in the same slot.
+
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/// A typed index into a [`HandleArena<T>`].
+///
+/// A handle is only meaningful for the arena that created it. Using it with any other arena may
+/// return unrelated values.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    // fn() -> T keeps the handle Send + Sync independent of T
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Self {
+            index,
+            generation,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the handle packed into a u64. The generation is stored in the upper 32 bits and
+    /// the index in the lower 32 bits.
+    pub fn to_raw(&self) -> u64 {
+        ((self.generation as u64) << 32) | (self.index as u64)
+    }
+
+    /// Creates a handle from a value previously returned by [`Handle::to_raw`].
+    pub fn from_raw(raw: u64) -> Self {
+        Self::new(raw as u32, (raw >> 32) as u32)
+    }
+
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn get_generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+// Manual impls since derive would add bounds on T
+
+impl<T> Copy for Handle<T> {
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {
+}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_raw().hash(state)
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle")
+            .field("index", &self.index)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+/// Stores values addressed by [`Handle`]s.
+///
+/// Freed slots are reused by later insertions. Insertion, lookup and removal are all `O(1)`.
+pub struct HandleArena<T> {
+    slots: Vec<Slot<T>>,
+    free_slots: Vec<u32>,
+    len: usize,
+}
+
+impl<T> HandleArena<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            free_slots: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Inserts a new value and returns its handle.
+    ///
+    /// # Panics
+    /// If the arena would contain more than `u32::MAX` slots.
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        self.len += 1;
+
+        if let Some(index) = self.free_slots.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            Handle::new(index, slot.generation)
+        } else {
+            let index = u32::try_from(self.slots.len()).expect("HandleArena slot count exceeds u32::MAX");
+            self.slots.push(Slot {
+                generation: 0,
+                value: Some(value),
+            });
+            Handle::new(index, 0)
+        }
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots.get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots.get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Removes the value associated with the handle and returns it. Returns [`None`] if the
+    /// handle is stale or was never valid.
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+
+        let value = slot.value.take()?;
+        self.len -= 1;
+
+        // A slot which exhausted its generations is retired to guarantee handles are never reused
+        if let Some(generation) = slot.generation.checked_add(1) {
+            slot.generation = generation;
+            self.free_slots.push(handle.index);
+        }
+
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over all values and their handles.
+    pub fn iter(&self) -> impl Iterator<Item=(Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|value| (Handle::new(index as u32, slot.generation), value))
+        })
+    }
+}
+
+impl<T> Default for HandleArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut arena = HandleArena::new();
+        let a = arena.insert(1u32);
+        let b = arena.insert(2u32);
+        assert_eq!(arena.len(), 2);
+
+        assert_eq!(arena.get(a), Some(&1));
+        *arena.get_mut(b).unwrap() = 3;
+        assert_eq!(arena.get(b), Some(&3));
+
+        assert_eq!(arena.remove(a), Some(1));
+        assert_eq!(arena.remove(a), None);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena.len(), 1);
+
+        assert_eq!(arena.iter().collect::<Vec<_>>(), vec![(b, &3)]);
+    }
+
+    #[test]
+    fn stale_handle_after_reuse() {
+        let mut arena = HandleArena::new();
+        let old = arena.insert("old");
+        arena.remove(old);
+
+        let new = arena.insert("new");
+        assert_eq!(new.get_index(), old.get_index());
+        assert_ne!(new, old);
+
+        assert_eq!(arena.get(old), None);
+        assert_eq!(arena.remove(old), None);
+        assert_eq!(arena.get(new), Some(&"new"));
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let handle: Handle<()> = Handle::new(7, 3);
+        assert_eq!(handle.to_raw(), (3 << 32) | 7);
+        assert_eq!(Handle::<()>::from_raw(handle.to_raw()), handle);
+    }
+}
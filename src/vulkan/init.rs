@@ -3,27 +3,64 @@ use std::ffi::CString;
 use std::sync::Arc;
 use ash::vk;
 
+use crate::debug::log_target;
 use crate::vulkan::{AgnajiVulkan, InstanceContext, surface};
-use crate::vulkan::device::MainDeviceReport;
+use crate::vulkan::device::{DeviceCreateError, DeviceQueuePriority, MainDeviceContext, MainDeviceReport};
+use crate::vulkan::error::{VkError, VkResultExt};
+use crate::vulkan::instance::{DebugConfig, DebugMessageHandler};
+use crate::vulkan::memory::{HeapConfig, HeapConfigFn};
 use crate::vulkan::output::SurfaceOutput;
-use crate::vulkan::surface::{SurfaceProviderId, VulkanSurfaceProvider};
+use crate::vulkan::surface::{SurfaceCreateError, SurfaceProviderId, VulkanSurfaceProvider};
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum DeviceReportGenerationError {
-    SurfaceCreationFailed(vk::Result),
-    Vulkan(vk::Result),
+    SurfaceCreationFailed(SurfaceCreateError),
+    Vulkan(VkError),
 }
 
-impl From<vk::Result> for DeviceReportGenerationError {
-    fn from(error: vk::Result) -> Self {
+impl From<VkError> for DeviceReportGenerationError {
+    fn from(error: VkError) -> Self {
         Self::Vulkan(error)
     }
 }
 
+impl std::fmt::Display for DeviceReportGenerationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SurfaceCreationFailed(err) => write!(f, "Surface creation failed: {:?}", err),
+            Self::Vulkan(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Selects the device used by [`AgnajiVulkanInitializer::build`].
+#[derive(Clone, Debug)]
+pub enum DeviceSelection<'a> {
+    /// Use the device described by a previously generated report.
+    Report(&'a MainDeviceReport),
+
+    /// Use the device with the specified pipeline cache uuid.
+    Uuid([u8; vk::UUID_SIZE]),
+
+    /// Use the first device with the specified name.
+    Name(String),
+
+    /// Use the suitable device with the highest score as determined by
+    /// [`MainDeviceReport::get_score`].
+    Best,
+}
+
+/// The log target of the initializer if no label is set.
+const INIT_LOG_TARGET: &str = "agnaji::vulkan::init";
+
 /// Used to build a [`AgnajiVulkan`] instance.
 pub struct AgnajiVulkanInitializer {
     instance: Arc<InstanceContext>,
     surfaces: Option<HashMap<SurfaceProviderId, RegisteredSurface>>,
+    queue_priorities: Option<DeviceQueuePriority>,
+    heap_config_fn: Option<Box<HeapConfigFn>>,
+    label: Option<String>,
+    log_target: String,
 }
 
 impl AgnajiVulkanInitializer {
@@ -39,14 +76,36 @@ impl AgnajiVulkanInitializer {
     /// some engine systems may disable certain debugging tools. Otherwise debugging features will
     /// be enabled as supported by the current platform.
     pub fn new<E>(required_instance_extensions: E, enable_debug: bool) -> Self where E: Iterator<Item=CString> {
+        Self::new_with_debug_config(required_instance_extensions, enable_debug.then(DebugConfig::default))
+    }
+
+    /// Equivalent to [`AgnajiVulkanInitializer::new`] but allows additional debugging features
+    /// like synchronization validation to be enabled. Debugging is disabled if `debug_config` is
+    /// [`None`].
+    pub fn new_with_debug_config<E>(required_instance_extensions: E, debug_config: Option<DebugConfig>) -> Self where E: Iterator<Item=CString> {
+        Self::create(required_instance_extensions, debug_config, None)
+    }
+
+    /// Equivalent to [`AgnajiVulkanInitializer::new_with_debug_config`] with debugging enabled
+    /// but sets `debug_handler` as the debug message handler before the instance is created. This
+    /// allows the handler to receive messages emitted during instance creation.
+    pub fn new_with_debug_handler<E>(required_instance_extensions: E, debug_config: DebugConfig, debug_handler: DebugMessageHandler) -> Self where E: Iterator<Item=CString> {
+        Self::create(required_instance_extensions, Some(debug_config), Some(debug_handler))
+    }
+
+    fn create<E>(required_instance_extensions: E, debug_config: Option<DebugConfig>, debug_handler: Option<DebugMessageHandler>) -> Self where E: Iterator<Item=CString> {
         let entry = unsafe { ash::Entry::load() }.unwrap();
-        let instance = Arc::new(InstanceContext::new(entry, enable_debug, required_instance_extensions).unwrap());
+        let instance = Arc::new(InstanceContext::new_with_debug_handler(entry, debug_config.as_ref(), debug_handler, required_instance_extensions).unwrap());
 
         let surfaces = instance.get_khr_surface().map(|_| HashMap::new());
 
         AgnajiVulkanInitializer {
             instance,
-            surfaces
+            surfaces,
+            queue_priorities: None,
+            heap_config_fn: None,
+            label: None,
+            log_target: String::from(INIT_LOG_TARGET),
         }
     }
 
@@ -56,6 +115,32 @@ impl AgnajiVulkanInitializer {
         Self::new(std::iter::empty(), enable_debug)
     }
 
+    /// Sets the queue priorities used when building the device. If not set all queues use a
+    /// priority of 1.0.
+    pub fn with_queue_priorities(mut self, priorities: DeviceQueuePriority) -> Self {
+        self.queue_priorities = Some(priorities);
+        self
+    }
+
+    /// Sets a function used to override the [`HeapConfig`] of memory types used by the memory
+    /// allocator (see [`crate::vulkan::memory::HeapManager`]). The function is called once for
+    /// every memory type of the selected device with the default config.
+    pub fn with_heap_config_fn<F>(mut self, config_fn: F) -> Self where F: Fn(u32, &vk::MemoryType, &vk::MemoryHeap, HeapConfig) -> HeapConfig + Send + 'static {
+        self.heap_config_fn = Some(Box::new(config_fn));
+        self
+    }
+
+    /// Sets a label which is appended to the log targets of the instance and all outputs created
+    /// by it (for example `agnaji::vulkan::output[editor]`). Used to tell apart multiple instances
+    /// in one process.
+    pub fn with_label<S: Into<String>>(mut self, label: S) -> Self {
+        let label = label.into();
+        self.instance.set_log_label(Some(&label));
+        self.log_target = log_target(INIT_LOG_TARGET, Some(&label));
+        self.label = Some(label);
+        self
+    }
+
     pub fn get_instance(&self) -> &Arc<InstanceContext> {
         &self.instance
     }
@@ -70,7 +155,7 @@ impl AgnajiVulkanInitializer {
             let id = SurfaceProviderId::new();
             let name = name.map(String::from);
 
-            log::debug!("Registered vulkan surface provider {:?} with name {:?}", id, name);
+            log::debug!(target: &self.log_target, "Registered vulkan surface provider {:?} with name {:?}", id, name);
 
             surfaces.insert(id, RegisteredSurface { name, surface_provider });
 
@@ -81,7 +166,8 @@ impl AgnajiVulkanInitializer {
     }
 
     pub fn generate_device_reports(&mut self) -> Result<Box<[MainDeviceReport]>, DeviceReportGenerationError> {
-        let physical_devices = unsafe { self.instance.get_instance().enumerate_physical_devices() }?;
+        let physical_devices = unsafe { self.instance.get_instance().enumerate_physical_devices() }
+            .context("enumerate physical devices")?;
 
         let mut reports = Vec::with_capacity(physical_devices.len());
 
@@ -103,7 +189,10 @@ impl AgnajiVulkanInitializer {
 
                     let handle = surface.get_handle();
                     for i in 0..queue_count {
-                        if !unsafe { khr_surface.get_physical_device_surface_support(physical_device, i as u32, handle)? } {
+                        let supported = unsafe {
+                            khr_surface.get_physical_device_surface_support(physical_device, i as u32, handle)
+                        }.with_details("query surface support", || format!("Queue family: {}, Surface: {:?}", i, registered.name))?;
+                        if !supported {
                             queue_surface_support[i] = false;
                         }
                     }
@@ -118,14 +207,61 @@ impl AgnajiVulkanInitializer {
         Ok(reports.into_boxed_slice())
     }
 
-    pub fn build(self, device: &MainDeviceReport) -> Option<(Arc<AgnajiVulkan>, Vec<(SurfaceProviderId, Arc<SurfaceOutput>)>)> {
-        let device = Arc::new(device.create_device(self.instance.clone()).ok()?);
+    /// Generates reports for all devices sorted by descending score. Unsuitable devices are
+    /// placed at the end.
+    pub fn generate_device_reports_sorted(&mut self) -> Result<Box<[MainDeviceReport]>, DeviceReportGenerationError> {
+        let mut reports = self.generate_device_reports()?;
+        reports.sort_by_key(|report| std::cmp::Reverse(report.get_score()));
+        Ok(reports)
+    }
+
+    /// Creates the device specified by `selection` and builds the [`AgnajiVulkan`] instance.
+    ///
+    /// Returns [`None`] if no matching suitable device could be found or device creation
+    /// failed.
+    pub fn build(mut self, selection: DeviceSelection) -> Option<(Arc<AgnajiVulkan>, Vec<(SurfaceProviderId, Arc<SurfaceOutput>)>)> {
+        let device = match selection {
+            DeviceSelection::Report(report) => self.create_device(report),
+            selection => {
+                let reports = self.generate_device_reports_sorted().inspect_err(|err| {
+                    log::error!(target: &self.log_target, "Failed to generate device reports: {}", err);
+                }).ok()?;
+
+                let report = reports.iter().filter(|report| report.is_suitable()).find(|report| {
+                    match &selection {
+                        DeviceSelection::Uuid(uuid) => report.get_uuid() == uuid,
+                        DeviceSelection::Name(name) => report.get_name() == name,
+                        _ => true,
+                    }
+                });
+
+                match report {
+                    Some(report) => self.create_device(report),
+                    None => {
+                        log::error!(target: &self.log_target, "Failed to find suitable device matching {:?}", selection);
+                        return None;
+                    }
+                }
+            }
+        };
+        let device = Arc::new(device.inspect_err(|err| {
+            log::error!(target: &self.log_target, "Failed to create device: {}", err);
+        }).ok()?);
 
         if let Some(surfaces) = self.surfaces {
             let surfaces = surfaces.into_iter().map(|(id, registered)| (id, registered.surface_provider, registered.name));
-            Some(AgnajiVulkan::new(self.instance, device, surfaces))
+            Some(AgnajiVulkan::new(self.instance, device, self.heap_config_fn.as_deref(), self.label, surfaces))
         } else {
-            Some(AgnajiVulkan::new(self.instance, device, std::iter::empty()))
+            Some(AgnajiVulkan::new(self.instance, device, self.heap_config_fn.as_deref(), self.label, std::iter::empty()))
+        }
+    }
+}
+
+impl AgnajiVulkanInitializer {
+    fn create_device(&self, report: &MainDeviceReport) -> Result<MainDeviceContext, DeviceCreateError> {
+        match self.queue_priorities {
+            Some(priorities) => report.create_device_with_priorities(self.instance.clone(), priorities),
+            None => report.create_device(self.instance.clone()),
         }
     }
 }
@@ -1,9 +1,14 @@
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::ffi::{CStr, CString};
 use std::fmt::Formatter;
+use std::marker::PhantomData;
+use std::sync::RwLock;
 
 use ash::vk;
 
+use crate::debug::log_target;
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct APIVersion {
     version: u32,
@@ -82,16 +87,79 @@ impl From<vk::Result> for InstanceCreateError {
     }
 }
 
+/// Configures the debugging features of a [`InstanceContext`].
+///
+/// The additional validation features are enabled through `VK_EXT_validation_features` and only
+/// take effect if the validation layer is available and supports the extension. Which features
+/// took effect can be queried using [`InstanceContext::get_enabled_validation_features`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct DebugConfig {
+    /// Enables GPU-assisted validation which instruments shaders to detect out of bounds
+    /// accesses. Requires the `fragment_stores_and_atomics` and `vertex_pipeline_stores_and_atomics`
+    /// features. The first is always enabled by agnaji, the second is not, so vertex stage
+    /// instrumentation may be unavailable. Reserves one descriptor set binding slot.
+    pub gpu_assisted: bool,
+
+    /// Enables best practices warnings. These are frequently noisy.
+    pub best_practices: bool,
+
+    /// Enables synchronization validation to detect hazards caused by missing barriers.
+    pub synchronization_validation: bool,
+
+    /// Enables `debugPrintfEXT` in shaders. Cannot be used together with
+    /// [`DebugConfig::gpu_assisted`] which takes precedence if both are set.
+    pub debug_printf: bool,
+}
+
+impl DebugConfig {
+    /// Returns the validation feature enables requested by this config.
+    pub fn get_validation_feature_enables(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut enables = Vec::new();
+        if self.gpu_assisted {
+            enables.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+        }
+        if self.best_practices {
+            enables.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        if self.synchronization_validation {
+            enables.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.debug_printf {
+            if self.gpu_assisted {
+                log::warn!("Debug printf cannot be enabled together with GPU-assisted validation. Ignoring debug printf");
+            } else {
+                enables.push(vk::ValidationFeatureEnableEXT::DEBUG_PRINTF);
+            }
+        }
+
+        enables
+    }
+}
+
 pub struct InstanceContext {
     entry: ash::Entry,
     instance: ash::Instance,
     khr_surface: Option<ash::extensions::khr::Surface>,
     ext_debug_utils: Option<ash::extensions::ext::DebugUtils>,
+    debug_messenger: vk::DebugUtilsMessengerEXT,
+    /// Passed as user data to the debug messenger so it must not move while the instance exists.
+    debug_messenger_data: Box<DebugMessengerData>,
+    validation_enabled: bool,
+    validation_features: Box<[vk::ValidationFeatureEnableEXT]>,
     enabled_extensions: Box<[CString]>,
 }
 
 impl InstanceContext {
-    pub fn new<E>(entry: ash::Entry, enable_debug: bool, required_extensions: E) -> Result<Self, InstanceCreateError> where E: Iterator<Item=CString> {
+    /// Creates a new instance. If `debug_config` is [`None`] no debugging extensions or validation
+    /// layers are enabled.
+    pub fn new<E>(entry: ash::Entry, debug_config: Option<&DebugConfig>, required_extensions: E) -> Result<Self, InstanceCreateError> where E: Iterator<Item=CString> {
+        Self::new_with_debug_handler(entry, debug_config, None, required_extensions)
+    }
+
+    /// Equivalent to [`InstanceContext::new`] but sets `debug_handler` as the debug message
+    /// handler before the instance is created so that it also receives the messages emitted while
+    /// creating and destroying the instance. See [`InstanceContext::set_debug_message_handler`].
+    pub fn new_with_debug_handler<E>(entry: ash::Entry, debug_config: Option<&DebugConfig>, debug_handler: Option<DebugMessageHandler>, required_extensions: E) -> Result<Self, InstanceCreateError> where E: Iterator<Item=CString> {
         // Validate API version
         let version = match entry.try_enumerate_instance_version().map_err(|err| {
             log::error!("Failed to enumerate instance version {:?}", err);
@@ -127,7 +195,7 @@ impl InstanceContext {
 
         let mut enabled_extensions = HashSet::new();
 
-        if supported_extensions.contains(ash::extensions::ext::DebugUtils::name()) && enable_debug {
+        if supported_extensions.contains(ash::extensions::ext::DebugUtils::name()) && debug_config.is_some() {
             enabled_extensions.insert(CString::from(ash::extensions::ext::DebugUtils::name()));
         }
 
@@ -153,16 +221,15 @@ impl InstanceContext {
             if supported_extensions.contains(&ext_swapchain_color_space_name) {
                 enabled_extensions.insert(ext_swapchain_color_space_name);
             }
+            // Required by VK_EXT_full_screen_exclusive
+            if supported_extensions.contains(vk::KhrGetSurfaceCapabilities2Fn::name()) {
+                enabled_extensions.insert(CString::from(vk::KhrGetSurfaceCapabilities2Fn::name()));
+            }
         }
 
-        let khr_surface_enabled = enabled_extensions.contains(ash::extensions::khr::Surface::name());
-        let ext_debug_utils = enabled_extensions.contains(ash::extensions::ext::DebugUtils::name());
-        let enabled_extensions: Box<[_]> = enabled_extensions.into_iter().collect();
-        let enabled_extensions_ptr: Vec<_> = enabled_extensions.iter().map(|e| e.as_ptr()).collect();
-
         // Check layer support
         let mut enabled_layers = Vec::new();
-        if enable_debug {
+        if debug_config.is_some() {
             let supported_layers: HashSet<_> = entry.enumerate_instance_layer_properties().map_err(|err| {
                 log::error!("Failed to enumerate instance layer properties: {:?}", err);
                 err
@@ -175,6 +242,30 @@ impl InstanceContext {
                 log::warn!("Debugging is enabled but VK_LAYER_KHRONOS_validation is not supported by instance");
             }
         }
+
+        // VK_EXT_validation_features is provided by the validation layer
+        let mut validation_features = Vec::new();
+        if let Some(debug_config) = debug_config.filter(|_| !enabled_layers.is_empty()) {
+            let requested = debug_config.get_validation_feature_enables();
+            if !requested.is_empty() {
+                let layer_extensions: HashSet<_> = entry.enumerate_instance_extension_properties(Some(enabled_layers[0])).inspect_err(|err| {
+                    log::error!("Failed to enumerate validation layer extension properties: {:?}", err);
+                })?.into_iter().map(|e| CString::from(unsafe { CStr::from_ptr(e.extension_name.as_ptr()) } )).collect();
+
+                if layer_extensions.contains(vk::ExtValidationFeaturesFn::name()) {
+                    enabled_extensions.insert(CString::from(vk::ExtValidationFeaturesFn::name()));
+                    validation_features = requested;
+                } else {
+                    log::warn!("Validation features {:?} requested but VK_EXT_validation_features is not supported by the validation layer", requested);
+                }
+            }
+        }
+
+        let khr_surface_enabled = enabled_extensions.contains(ash::extensions::khr::Surface::name());
+        let ext_debug_utils = enabled_extensions.contains(ash::extensions::ext::DebugUtils::name());
+        let enabled_extensions: Box<[_]> = enabled_extensions.into_iter().collect();
+        let enabled_extensions_ptr: Vec<_> = enabled_extensions.iter().map(|e| e.as_ptr()).collect();
+
         let enabled_layers_ptr: Vec<_> = enabled_layers.iter().map(|l| l.as_ptr()).collect();
 
         let application_info = vk::ApplicationInfo::builder()
@@ -189,17 +280,26 @@ impl InstanceContext {
             .enabled_layer_names(&enabled_layers_ptr)
             .enabled_extension_names(&enabled_extensions_ptr);
 
-        let mut messenger_create_info;
+        let debug_messenger_data = Box::new(DebugMessengerData {
+            handler: DebugMessageHandlerStorage::new(debug_handler),
+            log_target: RwLock::new(String::from(VALIDATION_LOG_TARGET)),
+        });
+        let mut messenger_create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::INFO | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE)
+            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
+            .pfn_user_callback(Some(debug_log_callback))
+            .user_data(debug_messenger_data.as_ref() as *const DebugMessengerData as *mut std::ffi::c_void);
         if ext_debug_utils {
-            messenger_create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-                .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::INFO | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE)
-                .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
-                .pfn_user_callback(Some(debug_log_callback));
-
             instance_create_info = instance_create_info.push_next(&mut messenger_create_info);
         }
 
-        log::info!("Creating vulkan instance {:?} Enabled extensions: {:?} Enabled layers: {:?}", version, enabled_extensions, enabled_layers);
+        let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&validation_features);
+        if !validation_features.is_empty() {
+            instance_create_info = instance_create_info.push_next(&mut validation_features_info);
+        }
+
+        log::info!("Creating vulkan instance {:?} Enabled extensions: {:?} Enabled layers: {:?} Enabled validation features: {:?}", version, enabled_extensions, enabled_layers, validation_features);
 
         let instance = unsafe {
             entry.create_instance(&instance_create_info, None)
@@ -219,11 +319,28 @@ impl InstanceContext {
             None
         };
 
+        // The messenger passed to the instance create info only receives messages of instance
+        // creation and destruction
+        let debug_messenger = if let Some(debug_utils) = &ext_debug_utils {
+            unsafe {
+                debug_utils.create_debug_utils_messenger(&messenger_create_info, None)
+            }.inspect_err(|err| {
+                log::error!("Failed to create debug utils messenger: {:?}", err);
+                unsafe { instance.destroy_instance(None) };
+            })?
+        } else {
+            vk::DebugUtilsMessengerEXT::null()
+        };
+
         Ok(Self {
             entry,
             instance,
             khr_surface,
             ext_debug_utils,
+            debug_messenger,
+            debug_messenger_data,
+            validation_enabled: !enabled_layers.is_empty(),
+            validation_features: validation_features.into_boxed_slice(),
             enabled_extensions,
         })
     }
@@ -244,55 +361,284 @@ impl InstanceContext {
         self.ext_debug_utils.as_ref()
     }
 
+    /// Sets a handler which is called for every message of the validation layers in addition to
+    /// logging it. Replaces any previously set handler. Messages are only received if debugging
+    /// has been enabled when creating the instance.
+    ///
+    /// The handler may be called from any thread which uses vulkan. It must not call this
+    /// function as that would deadlock. Panics of the handler are caught and logged.
+    pub fn set_debug_message_handler(&self, handler: DebugMessageHandler) {
+        *self.debug_messenger_data.handler.write().unwrap() = Some(handler);
+    }
+
+    /// Removes the handler set by [`InstanceContext::set_debug_message_handler`].
+    pub fn clear_debug_message_handler(&self) {
+        *self.debug_messenger_data.handler.write().unwrap() = None;
+    }
+
+    /// Appends `label` to the log target of validation layer messages (see
+    /// [`crate::debug::log_target`]).
+    pub fn set_log_label(&self, label: Option<&str>) {
+        *self.debug_messenger_data.log_target.write().unwrap() = log_target(VALIDATION_LOG_TARGET, label);
+    }
+
+    /// Returns true if `VK_LAYER_KHRONOS_validation` is enabled.
+    pub fn is_validation_enabled(&self) -> bool {
+        self.validation_enabled
+    }
+
+    /// Returns the `VK_EXT_validation_features` enables which took effect. This is empty if the
+    /// validation layer or the extension is not available. See [`DebugConfig`].
+    pub fn get_enabled_validation_features(&self) -> &[vk::ValidationFeatureEnableEXT] {
+        &self.validation_features
+    }
+
     pub fn is_extension_enabled(&self, name: &CStr) -> bool {
-        for ext in self.enabled_extensions.iter() {
-            if ext.as_c_str() == name {
-                return true;
-            }
-        }
+        self.enabled_extensions.iter().any(|ext| ext.as_c_str() == name)
+    }
 
-        false
+    pub fn get_enabled_extensions(&self) -> &[CString] {
+        &self.enabled_extensions
     }
 }
 
 impl Drop for InstanceContext {
     fn drop(&mut self) {
         unsafe {
+            if let Some(debug_utils) = &self.ext_debug_utils {
+                debug_utils.destroy_debug_utils_messenger(self.debug_messenger, None);
+            }
             self.instance.destroy_instance(None);
         }
     }
 }
 
-unsafe extern "system" fn debug_log_callback(message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, _message_types: vk::DebugUtilsMessageTypeFlagsEXT, p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT, _p_user_data: *mut std::ffi::c_void) -> vk::Bool32 {
-    if let Err(_) = std::panic::catch_unwind(|| {
-        match unsafe { CStr::from_ptr((*p_callback_data).p_message) }.to_str() {
-            Ok(message) => {
-                match message_severity {
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
-                        log::error!(target: "agnaji::Vulkan", "{}", message);
-                    },
-                    vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
-                        log::warn!(target: "agnaji::Vulkan", "{}", message);
-                    },
-                    vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
-                        log::info!(target: "agnaji::Vulkan", "{}", message);
-                    },
-                    vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => {
-                        log::debug!(target: "agnaji::Vulkan", "{}", message);
-                    },
-                    _ => {
-                        log::warn!("Unknown debug utils message severity: {:?}; {}", message_severity, message);
-                    }
-                }
-            },
+thread_local! {
+    static SUPPRESSED_MESSAGES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Suppresses all debug messages with a specific message id name emitted on the current thread
+/// while the guard is alive. Used to silence known false positives of the validation layers.
+///
+/// Guards may be nested, also for the same message id name.
+#[must_use]
+pub struct DebugSuppressionGuard {
+    message_id_name: &'static str,
+    /// The suppression list is thread local so the guard must be dropped on the same thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl DebugSuppressionGuard {
+    pub fn new(message_id_name: &'static str) -> Self {
+        SUPPRESSED_MESSAGES.with(|suppressed| suppressed.borrow_mut().push(message_id_name));
+
+        Self {
+            message_id_name,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for DebugSuppressionGuard {
+    fn drop(&mut self) {
+        SUPPRESSED_MESSAGES.with(|suppressed| {
+            let mut suppressed = suppressed.borrow_mut();
+            if let Some(index) = suppressed.iter().rposition(|name| *name == self.message_id_name) {
+                suppressed.swap_remove(index);
+            }
+        });
+    }
+}
+
+fn is_message_suppressed(message_id_name: &str) -> bool {
+    SUPPRESSED_MESSAGES.with(|suppressed| {
+        suppressed.borrow().contains(&message_id_name)
+    })
+}
+
+/// A message of the validation layers passed to a handler set by
+/// [`InstanceContext::set_debug_message_handler`].
+#[derive(Copy, Clone, Debug)]
+pub struct DebugMessage<'a> {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub message_id_name: Option<&'a str>,
+    pub message_id_number: i32,
+    pub message: &'a str,
+}
+
+pub type DebugMessageHandler = Box<dyn Fn(&DebugMessage) + Send + Sync>;
+
+type DebugMessageHandlerStorage = RwLock<Option<DebugMessageHandler>>;
+
+/// The log target of validation layer messages if no label is set.
+const VALIDATION_LOG_TARGET: &str = "agnaji::Vulkan";
+
+/// The user data of the debug messenger.
+struct DebugMessengerData {
+    handler: DebugMessageHandlerStorage,
+    log_target: RwLock<String>,
+}
+
+unsafe extern "system" fn debug_log_callback(message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, message_types: vk::DebugUtilsMessageTypeFlagsEXT, p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT, p_user_data: *mut std::ffi::c_void) -> vk::Bool32 {
+    if std::panic::catch_unwind(|| {
+        let data = unsafe { &*p_callback_data };
+        let message_id_name = (!data.p_message_id_name.is_null()).then(|| unsafe { CStr::from_ptr(data.p_message_id_name) }.to_string_lossy());
+        let message = match unsafe { CStr::from_ptr(data.p_message) }.to_str() {
+            Ok(message) => message,
             Err(err) => {
                 log::error!("Debug utils messenger received invalid message: {:?}", err);
+                return;
             }
         };
-    }) {
+
+        // The user data always points to the messenger data of the instance
+        let messenger_data = unsafe { (p_user_data as *const DebugMessengerData).as_ref() };
+        let log_target = messenger_data.map(|messenger_data| messenger_data.log_target.read().unwrap().clone());
+        process_debug_message(log_target.as_deref().unwrap_or(VALIDATION_LOG_TARGET), messenger_data.map(|messenger_data| &messenger_data.handler), &DebugMessage {
+            severity: message_severity,
+            message_type: message_types,
+            message_id_name: message_id_name.as_deref(),
+            message_id_number: data.message_id_number,
+            message,
+        });
+    }).is_err() {
         log::error!("Panic in debug utils messenger callback! Aborting...");
         std::process::exit(1);
     }
 
     vk::FALSE
-}
\ No newline at end of file
+}
+
+/// Logs a message received by [`debug_log_callback`] and passes it to the handler if one is
+/// set. Returns false if the message has been suppressed by a [`DebugSuppressionGuard`].
+fn process_debug_message(log_target: &str, handler: Option<&DebugMessageHandlerStorage>, message: &DebugMessage) -> bool {
+    if message.message_id_name.map(is_message_suppressed).unwrap_or(false) {
+        return false;
+    }
+
+    match message.severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+            log::error!(target: log_target, "{}", message.message);
+        },
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+            log::warn!(target: log_target, "{}", message.message);
+        },
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+            log::info!(target: log_target, "{}", message.message);
+        },
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => {
+            log::debug!(target: log_target, "{}", message.message);
+        },
+        _ => {
+            log::warn!("Unknown debug utils message severity: {:?}; {}", message.severity, message.message);
+        }
+    }
+
+    if let Some(handler) = handler.and_then(|handler| handler.read().ok()) {
+        if let Some(handler) = handler.as_ref() {
+            if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler(message))).is_err() {
+                log::error!("Panic in debug message handler while handling message {:?}", message.message_id_name);
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const MESSAGE_ID: &str = "UNASSIGNED-CoreValidation-DrawState-InvalidImageLayout";
+
+    fn test_message(message_id_name: Option<&str>) -> DebugMessage<'_> {
+        DebugMessage {
+            severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            message_id_name,
+            message_id_number: 0,
+            message: "test message",
+        }
+    }
+
+    fn log_test_message(message_id_name: &str) -> bool {
+        process_debug_message(VALIDATION_LOG_TARGET, None, &test_message(Some(message_id_name)))
+    }
+
+    #[test]
+    fn validation_feature_enables() {
+        assert!(DebugConfig::default().get_validation_feature_enables().is_empty());
+
+        let config = DebugConfig {
+            best_practices: true,
+            synchronization_validation: true,
+            debug_printf: true,
+            ..Default::default()
+        };
+        assert_eq!(config.get_validation_feature_enables(), vec![
+            vk::ValidationFeatureEnableEXT::BEST_PRACTICES,
+            vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+            vk::ValidationFeatureEnableEXT::DEBUG_PRINTF,
+        ]);
+
+        // Gpu assisted validation and debug printf are mutually exclusive
+        let config = DebugConfig {
+            gpu_assisted: true,
+            debug_printf: true,
+            ..Default::default()
+        };
+        assert_eq!(config.get_validation_feature_enables(), vec![vk::ValidationFeatureEnableEXT::GPU_ASSISTED]);
+    }
+
+    #[test]
+    fn suppression_guard() {
+        let other_id = "VUID-vkCmdFillBuffer-dstBuffer-00029";
+
+        assert!(log_test_message(MESSAGE_ID));
+
+        let guard = DebugSuppressionGuard::new(MESSAGE_ID);
+        assert!(!log_test_message(MESSAGE_ID));
+        assert!(log_test_message(other_id));
+
+        let nested = DebugSuppressionGuard::new(MESSAGE_ID);
+        drop(guard);
+        assert!(!log_test_message(MESSAGE_ID));
+        drop(nested);
+
+        assert!(log_test_message(MESSAGE_ID));
+        assert!(process_debug_message(VALIDATION_LOG_TARGET, None, &test_message(None)));
+    }
+
+    #[test]
+    fn suppression_is_thread_local() {
+        let _guard = DebugSuppressionGuard::new(MESSAGE_ID);
+        let logged = std::thread::spawn(|| log_test_message(MESSAGE_ID)).join().unwrap();
+        assert!(logged);
+    }
+
+    #[test]
+    fn message_handler() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_clone = count.clone();
+        let handler: DebugMessageHandlerStorage = RwLock::new(Some(Box::new(move |message: &DebugMessage| {
+            assert_eq!(message.message, "test message");
+            count_clone.fetch_add(1, Ordering::SeqCst);
+        })));
+
+        assert!(process_debug_message(VALIDATION_LOG_TARGET, Some(&handler), &test_message(Some(MESSAGE_ID))));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Suppressed messages are not passed to the handler
+        let guard = DebugSuppressionGuard::new(MESSAGE_ID);
+        assert!(!process_debug_message(VALIDATION_LOG_TARGET, Some(&handler), &test_message(Some(MESSAGE_ID))));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        drop(guard);
+
+        *handler.write().unwrap() = Some(Box::new(|_: &DebugMessage| panic!("Handler panic")));
+        assert!(process_debug_message(VALIDATION_LOG_TARGET, Some(&handler), &test_message(None)));
+    }
+}
@@ -0,0 +1,2162 @@
+//! Device memory management and suballocation of small buffers from a few large vulkan buffers.
+
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ash::vk;
+use bytemuck::Pod;
+
+use crate::debug::Statistics;
+use crate::utils::tlsf::{MovablePoolAllocation, PoolAllocation, PoolAllocator};
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+
+/// Selects a memory type index allowed by `type_bits` which has all `required` flags.
+///
+/// Types with all `preferred` flags are selected first, followed by types with the most
+/// preferred flags and finally types with only the required flags. Ties are resolved by
+/// selecting the lowest index, which the vulkan spec orders by performance.
+pub fn find_memory_type(properties: &vk::PhysicalDeviceMemoryProperties, type_bits: u32, required: vk::MemoryPropertyFlags, preferred: vk::MemoryPropertyFlags) -> Option<u32> {
+    let types = &properties.memory_types[0..(properties.memory_type_count as usize)];
+
+    types.iter().enumerate()
+        .filter(|(index, memory_type)| type_bits & (1 << index) != 0 && memory_type.property_flags.contains(required))
+        .max_by_key(|(index, memory_type)| ((memory_type.property_flags & preferred).as_raw().count_ones(), std::cmp::Reverse(*index)))
+        .map(|(index, _)| index as u32)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BufferArenaError {
+    /// The requested size is 0 or larger than the buffers of the arena.
+    InvalidSize(u64),
+
+    /// No host visible memory type supports the buffers.
+    NoSuitableMemoryType,
+
+    Vulkan(vk::Result),
+}
+
+impl From<vk::Result> for BufferArenaError {
+    fn from(result: vk::Result) -> Self {
+        BufferArenaError::Vulkan(result)
+    }
+}
+
+/// The kind of descriptor a [`BufferSlice`] will be used as. Each class is allocated from
+/// separate buffers.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BufferUsageClass {
+    Uniform,
+    Storage,
+}
+
+impl BufferUsageClass {
+    pub const ALL: [BufferUsageClass; 2] = [BufferUsageClass::Uniform, BufferUsageClass::Storage];
+
+    pub fn get_usage_flags(&self) -> vk::BufferUsageFlags {
+        match self {
+            BufferUsageClass::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
+            BufferUsageClass::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
+        }
+    }
+
+    /// Returns the alignment required by the device for slices of this class.
+    pub fn get_offset_alignment(&self, limits: &vk::PhysicalDeviceLimits) -> u64 {
+        let alignment = match self {
+            BufferUsageClass::Uniform => limits.min_uniform_buffer_offset_alignment,
+            BufferUsageClass::Storage => limits.min_storage_buffer_offset_alignment,
+        };
+
+        // The spec guarantees a power of 2 but a value of 0 has been observed on some drivers
+        alignment.max(1)
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            BufferUsageClass::Uniform => 0,
+            BufferUsageClass::Storage => 1,
+        }
+    }
+}
+
+/// Hands out [`BufferSlice`]s of large host visible buffers.
+///
+/// Buffers are created lazily when no existing buffer of the usage class has enough free space.
+/// All buffers are persistently mapped and created with
+/// [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`].
+///
+/// All slices must be dropped before the arena is destroyed. This is verified in debug builds.
+pub struct BufferArena {
+    device: Arc<MainDeviceContext>,
+    buffer_size: u64,
+    classes: [ArenaClass; 2],
+}
+
+impl BufferArena {
+    /// Creates a new arena whose buffers are `buffer_size` bytes large.
+    ///
+    /// # Panics
+    /// If `buffer_size` is not a power of 2 or smaller than 1KiB.
+    pub fn new(device: Arc<MainDeviceContext>, buffer_size: u64) -> Result<Self, BufferArenaError> {
+        assert!(buffer_size.is_power_of_two() && buffer_size >= 1024, "Invalid arena buffer size {}", buffer_size);
+
+        let classes = BufferUsageClass::ALL.map(|usage_class| {
+            ArenaClass {
+                alignment: usage_class.get_offset_alignment(device.get_limits()),
+                allocator: PoolAllocator::new(buffer_size as usize),
+                buffers: Mutex::new(Vec::new()),
+            }
+        });
+
+        Ok(Self {
+            device,
+            buffer_size,
+            classes,
+        })
+    }
+
+    /// Allocates a slice of `size` bytes whose offset satisfies the alignment requirements of the
+    /// usage class.
+    pub fn allocate(&self, usage_class: BufferUsageClass, size: u64) -> Result<BufferSlice, BufferArenaError> {
+        self.allocate_with(usage_class, size, |allocator, size_nz, alignment| {
+            allocator.allocate_aligned(size_nz, alignment).map(|allocation| BufferSlice::from_allocation(allocation, size))
+        })
+    }
+
+    /// Allocates a slice like [`BufferArena::allocate`] which may be relocated by
+    /// [`BufferArena::begin_defrag`].
+    pub fn allocate_movable(&self, usage_class: BufferUsageClass, size: u64) -> Result<MovableBufferSlice, BufferArenaError> {
+        self.allocate_with(usage_class, size, |allocator, size_nz, alignment| {
+            allocator.allocate_movable(size_nz, alignment).map(|allocation| MovableBufferSlice { allocation })
+        })
+    }
+
+    /// Proposes moves of movable slices to free up contiguous space in the arena buffers. At most
+    /// `budget_bytes` bytes are moved. Slices created with [`BufferArena::allocate`] are never
+    /// moved.
+    ///
+    /// The caller must copy every move (for example using [`BufferMove::get_copy_region`] on the
+    /// transfer queue) and then call [`BufferArena::commit_moves`] with the timeline value
+    /// signalled once the copies complete. Moved slices must not be written to until then.
+    ///
+    /// # Panics
+    /// If the moves of a previous call have neither been committed nor cancelled.
+    pub fn begin_defrag(&self, budget_bytes: u64) -> Vec<BufferMove> {
+        let mut remaining = budget_bytes;
+        let mut moves = Vec::new();
+
+        for class in &self.classes {
+            let budget = usize::try_from(remaining).unwrap_or(usize::MAX);
+            for pool_move in class.allocator.begin_defrag(budget) {
+                remaining -= pool_move.size as u64;
+                moves.push(BufferMove {
+                    src_buffer: pool_move.src_pool.buffer,
+                    src_offset: pool_move.src_offset as u64,
+                    dst_buffer: pool_move.dst_pool.buffer,
+                    dst_offset: pool_move.dst_offset as u64,
+                    size: pool_move.size as u64,
+                });
+            }
+        }
+
+        log::debug!("Proposed {} arena slice moves ({} bytes)", moves.len(), budget_bytes - remaining);
+        moves
+    }
+
+    /// Moves all slices of the last call to [`BufferArena::begin_defrag`] to their new location.
+    /// The old locations are reused once [`BufferArena::recycle`] is called with a value greater
+    /// or equal to `timeline_value`.
+    pub fn commit_moves(&self, timeline_value: u64) {
+        for class in &self.classes {
+            class.allocator.commit_moves(timeline_value);
+        }
+    }
+
+    /// Discards all moves of the last call to [`BufferArena::begin_defrag`]. No copy of the moves
+    /// may still be executing.
+    pub fn cancel_defrag(&self) {
+        for class in &self.classes {
+            class.allocator.cancel_defrag();
+        }
+    }
+
+    /// Frees the old locations of committed moves whose timeline value is less or equal to
+    /// `completed_value`.
+    pub fn recycle(&self, completed_value: u64) {
+        for class in &self.classes {
+            class.allocator.recycle(completed_value);
+        }
+    }
+
+    fn allocate_with<R, F>(&self, usage_class: BufferUsageClass, size: u64, f: F) -> Result<R, BufferArenaError>
+        where F: Fn(&PoolAllocator<MappedBuffer>, NonZeroUsize, NonZeroUsize) -> Option<R> {
+
+        if size == 0 || size > self.buffer_size {
+            return Err(BufferArenaError::InvalidSize(size));
+        }
+
+        let class = &self.classes[usage_class.index()];
+        let size_nz = NonZeroUsize::new(size as usize).unwrap();
+        let alignment = NonZeroUsize::new(class.alignment as usize).unwrap();
+
+        if let Some(result) = f(&class.allocator, size_nz, alignment) {
+            return Ok(result);
+        }
+
+        // Hold the lock while creating the buffer so concurrent allocations dont all create one
+        let mut buffers = class.buffers.lock().unwrap();
+        if let Some(result) = f(&class.allocator, size_nz, alignment) {
+            return Ok(result);
+        }
+
+        let name = self.device.is_object_naming_enabled()
+            .then(|| format!("agnaji {:?} pool page={}", usage_class, buffers.len()));
+        let buffer = MappedBuffer::new(&self.device, self.buffer_size, usage_class.get_usage_flags(), vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, vk::MemoryPropertyFlags::DEVICE_LOCAL, name.as_deref())?;
+        log::debug!("Created {:?} arena buffer {:?} of size {} in memory type {}", usage_class, buffer.buffer, self.buffer_size, buffer.memory_type);
+        buffers.push(buffer);
+        class.allocator.add_page(Box::new(buffer), self.buffer_size as usize);
+        drop(buffers);
+
+        // Slices are never larger than a buffer so this cannot fail, except for very large alignments
+        f(&class.allocator, size_nz, alignment).ok_or(BufferArenaError::InvalidSize(size))
+    }
+
+    pub fn get_buffer_size(&self) -> u64 {
+        self.buffer_size
+    }
+
+    /// Returns the number of slices that have not been dropped yet.
+    pub fn get_slice_count(&self) -> usize {
+        self.classes.iter().map(|class| class.allocator.get_allocation_count()).sum()
+    }
+}
+
+impl Drop for BufferArena {
+    fn drop(&mut self) {
+        // The device must be idle so the old locations of moved slices can be freed
+        self.cancel_defrag();
+        self.recycle(u64::MAX);
+
+        for (usage_class, class) in BufferUsageClass::ALL.iter().zip(&self.classes) {
+            class.allocator.for_each_allocated_block(|block| {
+                log::error!("Leaked {:?} arena slice {:?} (Buffer: {:?}, Offset: {}, Size: {})", usage_class, block.debug_name.unwrap_or("<unnamed>"), block.pool.buffer, block.offset, block.size);
+            });
+        }
+        debug_assert_eq!(self.get_slice_count(), 0, "BufferArena destroyed with live slices");
+
+        for class in &mut self.classes {
+            for buffer in class.buffers.get_mut().unwrap().drain(..) {
+                unsafe { buffer.destroy(&self.device) };
+            }
+        }
+    }
+}
+
+struct ArenaClass {
+    alignment: u64,
+    allocator: PoolAllocator<MappedBuffer>,
+    /// All buffers created for this class. Also used to serialize buffer creation.
+    buffers: Mutex<Vec<MappedBuffer>>,
+}
+
+/// A persistently mapped buffer. Buffers are always created with
+/// [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`] and can be used as transfer source and
+/// destination.
+#[derive(Copy, Clone)]
+pub(in crate::vulkan) struct MappedBuffer {
+    pub(in crate::vulkan) buffer: vk::Buffer,
+    pub(in crate::vulkan) memory: vk::DeviceMemory,
+    memory_size: u64,
+    memory_type: u32,
+    /// Set if the memory type is host coherent.
+    pub(in crate::vulkan) coherent: bool,
+    device_address: vk::DeviceAddress,
+    pub(in crate::vulkan) mapped: *mut u8,
+}
+
+impl MappedBuffer {
+    /// Creates a new buffer in a memory type with all `required` flags which is allowed by the
+    /// memory requirements of the buffer. See [`find_memory_type`] for how `preferred` is used. If
+    /// `name` is provided it is used as the debug name of the buffer and its memory.
+    pub(in crate::vulkan) fn new(device: &MainDeviceContext, size: u64, usage: vk::BufferUsageFlags, required: vk::MemoryPropertyFlags, preferred: vk::MemoryPropertyFlags, name: Option<&str>) -> Result<Self, BufferArenaError> {
+        let vk_device = device.get_device();
+
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let buffer = unsafe { vk_device.create_buffer(&create_info, None) }?;
+
+        let requirements = unsafe { vk_device.get_buffer_memory_requirements(buffer) };
+        let properties = unsafe {
+            device.get_instance().get_instance().get_physical_device_memory_properties(device.get_physical_device())
+        };
+        let Some(memory_type) = find_memory_type(&properties, requirements.memory_type_bits, required, preferred) else {
+            unsafe { vk_device.destroy_buffer(buffer, None) };
+            return Err(BufferArenaError::NoSuitableMemoryType);
+        };
+        let coherent = properties.memory_types[memory_type as usize].property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+
+        let mut flags_info = vk::MemoryAllocateFlagsInfo::builder()
+            .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type)
+            .push_next(&mut flags_info);
+
+        let memory = match unsafe { device.allocate_memory(&allocate_info) } {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { vk_device.destroy_buffer(buffer, None) };
+                return Err(err.into());
+            }
+        };
+
+        device.get_memory_statistics().record_pool_allocation(requirements.size);
+
+        let mapped = unsafe {
+            vk_device.bind_buffer_memory(buffer, memory, 0)
+                .and_then(|_| vk_device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()))
+        };
+        let mapped = match mapped {
+            Ok(mapped) => mapped as *mut u8,
+            Err(err) => {
+                unsafe {
+                    vk_device.destroy_buffer(buffer, None);
+                    device.get_allocator().free_memory(vk_device, memory, memory_type, requirements.size);
+                }
+                device.get_memory_statistics().record_pool_free(requirements.size);
+                return Err(err.into());
+            }
+        };
+
+        let address_info = vk::BufferDeviceAddressInfo::builder()
+            .buffer(buffer);
+        let device_address = unsafe {
+            device.get_khr_buffer_device_address().get_buffer_device_address(&address_info)
+        };
+
+        if let Some(name) = name {
+            device.set_object_name(buffer, name);
+            device.set_object_name(memory, name);
+        }
+
+        Ok(Self {
+            buffer,
+            memory,
+            memory_size: requirements.size,
+            memory_type,
+            coherent,
+            device_address,
+            mapped,
+        })
+    }
+
+    /// # Safety
+    /// The buffer must not be used by the device anymore and must not be destroyed twice.
+    pub(in crate::vulkan) unsafe fn destroy(&self, device: &MainDeviceContext) {
+        device.get_device().destroy_buffer(self.buffer, None);
+        device.get_allocator().free_memory(device.get_device(), self.memory, self.memory_type, self.memory_size);
+        device.get_memory_statistics().record_pool_free(self.memory_size);
+    }
+
+    fn slice(&self, offset: u64, size: u64, allocation: Option<PoolAllocation<MappedBuffer>>) -> BufferSlice {
+        BufferSlice {
+            buffer: self.buffer,
+            offset,
+            size,
+            device_address: self.device_address + offset,
+            mapped: unsafe { self.mapped.add(offset as usize) },
+            allocation,
+        }
+    }
+}
+
+// The mapped pointer is only handed out through BufferSlice
+unsafe impl Send for MappedBuffer {
+}
+
+unsafe impl Sync for MappedBuffer {
+}
+
+/// A move proposed by [`BufferArena::begin_defrag`]. The source and destination ranges never
+/// overlap.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BufferMove {
+    pub src_buffer: vk::Buffer,
+    pub src_offset: u64,
+    pub dst_buffer: vk::Buffer,
+    pub dst_offset: u64,
+    pub size: u64,
+}
+
+impl BufferMove {
+    /// Returns the region to pass to `vkCmdCopyBuffer` when copying from `src_buffer` to
+    /// `dst_buffer`.
+    pub fn get_copy_region(&self) -> vk::BufferCopy {
+        vk::BufferCopy {
+            src_offset: self.src_offset,
+            dst_offset: self.dst_offset,
+            size: self.size,
+        }
+    }
+}
+
+/// A slice allocated by [`BufferArena::allocate_movable`]. The slice may be moved to a different
+/// buffer or offset by [`BufferArena::commit_moves`] and is returned to the arena when dropped.
+pub struct MovableBufferSlice {
+    allocation: MovablePoolAllocation<MappedBuffer>,
+}
+
+impl MovableBufferSlice {
+    /// Returns the current location of the slice. The returned slice does not own the memory and
+    /// becomes stale once the next committed move of this slice has completed.
+    pub fn get_slice(&self) -> BufferSlice {
+        let allocation = self.allocation.lock();
+        allocation.get_pool().slice(allocation.get_offset() as u64, allocation.get_size() as u64, None)
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.allocation.lock().get_size() as u64
+    }
+
+    /// Sets the name used to report the slice if it is leaked when its [`BufferArena`] is
+    /// destroyed. The name is not carried over when the slice is moved.
+    pub fn set_debug_name(&self, name: &str) {
+        self.allocation.lock().set_debug_name(name);
+    }
+}
+
+/// A range of a persistently mapped buffer.
+///
+/// Slices allocated from a [`BufferArena`] are returned to the arena when dropped. Slices pushed
+/// to a [`FrameRing`] are recycled once their frame has completed.
+pub struct BufferSlice {
+    buffer: vk::Buffer,
+    offset: u64,
+    size: u64,
+    device_address: vk::DeviceAddress,
+    mapped: *mut u8,
+    allocation: Option<PoolAllocation<MappedBuffer>>,
+}
+
+impl BufferSlice {
+    fn from_allocation(allocation: PoolAllocation<MappedBuffer>, size: u64) -> Self {
+        let buffer = *allocation.get_pool();
+        buffer.slice(allocation.get_offset() as u64, size, Some(allocation))
+    }
+
+    pub fn get_buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the device address of the start of the slice.
+    pub fn get_device_address(&self) -> vk::DeviceAddress {
+        self.device_address
+    }
+
+    /// Returns a host pointer to the start of the slice.
+    pub fn get_mapped_ptr(&self) -> *mut u8 {
+        self.mapped
+    }
+
+    /// Grows the slice to `new_size` bytes without changing its offset, so no data has to be
+    /// copied. Returns false if the memory after the slice is in use or the slice is not
+    /// allocated from a [`BufferArena`]. In that case the slice is left unchanged.
+    pub fn try_grow(&mut self, new_size: u64) -> bool {
+        if new_size <= self.size {
+            return true;
+        }
+        let grown = match (&mut self.allocation, NonZeroUsize::new(new_size as usize)) {
+            (Some(allocation), Some(new_size)) => allocation.try_grow(new_size),
+            _ => false,
+        };
+        if grown {
+            self.size = new_size;
+        }
+        grown
+    }
+
+    /// Shrinks the slice to `new_size` bytes without changing its offset. If the slice is
+    /// allocated from a [`BufferArena`] the freed memory can be reused by other slices.
+    ///
+    /// # Panics
+    /// If `new_size` is 0 or larger than the current size.
+    pub fn shrink(&mut self, new_size: u64) {
+        assert!(new_size != 0 && new_size <= self.size, "Cannot shrink slice of size {} to {}", self.size, new_size);
+        if let Some(allocation) = &mut self.allocation {
+            allocation.shrink(NonZeroUsize::new(new_size as usize).unwrap());
+        }
+        self.size = new_size;
+    }
+
+    /// Sets the name used to report the slice if it is leaked when its [`BufferArena`] is
+    /// destroyed. Has no effect for slices which are not allocated from a [`BufferArena`].
+    pub fn set_debug_name(&self, name: &str) {
+        if let Some(allocation) = &self.allocation {
+            allocation.set_debug_name(name);
+        }
+    }
+}
+
+// The slice only exposes the mapped pointer, writing through it is already unsafe
+unsafe impl Send for BufferSlice {
+}
+
+unsafe impl Sync for BufferSlice {
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FrameRingError {
+    /// Not enough space is available without overwriting data of frames still in flight. The
+    /// ring must be recycled or created with a larger size.
+    OutOfSpace {
+        requested: u64,
+        available: u64,
+    },
+
+    /// Pushes of 0 bytes are not allowed.
+    EmptyPush,
+}
+
+/// A persistently mapped, host visible buffer for per frame data.
+///
+/// Data is appended with [`FrameRing::push`] and [`FrameRing::push_slice`]. All pushes made
+/// before [`FrameRing::end_frame`] belong to the frame identified by its timeline value and are
+/// recycled by [`FrameRing::recycle`] once that value has been reached. Space which may still be
+/// read by the device is never overwritten.
+pub struct FrameRing {
+    device: Arc<MainDeviceContext>,
+    buffer: MappedBuffer,
+    coherent: bool,
+    state: RingState,
+}
+
+impl FrameRing {
+    /// Creates a new ring of `size` bytes usable as uniform and storage buffers.
+    ///
+    /// # Panics
+    /// If `size` is not a power of 2 or smaller than the offset alignment of the device.
+    pub fn new(device: Arc<MainDeviceContext>, size: u64) -> Result<Self, BufferArenaError> {
+        let alignment = BufferUsageClass::ALL.iter()
+            .map(|usage_class| usage_class.get_offset_alignment(device.get_limits()))
+            .max()
+            .unwrap();
+        assert!(size.is_power_of_two() && size >= alignment, "Invalid frame ring size {}", size);
+
+        let usage = vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER;
+        let buffer = MappedBuffer::new(&device, size, usage, vk::MemoryPropertyFlags::HOST_VISIBLE, vk::MemoryPropertyFlags::HOST_COHERENT, Some("agnaji frame ring"))?;
+        let coherent = buffer.coherent;
+
+        Ok(Self {
+            device,
+            buffer,
+            coherent,
+            state: RingState::new(size, alignment),
+        })
+    }
+
+    pub fn push<T: Pod>(&mut self, value: &T) -> Result<BufferSlice, FrameRingError> {
+        self.push_slice(std::slice::from_ref(value))
+    }
+
+    pub fn push_slice<T: Pod>(&mut self, values: &[T]) -> Result<BufferSlice, FrameRingError> {
+        let bytes: &[u8] = bytemuck::cast_slice(values);
+        let size = bytes.len() as u64;
+
+        let offset = self.state.allocate(size)?;
+        let slice = self.buffer.slice(offset, size, None);
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), slice.get_mapped_ptr(), bytes.len());
+        }
+
+        Ok(slice)
+    }
+
+    /// Ends the current frame. All pushes since the last call belong to the frame which
+    /// completes once the timeline reaches `timeline_value`.
+    pub fn end_frame(&mut self, timeline_value: u64) -> Result<(), vk::Result> {
+        self.state.end_frame(timeline_value);
+
+        if !self.coherent {
+            let range = vk::MappedMemoryRange::builder()
+                .memory(self.buffer.memory)
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            unsafe { self.device.get_device().flush_mapped_memory_ranges(std::slice::from_ref(&range)) }?;
+        }
+
+        Ok(())
+    }
+
+    /// Recycles the space of all frames whose timeline value is less or equal to
+    /// `completed_value`.
+    pub fn recycle(&mut self, completed_value: u64) {
+        self.state.recycle(completed_value);
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.state.size
+    }
+
+    /// Returns the number of bytes currently used by frames in flight and the current frame.
+    pub fn get_used_bytes(&self) -> u64 {
+        self.state.used()
+    }
+}
+
+impl Drop for FrameRing {
+    fn drop(&mut self) {
+        unsafe { self.buffer.destroy(&self.device) };
+    }
+}
+
+/// The allocation state of a [`FrameRing`].
+///
+/// `head` and `tail` are monotonically increasing byte positions. The offset into the buffer is
+/// the position modulo the size. Everything between `tail` and `head` may be in use.
+pub(in crate::vulkan) struct RingState {
+    size: u64,
+    alignment: u64,
+    head: u64,
+    tail: u64,
+    /// The timeline value and end position of every frame in flight in submission order.
+    frames: VecDeque<(u64, u64)>,
+}
+
+impl RingState {
+    pub(in crate::vulkan) fn new(size: u64, alignment: u64) -> Self {
+        Self {
+            size,
+            alignment,
+            head: 0,
+            tail: 0,
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub(in crate::vulkan) fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub(in crate::vulkan) fn used(&self) -> u64 {
+        self.head - self.tail
+    }
+
+    /// Returns the offset of a new range of `size` bytes.
+    pub(in crate::vulkan) fn allocate(&mut self, size: u64) -> Result<u64, FrameRingError> {
+        if size == 0 {
+            return Err(FrameRingError::EmptyPush);
+        }
+
+        // Nothing can be in use so start over to avoid wrap padding
+        if self.frames.is_empty() && self.head == self.tail {
+            self.head = 0;
+            self.tail = 0;
+        }
+
+        let offset = self.head % self.size;
+        let aligned = (offset + self.alignment - 1) & !(self.alignment - 1);
+
+        // Ranges must be contiguous so if it doesnt fit before the end we skip to the start
+        let (start, padding) = if aligned + size <= self.size {
+            (aligned, aligned - offset)
+        } else {
+            (0, self.size - offset)
+        };
+
+        let available = self.size - self.used();
+        if padding + size > available {
+            return Err(FrameRingError::OutOfSpace {
+                requested: size,
+                available: available.saturating_sub(padding),
+            });
+        }
+
+        self.head += padding + size;
+        Ok(start)
+    }
+
+    pub(in crate::vulkan) fn end_frame(&mut self, timeline_value: u64) {
+        self.frames.push_back((timeline_value, self.head));
+    }
+
+    pub(in crate::vulkan) fn recycle(&mut self, completed_value: u64) {
+        while let Some((timeline_value, end)) = self.frames.front().copied() {
+            if timeline_value > completed_value {
+                break;
+            }
+            self.tail = end;
+            self.frames.pop_front();
+        }
+    }
+}
+
+/// Tracks the device memory allocated through this module.
+///
+/// Pool allocations are the large blocks of memory which are suballocated (for example the
+/// buffers of a [`BufferArena`]). Dedicated allocations back exactly one resource.
+#[derive(Default, Debug)]
+pub struct MemoryStatistics {
+    pool_bytes: AtomicU64,
+    pool_count: AtomicU64,
+    dedicated_bytes: AtomicU64,
+    dedicated_count: AtomicU64,
+}
+
+impl MemoryStatistics {
+    pub fn snapshot(&self) -> MemoryStatisticsSnapshot {
+        MemoryStatisticsSnapshot {
+            pool_bytes: self.pool_bytes.load(Ordering::Relaxed),
+            pool_allocation_count: self.pool_count.load(Ordering::Relaxed),
+            dedicated_bytes: self.dedicated_bytes.load(Ordering::Relaxed),
+            dedicated_allocation_count: self.dedicated_count.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_pool_allocation(&self, size: u64) {
+        self.pool_bytes.fetch_add(size, Ordering::Relaxed);
+        self.pool_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_pool_free(&self, size: u64) {
+        self.pool_bytes.fetch_sub(size, Ordering::Relaxed);
+        self.pool_count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn record_dedicated_allocation(&self, size: u64) {
+        self.dedicated_bytes.fetch_add(size, Ordering::Relaxed);
+        self.dedicated_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_dedicated_free(&self, size: u64) {
+        self.dedicated_bytes.fetch_sub(size, Ordering::Relaxed);
+        self.dedicated_count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct MemoryStatisticsSnapshot {
+    pub pool_bytes: u64,
+    pub pool_allocation_count: u64,
+    pub dedicated_bytes: u64,
+    pub dedicated_allocation_count: u64,
+}
+
+impl MemoryStatisticsSnapshot {
+    pub fn get_total_bytes(&self) -> u64 {
+        self.pool_bytes + self.dedicated_bytes
+    }
+}
+
+/// Called by a [`DeviceAllocator`] if an allocation would exceed the soft budget of a heap. The
+/// callback should release memory (for example by evicting streamed assets) before returning.
+///
+/// The callback is called from the thread performing the allocation and must not allocate device
+/// memory itself.
+pub type MemoryPressureCallback = dyn Fn(&MemoryPressure) + Send + Sync;
+
+/// Information passed to a [`MemoryPressureCallback`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MemoryPressure {
+    pub heap_index: u32,
+    /// The size of the allocation which exceeded the budget.
+    pub requested_bytes: u64,
+    pub allocated_bytes: u64,
+    pub budget: u64,
+}
+
+/// What a [`DeviceAllocator`] does if an allocation would exceed the soft budget of a heap.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum MemoryBudgetPolicy {
+    /// Allocations are not checked against the budget.
+    Ignore,
+
+    /// A warning is logged and the [`MemoryPressureCallback`] is called but the allocation is
+    /// performed anyways.
+    #[default]
+    Warn,
+
+    /// A warning is logged and the [`MemoryPressureCallback`] is called. If the allocation still
+    /// exceeds the budget afterwards it fails with [`vk::Result::ERROR_OUT_OF_DEVICE_MEMORY`].
+    Fail,
+}
+
+/// The usage of a single memory heap as returned by [`DeviceAllocator::heap_usage`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HeapUsage {
+    pub size: u64,
+    /// The soft budget of the heap.
+    pub budget: u64,
+    /// The number of bytes allocated through the [`DeviceAllocator`].
+    pub allocated_bytes: u64,
+}
+
+/// Allocates all device memory used by [`crate::vulkan::memory`] and tracks it per heap.
+///
+/// Every heap has a soft budget. By default it is the budget reported by `VK_EXT_memory_budget`
+/// or the size of the heap if the extension is not available. The budget can be overwritten
+/// using [`DeviceAllocator::set_soft_budget`]. What happens if the budget is exceeded is controlled
+/// by the [`MemoryBudgetPolicy`].
+pub struct DeviceAllocator {
+    statistics: MemoryStatistics,
+    memory_type_heaps: Box<[u32]>,
+    heaps: Box<[HeapTracker]>,
+    guarded: Mutex<DeviceAllocatorGuarded>,
+}
+
+impl DeviceAllocator {
+    pub(in crate::vulkan) fn new(properties: &vk::PhysicalDeviceMemoryProperties) -> Self {
+        let memory_type_heaps = properties.memory_types[0..(properties.memory_type_count as usize)].iter()
+            .map(|memory_type| memory_type.heap_index)
+            .collect();
+        let heaps = properties.memory_heaps[0..(properties.memory_heap_count as usize)].iter()
+            .map(|heap| HeapTracker {
+                size: heap.size,
+                allocated: AtomicU64::new(0),
+                reported_budget: AtomicU64::new(heap.size),
+                budget_override: AtomicU64::new(NO_BUDGET_OVERRIDE),
+            })
+            .collect();
+
+        Self {
+            statistics: MemoryStatistics::default(),
+            memory_type_heaps,
+            heaps,
+            guarded: Mutex::new(DeviceAllocatorGuarded {
+                policy: MemoryBudgetPolicy::default(),
+                pressure_callback: None,
+            }),
+        }
+    }
+
+    pub fn get_statistics(&self) -> &MemoryStatistics {
+        &self.statistics
+    }
+
+    /// Returns the usage of every memory heap indexed by the heap index.
+    pub fn heap_usage(&self) -> Vec<HeapUsage> {
+        self.heaps.iter().map(|heap| HeapUsage {
+            size: heap.size,
+            budget: heap.get_budget(),
+            allocated_bytes: heap.allocated.load(Ordering::Relaxed),
+        }).collect()
+    }
+
+    pub fn set_budget_policy(&self, policy: MemoryBudgetPolicy) {
+        self.guarded.lock().unwrap().policy = policy;
+    }
+
+    pub fn get_budget_policy(&self) -> MemoryBudgetPolicy {
+        self.guarded.lock().unwrap().policy
+    }
+
+    /// Sets the callback called if an allocation would exceed the soft budget of a heap.
+    pub fn set_memory_pressure_callback<F>(&self, callback: F) where F: Fn(&MemoryPressure) + Send + Sync + 'static {
+        self.guarded.lock().unwrap().pressure_callback = Some(Arc::new(callback));
+    }
+
+    /// Overwrites the soft budget of a heap. If [`None`] the reported budget is used again.
+    ///
+    /// # Panics
+    /// If `heap_index` is not a valid heap index.
+    pub fn set_soft_budget(&self, heap_index: u32, budget: Option<u64>) {
+        self.heaps[heap_index as usize].budget_override.store(budget.unwrap_or(NO_BUDGET_OVERRIDE), Ordering::Relaxed);
+    }
+
+    /// Updates the budgets reported by the driver. `budgets` is indexed by the heap index.
+    pub(in crate::vulkan) fn update_reported_budgets(&self, budgets: &[u64]) {
+        for (heap, budget) in self.heaps.iter().zip(budgets) {
+            heap.reported_budget.store(*budget, Ordering::Relaxed);
+        }
+    }
+
+    /// Allocates device memory after checking the budget of the heap.
+    ///
+    /// # Safety
+    /// `allocate_info` must be a valid allocate info for `device`.
+    pub(in crate::vulkan) unsafe fn allocate_memory(&self, device: &ash::Device, allocate_info: &vk::MemoryAllocateInfo) -> Result<vk::DeviceMemory, vk::Result> {
+        self.reserve(allocate_info.memory_type_index, allocate_info.allocation_size)?;
+
+        device.allocate_memory(allocate_info, None).inspect_err(|_| {
+            self.release(allocate_info.memory_type_index, allocate_info.allocation_size);
+        })
+    }
+
+    /// Frees memory previously allocated by [`DeviceAllocator::allocate_memory`].
+    ///
+    /// # Safety
+    /// The memory must not be used by the device anymore and `memory_type` and `size` must match
+    /// the values used to allocate the memory.
+    pub(in crate::vulkan) unsafe fn free_memory(&self, device: &ash::Device, memory: vk::DeviceMemory, memory_type: u32, size: u64) {
+        device.free_memory(memory, None);
+        self.release(memory_type, size);
+    }
+
+    /// Adds `size` bytes to the heap of the memory type applying the budget policy.
+    fn reserve(&self, memory_type: u32, size: u64) -> Result<(), vk::Result> {
+        let heap_index = self.memory_type_heaps[memory_type as usize];
+        let heap = &self.heaps[heap_index as usize];
+
+        let (policy, callback) = {
+            let guard = self.guarded.lock().unwrap();
+            (guard.policy, guard.pressure_callback.clone())
+        };
+
+        let allocated = heap.allocated.fetch_add(size, Ordering::Relaxed) + size;
+        let budget = heap.get_budget();
+        if policy == MemoryBudgetPolicy::Ignore || allocated <= budget {
+            return Ok(());
+        }
+
+        // Undo the reservation so the callback sees the actual usage
+        let allocated = heap.allocated.fetch_sub(size, Ordering::Relaxed) - size;
+        log::warn!("Allocation of {} bytes exceeds the soft budget of heap {} ({} of {} bytes allocated)", size, heap_index, allocated, budget);
+
+        if let Some(callback) = callback {
+            callback(&MemoryPressure {
+                heap_index,
+                requested_bytes: size,
+                allocated_bytes: allocated,
+                budget,
+            });
+        }
+
+        let allocated = heap.allocated.fetch_add(size, Ordering::Relaxed) + size;
+        let budget = heap.get_budget();
+        if policy == MemoryBudgetPolicy::Fail && allocated > budget {
+            heap.allocated.fetch_sub(size, Ordering::Relaxed);
+            log::error!("Allocation of {} bytes still exceeds the soft budget of heap {} ({} bytes). Failing allocation", size, heap_index, budget);
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, memory_type: u32, size: u64) {
+        let heap_index = self.memory_type_heaps[memory_type as usize];
+        self.heaps[heap_index as usize].allocated.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+/// Value of [`HeapTracker::budget_override`] if the budget is not overwritten.
+const NO_BUDGET_OVERRIDE: u64 = u64::MAX;
+
+struct HeapTracker {
+    size: u64,
+    allocated: AtomicU64,
+    reported_budget: AtomicU64,
+    budget_override: AtomicU64,
+}
+
+impl HeapTracker {
+    fn get_budget(&self) -> u64 {
+        match self.budget_override.load(Ordering::Relaxed) {
+            NO_BUDGET_OVERRIDE => self.reported_budget.load(Ordering::Relaxed),
+            budget => budget,
+        }
+    }
+}
+
+struct DeviceAllocatorGuarded {
+    policy: MemoryBudgetPolicy,
+    pressure_callback: Option<Arc<MemoryPressureCallback>>,
+}
+
+/// A resource to allocate memory for with [`VulkanMemoryAllocator::allocate_resource`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MemoryResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+/// The memory requirements of a resource including its dedicated allocation preferences.
+#[derive(Copy, Clone, Debug)]
+pub struct ResourceMemoryRequirements {
+    pub requirements: vk::MemoryRequirements,
+    pub prefers_dedicated_allocation: bool,
+    pub requires_dedicated_allocation: bool,
+}
+
+impl ResourceMemoryRequirements {
+    pub fn query(device: &MainDeviceContext, resource: MemoryResource) -> Self {
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::builder()
+            .push_next(&mut dedicated);
+
+        unsafe {
+            match resource {
+                MemoryResource::Buffer(buffer) => {
+                    let info = vk::BufferMemoryRequirementsInfo2::builder().buffer(buffer);
+                    device.get_device().get_buffer_memory_requirements2(&info, &mut requirements);
+                }
+                MemoryResource::Image(image) => {
+                    let info = vk::ImageMemoryRequirementsInfo2::builder().image(image);
+                    device.get_device().get_image_memory_requirements2(&info, &mut requirements);
+                }
+            }
+        }
+        let requirements = requirements.memory_requirements;
+
+        Self {
+            requirements,
+            prefers_dedicated_allocation: dedicated.prefers_dedicated_allocation == vk::TRUE,
+            requires_dedicated_allocation: dedicated.requires_dedicated_allocation == vk::TRUE,
+        }
+    }
+}
+
+/// Device memory backing exactly one resource. The memory is freed when dropped but the
+/// resource must be destroyed by the caller before that.
+struct DedicatedAllocation {
+    device: Arc<MainDeviceContext>,
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    size: u64,
+}
+
+impl DedicatedAllocation {
+    /// Allocates memory for the resource and binds it.
+    fn new(device: Arc<MainDeviceContext>, resource: MemoryResource, requirements: &ResourceMemoryRequirements, memory_type: u32) -> Result<Self, vk::Result> {
+        let vk_device = device.get_device();
+        let size = requirements.requirements.size;
+
+        let mut dedicated_info = match resource {
+            MemoryResource::Buffer(buffer) => vk::MemoryDedicatedAllocateInfo::builder().buffer(buffer),
+            MemoryResource::Image(image) => vk::MemoryDedicatedAllocateInfo::builder().image(image),
+        };
+        let mut flags_info = vk::MemoryAllocateFlagsInfo::builder();
+        if let MemoryResource::Buffer(_) = resource {
+            flags_info = flags_info.flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        }
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type)
+            .push_next(&mut dedicated_info)
+            .push_next(&mut flags_info);
+
+        let memory = unsafe { device.allocate_memory(&allocate_info) }?;
+
+        let result = unsafe {
+            match resource {
+                MemoryResource::Buffer(buffer) => vk_device.bind_buffer_memory(buffer, memory, 0),
+                MemoryResource::Image(image) => vk_device.bind_image_memory(image, memory, 0),
+            }
+        };
+        if let Err(err) = result {
+            unsafe { device.get_allocator().free_memory(vk_device, memory, memory_type, size) };
+            return Err(err);
+        }
+
+        device.get_memory_statistics().record_dedicated_allocation(size);
+
+        Ok(Self {
+            device,
+            memory,
+            memory_type,
+            size,
+        })
+    }
+}
+
+impl Drop for DedicatedAllocation {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.get_allocator().free_memory(self.device.get_device(), self.memory, self.memory_type, self.size);
+        }
+        self.device.get_memory_statistics().record_dedicated_free(self.size);
+    }
+}
+
+/// The default page size of device local memory types used by [`HeapConfig::default_for`].
+pub const DEFAULT_DEVICE_PAGE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The default page size of host visible memory types used by [`HeapConfig::default_for`].
+pub const DEFAULT_HOST_PAGE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Called once for every memory type to customize its [`HeapConfig`]. Receives the memory type
+/// index, the memory type, its heap and the default config and returns the config to use.
+pub type HeapConfigFn = dyn Fn(u32, &vk::MemoryType, &vk::MemoryHeap, HeapConfig) -> HeapConfig + Send;
+
+/// Defines how a [`VulkanMemoryAllocator`] allocates memory of a memory type.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HeapConfig {
+    /// The size of the pages allocations are suballocated from. Must be a power of 2 and at
+    /// least 1KiB. If 0 no pages are created and every allocation uses its own device memory.
+    pub page_size: u64,
+
+    /// The maximum number of pages created for the memory type.
+    pub max_pages: u32,
+
+    /// Allocations of at least this many bytes use their own device memory instead of a page.
+    pub dedicated_threshold: u64,
+}
+
+impl HeapConfig {
+    /// Returns a config which allocates every allocation separately.
+    pub const fn unpooled() -> Self {
+        Self {
+            page_size: 0,
+            max_pages: 0,
+            dedicated_threshold: 0,
+        }
+    }
+
+    /// Returns the default config for a memory type.
+    ///
+    /// Device local memory uses [`DEFAULT_DEVICE_PAGE_SIZE`] pages, host visible memory uses
+    /// [`DEFAULT_HOST_PAGE_SIZE`] pages. Pages are never larger than an eighth of the heap so
+    /// small heaps are not exhausted by a few pages. Lazily allocated memory is only used for
+    /// transient attachments which should not share memory so it is not pooled.
+    pub fn default_for(memory_type: &vk::MemoryType, heap: &vk::MemoryHeap) -> Self {
+        let flags = memory_type.property_flags;
+        if flags.contains(vk::MemoryPropertyFlags::LAZILY_ALLOCATED) || heap.size < 8 * 1024 {
+            return Self::unpooled();
+        }
+
+        let preferred = if flags.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL) && !flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            DEFAULT_DEVICE_PAGE_SIZE
+        } else {
+            DEFAULT_HOST_PAGE_SIZE
+        };
+        let eighth = heap.size / 8;
+        let page_size = std::cmp::min(preferred, 1u64 << (u64::BITS - 1 - eighth.leading_zeros()));
+
+        Self {
+            page_size,
+            max_pages: (heap.size / page_size) as u32,
+            dedicated_threshold: page_size / 4,
+        }
+    }
+
+    fn is_pooled(&self) -> bool {
+        self.page_size != 0 && self.max_pages != 0
+    }
+
+    /// Returns true if an allocation of `size` bytes must use its own device memory.
+    fn is_separate(&self, size: u64) -> bool {
+        !self.is_pooled() || size >= self.dedicated_threshold || size > self.page_size
+    }
+}
+
+/// Stores the [`HeapConfig`] of every memory type of a device.
+#[derive(Clone, Debug)]
+pub struct HeapManager {
+    configs: Box<[HeapConfig]>,
+    heap_indices: Box<[u32]>,
+}
+
+impl HeapManager {
+    /// Creates the configs for all memory types in `properties`. If `config_fn` is provided it
+    /// is called for every memory type to override the default config.
+    ///
+    /// # Panics
+    /// If a config has an invalid page size.
+    pub fn new(properties: &vk::PhysicalDeviceMemoryProperties, config_fn: Option<&HeapConfigFn>) -> Self {
+        let memory_types = &properties.memory_types[0..properties.memory_type_count as usize];
+
+        let configs = memory_types.iter().enumerate().map(|(index, memory_type)| {
+            let heap = &properties.memory_heaps[memory_type.heap_index as usize];
+            let mut config = HeapConfig::default_for(memory_type, heap);
+            if let Some(config_fn) = config_fn {
+                config = config_fn(index as u32, memory_type, heap, config);
+            }
+            assert!(config.page_size == 0 || (config.page_size.is_power_of_two() && config.page_size >= 1024), "Invalid page size {} for memory type {}", config.page_size, index);
+
+            config
+        }).collect();
+
+        Self {
+            configs,
+            heap_indices: memory_types.iter().map(|memory_type| memory_type.heap_index).collect(),
+        }
+    }
+
+    /// # Panics
+    /// If `memory_type` is not a valid memory type index.
+    pub fn get_config(&self, memory_type: u32) -> HeapConfig {
+        self.configs[memory_type as usize]
+    }
+
+    pub fn get_memory_type_count(&self) -> u32 {
+        self.configs.len() as u32
+    }
+}
+
+/// Statistics of one memory type of a [`VulkanMemoryAllocator`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HeapStatistics {
+    pub memory_type: u32,
+    pub heap_index: u32,
+    pub config: HeapConfig,
+    pub page_count: usize,
+    /// The number of live allocations suballocated from pages.
+    pub pooled_allocation_count: usize,
+    /// The number of live allocations using their own device memory.
+    pub separate_allocation_count: u64,
+    pub separate_bytes: u64,
+}
+
+/// Suballocates resource memory from large device memory pages to avoid hitting the driver limit
+/// on the number of device memory allocations.
+///
+/// Every memory type uses its own set of pages which are created on demand as configured by the
+/// [`HeapManager`]. Pages are only freed when the allocator is dropped. All pooled allocations
+/// must be dropped before the allocator is destroyed. This is verified in debug builds.
+pub struct VulkanMemoryAllocator {
+    device: Arc<MainDeviceContext>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    heap_manager: HeapManager,
+    memory_types: Box<[MemoryTypePool]>,
+    /// Set if `bufferImageGranularity` is 1 in which case linear and optimal resources may share
+    /// pages.
+    shared_tiling: bool,
+}
+
+impl VulkanMemoryAllocator {
+    pub fn new(device: Arc<MainDeviceContext>, config_fn: Option<&HeapConfigFn>) -> Self {
+        let memory_properties = unsafe {
+            device.get_instance().get_instance().get_physical_device_memory_properties(device.get_physical_device())
+        };
+        let heap_manager = HeapManager::new(&memory_properties, config_fn);
+        let shared_tiling = device.get_limits().buffer_image_granularity <= 1;
+
+        let memory_types = heap_manager.configs.iter().map(|config| {
+            let create_allocator = || config.is_pooled().then(|| PoolAllocator::new(config.page_size as usize));
+            MemoryTypePool {
+                allocators: [create_allocator(), create_allocator()],
+                pages: Mutex::new(Vec::new()),
+                separate: Arc::new(SeparateCounters::default()),
+            }
+        }).collect();
+
+        Self {
+            device,
+            memory_properties,
+            heap_manager,
+            memory_types,
+            shared_tiling,
+        }
+    }
+
+    /// Allocates memory satisfying `requirements` from a memory type with all `properties` flags
+    /// for a resource with the provided `tiling`.
+    ///
+    /// If no memory type is suitable [`vk::Result::ERROR_FEATURE_NOT_PRESENT`] is returned. If
+    /// the memory type already has the maximum number of pages and none of them has enough free
+    /// space [`vk::Result::ERROR_OUT_OF_DEVICE_MEMORY`] is returned.
+    ///
+    /// Allocations of host visible memory types which are not host coherent are aligned and
+    /// padded to `nonCoherentAtomSize` so they can be flushed and invalidated independently.
+    ///
+    /// Unless `bufferImageGranularity` is 1 linear and optimal resources are suballocated from
+    /// separate pages so that they never violate the granularity.
+    pub fn allocate(&self, requirements: vk::MemoryRequirements, properties: vk::MemoryPropertyFlags, tiling: ResourceTiling) -> Result<VkMemoryAllocation, vk::Result> {
+        let memory_type = find_memory_type(&self.memory_properties, requirements.memory_type_bits, properties, vk::MemoryPropertyFlags::empty())
+            .ok_or(vk::Result::ERROR_FEATURE_NOT_PRESENT)?;
+        let config = self.heap_manager.get_config(memory_type);
+        let pool = &self.memory_types[memory_type as usize];
+
+        let (size, alignment) = get_allocation_layout(&requirements, self.get_atom_size(memory_type));
+        let size = NonZeroUsize::new(size as usize).ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+        let tiling = if self.shared_tiling { ResourceTiling::Linear } else { tiling };
+        let allocator = match &pool.allocators[tiling as usize] {
+            Some(allocator) if !config.is_separate(size.get() as u64) => allocator,
+            _ => return self.allocate_separate(memory_type, requirements.size),
+        };
+        let alignment = NonZeroUsize::new(alignment as usize).unwrap_or(NonZeroUsize::new(1).unwrap());
+
+        if let Some(allocation) = allocator.allocate_aligned(size, alignment) {
+            return Ok(self.create_pooled(allocation, memory_type, config.page_size));
+        }
+
+        // Hold the lock while creating the page so concurrent allocations dont all create one
+        let mut pages = pool.pages.lock().unwrap();
+        if let Some(allocation) = allocator.allocate_aligned(size, alignment) {
+            return Ok(self.create_pooled(allocation, memory_type, config.page_size));
+        }
+        if pages.len() >= config.max_pages as usize {
+            log::warn!("Reached max page count {} of memory type {}", config.max_pages, memory_type);
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+
+        let page = self.create_page(memory_type, config.page_size, pages.len())?;
+        log::debug!("Created memory page {:?} of size {} for memory type {} ({:?})", page.memory, config.page_size, memory_type, tiling);
+        pages.push(page);
+        allocator.add_page(Box::new(page), config.page_size as usize);
+        drop(pages);
+
+        // Can only fail for alignments larger than the page size
+        allocator.allocate_aligned(size, alignment)
+            .map(|allocation| self.create_pooled(allocation, memory_type, config.page_size))
+            .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+    }
+
+    /// Allocates memory for `resource` from a memory type with all `properties` flags and binds
+    /// it to the resource. Buffers must use [`ResourceTiling::Linear`].
+    ///
+    /// The memory requirements are queried from the driver. If the driver requires a dedicated
+    /// allocation for the resource, or prefers one and the resource would use its own device
+    /// memory anyway, the memory is allocated as a dedicated allocation. Otherwise the memory is
+    /// allocated like [`VulkanMemoryAllocator::allocate`] does.
+    pub fn allocate_resource(&self, resource: MemoryResource, properties: vk::MemoryPropertyFlags, tiling: ResourceTiling) -> Result<VkMemoryAllocation, vk::Result> {
+        let requirements = ResourceMemoryRequirements::query(&self.device, resource);
+        let memory_type = find_memory_type(&self.memory_properties, requirements.requirements.memory_type_bits, properties, vk::MemoryPropertyFlags::empty())
+            .ok_or(vk::Result::ERROR_FEATURE_NOT_PRESENT)?;
+
+        let (size, _) = get_allocation_layout(&requirements.requirements, self.get_atom_size(memory_type));
+        let large = self.heap_manager.get_config(memory_type).is_separate(size);
+        if requirements.requires_dedicated_allocation || (requirements.prefers_dedicated_allocation && large) {
+            return self.allocate_dedicated(resource, &requirements, memory_type);
+        }
+
+        let allocation = self.allocate(requirements.requirements, properties, tiling)?;
+        unsafe {
+            match resource {
+                MemoryResource::Buffer(buffer) => self.device.get_device().bind_buffer_memory(buffer, allocation.memory, allocation.offset),
+                MemoryResource::Image(image) => self.device.get_device().bind_image_memory(image, allocation.memory, allocation.offset),
+            }
+        }?;
+
+        Ok(allocation)
+    }
+
+    pub fn get_heap_manager(&self) -> &HeapManager {
+        &self.heap_manager
+    }
+
+    /// Returns the statistics of every memory type.
+    pub fn get_heap_statistics(&self) -> Vec<HeapStatistics> {
+        self.memory_types.iter().enumerate().map(|(memory_type, pool)| {
+            HeapStatistics {
+                memory_type: memory_type as u32,
+                heap_index: self.heap_manager.heap_indices[memory_type],
+                config: self.heap_manager.configs[memory_type],
+                page_count: pool.pages.lock().unwrap().len(),
+                pooled_allocation_count: pool.allocators.iter().flatten().map(PoolAllocator::get_allocation_count).sum(),
+                separate_allocation_count: pool.separate.count.load(Ordering::Relaxed),
+                separate_bytes: pool.separate.bytes.load(Ordering::Relaxed),
+            }
+        }).collect()
+    }
+
+    /// Returns the number of allocations that have not been dropped yet.
+    pub fn get_allocation_count(&self) -> usize {
+        self.get_heap_statistics().iter()
+            .map(|statistics| statistics.pooled_allocation_count + statistics.separate_allocation_count as usize)
+            .sum()
+    }
+
+    /// Registers gauges for the device memory usage and the number of live allocations of this
+    /// allocator with `statistics`. The gauges read 0 after the allocator has been dropped.
+    pub fn register_statistics(self: &Arc<Self>, statistics: &Statistics) {
+        let device = self.device.clone();
+        statistics.register_gauge_fn("memory.device_bytes", Box::new(move || {
+            device.get_memory_statistics().snapshot().get_total_bytes()
+        }));
+
+        let device = self.device.clone();
+        statistics.register_gauge_fn("memory.device_allocations", Box::new(move || {
+            let snapshot = device.get_memory_statistics().snapshot();
+            snapshot.pool_allocation_count + snapshot.dedicated_allocation_count
+        }));
+
+        let allocator = Arc::downgrade(self);
+        statistics.register_gauge_fn("memory.allocator_allocations", Box::new(move || {
+            allocator.upgrade().map(|allocator| allocator.get_allocation_count() as u64).unwrap_or(0)
+        }));
+    }
+
+    fn allocate_memory(&self, memory_type: u32, size: u64) -> Result<vk::DeviceMemory, vk::Result> {
+        // Buffers with device addresses may be bound to any allocation
+        let mut flags_info = vk::MemoryAllocateFlagsInfo::builder()
+            .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type)
+            .push_next(&mut flags_info);
+
+        unsafe { self.device.allocate_memory(&allocate_info) }
+    }
+
+    fn create_page(&self, memory_type: u32, page_size: u64, index: usize) -> Result<MemoryPage, vk::Result> {
+        let memory = self.allocate_memory(memory_type, page_size)?;
+        let mapped = match self.map_if_host_visible(memory_type, memory) {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                unsafe { self.device.get_allocator().free_memory(self.device.get_device(), memory, memory_type, page_size) };
+                return Err(err);
+            }
+        };
+        self.device.get_memory_statistics().record_pool_allocation(page_size);
+
+        if self.device.is_object_naming_enabled() {
+            self.device.set_object_name(memory, &format!("agnaji pool type={} page={}", memory_type, index));
+        }
+
+        Ok(MemoryPage {
+            memory,
+            mapped,
+        })
+    }
+
+    /// Persistently maps the whole memory if the memory type is host visible. Returns a null
+    /// pointer otherwise.
+    fn map_if_host_visible(&self, memory_type: u32, memory: vk::DeviceMemory) -> Result<*mut u8, vk::Result> {
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        if !flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            return Ok(std::ptr::null_mut());
+        }
+
+        let mapped = unsafe {
+            self.device.get_device().map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+        }?;
+        Ok(mapped as *mut u8)
+    }
+
+    /// Returns the `nonCoherentAtomSize` of the device if `memory_type` is host visible but not
+    /// host coherent or [`None`] otherwise.
+    fn get_atom_size(&self, memory_type: u32) -> Option<u64> {
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        (flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) && !flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT))
+            .then(|| self.device.get_limits().non_coherent_atom_size.max(1))
+    }
+
+    /// Creates the mapping info of an allocation at `offset` of `memory` which is mapped at
+    /// `mapped`.
+    fn create_mapping(&self, memory_type: u32, mapped: *mut u8, offset: u64, memory_size: u64) -> Option<MemoryMapping> {
+        NonNull::new(mapped).map(|mapped| MemoryMapping {
+            device: self.device.clone(),
+            // Pages are mapped as a whole
+            ptr: unsafe { NonNull::new_unchecked(mapped.as_ptr().add(offset as usize)) },
+            atom_size: self.get_atom_size(memory_type),
+            memory_size,
+        })
+    }
+
+    fn create_pooled(&self, allocation: PoolAllocation<MemoryPage>, memory_type: u32, page_size: u64) -> VkMemoryAllocation {
+        let page = *allocation.get_pool();
+        let offset = allocation.get_offset() as u64;
+
+        VkMemoryAllocation {
+            memory: page.memory,
+            offset,
+            memory_type,
+            mapping: self.create_mapping(memory_type, page.mapped, offset, page_size),
+            backing: AllocationBacking::Pooled(allocation),
+        }
+    }
+
+    fn allocate_separate(&self, memory_type: u32, size: u64) -> Result<VkMemoryAllocation, vk::Result> {
+        let memory = self.allocate_memory(memory_type, size)?;
+        let mapped = match self.map_if_host_visible(memory_type, memory) {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                unsafe { self.device.get_allocator().free_memory(self.device.get_device(), memory, memory_type, size) };
+                return Err(err);
+            }
+        };
+        self.device.get_memory_statistics().record_dedicated_allocation(size);
+
+        let counters = self.memory_types[memory_type as usize].separate.clone();
+        counters.count.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(size, Ordering::Relaxed);
+
+        Ok(VkMemoryAllocation {
+            memory,
+            offset: 0,
+            memory_type,
+            mapping: self.create_mapping(memory_type, mapped, 0, size),
+            backing: AllocationBacking::Separate {
+                device: self.device.clone(),
+                size,
+                counters,
+            },
+        })
+    }
+
+    fn allocate_dedicated(&self, resource: MemoryResource, requirements: &ResourceMemoryRequirements, memory_type: u32) -> Result<VkMemoryAllocation, vk::Result> {
+        let allocation = DedicatedAllocation::new(self.device.clone(), resource, requirements, memory_type)?;
+        let mapped = self.map_if_host_visible(memory_type, allocation.memory)?;
+
+        let counters = self.memory_types[memory_type as usize].separate.clone();
+        counters.count.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(allocation.size, Ordering::Relaxed);
+
+        Ok(VkMemoryAllocation {
+            memory: allocation.memory,
+            offset: 0,
+            memory_type,
+            mapping: self.create_mapping(memory_type, mapped, 0, allocation.size),
+            backing: AllocationBacking::Dedicated {
+                allocation,
+                counters,
+            },
+        })
+    }
+}
+
+impl Drop for VulkanMemoryAllocator {
+    fn drop(&mut self) {
+        let mut leaked = 0;
+        for (memory_type, allocator) in self.memory_types.iter().enumerate().flat_map(|(index, pool)| pool.allocators.iter().flatten().map(move |allocator| (index, allocator))) {
+            allocator.for_each_allocated_block(|block| {
+                log::error!("Leaked memory allocation {:?} (Memory: {:?}, Type: {}, Offset: {}, Size: {})", block.debug_name.unwrap_or("<unnamed>"), block.pool.memory, memory_type, block.offset, block.size);
+            });
+            leaked += allocator.get_allocation_count();
+        }
+        debug_assert_eq!(leaked, 0, "VulkanMemoryAllocator destroyed with live allocations");
+
+        let vk_device = self.device.get_device();
+        for (memory_type, pool) in self.memory_types.iter_mut().enumerate() {
+            let page_size = self.heap_manager.configs[memory_type].page_size;
+            for page in pool.pages.get_mut().unwrap().drain(..) {
+                unsafe { self.device.get_allocator().free_memory(vk_device, page.memory, memory_type as u32, page_size) };
+                self.device.get_memory_statistics().record_pool_free(page_size);
+            }
+        }
+    }
+}
+
+/// The tiling of the resource an allocation of a [`VulkanMemoryAllocator`] is bound to.
+///
+/// Vulkan requires linear and optimal resources in the same device memory to be at least
+/// `bufferImageGranularity` bytes apart so the allocator keeps them in separate pages.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ResourceTiling {
+    /// Buffers and images created with [`vk::ImageTiling::LINEAR`].
+    Linear = 0,
+
+    /// Images created with [`vk::ImageTiling::OPTIMAL`].
+    Optimal = 1,
+}
+
+struct MemoryTypePool {
+    /// The allocators of every [`ResourceTiling`] indexed by the tiling. [`None`] if the memory
+    /// type is not pooled.
+    allocators: [Option<PoolAllocator<MemoryPage>>; 2],
+    /// All pages created for this memory type. Also used to serialize page creation.
+    pages: Mutex<Vec<MemoryPage>>,
+    separate: Arc<SeparateCounters>,
+}
+
+/// Counts the allocations of a memory type which use their own device memory.
+#[derive(Default)]
+struct SeparateCounters {
+    count: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[derive(Copy, Clone)]
+struct MemoryPage {
+    memory: vk::DeviceMemory,
+    /// The persistent mapping of the whole page or null if the memory type is not host visible.
+    mapped: *mut u8,
+}
+
+// The mapped pointer is only accessed through MappedSlice
+unsafe impl Send for MemoryPage {
+}
+
+unsafe impl Sync for MemoryPage {
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MapError {
+    /// The memory type of the allocation is not host visible.
+    NotHostVisible,
+
+    Vulkan(vk::Result),
+}
+
+impl From<vk::Result> for MapError {
+    fn from(result: vk::Result) -> Self {
+        MapError::Vulkan(result)
+    }
+}
+
+/// Memory allocated by a [`VulkanMemoryAllocator`]. The memory is returned to the allocator
+/// when dropped but the resource bound to it must be destroyed by the caller before that.
+///
+/// Memory of host visible memory types is persistently mapped and can be accessed using
+/// [`VkMemoryAllocation::map`].
+pub struct VkMemoryAllocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    memory_type: u32,
+    mapping: Option<MemoryMapping>,
+    backing: AllocationBacking,
+}
+
+/// The persistent mapping of a host visible allocation.
+struct MemoryMapping {
+    device: Arc<MainDeviceContext>,
+    /// Points to the start of the allocation.
+    ptr: NonNull<u8>,
+    /// The `nonCoherentAtomSize` of the device or [`None`] if the memory type is host coherent.
+    atom_size: Option<u64>,
+    /// The size of the whole device memory the allocation is part of.
+    memory_size: u64,
+}
+
+enum AllocationBacking {
+    Pooled(PoolAllocation<MemoryPage>),
+    /// The allocation owns the whole device memory.
+    Separate {
+        device: Arc<MainDeviceContext>,
+        size: u64,
+        counters: Arc<SeparateCounters>,
+    },
+    /// The allocation is a dedicated allocation of the resource it is bound to.
+    Dedicated {
+        allocation: DedicatedAllocation,
+        counters: Arc<SeparateCounters>,
+    },
+}
+
+impl VkMemoryAllocation {
+    pub fn get_memory_type(&self) -> u32 {
+        self.memory_type
+    }
+
+    pub fn get_size(&self) -> u64 {
+        match &self.backing {
+            AllocationBacking::Pooled(allocation) => allocation.get_size() as u64,
+            AllocationBacking::Separate { size, .. } => *size,
+            AllocationBacking::Dedicated { allocation, .. } => allocation.size,
+        }
+    }
+
+    /// Returns true if the memory is host visible and can be accessed using
+    /// [`VkMemoryAllocation::map`].
+    pub fn is_mapped(&self) -> bool {
+        self.mapping.is_some()
+    }
+
+    /// Provides host access to the persistently mapped memory of the allocation.
+    ///
+    /// If the memory type is not host coherent the mapped range is invalidated so that device
+    /// writes are visible and flushed once the returned [`MappedSlice`] is dropped. All ranges
+    /// are aligned to `nonCoherentAtomSize`.
+    ///
+    /// The caller has to ensure that the device does not access the memory concurrently.
+    pub fn map(&mut self) -> Result<MappedSlice<'_>, MapError> {
+        let size = self.get_size();
+        let mapping = self.mapping.as_ref().ok_or(MapError::NotHostVisible)?;
+
+        let mapped = MappedSlice {
+            memory: self.memory,
+            offset: self.offset,
+            size,
+            mapping,
+            dirty: false,
+        };
+        mapped.sync(|device, ranges| unsafe { device.invalidate_mapped_memory_ranges(ranges) })?;
+
+        Ok(mapped)
+    }
+
+    /// Returns true if the allocation owns the whole device memory instead of being suballocated
+    /// from a page.
+    pub fn is_separate(&self) -> bool {
+        !matches!(self.backing, AllocationBacking::Pooled(_))
+    }
+
+    /// Returns true if the allocation is a dedicated allocation of the resource it is bound to.
+    pub fn is_dedicated(&self) -> bool {
+        matches!(self.backing, AllocationBacking::Dedicated { .. })
+    }
+
+    /// Sets the name used to report the allocation if it is leaked. Separate allocations name
+    /// their device memory instead.
+    pub fn set_debug_name(&self, name: &str) {
+        match &self.backing {
+            AllocationBacking::Pooled(allocation) => allocation.set_debug_name(name),
+            AllocationBacking::Separate { device, .. } => device.set_object_name(self.memory, name),
+            AllocationBacking::Dedicated { allocation, .. } => allocation.device.set_object_name(self.memory, name),
+        }
+    }
+}
+
+impl Drop for VkMemoryAllocation {
+    fn drop(&mut self) {
+        match &self.backing {
+            AllocationBacking::Pooled(_) => {}
+            AllocationBacking::Separate { device, size, counters } => {
+                unsafe { device.get_allocator().free_memory(device.get_device(), self.memory, self.memory_type, *size) };
+                device.get_memory_statistics().record_dedicated_free(*size);
+
+                counters.count.fetch_sub(1, Ordering::Relaxed);
+                counters.bytes.fetch_sub(*size, Ordering::Relaxed);
+            }
+            // The memory is freed when the allocation is dropped
+            AllocationBacking::Dedicated { allocation, counters } => {
+                counters.count.fetch_sub(1, Ordering::Relaxed);
+                counters.bytes.fetch_sub(allocation.size, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// The mapped pointer can only be accessed through a mutable reference
+unsafe impl Send for VkMemoryAllocation {
+}
+
+unsafe impl Sync for VkMemoryAllocation {
+}
+
+/// Host access to the mapped memory of a [`VkMemoryAllocation`]. Writes to non coherent memory
+/// are flushed when dropped.
+pub struct MappedSlice<'a> {
+    memory: vk::DeviceMemory,
+    offset: u64,
+    size: u64,
+    mapping: &'a MemoryMapping,
+    /// Set if the memory may have been written since the last flush.
+    dirty: bool,
+}
+
+impl MappedSlice<'_> {
+    pub fn as_slice(&self) -> &[u8] {
+        // The mapping covers the whole allocation
+        unsafe { std::slice::from_raw_parts(self.mapping.ptr.as_ptr(), self.size as usize) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.dirty = true;
+        // The mapping covers the whole allocation and the allocation is borrowed mutably
+        unsafe { std::slice::from_raw_parts_mut(self.mapping.ptr.as_ptr(), self.size as usize) }
+    }
+
+    /// Writes `value` at `offset` bytes into the allocation.
+    ///
+    /// # Panics
+    /// If the value does not fit into the allocation.
+    pub fn write<T: Pod>(&mut self, offset: u64, value: &T) {
+        self.write_slice(offset, std::slice::from_ref(value));
+    }
+
+    /// Writes `values` starting at `offset` bytes into the allocation.
+    ///
+    /// # Panics
+    /// If the values do not fit into the allocation.
+    pub fn write_slice<T: Pod>(&mut self, offset: u64, values: &[T]) {
+        let bytes: &[u8] = bytemuck::cast_slice(values);
+        let start = offset as usize;
+        self.as_mut_slice()[start..(start + bytes.len())].copy_from_slice(bytes);
+    }
+
+    /// Reads a value at `offset` bytes into the allocation.
+    ///
+    /// # Panics
+    /// If the value does not fit into the allocation.
+    pub fn read<T: Pod>(&self, offset: u64) -> T {
+        let start = offset as usize;
+        bytemuck::pod_read_unaligned(&self.as_slice()[start..(start + std::mem::size_of::<T>())])
+    }
+
+    /// Makes host writes available to the device. Does nothing for host coherent memory.
+    pub fn flush(&mut self) -> Result<(), vk::Result> {
+        self.sync(|device, ranges| unsafe { device.flush_mapped_memory_ranges(ranges) })?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Makes device writes visible to the host. Does nothing for host coherent memory.
+    pub fn invalidate(&self) -> Result<(), vk::Result> {
+        self.sync(|device, ranges| unsafe { device.invalidate_mapped_memory_ranges(ranges) })
+    }
+
+    fn sync<F>(&self, f: F) -> Result<(), vk::Result> where F: FnOnce(&ash::Device, &[vk::MappedMemoryRange]) -> Result<(), vk::Result> {
+        let Some((offset, size)) = get_non_coherent_range(self.mapping.atom_size, self.offset, self.size, self.mapping.memory_size) else {
+            return Ok(());
+        };
+
+        let range = vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(offset)
+            .size(size)
+            .build();
+        f(self.mapping.device.get_device(), &[range])
+    }
+}
+
+impl Drop for MappedSlice<'_> {
+    fn drop(&mut self) {
+        if self.dirty {
+            if let Err(err) = self.flush() {
+                log::warn!("Failed to flush mapped memory {:?}: {:?}", self.memory, err);
+            }
+        }
+    }
+}
+
+/// Returns the size and alignment used to suballocate memory for `requirements`.
+///
+/// Flushes and invalidations of non coherent memory are expanded to multiples of `atom_size` (see
+/// [`get_non_coherent_range`]). Allocations of such memory types are therefore aligned to and
+/// padded to multiples of `atom_size` so that the expanded range of one allocation never
+/// overlaps another allocation. Invalidating a overlapping range would discard host writes of
+/// the neighbouring allocation.
+fn get_allocation_layout(requirements: &vk::MemoryRequirements, atom_size: Option<u64>) -> (u64, u64) {
+    let alignment = requirements.alignment.max(1);
+    match atom_size {
+        Some(atom_size) => (requirements.size.div_ceil(atom_size) * atom_size, alignment.max(atom_size)),
+        None => (requirements.size, alignment),
+    }
+}
+
+/// Returns the range to flush or invalidate for `size` bytes at `offset` of a device memory
+/// object of `memory_size` bytes. The range is expanded to multiples of `atom_size` or to the
+/// end of the memory as required by the spec. Returns [`None`] for coherent memory, where
+/// `atom_size` is [`None`].
+fn get_non_coherent_range(atom_size: Option<u64>, offset: u64, size: u64, memory_size: u64) -> Option<(u64, u64)> {
+    let atom_size = atom_size?;
+
+    let start = offset - (offset % atom_size);
+    let end = (offset + size).div_ceil(atom_size) * atom_size;
+    if end >= memory_size {
+        Some((start, vk::WHOLE_SIZE))
+    } else {
+        Some((start, end - start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_alignment() {
+        let limits = vk::PhysicalDeviceLimits {
+            min_uniform_buffer_offset_alignment: 256,
+            min_storage_buffer_offset_alignment: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(BufferUsageClass::Uniform.get_offset_alignment(&limits), 256);
+        assert_eq!(BufferUsageClass::Storage.get_offset_alignment(&limits), 1);
+    }
+
+    #[test]
+    fn statistics() {
+        let statistics = MemoryStatistics::default();
+        statistics.record_pool_allocation(1 << 20);
+        statistics.record_dedicated_allocation(4096);
+        statistics.record_dedicated_allocation(8192);
+        statistics.record_dedicated_free(4096);
+
+        let snapshot = statistics.snapshot();
+        assert_eq!(snapshot, MemoryStatisticsSnapshot {
+            pool_bytes: 1 << 20,
+            pool_allocation_count: 1,
+            dedicated_bytes: 8192,
+            dedicated_allocation_count: 1,
+        });
+        assert_eq!(snapshot.get_total_bytes(), (1 << 20) + 8192);
+
+        statistics.record_pool_free(1 << 20);
+        assert_eq!(statistics.snapshot().pool_allocation_count, 0);
+    }
+
+    fn memory_properties(types: &[(vk::MemoryPropertyFlags, u32)], heaps: &[vk::MemoryHeapFlags]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: types.len() as u32,
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (index, (property_flags, heap_index)) in types.iter().enumerate() {
+            properties.memory_types[index] = vk::MemoryType { property_flags: *property_flags, heap_index: *heap_index };
+        }
+        for (index, flags) in heaps.iter().enumerate() {
+            properties.memory_heaps[index] = vk::MemoryHeap { size: 1 << 30, flags: *flags };
+        }
+        properties
+    }
+
+    const DEVICE_LOCAL: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+    const HOST_VISIBLE: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_VISIBLE;
+    const HOST_COHERENT: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_COHERENT;
+    const HOST_CACHED: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_CACHED;
+
+    #[test]
+    fn budget_policy() {
+        let properties = memory_properties(&[(DEVICE_LOCAL, 0), (HOST_VISIBLE, 1)], &[vk::MemoryHeapFlags::DEVICE_LOCAL, vk::MemoryHeapFlags::empty()]);
+        let allocator = Arc::new(DeviceAllocator::new(&properties));
+        assert_eq!(allocator.heap_usage()[0], HeapUsage { size: 1 << 30, budget: 1 << 30, allocated_bytes: 0 });
+
+        allocator.set_soft_budget(0, Some(1000));
+        allocator.reserve(0, 600).unwrap();
+
+        // Warn allows exceeding the budget
+        let pressure_count = Arc::new(AtomicU64::new(0));
+        let count = pressure_count.clone();
+        allocator.set_memory_pressure_callback(move |pressure| {
+            assert_eq!(pressure.heap_index, 0);
+            assert_eq!(pressure.budget, 1000);
+            count.fetch_add(1, Ordering::SeqCst);
+        });
+        allocator.reserve(0, 600).unwrap();
+        assert_eq!(pressure_count.load(Ordering::SeqCst), 1);
+        assert_eq!(allocator.heap_usage()[0].allocated_bytes, 1200);
+        allocator.release(0, 600);
+
+        // Fail only fails if the callback does not free enough memory
+        allocator.set_budget_policy(MemoryBudgetPolicy::Fail);
+        assert_eq!(allocator.reserve(0, 600), Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY));
+        assert_eq!(allocator.heap_usage()[0].allocated_bytes, 600);
+
+        let weak = Arc::downgrade(&allocator);
+        allocator.set_memory_pressure_callback(move |pressure| {
+            // Evict the first allocation
+            weak.upgrade().unwrap().release(0, pressure.allocated_bytes);
+        });
+        allocator.reserve(0, 600).unwrap();
+        assert_eq!(allocator.heap_usage()[0].allocated_bytes, 600);
+
+        // Other heaps are unaffected
+        allocator.reserve(1, 1 << 20).unwrap();
+        assert_eq!(allocator.heap_usage()[1].allocated_bytes, 1 << 20);
+
+        allocator.set_memory_pressure_callback(|_| {});
+        allocator.set_soft_budget(0, None);
+        allocator.update_reported_budgets(&[500, 1 << 30]);
+        assert_eq!(allocator.reserve(0, 1), Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY));
+
+        allocator.set_budget_policy(MemoryBudgetPolicy::Ignore);
+        allocator.reserve(0, 1).unwrap();
+    }
+
+    #[test]
+    fn find_memory_type_uma() {
+        // Single device local heap where every type is host visible
+        let properties = memory_properties(&[
+            (DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT, 0),
+            (DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT | HOST_CACHED, 0),
+        ], &[vk::MemoryHeapFlags::DEVICE_LOCAL]);
+
+        assert_eq!(find_memory_type(&properties, u32::MAX, DEVICE_LOCAL, vk::MemoryPropertyFlags::empty()), Some(0));
+        assert_eq!(find_memory_type(&properties, u32::MAX, HOST_VISIBLE, HOST_CACHED), Some(1));
+        assert_eq!(find_memory_type(&properties, 0b01, HOST_VISIBLE, HOST_CACHED), Some(0));
+        assert_eq!(find_memory_type(&properties, 0b10, DEVICE_LOCAL, vk::MemoryPropertyFlags::empty()), Some(1));
+        assert_eq!(find_memory_type(&properties, 0, DEVICE_LOCAL, vk::MemoryPropertyFlags::empty()), None);
+        assert_eq!(find_memory_type(&properties, u32::MAX, vk::MemoryPropertyFlags::LAZILY_ALLOCATED, vk::MemoryPropertyFlags::empty()), None);
+    }
+
+    #[test]
+    fn find_memory_type_discrete() {
+        // Device local heap, host heap and a small rebar heap
+        let properties = memory_properties(&[
+            (DEVICE_LOCAL, 0),
+            (HOST_VISIBLE | HOST_COHERENT, 1),
+            (HOST_VISIBLE | HOST_COHERENT | HOST_CACHED, 1),
+            (DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT, 2),
+        ], &[vk::MemoryHeapFlags::DEVICE_LOCAL, vk::MemoryHeapFlags::empty(), vk::MemoryHeapFlags::DEVICE_LOCAL]);
+
+        let host = HOST_VISIBLE | HOST_COHERENT;
+
+        // All preferred flags
+        assert_eq!(find_memory_type(&properties, u32::MAX, host, DEVICE_LOCAL), Some(3));
+        assert_eq!(find_memory_type(&properties, u32::MAX, vk::MemoryPropertyFlags::empty(), DEVICE_LOCAL), Some(0));
+        assert_eq!(find_memory_type(&properties, u32::MAX, host, HOST_CACHED), Some(2));
+
+        // Most preferred flags
+        assert_eq!(find_memory_type(&properties, u32::MAX, HOST_VISIBLE, DEVICE_LOCAL | HOST_CACHED | HOST_COHERENT), Some(2));
+        assert_eq!(find_memory_type(&properties, 0b1011, HOST_VISIBLE, DEVICE_LOCAL | HOST_CACHED | HOST_COHERENT), Some(3));
+
+        // Required only
+        assert_eq!(find_memory_type(&properties, 0b0011, host, DEVICE_LOCAL), Some(1));
+        assert_eq!(find_memory_type(&properties, 0b0001, host, DEVICE_LOCAL), None);
+        assert_eq!(find_memory_type(&properties, u32::MAX, vk::MemoryPropertyFlags::empty(), vk::MemoryPropertyFlags::empty()), Some(0));
+    }
+
+    #[test]
+    fn ring_wraps_and_recycles() {
+        let mut ring = RingState::new(1024, 256);
+
+        assert_eq!(ring.allocate(0), Err(FrameRingError::EmptyPush));
+        assert_eq!(ring.allocate(100), Ok(0));
+        assert_eq!(ring.allocate(100), Ok(256));
+        ring.end_frame(1);
+        assert_eq!(ring.allocate(300), Ok(512));
+
+        // Would wrap into the in flight frame 1
+        assert_eq!(ring.allocate(300), Err(FrameRingError::OutOfSpace { requested: 300, available: 0 }));
+        ring.end_frame(2);
+
+        ring.recycle(1);
+        assert_eq!(ring.allocate(300), Ok(0));
+        assert_eq!(ring.allocate(1), Err(FrameRingError::OutOfSpace { requested: 1, available: 0 }));
+
+        ring.end_frame(3);
+        ring.recycle(3);
+        assert_eq!(ring.used(), 0);
+    }
+
+    #[test]
+    fn ring_stress() {
+        const SIZE: u64 = 1 << 14;
+        const FRAMES_IN_FLIGHT: u64 = 2;
+
+        let mut ring = RingState::new(SIZE, 64);
+
+        // Simple deterministic lcg so the test is reproducible
+        let mut state = 0x9E3779B97F4A7C15u64;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            state >> 33
+        };
+
+        // The ranges of every frame that may still be read by the mocked device
+        let mut in_flight: VecDeque<(u64, Vec<(u64, u64)>)> = VecDeque::new();
+        let mut out_of_space = 0;
+
+        for frame in 1..=2000u64 {
+            // The mocked timeline lags FRAMES_IN_FLIGHT frames behind
+            let completed = frame.saturating_sub(FRAMES_IN_FLIGHT + 1);
+            ring.recycle(completed);
+            while in_flight.front().map(|(value, _)| *value <= completed).unwrap_or(false) {
+                in_flight.pop_front();
+            }
+
+            let mut ranges: Vec<(u64, u64)> = Vec::new();
+            for _ in 0..(next() % 16) {
+                let size = 1 + next() % 1024;
+                match ring.allocate(size) {
+                    Ok(offset) => {
+                        assert_eq!(offset % 64, 0);
+                        assert!(offset + size <= SIZE);
+
+                        let overlaps = |(o, s): &(u64, u64)| offset < o + s && *o < offset + size;
+                        assert!(!ranges.iter().any(overlaps), "Overlap within frame {}", frame);
+                        for (value, frame_ranges) in in_flight.iter() {
+                            assert!(!frame_ranges.iter().any(overlaps), "Frame {} overwrote in flight frame {}", frame, value);
+                        }
+                        ranges.push((offset, size));
+                    }
+                    Err(FrameRingError::OutOfSpace { .. }) => out_of_space += 1,
+                    Err(err) => panic!("Unexpected error {:?}", err),
+                }
+            }
+
+            ring.end_frame(frame);
+            in_flight.push_back((frame, ranges));
+            assert!(ring.used() <= SIZE);
+        }
+
+        // The ring is small enough that some frames must run out of space
+        assert!(out_of_space > 0);
+
+        ring.recycle(u64::MAX);
+        assert_eq!(ring.used(), 0);
+    }
+
+    #[test]
+    fn non_coherent_allocation_layout() {
+        let requirements = |size, alignment| vk::MemoryRequirements {
+            size,
+            alignment,
+            memory_type_bits: u32::MAX,
+        };
+
+        // Coherent memory is not padded
+        assert_eq!(get_allocation_layout(&requirements(100, 16), None), (100, 16));
+        assert_eq!(get_allocation_layout(&requirements(100, 0), None), (100, 1));
+
+        assert_eq!(get_allocation_layout(&requirements(100, 16), Some(64)), (128, 64));
+        assert_eq!(get_allocation_layout(&requirements(128, 256), Some(64)), (128, 256));
+        assert_eq!(get_allocation_layout(&requirements(1, 1), Some(1)), (1, 1));
+
+        // Two allocations placed directly after each other must not share an atom
+        let atom_size = Some(64);
+        let (first_size, _) = get_allocation_layout(&requirements(100, 4), atom_size);
+        let (second_size, second_alignment) = get_allocation_layout(&requirements(20, 4), atom_size);
+        let second_offset = first_size.div_ceil(second_alignment) * second_alignment;
+        let (first_start, first_len) = get_non_coherent_range(atom_size, 0, first_size, 4096).unwrap();
+        let (second_start, _) = get_non_coherent_range(atom_size, second_offset, second_size, 4096).unwrap();
+        assert!(first_start + first_len <= second_start);
+    }
+
+    #[test]
+    fn non_coherent_ranges() {
+        // Coherent memory is never flushed
+        assert_eq!(get_non_coherent_range(None, 13, 100, 1024), None);
+
+        assert_eq!(get_non_coherent_range(Some(64), 0, 64, 1024), Some((0, 64)));
+        assert_eq!(get_non_coherent_range(Some(64), 13, 100, 1024), Some((0, 128)));
+        assert_eq!(get_non_coherent_range(Some(64), 128, 1, 1024), Some((128, 64)));
+        assert_eq!(get_non_coherent_range(Some(1), 13, 100, 1024), Some((13, 100)));
+
+        // Ranges reaching the end of the memory use the whole size
+        assert_eq!(get_non_coherent_range(Some(64), 960, 64, 1024), Some((960, vk::WHOLE_SIZE)));
+        assert_eq!(get_non_coherent_range(Some(256), 900, 100, 1000), Some((768, vk::WHOLE_SIZE)));
+    }
+
+    #[test]
+    fn heap_config_defaults() {
+        let heap = |size| vk::MemoryHeap { size, flags: vk::MemoryHeapFlags::empty() };
+        let memory_type = |property_flags| vk::MemoryType { property_flags, heap_index: 0 };
+        const MIB: u64 = 1024 * 1024;
+
+        let device_local = HeapConfig::default_for(&memory_type(vk::MemoryPropertyFlags::DEVICE_LOCAL), &heap(8192 * MIB));
+        assert_eq!(device_local, HeapConfig {
+            page_size: DEFAULT_DEVICE_PAGE_SIZE,
+            max_pages: 32,
+            dedicated_threshold: DEFAULT_DEVICE_PAGE_SIZE / 4,
+        });
+
+        let host = HeapConfig::default_for(&memory_type(vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT), &heap(16384 * MIB));
+        assert_eq!(host.page_size, DEFAULT_HOST_PAGE_SIZE);
+
+        // Small heaps use smaller pages
+        let bar = HeapConfig::default_for(&memory_type(vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE), &heap(50 * MIB));
+        assert_eq!(bar.page_size, 4 * MIB);
+        assert_eq!(bar.max_pages, 12);
+
+        let lazy = HeapConfig::default_for(&memory_type(vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::LAZILY_ALLOCATED), &heap(8192 * MIB));
+        assert_eq!(lazy, HeapConfig::unpooled());
+        assert!(lazy.is_separate(1));
+
+        assert!(!device_local.is_separate(1024));
+        assert!(device_local.is_separate(DEFAULT_DEVICE_PAGE_SIZE / 4));
+    }
+
+    #[test]
+    fn heap_manager_overrides() {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 2,
+            memory_heap_count: 1,
+            ..Default::default()
+        };
+        properties.memory_heaps[0].size = 1 << 32;
+        properties.memory_types[0].property_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        properties.memory_types[1].property_flags = vk::MemoryPropertyFlags::HOST_VISIBLE;
+
+        let manager = HeapManager::new(&properties, None);
+        assert_eq!(manager.get_memory_type_count(), 2);
+        assert_eq!(manager.get_config(0).page_size, DEFAULT_DEVICE_PAGE_SIZE);
+
+        let config_fn = |index, memory_type: &vk::MemoryType, _: &vk::MemoryHeap, config: HeapConfig| {
+            if index == 1 {
+                assert!(memory_type.property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE));
+                HeapConfig { page_size: 1 << 20, ..config }
+            } else {
+                config
+            }
+        };
+        let manager = HeapManager::new(&properties, Some(&config_fn));
+        assert_eq!(manager.get_config(0).page_size, DEFAULT_DEVICE_PAGE_SIZE);
+        assert_eq!(manager.get_config(1).page_size, 1 << 20);
+    }
+}
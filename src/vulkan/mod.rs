@@ -1,20 +1,36 @@
 pub mod device;
+pub mod error;
 pub mod instance;
 pub mod scene;
 pub mod surface;
 pub mod output;
 mod swapchain;
 pub mod init;
+pub mod timestamp;
+pub mod pipeline_statistics;
+pub mod handle;
+pub mod reflection;
+pub mod shader;
+pub mod memory;
+pub mod staging;
+pub mod pipeline_layout;
+pub mod render_graph;
+pub mod background;
 
-use std::sync::{Arc, Weak};
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex, Weak};
 
 use crate::Agnaji;
+use crate::debug::Statistics;
+use crate::output::OutputTarget;
 
 pub use instance::InstanceContext;
 
 use crate::scene::Scene;
-use crate::vulkan::device::MainDeviceContext;
+use crate::vulkan::device::{MainDeviceContext, MainDeviceReport};
+use crate::vulkan::memory::{HeapConfigFn, VulkanMemoryAllocator};
 use crate::vulkan::output::SurfaceOutput;
+use crate::vulkan::pipeline_layout::PipelineLayoutCache;
 use crate::vulkan::scene::VulkanScene;
 use crate::vulkan::surface::{SurfaceProviderId, VulkanSurfaceProvider};
 
@@ -22,29 +38,129 @@ pub struct AgnajiVulkan {
     weak: Weak<Self>,
     instance: Arc<InstanceContext>,
     device: Arc<MainDeviceContext>,
+    memory_allocator: Arc<VulkanMemoryAllocator>,
+    pipeline_layout_cache: PipelineLayoutCache,
+    statistics: Statistics,
+    label: Option<String>,
+    /// All outputs created by this instance in creation order. Outputs keep the instance alive
+    /// so only weak references are stored here.
+    outputs: Mutex<Vec<Weak<dyn OutputTarget>>>,
 }
 
 impl AgnajiVulkan {
-    fn new<T>(instance: Arc<InstanceContext>, device: Arc<MainDeviceContext>, surfaces: T) -> (Arc<Self>, Vec<(SurfaceProviderId, Arc<SurfaceOutput>)>)
+    fn new<T>(instance: Arc<InstanceContext>, device: Arc<MainDeviceContext>, heap_config_fn: Option<&HeapConfigFn>, label: Option<String>, surfaces: T) -> (Arc<Self>, Vec<(SurfaceProviderId, Arc<SurfaceOutput>)>)
         where T: Iterator<Item=(SurfaceProviderId, Box<dyn VulkanSurfaceProvider>, Option<String>)> {
 
+        let memory_allocator = Arc::new(VulkanMemoryAllocator::new(device.clone(), heap_config_fn));
+        let pipeline_layout_cache = PipelineLayoutCache::new(device.clone());
+        let statistics = Statistics::new();
+        memory_allocator.register_statistics(&statistics);
         let agnaji = Arc::new_cyclic(|weak| {
             Self {
                 weak: weak.clone(),
                 instance,
-                device
+                device,
+                memory_allocator,
+                pipeline_layout_cache,
+                statistics,
+                label,
+                outputs: Mutex::new(Vec::new()),
             }
         });
 
         let output = surfaces.map(|(id, surface, name)| {
-            (id, Arc::new(SurfaceOutput::new(agnaji.clone(), surface, name)))
+            let output = Arc::new(SurfaceOutput::new(agnaji.clone(), surface, name));
+            agnaji.register_output(&output);
+            (id, output)
         }).collect::<Vec<_>>();
 
         (agnaji, output)
     }
 
+    pub fn get_instance(&self) -> &Arc<InstanceContext> {
+        &self.instance
+    }
+
+    pub fn get_device(&self) -> &Arc<MainDeviceContext> {
+        &self.device
+    }
+
+    /// Returns true if the instance extension `name` is enabled.
+    pub fn is_instance_extension_enabled(&self, name: &CStr) -> bool {
+        self.instance.is_extension_enabled(name)
+    }
+
+    /// Returns true if the device extension `name` is enabled.
+    pub fn is_device_extension_enabled(&self, name: &CStr) -> bool {
+        self.device.is_extension_enabled(name)
+    }
+
+    pub fn get_enabled_instance_extensions(&self) -> &[CString] {
+        self.instance.get_enabled_extensions()
+    }
+
+    /// Returns all enabled device extensions in no particular order.
+    pub fn get_enabled_device_extensions(&self) -> impl Iterator<Item=&CString> {
+        self.device.get_enabled_extensions()
+    }
+
+    /// Returns the allocator used to suballocate device memory for resources.
+    pub fn get_memory_allocator(&self) -> &Arc<VulkanMemoryAllocator> {
+        &self.memory_allocator
+    }
+
+    /// Returns the cache used to share pipeline layouts between pipelines.
+    pub fn get_pipeline_layout_cache(&self) -> &PipelineLayoutCache {
+        &self.pipeline_layout_cache
+    }
+
+    /// Returns true if `report` describes the same physical device this instance was built with.
+    ///
+    /// Only a rebuild on the same physical device could keep the [`InstanceContext`] and surfaces
+    /// alive. Switching to a different physical device requires a full teardown and recreation
+    /// through [`init::AgnajiVulkanInitializer`].
+    ///
+    /// **Note:** Rebuilding the device in place is not supported yet. The memory allocator,
+    /// pipeline layout cache, outputs and their swapchains all own objects of the current device
+    /// so every rebuild currently requires a full teardown as well.
+    pub fn can_rebuild_with(&self, report: &MainDeviceReport) -> bool {
+        report.get_uuid() == self.device.get_uuid()
+    }
+
+    /// Returns the counters and gauges of this instance. Outputs, scenes and the memory allocator
+    /// register their statistics here.
+    pub fn get_statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Returns the label set by [`init::AgnajiVulkanInitializer::with_label`].
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
     pub fn create_surface_output(&self, surface_provider: Box<dyn VulkanSurfaceProvider>, name: Option<String>) -> Result<Arc<SurfaceOutput>, ()> {
-        Ok(Arc::new(SurfaceOutput::new(self.weak.upgrade().unwrap(), surface_provider, name)))
+        let output = Arc::new(SurfaceOutput::new(self.weak.upgrade().unwrap(), surface_provider, name));
+        self.register_output(&output);
+
+        Ok(output)
+    }
+
+    /// Returns all live outputs of this instance sorted by their render priority (see
+    /// [`OutputTarget::set_render_priority`]). Outputs with the same priority are returned in
+    /// creation order.
+    pub fn get_sorted_outputs(&self) -> Vec<Arc<dyn OutputTarget>> {
+        let mut guard = self.outputs.lock().unwrap();
+        guard.retain(|output| output.strong_count() != 0);
+
+        let outputs = guard.iter().filter_map(Weak::upgrade).collect();
+        drop(guard);
+
+        sort_outputs(outputs)
+    }
+
+    fn register_output<O: OutputTarget + 'static>(&self, output: &Arc<O>) {
+        let weak: Weak<dyn OutputTarget> = Arc::downgrade(output) as Weak<O>;
+        self.outputs.lock().unwrap().push(weak);
     }
 
     /// Creates a new scene. See [`Agnaji::create_scene`] for more details.
@@ -53,12 +169,73 @@ impl AgnajiVulkan {
     /// provided so that any caller doesnt have to cast the returned [`Scene`] if they need access
     /// to the underlying [`VulkanScene`].
     pub fn create_vulkan_scene(&self) -> Arc<VulkanScene> {
-        todo!()
+        VulkanScene::new(&self.statistics)
     }
 }
 
+impl std::fmt::Debug for AgnajiVulkan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgnajiVulkan")
+            .field("device", &self.device)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stable sorts outputs by ascending render priority.
+fn sort_outputs(mut outputs: Vec<Arc<dyn OutputTarget>>) -> Vec<Arc<dyn OutputTarget>> {
+    outputs.sort_by_key(|output| output.get_render_priority());
+    outputs
+}
+
 impl Agnaji for AgnajiVulkan {
     fn create_scene(&self) -> Arc<dyn Scene> {
         self.create_vulkan_scene()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use crate::scene::{CameraComponent, Color};
+
+    use super::*;
+
+    struct TestOutput {
+        priority: AtomicI32,
+    }
+
+    impl OutputTarget for TestOutput {
+        fn set_source_camera(&self, _: Option<Arc<dyn CameraComponent>>) {
+        }
+
+        fn set_render_priority(&self, priority: i32) {
+            self.priority.store(priority, Ordering::Relaxed);
+        }
+
+        fn get_render_priority(&self) -> i32 {
+            self.priority.load(Ordering::Relaxed)
+        }
+
+        fn get_clear_color(&self) -> Option<Color> {
+            None
+        }
+    }
+
+    #[test]
+    fn outputs_sorted_by_priority() {
+        let outputs: Vec<_> = [10, 0, 5, 0].into_iter().map(|priority| {
+            Arc::new(TestOutput { priority: AtomicI32::new(priority) })
+        }).collect();
+
+        let sorted = sort_outputs(outputs.iter().map(|output| output.clone() as Arc<dyn OutputTarget>).collect());
+        let priorities: Vec<_> = sorted.iter().map(|output| output.get_render_priority()).collect();
+        assert_eq!(priorities, vec![0, 0, 5, 10]);
+
+        // Equal priorities keep creation order
+        let order: Vec<_> = sorted.iter().map(|output| {
+            outputs.iter().position(|o| std::ptr::addr_eq(Arc::as_ptr(o), Arc::as_ptr(output))).unwrap()
+        }).collect();
+        assert_eq!(order, vec![1, 3, 2, 0]);
+    }
+}
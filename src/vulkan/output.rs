@@ -10,26 +10,201 @@ mod surface {
     use std::collections::hash_map::Keys;
     use std::iter::{Map, Repeat, Zip};
     use std::slice::Iter;
-    use std::sync::{Arc, Mutex};
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
     use std::thread::JoinHandle;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use ash::vk;
 
+    use crate::debug::{BreadcrumbKind, Counter, FrameStatistics, GpuProfiler, log_target};
     use crate::output::OutputTarget;
     use crate::prelude::Vec2u32;
-    use crate::scene::CameraComponent;
+    use crate::scene::{CameraClearMode, CameraComponent, Color, ComponentId, Scene};
+    use crate::utils::{define_counting_id_type, trace_span};
     use crate::vulkan::AgnajiVulkan;
-    use crate::vulkan::device::{DeviceProvider, SwapchainProvider};
-    use crate::vulkan::surface::VulkanSurfaceProvider;
-    use crate::vulkan::swapchain::{NextImageResult, Swapchain};
+    use crate::vulkan::background::BackgroundPipeline;
+    use crate::vulkan::device::{DeviceProvider, MainDeviceContext, SwapchainProvider};
+    use crate::vulkan::error::{VkError, VkResultExt};
+    use crate::vulkan::memory::{MemoryResource, ResourceTiling, VkMemoryAllocation};
+    use crate::vulkan::surface::{SurfaceCreateError, VulkanSurfaceProvider};
+    use crate::vulkan::render_graph::{RenderGraph, ResourceAccess, ResourceId};
+    use crate::vulkan::scene::{Background, VulkanCameraComponent, VulkanScene};
+    use crate::vulkan::swapchain::{NextImageResult, Swapchain, SwapchainImage};
+
+    define_counting_id_type!(pub, SurfaceOutputId);
 
     /// Selects a format for a swapchain from the list of available formats.
     ///
     /// If this function returns [`None`] the default selection algorithm will be used as backup.
     pub type SurfaceFormatSelectionFn = dyn Fn(&SurfaceFormatList) -> Option<&SurfaceFormat> + Send;
 
+    /// Called by the worker thread of a [`SurfaceOutput`] if the device has been lost.
+    pub type DeviceLostFn = dyn Fn() + Send + Sync;
+
+    /// Called by the worker thread of a [`SurfaceOutput`] after a new swapchain has been created
+    /// with the extent and format of the swapchain.
+    pub type SwapchainRecreatedFn = dyn Fn(Vec2u32, vk::Format) + Send + Sync;
+
+    /// Called by the worker thread of a [`SurfaceOutput`] after a swapchain has been destroyed.
+    pub type SwapchainDestroyedFn = dyn Fn() + Send + Sync;
+
+    /// How often a [`SurfaceOutputWorker`] checks if a occluded surface became visible again.
+    const OCCLUDED_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+    /// How long a [`SurfaceOutputWorker`] with on demand rendering enabled waits for a redraw
+    /// request before checking if it should stop.
+    const REDRAW_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// How often a [`SurfaceOutputWorker`] retries surface creation while the application is
+    /// suspended.
+    const SUSPENDED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// The default wait after the first failed surface creation. See [`BackoffState`].
+    const DEFAULT_BACKOFF_BASE_WAIT: Duration = Duration::from_millis(10);
+
+    /// The default maximum wait between failed surface creations. See [`BackoffState`].
+    const DEFAULT_BACKOFF_MAX_WAIT: Duration = Duration::from_millis(2000);
+
+    /// The color of the queue debug label wrapping the submission of a frame.
+    const FRAME_LABEL_COLOR: [f32; 4] = [1.0, 0.6, 0.2, 1.0];
+
+    /// Stored in [`Share::swapchain_image_index`] while no image is being rendered.
+    const NO_SWAPCHAIN_IMAGE: u32 = u32::MAX;
+
+    /// The maximum number of phases per frame measured by the [`GpuProfiler`] of a
+    /// [`SurfaceOutputWorker`].
+    const MAX_PROFILED_PHASES: u32 = 32;
+
+    /// The log target of outputs before the instance label and output name are appended.
+    const OUTPUT_LOG_TARGET: &str = "agnaji::vulkan::output";
+
+    /// The usage of the multisampled color image. It is cleared, rendered to and resolved into the
+    /// swapchain image.
+    const MSAA_COLOR_USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+        vk::ImageUsageFlags::COLOR_ATTACHMENT.as_raw() |
+        vk::ImageUsageFlags::TRANSFER_SRC.as_raw() |
+        vk::ImageUsageFlags::TRANSFER_DST.as_raw()
+    );
+
+    /// The usage of the depth image. It is cleared and rendered to.
+    const DEPTH_USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT.as_raw() |
+        vk::ImageUsageFlags::TRANSFER_DST.as_raw()
+    );
+
+    /// The value the depth image is cleared to.
+    const CLEAR_DEPTH: f32 = 1.0;
+
+    /// The formats considered for the depth image in order of preference. Every device supports
+    /// [`vk::Format::D16_UNORM`] as depth attachment.
+    const DEPTH_FORMAT_PRIORITIES: &[vk::Format] = &[
+        vk::Format::D32_SFLOAT,
+        vk::Format::X8_D24_UNORM_PACK32,
+        vk::Format::D16_UNORM,
+    ];
+
+    /// The number of samples per pixel used for multi-sample anti-aliasing.
+    #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+    pub enum MsaaSamples {
+        /// Multi-sampling is disabled.
+        #[default]
+        None_,
+        X2,
+        X4,
+        X8,
+    }
+
+    impl MsaaSamples {
+        /// All values in increasing sample count order.
+        pub const ALL: [MsaaSamples; 4] = [Self::None_, Self::X2, Self::X4, Self::X8];
+
+        pub fn to_sample_count(&self) -> vk::SampleCountFlags {
+            match self {
+                Self::None_ => vk::SampleCountFlags::TYPE_1,
+                Self::X2 => vk::SampleCountFlags::TYPE_2,
+                Self::X4 => vk::SampleCountFlags::TYPE_4,
+                Self::X8 => vk::SampleCountFlags::TYPE_8,
+            }
+        }
+
+        /// Returns the highest sample count in `supported` which is not higher than this. If none
+        /// is supported [`MsaaSamples::None_`] is returned since 1 sample is always supported.
+        pub fn clamp_to(self, supported: vk::SampleCountFlags) -> Self {
+            Self::ALL.into_iter().rev()
+                .filter(|samples| *samples <= self)
+                .find(|samples| supported.contains(samples.to_sample_count()))
+                .unwrap_or(Self::None_)
+        }
+
+        /// Returns all values whose sample count is contained in `supported`.
+        pub fn supported_modes(supported: vk::SampleCountFlags) -> Vec<Self> {
+            Self::ALL.into_iter().filter(|samples| supported.contains(samples.to_sample_count())).collect()
+        }
+    }
+
+    /// How the window system should compose the surface with the content behind it.
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+    pub enum CompositorHint {
+        /// The alpha channel is ignored.
+        #[default]
+        Opaque,
+
+        /// The surface is blended with the content behind it using non premultiplied alpha.
+        Transparent,
+
+        /// Like [`CompositorHint::Transparent`] but the content behind the surface should be
+        /// blurred. Vulkan has no way to request this so the blur itself must be enabled through
+        /// the window system (for example by the [`VulkanSurfaceProvider`]).
+        Blur,
+    }
+
+    impl CompositorHint {
+        /// Returns the composite alpha mode to use for this hint if it is contained in `supported`.
+        pub fn to_composite_alpha(&self, supported: vk::CompositeAlphaFlagsKHR) -> Option<vk::CompositeAlphaFlagsKHR> {
+            let composite_alpha = match self {
+                Self::Opaque => vk::CompositeAlphaFlagsKHR::OPAQUE,
+                Self::Transparent | Self::Blur => vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+            };
+            supported.contains(composite_alpha).then_some(composite_alpha)
+        }
+    }
+
+    /// Selects the composite alpha mode for `hint`. If the hint is not supported a warning is
+    /// logged and the first supported mode of `OPAQUE`, `PRE_MULTIPLIED`, `POST_MULTIPLIED` and
+    /// `INHERIT` is returned.
+    fn select_composite_alpha(hint: CompositorHint, supported: vk::CompositeAlphaFlagsKHR, log_target: &str) -> vk::CompositeAlphaFlagsKHR {
+        if let Some(composite_alpha) = hint.to_composite_alpha(supported) {
+            return composite_alpha;
+        }
+
+        let fallback = [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ].into_iter().find(|composite_alpha| supported.contains(*composite_alpha)).unwrap_or(vk::CompositeAlphaFlagsKHR::INHERIT);
+        log::warn!(target: log_target, "Compositor hint {:?} is not supported by the surface. Falling back to {:?} (Supported: {:?})", hint, fallback, supported);
+
+        fallback
+    }
+
+    /// Returns the usage flags of swapchain images. `extra` flags are dropped with a warning if
+    /// they are not in `supported` or if [`vk::ImageUsageFlags::STORAGE`] is requested but the
+    /// swapchain format does not support [`vk::FormatFeatureFlags::STORAGE_IMAGE`].
+    fn select_image_usage(extra: vk::ImageUsageFlags, supported: vk::ImageUsageFlags, format_features: vk::FormatFeatureFlags, log_target: &str) -> vk::ImageUsageFlags {
+        let mut usage = extra & supported;
+        if usage != extra {
+            log::warn!(target: log_target, "Extra swapchain image usage flags {:?} are not supported by the surface. (Supported: {:?})", extra & !supported, supported);
+        }
+        if usage.contains(vk::ImageUsageFlags::STORAGE) && !format_features.contains(vk::FormatFeatureFlags::STORAGE_IMAGE) {
+            log::warn!(target: log_target, "Swapchain format does not support storage images. Dropping storage image usage.");
+            usage &= !vk::ImageUsageFlags::STORAGE;
+        }
+
+        // Clearing the swapchain images requires transfer support
+        usage | vk::ImageUsageFlags::COLOR_ATTACHMENT | (supported & vk::ImageUsageFlags::TRANSFER_DST)
+    }
+
     /// Output to a vulkan surface. The surface is provided by a [`VulkanSurfaceProvider`].
     ///
     /// By default this output will always wait for a scene update to start rendering a new frame.
@@ -62,6 +237,15 @@ mod surface {
             self.share.guarded.lock().unwrap().wait_for_scene_update = wait;
         }
 
+        /// Sets the color the output clears its images to at the start of every frame. If [`None`]
+        /// the images are not cleared. Defaults to [`Color::BLACK`].
+        ///
+        /// Clearing requires the swapchain images to support transfer destination usage unless msaa
+        /// is enabled.
+        pub fn set_clear_color(&self, color: Option<Color>) {
+            self.share.guarded.lock().unwrap().clear_color = color;
+        }
+
         /// Sets the format selection function. If [`None`] the default format selection will be
         /// used.
         ///
@@ -84,17 +268,226 @@ mod surface {
         pub fn reselect_format(&self) {
             self.share.guarded.lock().unwrap().should_select_format = true;
         }
+
+        /// If true the output pauses rendering while the surface provider reports the canvas as
+        /// occluded (see [`VulkanSurfaceProvider::is_occluded`]). Rendering resumes within one
+        /// frame once the canvas becomes visible again. Disabled by default.
+        pub fn set_pause_when_occluded(&self, pause: bool) {
+            self.share.guarded.lock().unwrap().pause_when_occluded = pause;
+        }
+
+        /// If true the output only renders a new frame after the surface provider reports a redraw
+        /// request (see [`VulkanSurfaceProvider::wait_redraw_requested`]) instead of rendering
+        /// continuously. Disabled by default.
+        pub fn set_on_demand_rendering(&self, on_demand: bool) {
+            self.share.guarded.lock().unwrap().on_demand_rendering = on_demand;
+        }
+
+        /// Sets the number of samples used for multi-sample anti-aliasing. The value is clamped to
+        /// the highest sample count supported by the device for color attachments (see
+        /// [`SurfaceOutput::get_msaa_samples`]). Disabled by default.
+        ///
+        /// If enabled frames are rendered into a multisampled color image at swapchain extent which
+        /// is then resolved into the swapchain image. Changing the sample count recreates the
+        /// swapchain together with this image. Msaa is disabled if the swapchain images cannot be
+        /// used as transfer destination since resolving requires it.
+        pub fn set_msaa_samples(&self, samples: MsaaSamples) {
+            let mut guard = self.share.guarded.lock().unwrap();
+            if guard.msaa_samples != samples {
+                guard.msaa_samples = samples;
+                guard.msaa_changed = true;
+            }
+        }
+
+        /// Returns the number of samples used for multi-sample anti-aliasing after clamping to the
+        /// sample counts supported by the device.
+        pub fn get_msaa_samples(&self) -> MsaaSamples {
+            self.share.get_msaa_samples()
+        }
+
+        /// Sets how the window system should compose the surface. If the surface does not support
+        /// the hint a warning is logged and the surface is composed opaquely. Defaults to
+        /// [`CompositorHint::Opaque`].
+        ///
+        /// Changing the hint recreates the swapchain.
+        pub fn set_compositor_hint(&self, hint: CompositorHint) {
+            let mut guard = self.share.guarded.lock().unwrap();
+            if guard.compositor_hint != hint {
+                guard.compositor_hint = hint;
+                guard.compositor_hint_changed = true;
+            }
+        }
+
+        pub fn get_compositor_hint(&self) -> CompositorHint {
+            self.share.guarded.lock().unwrap().compositor_hint
+        }
+
+        /// Sets additional usage flags swapchain images are created with, for example
+        /// [`vk::ImageUsageFlags::STORAGE`] for compute based post-processing. Flags not supported
+        /// by the surface or the swapchain format are dropped with a warning.
+        ///
+        /// Changing the flags recreates the swapchain.
+        pub fn set_extra_image_usage_flags(&self, flags: vk::ImageUsageFlags) {
+            let mut guard = self.share.guarded.lock().unwrap();
+            if guard.extra_image_usage != flags {
+                guard.extra_image_usage = flags;
+                guard.extra_image_usage_changed = true;
+            }
+        }
+
+        pub fn get_extra_image_usage_flags(&self) -> vk::ImageUsageFlags {
+            self.share.guarded.lock().unwrap().extra_image_usage
+        }
+
+        /// Requests exclusive full screen mode using `VK_EXT_full_screen_exclusive` which gives
+        /// the lowest presentation latency on windows. The swapchain is recreated and exclusive
+        /// access is acquired when the first frame is rendered.
+        ///
+        /// Does nothing if the extension is not enabled or the surface provider does not return
+        /// a monitor from [`VulkanSurfaceProvider::get_win32_monitor`].
+        pub fn request_exclusive_fullscreen(&self) {
+            self.set_exclusive_fullscreen(true);
+        }
+
+        /// Releases exclusive full screen mode requested by
+        /// [`SurfaceOutput::request_exclusive_fullscreen`]. The swapchain is recreated.
+        pub fn release_exclusive_fullscreen(&self) {
+            self.set_exclusive_fullscreen(false);
+        }
+
+        fn set_exclusive_fullscreen(&self, exclusive: bool) {
+            let mut guard = self.share.guarded.lock().unwrap();
+            if guard.exclusive_fullscreen != exclusive {
+                guard.exclusive_fullscreen = exclusive;
+                guard.exclusive_fullscreen_changed = true;
+            }
+        }
+
+        /// Returns the gpu timings of the most recent frame whose results have been resolved.
+        /// Every render graph pass is measured as a phase named after the pass and the whole
+        /// frame as the phase `"frame"`. If the device supports pipeline statistics queries the
+        /// statistics of the whole frame are included as well.
+        ///
+        /// Results are resolved a few frames after the frame has been submitted. Returns [`None`]
+        /// if no results are available yet or the main queue does not support timestamps.
+        pub fn get_frame_statistics(&self) -> Option<FrameStatistics> {
+            self.share.guarded.lock().unwrap().frame_statistics.clone()
+        }
+
+        /// Requests the next frame to be captured. See [`crate::debug::FrameCapture`].
+        #[cfg(feature = "renderdoc")]
+        pub(crate) fn request_frame_capture(&self) {
+            self.share.capture_requested.store(true, Ordering::SeqCst);
+        }
+
+        /// Configures how long the worker waits before retrying after surface or swapchain
+        /// creation failed. The wait starts at `base` and doubles with every consecutive failure
+        /// up to `max`. Defaults to 10ms and 2s.
+        pub fn set_creation_backoff(&self, base: Duration, max: Duration) {
+            self.share.guarded.lock().unwrap().creation_backoff = (base, max);
+        }
+
+        /// Sets a callback called every time a new swapchain has been created, for example after
+        /// the window has been resized. The callback receives the extent and format of the new
+        /// swapchain.
+        ///
+        /// The callback is called from the worker thread of this output and must not block since
+        /// rendering is stalled until it returns.
+        pub fn set_swapchain_recreated_callback<F>(&self, callback: F) where F: Fn(Vec2u32, vk::Format) + Send + Sync + 'static {
+            self.share.guarded.lock().unwrap().on_swapchain_recreated = Some(Arc::new(callback));
+        }
+
+        /// Sets a callback called every time a swapchain has been destroyed. Every call of the
+        /// swapchain recreated callback is followed by exactly one call of this callback.
+        ///
+        /// The callback is called from the worker thread of this output and must not block since
+        /// rendering is stalled until it returns.
+        pub fn set_swapchain_destroyed_callback<F>(&self, callback: F) where F: Fn() + Send + Sync + 'static {
+            self.share.guarded.lock().unwrap().on_swapchain_destroyed = Some(Arc::new(callback));
+        }
+
+        /// Sets the function called if the device is lost while rendering to this output.
+        ///
+        /// A lost device cannot be recovered. After calling the handler the worker thread of this
+        /// output stops and the output will not render anymore. The handler must trigger
+        /// recreation of the [`AgnajiVulkan`] instance and all resources depending on it
+        /// (including this output).
+        ///
+        /// The handler is called from the worker thread of this output and must not block on
+        /// this output being dropped.
+        pub fn set_device_lost_handler<F>(&self, handler: F) where F: Fn() + Send + Sync + 'static {
+            self.share.guarded.lock().unwrap().on_device_lost = Some(Arc::new(handler));
+        }
+
+        /// Returns the extent of the most recently created swapchain or [`None`] if no swapchain
+        /// has been created yet.
+        ///
+        /// The swapchain is created asynchronously by the worker thread so the extent may lag
+        /// behind changes of the canvas size.
+        pub fn get_current_extent(&self) -> Option<Vec2u32> {
+            unpack_extent(self.share.current_extent.load(Ordering::Acquire))
+        }
+
+        /// Blocks until the first frame has been presented or the timeout elapsed. Returns true if
+        /// the first frame has been presented.
+        pub fn wait_for_first_frame(&self, timeout: Duration) -> bool {
+            self.share.first_frame.wait_timeout(timeout)
+        }
+
+        /// Returns the id uniquely identifying this output.
+        pub fn get_id(&self) -> SurfaceOutputId {
+            self.share.id
+        }
+
+        /// Returns the number of frames presented so far. The counter starts at 0, is incremented
+        /// after every successful present and is never reset, not even if the swapchain is
+        /// recreated.
+        ///
+        /// This is not the index of a swapchain image. See
+        /// [`SurfaceOutput::get_swapchain_image_index`] for that.
+        pub fn get_frame_index(&self) -> u64 {
+            self.share.frame_index.load(Ordering::Acquire)
+        }
+
+        /// Returns the index of the swapchain image currently being rendered and presented or
+        /// [`None`] if the worker is not rendering at the moment.
+        pub fn get_swapchain_image_index(&self) -> Option<u32> {
+            let index = self.share.swapchain_image_index.load(Ordering::Acquire);
+            (index != NO_SWAPCHAIN_IMAGE).then_some(index)
+        }
     }
 
     impl OutputTarget for SurfaceOutput {
+        /// Every frame renders all cameras of the scene of `camera` ordered by their render order.
         fn set_source_camera(&self, camera: Option<Arc<dyn CameraComponent>>) {
-            todo!()
+            self.share.guarded.lock().unwrap().source_camera = camera;
+        }
+
+        fn get_clear_color(&self) -> Option<Color> {
+            self.share.guarded.lock().unwrap().clear_color
+        }
+
+        fn set_render_priority(&self, priority: i32) {
+            self.share.render_priority.store(priority, Ordering::Relaxed);
+        }
+
+        fn get_render_priority(&self) -> i32 {
+            self.share.render_priority.load(Ordering::Relaxed)
+        }
+    }
+
+    impl std::fmt::Debug for SurfaceOutput {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SurfaceOutput")
+                .field("name", &self.share.name)
+                .field("paused", &self.share.paused.load(Ordering::Relaxed))
+                .finish()
         }
     }
 
     impl Drop for SurfaceOutput {
         fn drop(&mut self) {
-            self.share.destroy.store(true, Ordering::SeqCst);
+            self.share.destroy.signal();
             self.worker.take().unwrap().join().unwrap();
         }
     }
@@ -103,38 +496,194 @@ mod surface {
     /// [`SurfaceOutputWorker`] used for communication.
     struct Share {
         agnaji: Arc<AgnajiVulkan>,
+        id: SurfaceOutputId,
         name: Option<String>,
-        destroy: AtomicBool,
+        /// The log target of the output. Includes the label of the instance and the name of the
+        /// output.
+        log_target: String,
+        /// Set once the output is dropped. The worker waits on it instead of sleeping so it can
+        /// stop without delay.
+        destroy: OneShotSignal,
+        /// Set while the worker pauses rendering because the surface is occluded.
+        paused: AtomicBool,
+        /// The extent of the most recent swapchain packed using [`pack_extent`]. 0 if no
+        /// swapchain has been created yet.
+        current_extent: AtomicU64,
+        first_frame: OneShotSignal,
+        /// The number of frames presented. See [`SurfaceOutput::get_frame_index`].
+        frame_index: AtomicU64,
+        /// Registered in the statistics of the instance. See [`frames_presented_counter_name`].
+        frames_presented: Counter,
+        /// The index of the swapchain image currently being rendered or [`NO_SWAPCHAIN_IMAGE`].
+        swapchain_image_index: AtomicU32,
+        render_priority: AtomicI32,
+        /// Set if the next frame should be captured using [`crate::debug::FrameCapture`].
+        #[cfg(feature = "renderdoc")]
+        capture_requested: AtomicBool,
 
         guarded: Mutex<ShareGuarded>,
     }
 
     impl Share {
         fn new(agnaji: Arc<AgnajiVulkan>, name: Option<String>) -> Self {
+            let id = SurfaceOutputId::new();
+            let frames_presented = agnaji.get_statistics()
+                .register_counter(frames_presented_counter_name(id, name.as_deref()));
+            let log_target = log_target(&log_target(OUTPUT_LOG_TARGET, agnaji.get_label()), name.as_deref());
+
             Self {
                 agnaji,
+                id,
                 name,
-                destroy: AtomicBool::new(false),
+                log_target,
+                destroy: OneShotSignal::new(),
+                paused: AtomicBool::new(false),
+                current_extent: AtomicU64::new(0),
+                first_frame: OneShotSignal::new(),
+                frame_index: AtomicU64::new(0),
+                frames_presented,
+                swapchain_image_index: AtomicU32::new(NO_SWAPCHAIN_IMAGE),
+                render_priority: AtomicI32::new(0),
+                #[cfg(feature = "renderdoc")]
+                capture_requested: AtomicBool::new(false),
 
                 guarded: Mutex::new(ShareGuarded {
                     format_selection_fn: None,
                     should_select_format: false,
+                    on_device_lost: None,
+                    on_swapchain_recreated: None,
+                    on_swapchain_destroyed: None,
 
+                    source_camera: None,
+                    clear_color: Some(Color::BLACK),
                     wait_for_scene_update: true,
+                    pause_when_occluded: false,
+                    on_demand_rendering: false,
+                    msaa_samples: MsaaSamples::None_,
+                    msaa_changed: false,
+                    compositor_hint: CompositorHint::Opaque,
+                    compositor_hint_changed: false,
+                    extra_image_usage: vk::ImageUsageFlags::empty(),
+                    exclusive_fullscreen: false,
+                    exclusive_fullscreen_changed: false,
+                    extra_image_usage_changed: false,
+                    creation_backoff: (DEFAULT_BACKOFF_BASE_WAIT, DEFAULT_BACKOFF_MAX_WAIT),
+                    frame_statistics: None,
                 })
             }
         }
 
+        fn get_msaa_samples(&self) -> MsaaSamples {
+            // The depth attachment uses the same sample count as the color attachment
+            let limits = self.agnaji.device.get_limits();
+            let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+            self.guarded.lock().unwrap().msaa_samples.clamp_to(supported)
+        }
+
         fn should_destroy(&self) -> bool {
-            self.destroy.load(Ordering::SeqCst)
+            self.destroy.is_set()
+        }
+    }
+
+    /// Returns the name of the frames presented counter of a output. Named outputs use
+    /// `output.<name>.frames_presented`. Unnamed outputs use `output.unnamed-<id>.frames_presented`
+    /// so that their counters are not shared.
+    fn frames_presented_counter_name(id: SurfaceOutputId, name: Option<&str>) -> String {
+        match name {
+            Some(name) => format!("output.{}.frames_presented", name),
+            None => format!("output.unnamed-{}.frames_presented", id.get_raw()),
+        }
+    }
+
+    /// Packs an extent into a single u64 with the width stored in the upper 32 bits and the
+    /// height in the lower 32 bits. Swapchain extents are never 0 so 0 can be used to indicate
+    /// that no extent is available.
+    fn pack_extent(extent: vk::Extent2D) -> u64 {
+        ((extent.width as u64) << 32) | (extent.height as u64)
+    }
+
+    /// Unpacks a value previously returned by [`pack_extent`]. Returns [`None`] for 0.
+    fn unpack_extent(packed: u64) -> Option<Vec2u32> {
+        if packed == 0 {
+            None
+        } else {
+            Some(Vec2u32::new((packed >> 32) as u32, packed as u32))
+        }
+    }
+
+    /// One shot signal which can be waited on. Used to signal the first presented frame and the
+    /// destruction of the output.
+    struct OneShotSignal {
+        set: Mutex<bool>,
+        condvar: Condvar,
+    }
+
+    impl OneShotSignal {
+        fn new() -> Self {
+            Self {
+                set: Mutex::new(false),
+                condvar: Condvar::new(),
+            }
+        }
+
+        fn signal(&self) {
+            let mut guard = self.set.lock().unwrap();
+            if !*guard {
+                *guard = true;
+                drop(guard);
+                self.condvar.notify_all();
+            }
+        }
+
+        fn is_set(&self) -> bool {
+            *self.set.lock().unwrap()
+        }
+
+        /// Waits until the signal is set or `timeout` elapsed. Returns true if the signal is set.
+        fn wait_timeout(&self, timeout: Duration) -> bool {
+            let deadline = Instant::now() + timeout;
+
+            let mut guard = self.set.lock().unwrap();
+            while !*guard {
+                let now = Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+                guard = self.condvar.wait_timeout(guard, deadline - now).unwrap().0;
+            }
+
+            true
         }
     }
 
     struct ShareGuarded {
         format_selection_fn: Option<Box<SurfaceFormatSelectionFn>>,
         should_select_format: bool,
+        on_device_lost: Option<Arc<DeviceLostFn>>,
+        on_swapchain_recreated: Option<Arc<SwapchainRecreatedFn>>,
+        on_swapchain_destroyed: Option<Arc<SwapchainDestroyedFn>>,
 
+        source_camera: Option<Arc<dyn CameraComponent>>,
+        clear_color: Option<Color>,
         wait_for_scene_update: bool,
+        pause_when_occluded: bool,
+        on_demand_rendering: bool,
+        msaa_samples: MsaaSamples,
+        /// Set if the msaa samples changed since the last swapchain has been created.
+        msaa_changed: bool,
+        compositor_hint: CompositorHint,
+        /// Set if the compositor hint changed since the last swapchain has been created.
+        compositor_hint_changed: bool,
+        extra_image_usage: vk::ImageUsageFlags,
+        /// Set if the extra image usage flags changed since the last swapchain has been created.
+        extra_image_usage_changed: bool,
+        exclusive_fullscreen: bool,
+        /// Set if exclusive full screen mode has been requested or released since the last
+        /// swapchain has been created.
+        exclusive_fullscreen_changed: bool,
+        /// The base and max wait of the [`BackoffState`] of the worker.
+        creation_backoff: (Duration, Duration),
+        frame_statistics: Option<FrameStatistics>,
     }
 
     struct SurfaceOutputWorker {
@@ -151,69 +700,95 @@ mod surface {
         }
 
         fn run_internal(&self) {
-            log::info!("Starting SurfaceOutput worker thread. (Output: {:?})", self.share.name);
+            log::info!(target: &self.share.log_target, "Starting SurfaceOutput worker thread.");
 
-            // How often did surface creation fail in a row. Used to determine wait times
-            let mut err_repeat = 0;
+            let (base_wait, max_wait) = self.share.guarded.lock().unwrap().creation_backoff;
+            let mut backoff = BackoffState::new(base_wait, max_wait);
 
             while !self.share.should_destroy() {
+                let (base_wait, max_wait) = self.share.guarded.lock().unwrap().creation_backoff;
+                backoff.set_limits(base_wait, max_wait);
+
                 let instance = self.share.agnaji.instance.clone();
                 match unsafe { self.surface_provider.create_surface(&instance) } {
                     Ok(surface) => {
-                        log::info!("Surface created (Output: {:?})", self.share.name);
-                        if self.run_surface_loop(surface.get_handle()).is_ok() {
-                            err_repeat = 0;
-                        } else {
-                            err_repeat += 1;
-                            if err_repeat > 3 {
-                                std::thread::sleep(std::time::Duration::from_millis(1000));
+                        log::info!(target: &self.share.log_target, "Surface created");
+                        match self.run_surface_loop(surface.get_handle(), &mut backoff) {
+                            Ok(_) => {
+                                backoff.reset();
+                            }
+                            Err(err) if err.result == vk::Result::ERROR_DEVICE_LOST => {
+                                log::error!(target: &self.share.log_target, "{}", err);
+                                drop(surface);
+                                self.on_device_lost();
+                                break;
+                            }
+                            Err(err) => {
+                                let wait = backoff.next_wait();
+                                log::error!(target: &self.share.log_target, "{}. Retrying in {:?}.", err, wait);
+                                self.wait_unless_destroyed(wait);
                             }
                         }
                     }
+                    Err(SurfaceCreateError::Vulkan(vk::Result::ERROR_DEVICE_LOST)) => {
+                        self.on_device_lost();
+                        break;
+                    }
+                    Err(SurfaceCreateError::Suspended) => {
+                        log::trace!(target: &self.share.log_target, "Surface creation failed because the application is suspended.");
+                        self.wait_unless_destroyed(SUSPENDED_POLL_INTERVAL);
+                    }
                     Err(err) => {
-                        if err_repeat <= 2 {
-                            log::error!("Failed to create vulkan surface: {:?} (Output: {:?})", err, self.share.name);
-                            std::thread::yield_now();
-                        } else {
-                            let millis = std::cmp::min(2000, err_repeat * 10);
-                            log::error!("Failed to create vulkan surface: {:?}. Retrying in {}ms. (Output: {:?})", err, millis, self.share.name);
-                            std::thread::sleep(std::time::Duration::from_millis(millis));
-                        }
-                        err_repeat += 1;
+                        let wait = backoff.next_wait();
+                        log::error!(target: &self.share.log_target, "Failed to create vulkan surface: {:?}. Retrying in {:?}.", err, wait);
+                        self.wait_unless_destroyed(wait);
                     }
                 };
             }
 
-            log::info!("SurfaceOutput worker thread destroyed. (Output: {:?})", self.share.name);
+            log::info!(target: &self.share.log_target, "SurfaceOutput worker thread destroyed.");
+        }
+
+        fn on_device_lost(&self) {
+            log::error!(target: &self.share.log_target, "Device lost. Stopping SurfaceOutput worker thread.");
+            self.share.agnaji.device.report_device_lost();
+
+            // Clone the handler so that it can call functions on the output without deadlocking
+            let handler = self.share.guarded.lock().unwrap().on_device_lost.clone();
+            if let Some(handler) = handler {
+                handler();
+            } else {
+                log::warn!(target: &self.share.log_target, "No device lost handler set.");
+            }
+        }
+
+        /// Waits for `timeout` or until the output is destroyed.
+        fn wait_unless_destroyed(&self, timeout: Duration) {
+            self.share.destroy.wait_timeout(timeout);
         }
 
-        fn run_surface_loop(&self, surface: vk::SurfaceKHR) -> Result<(), vk::Result> {
+        fn run_surface_loop(&self, surface: vk::SurfaceKHR, backoff: &mut BackoffState) -> Result<(), VkError> {
             while !self.share.should_destroy() {
-                match self.create_swapchain(surface) {
-                    Ok(mut swapchain) => {
-                        while !self.share.should_destroy() {
-                            match swapchain.with_next_image(Duration::from_millis(500), |image, acquire_semaphore| {
-                                todo!()
-                            }) {
-                                NextImageResult::Ok => {}
-                                NextImageResult::MustRecreate |
-                                NextImageResult::Suboptimal => {
-                                    break;
-                                }
-                                NextImageResult::Timeout => {}
-                                NextImageResult::VulkanError(err) => {
-                                    return Err(err);
-                                }
-                            }
+                match self.create_swapchain(surface)? {
+                    Some((mut swapchain, msaa_samples)) => {
+                        backoff.reset();
+                        self.share.current_extent.store(pack_extent(swapchain.get_extent()), Ordering::Release);
+                        self.notify_swapchain_recreated(&swapchain);
+                        let result = self.run_swapchain_loop(&mut swapchain, msaa_samples);
+                        drop(swapchain);
+                        self.notify_swapchain_destroyed();
+
+                        result?;
+
+                        if self.surface_provider.should_release_surface() {
+                            log::info!(target: &self.share.log_target, "Releasing surface.");
+                            return Ok(());
                         }
                     },
-                    Err(vk::Result::SUCCESS) => {
-                        log::info!("Unable to create swapchain. Retrying in 500ms... (Output: {:?})", self.share.name);
-                        std::thread::sleep(Duration::from_millis(500));
-                    },
-                    Err(err) => {
-                        log::error!("Failed to create swapchain: {:?}. (Output: {:?})", err, self.share.name);
-                        return Err(err);
+                    None => {
+                        let wait = backoff.next_wait();
+                        log::info!(target: &self.share.log_target, "Unable to create swapchain. Retrying in {:?}.", wait);
+                        self.wait_unless_destroyed(wait);
                     },
                 }
             }
@@ -221,22 +796,341 @@ mod surface {
             Ok(())
         }
 
-        /// Lists all supported surface formats for the provided surface.
-        fn get_supported_surface_formats(&self, surface: vk::SurfaceKHR) -> Result<SurfaceFormatList, vk::Result> {
+        /// Renders to the swapchain until it must be recreated or the output is destroyed.
+        fn run_swapchain_loop(&self, swapchain: &mut Swapchain, msaa_samples: MsaaSamples) -> Result<(), VkError> {
+            let device = &self.share.agnaji.device;
+            // Declared before frame_commands so that it is dropped after all frames have finished
+            let attachments = self.create_attachments(swapchain, msaa_samples)?;
+            let mut frame_commands = FrameCommands::new(device, swapchain.get_image_count())
+                .with_details("create frame commands", || self.get_error_details())?;
+            // Uses the same number of frames as frame_commands so a frame is only resolved after
+            // its fence has been waited on
+            let mut profiler = GpuProfiler::new(device.clone(), swapchain.get_image_count(), MAX_PROFILED_PHASES);
+            let can_clear_color = attachments.msaa_color.is_some() || swapchain.get_image_usage().contains(vk::ImageUsageFlags::TRANSFER_DST);
+            if !can_clear_color {
+                log::warn!(target: &self.share.log_target, "Swapchain images do not support transfer destination usage. Color clears are skipped.");
+            }
+            let mut acquire_full_screen_exclusive = true;
+
+            while !self.share.should_destroy() {
+                if self.surface_provider.should_release_surface() {
+                    break;
+                }
+
+                if self.should_pause() {
+                    self.wait_unless_destroyed(OCCLUDED_POLL_INTERVAL);
+                    continue;
+                }
+
+                if let Some(reason) = self.get_recreate_reason() {
+                    log::info!(target: &self.share.log_target, "{}. Recreating swapchain.", reason);
+                    break;
+                }
+
+                if !self.should_render_frame() {
+                    continue;
+                }
+
+                trace_span!("frame", output = ?self.share.name);
+
+                if acquire_full_screen_exclusive {
+                    acquire_full_screen_exclusive = false;
+                    match swapchain.acquire_full_screen_exclusive() {
+                        Ok(_) => {}
+                        Err(err) if err.result == vk::Result::ERROR_DEVICE_LOST => return Err(err),
+                        Err(err) => log::warn!(target: &self.share.log_target, "{}", err),
+                    }
+                }
+
+                #[cfg(feature = "renderdoc")]
+                let _capture = self.share.capture_requested.swap(false, Ordering::SeqCst)
+                    .then(|| crate::debug::FrameCapture::get().begin_capture())
+                    .flatten();
+
+                let mut frame_result = Ok(());
+                let result = swapchain.with_next_image(Duration::from_millis(500), |image, acquire_semaphore| {
+                    self.share.swapchain_image_index.store(image.index, Ordering::Release);
+                    frame_result = self.render_frame(&mut frame_commands, profiler.as_mut(), &attachments, image, acquire_semaphore, can_clear_color);
+                    frame_result.is_ok().then(|| device.get_main_queue())
+                });
+                self.share.swapchain_image_index.store(NO_SWAPCHAIN_IMAGE, Ordering::Release);
+
+                match result {
+                    NextImageResult::Ok => {
+                        self.share.frame_index.fetch_add(1, Ordering::AcqRel);
+                        self.share.frames_presented.increment();
+                        self.share.first_frame.signal();
+                    }
+                    NextImageResult::Suboptimal => {
+                        // The image has still been presented
+                        self.share.frame_index.fetch_add(1, Ordering::AcqRel);
+                        self.share.frames_presented.increment();
+                        self.share.first_frame.signal();
+                        break;
+                    }
+                    NextImageResult::MustRecreate => {
+                        break;
+                    }
+                    NextImageResult::Timeout => {}
+                    NextImageResult::DeviceLost => {
+                        return Err(VkError::new(vk::Result::ERROR_DEVICE_LOST, "acquire or present swapchain image", self.get_error_details()));
+                    }
+                    NextImageResult::VulkanError(err) => {
+                        return Err(VkError::new(err, "acquire or present swapchain image", self.get_error_details()));
+                    }
+                }
+                frame_result?;
+            }
+
+            Ok(())
+        }
+
+        /// Returns why the swapchain must be recreated because of a changed setting or [`None`] if
+        /// no setting changed. All flags are read under a single lock.
+        fn get_recreate_reason(&self) -> Option<&'static str> {
+            let guard = self.share.guarded.lock().unwrap();
+            if guard.msaa_changed {
+                Some("Msaa samples changed")
+            } else if guard.compositor_hint_changed {
+                Some("Compositor hint changed")
+            } else if guard.extra_image_usage_changed {
+                Some("Extra image usage flags changed")
+            } else if guard.exclusive_fullscreen_changed {
+                Some("Exclusive fullscreen changed")
+            } else {
+                None
+            }
+        }
+
+        /// Creates the attachments used to render to the images of `swapchain`. `msaa_samples` is
+        /// clamped to the sample counts supported for the formats of the attachments.
+        fn create_attachments(&self, swapchain: &Swapchain, msaa_samples: MsaaSamples) -> Result<FrameAttachments, VkError> {
+            let extent = swapchain.get_extent();
+            let depth_format = self.select_depth_format();
+            let msaa_samples = msaa_samples.clamp_to(self.get_msaa_sample_counts(swapchain.get_format(), depth_format));
+            log::debug!(target: &self.share.log_target, "Creating attachments with msaa samples {:?} and depth format {:?}.", msaa_samples, depth_format);
+
+            let msaa_color = if msaa_samples == MsaaSamples::None_ {
+                None
+            } else if !swapchain.get_image_usage().contains(vk::ImageUsageFlags::TRANSFER_DST) {
+                log::warn!(target: &self.share.log_target, "Swapchain images do not support transfer destination usage which is required to resolve multisampled images. Disabling msaa.");
+                None
+            } else {
+                let image = AttachmentImage::new(&self.share.agnaji, extent, swapchain.get_format(), msaa_samples.to_sample_count(), MSAA_COLOR_USAGE, vk::ImageAspectFlags::COLOR, "agnaji output msaa color")
+                    .with_details("create msaa color image", || format!("{:?} {:?}, Output: {:?}", msaa_samples, extent, self.share.name))?;
+                Some(image)
+            };
+
+            // The depth image must have the same sample count as the color image rendered to
+            let samples = match &msaa_color {
+                Some(_) => msaa_samples.to_sample_count(),
+                None => vk::SampleCountFlags::TYPE_1,
+            };
+            let depth = AttachmentImage::new(&self.share.agnaji, extent, depth_format, samples, DEPTH_USAGE, vk::ImageAspectFlags::DEPTH, "agnaji output depth")
+                .with_details("create depth image", || format!("{:?} {:?} {:?}, Output: {:?}", depth_format, samples, extent, self.share.name))?;
+
+            let color_images: Vec<_> = match &msaa_color {
+                Some(msaa_color) => vec![msaa_color.image],
+                None => swapchain.get_images().iter().map(|image| image.image).collect(),
+            };
+            let background = BackgroundPipeline::new(&self.share.agnaji, swapchain.get_format(), samples, extent, &color_images)
+                .with_details("create background pipeline", || format!("{:?} {:?}, Output: {:?}", swapchain.get_format(), samples, self.share.name))?;
+
+            Ok(FrameAttachments {
+                extent,
+                msaa_color,
+                depth,
+                background,
+            })
+        }
+
+        /// Records and submits the commands rendering to `image`. The submission waits on
+        /// `acquire_semaphore` and signals the present semaphore of the image.
+        ///
+        /// The attachments are first cleared using the clear color of the output. Then every
+        /// camera of the scene of the source camera is processed in render order applying its
+        /// [`CameraClearMode`]. Cameras clearing color draw the background of the scene instead
+        /// if it has one. If `attachments` contains a multisampled color image the frame is
+        /// rendered into it and resolved into `image` afterwards. Color clears are skipped unless
+        /// `can_clear_color` is true.
+        fn render_frame(&self, frame_commands: &mut FrameCommands, profiler: Option<&mut GpuProfiler>, attachments: &FrameAttachments, image: &SwapchainImage, acquire_semaphore: vk::Semaphore, can_clear_color: bool) -> Result<(), VkError> {
+            let device = &self.share.agnaji.device;
+
+            let (clear_color, source_camera) = {
+                let guard = self.share.guarded.lock().unwrap();
+                (guard.clear_color, guard.source_camera.clone())
+            };
+            let camera_passes = match source_camera {
+                Some(camera) => {
+                    let scene = camera.get_scene();
+                    let background = scene.as_any().downcast_ref::<VulkanScene>().and_then(VulkanScene::get_background);
+                    plan_camera_passes(&get_camera_settings(scene.as_ref()), clear_color, background)
+                }
+                None => Vec::new(),
+            };
+
+            let mut graph = RenderGraph::new();
+            let target = graph.register_image(image.image, subresource_range(vk::ImageAspectFlags::COLOR), vk::ImageLayout::UNDEFINED);
+            // The attachments are shared by all frames. Their first layout transition waits for all
+            // previously submitted frames so the content of the previous frame can be discarded.
+            let msaa = attachments.msaa_color.as_ref().map(|msaa| {
+                (msaa.image, graph.register_image(msaa.image, msaa.subresource_range, vk::ImageLayout::UNDEFINED))
+            });
+            let (color_image, color) = msaa.unwrap_or((image.image, target));
+            let targets = FrameTargets {
+                color_image,
+                color,
+                can_clear_color,
+                depth_image: attachments.depth.image,
+                depth: graph.register_image(attachments.depth.image, attachments.depth.subresource_range, vk::ImageLayout::UNDEFINED),
+            };
+
+            targets.add_clear_pass(&mut graph, device, "clear", clear_color, clear_color.is_some());
+            // The msaa image is the only image of the background pipeline
+            let background_index = if msaa.is_some() { 0 } else { image.index as usize };
+            for pass in &camera_passes {
+                let name = format!("camera {}", pass.camera.get_raw());
+                // The background overwrites the whole color image
+                let clear_color = pass.clear_color.filter(|_| pass.background.is_none());
+                targets.add_clear_pass(&mut graph, device, &format!("{} clear", name), clear_color, pass.clear_depth);
+
+                if let Some(background) = pass.background {
+                    let writes = [ResourceAccess::image(color, vk::PipelineStageFlags2KHR::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2KHR::COLOR_ATTACHMENT_WRITE, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+                    graph.add_pass(&format!("{} background", name), &[], &writes, move |cmd| {
+                        attachments.background.record(cmd, background_index, background);
+                    });
+                }
+            }
+
+            if let Some((msaa_image, msaa)) = msaa {
+                let subresource = vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+                let region = vk::ImageResolve {
+                    src_subresource: subresource,
+                    src_offset: vk::Offset3D::default(),
+                    dst_subresource: subresource,
+                    dst_offset: vk::Offset3D::default(),
+                    extent: vk::Extent3D { width: attachments.extent.width, height: attachments.extent.height, depth: 1 },
+                };
+                let reads = [ResourceAccess::image(msaa, vk::PipelineStageFlags2KHR::RESOLVE, vk::AccessFlags2KHR::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)];
+                let writes = [ResourceAccess::image(target, vk::PipelineStageFlags2KHR::RESOLVE, vk::AccessFlags2KHR::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL)];
+                graph.add_pass("resolve", &reads, &writes, move |cmd| {
+                    unsafe {
+                        device.get_device().cmd_resolve_image(cmd, msaa_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, image.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, std::slice::from_ref(&region));
+                    }
+                });
+            }
+            graph.add_pass("present", &[ResourceAccess::image(target, vk::PipelineStageFlags2KHR::NONE, vk::AccessFlags2KHR::NONE, vk::ImageLayout::PRESENT_SRC_KHR)], &[], |_| {});
+
+            let mut statistics = None;
+            let (cmd, fence) = {
+                trace_span!("record_frame");
+                frame_commands.record(|cmd| match profiler {
+                    Some(profiler) => {
+                        statistics = profiler.begin_frame(cmd);
+                        profiler.begin_phase(cmd, "frame");
+                        profiler.begin_pipeline_statistics(cmd);
+                        graph.execute_profiled(device, cmd, profiler);
+                        profiler.end_pipeline_statistics(cmd);
+                        profiler.end_phase(cmd);
+                        profiler.end_frame();
+                    }
+                    None => graph.execute(device, cmd),
+                }).with_details("record frame", || self.get_error_details())?
+            };
+            if let Some(statistics) = statistics {
+                self.share.guarded.lock().unwrap().frame_statistics = Some(statistics);
+            }
+
+            // The graph waits for all commands before the first layout transition
+            let wait_info = vk::SemaphoreSubmitInfoKHR::builder()
+                .semaphore(acquire_semaphore)
+                .stage_mask(vk::PipelineStageFlags2KHR::ALL_COMMANDS);
+            let command_buffer_info = vk::CommandBufferSubmitInfoKHR::builder()
+                .command_buffer(cmd);
+            let signal_info = vk::SemaphoreSubmitInfoKHR::builder()
+                .semaphore(image.present_semaphore)
+                .stage_mask(vk::PipelineStageFlags2KHR::ALL_COMMANDS);
+            let submit_info = vk::SubmitInfo2KHR::builder()
+                .wait_semaphore_infos(std::slice::from_ref(&wait_info))
+                .command_buffer_infos(std::slice::from_ref(&command_buffer_info))
+                .signal_semaphore_infos(std::slice::from_ref(&signal_info));
+
+            trace_span!("submit_frame");
+            device.add_breadcrumb(BreadcrumbKind::Submit, format!("agnaji output frame (Output: {:?})", self.share.name));
+            let queue = device.get_main_queue().lock().unwrap();
+            let _label = device.get_debug_utils().queue_label_scope(*queue, "agnaji output frame", FRAME_LABEL_COLOR);
+            unsafe {
+                device.get_khr_synchronization_2().queue_submit2(*queue, std::slice::from_ref(&submit_info), fence)
+            }.with_details("submit frame", || self.get_error_details())
+        }
+
+        /// Returns the details attached to errors of this worker.
+        fn get_error_details(&self) -> String {
+            format!("Output: {:?}", self.share.name)
+        }
+
+        fn notify_swapchain_recreated(&self, swapchain: &Swapchain) {
+            let callback = self.share.guarded.lock().unwrap().on_swapchain_recreated.clone();
+            if let Some(callback) = callback {
+                let extent = swapchain.get_extent();
+                callback(Vec2u32::new(extent.width, extent.height), swapchain.get_format());
+            }
+        }
+
+        fn notify_swapchain_destroyed(&self) {
+            let callback = self.share.guarded.lock().unwrap().on_swapchain_destroyed.clone();
+            if let Some(callback) = callback {
+                callback();
+            }
+        }
+
+        /// Returns true if the next frame should be rendered. If on demand rendering is enabled this
+        /// waits for a redraw request for at most [`REDRAW_POLL_INTERVAL`].
+        fn should_render_frame(&self) -> bool {
+            let on_demand = self.share.guarded.lock().unwrap().on_demand_rendering;
+            !on_demand || self.surface_provider.wait_redraw_requested(REDRAW_POLL_INTERVAL)
+        }
+
+        fn should_pause(&self) -> bool {
+            let paused = self.share.guarded.lock().unwrap().pause_when_occluded && self.surface_provider.is_occluded();
+            self.share.paused.store(paused, Ordering::Relaxed);
+            paused
+        }
+
+        /// Queries the capabilities, supported formats and present modes of the provided surface.
+        fn get_surface_capabilities(&self, surface: vk::SurfaceKHR) -> Result<SurfaceCapabilities, VkError> {
             let device = &self.share.agnaji.device;
             let physical_device = device.get_physical_device();
             let khr_surface = device.get_instance().get_khr_surface().unwrap();
 
+            let capabilities = unsafe {
+                khr_surface.get_physical_device_surface_capabilities(physical_device, surface)
+            }.with_details("query surface capabilities", || self.get_error_details())?;
+
             let supported_surface_formats = unsafe {
                 khr_surface.get_physical_device_surface_formats(physical_device, surface)
-            }?;
-
-            Ok(SurfaceFormatList::from_surface_formats(supported_surface_formats.into_iter().map(|f| {
+            }.with_details("query surface formats", || self.get_error_details())?;
+            let formats = SurfaceFormatList::from_surface_formats(supported_surface_formats.into_iter().map(|f| {
                 SurfaceFormat {
                     color_space: f.color_space,
                     format: f.format,
                 }
-            })))
+            }));
+
+            let present_modes = unsafe {
+                khr_surface.get_physical_device_surface_present_modes(physical_device, surface)
+            }.with_details("query surface present modes", || self.get_error_details())?;
+
+            Ok(SurfaceCapabilities {
+                capabilities,
+                formats,
+                present_modes,
+            })
         }
 
         fn select_format<'a>(&self, supported: &'a SurfaceFormatList) -> &'a SurfaceFormat {
@@ -304,94 +1198,471 @@ mod surface {
             &supported.surface_formats()[0]
         }
 
-        fn select_present_mode(&self, surface: vk::SurfaceKHR) -> Result<vk::PresentModeKHR, vk::Result> {
+        fn select_present_mode(&self, capabilities: &SurfaceCapabilities) -> vk::PresentModeKHR {
             const PRESENT_MODE_PRIORITIES: &[vk::PresentModeKHR] = &[
                 vk::PresentModeKHR::MAILBOX,
                 vk::PresentModeKHR::FIFO
             ];
 
-            let supported_present_modes = unsafe {
-                self.share.agnaji.instance.get_khr_surface().unwrap()
-                    .get_physical_device_surface_present_modes(self.share.agnaji.device.get_physical_device(), surface)
-            }?;
-
             for present_mode in PRESENT_MODE_PRIORITIES {
-                if supported_present_modes.contains(present_mode) {
-                    return Ok(*present_mode)
+                if capabilities.supports_present_mode(*present_mode) {
+                    return *present_mode;
                 }
             }
 
             panic!("VK_PRESENT_MODE_FIFO_KHR must be supported by all vulkan implementations");
         }
 
-        /// Note: we hijacked the result value SUCCESS to mean that swapchain creation failed due to
-        /// not having a valid size.
-        fn create_swapchain(&self, surface: vk::SurfaceKHR) -> Result<Swapchain, vk::Result> {
-            let surface_khr = self.share.agnaji.instance.get_khr_surface().unwrap();
+        /// Returns the monitor used for exclusive full screen mode or [`None`] if exclusive full
+        /// screen mode is not available.
+        fn get_full_screen_exclusive_monitor(&self) -> Option<vk::HMONITOR> {
+            if self.share.agnaji.device.get_ext_full_screen_exclusive().is_none() {
+                log::warn!(target: &self.share.log_target, "Exclusive fullscreen requested but VK_EXT_full_screen_exclusive is not enabled.");
+                return None;
+            }
+
+            let monitor = self.surface_provider.get_win32_monitor();
+            if monitor.is_none() {
+                log::warn!(target: &self.share.log_target, "Exclusive fullscreen requested but the surface provider did not return a monitor.");
+            }
+            monitor
+        }
+
+        /// Returns the sample counts supported by the device for images of `format` with `usage`.
+        fn get_image_sample_counts(&self, format: vk::Format, usage: vk::ImageUsageFlags) -> vk::SampleCountFlags {
+            let properties = unsafe {
+                self.share.agnaji.instance.get_instance().get_physical_device_image_format_properties(self.share.agnaji.device.get_physical_device(), format, vk::ImageType::TYPE_2D, vk::ImageTiling::OPTIMAL, usage, vk::ImageCreateFlags::empty())
+            };
+            // Unsupported combinations only leave a single sample which is never used for msaa
+            properties.map(|properties| properties.sample_counts).unwrap_or(vk::SampleCountFlags::TYPE_1)
+        }
+
+        /// Returns the sample counts supported by the device for a multisampled color image of
+        /// `color_format` together with a depth image of `depth_format`.
+        fn get_msaa_sample_counts(&self, color_format: vk::Format, depth_format: vk::Format) -> vk::SampleCountFlags {
+            let limits = self.share.agnaji.device.get_limits();
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts &
+                self.get_image_sample_counts(color_format, MSAA_COLOR_USAGE) & self.get_image_sample_counts(depth_format, DEPTH_USAGE)
+        }
+
+        /// Returns the first format of [`DEPTH_FORMAT_PRIORITIES`] supported for the depth image.
+        fn select_depth_format(&self) -> vk::Format {
+            let instance = self.share.agnaji.instance.get_instance();
             let physical_device = self.share.agnaji.device.get_physical_device();
+            let required = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::TRANSFER_DST;
 
-            let capabilities = unsafe {
-                surface_khr.get_physical_device_surface_capabilities(physical_device, surface)
-            }?;
+            DEPTH_FORMAT_PRIORITIES.iter().copied().find(|format| {
+                let properties = unsafe { instance.get_physical_device_format_properties(physical_device, *format) };
+                properties.optimal_tiling_features.contains(required)
+            }).unwrap_or(vk::Format::D16_UNORM)
+        }
+
+        /// Returns [`None`] if the swapchain could not be created because the surface currently
+        /// does not have a valid size. Otherwise the swapchain is returned together with the
+        /// requested msaa samples.
+        fn create_swapchain(&self, surface: vk::SurfaceKHR) -> Result<Option<(Swapchain, MsaaSamples)>, VkError> {
+            trace_span!("create_swapchain", output = ?self.share.name);
+            let surface_capabilities = self.get_surface_capabilities(surface)?;
+            let capabilities = &surface_capabilities.capabilities;
 
             let canvas_size = self.surface_provider.get_canvas_size().unwrap_or(Vec2u32::new(1, 1));
             let image_extent = if capabilities.current_extent.width == u32::MAX && capabilities.current_extent.height == u32::MAX {
                 vk::Extent2D{ width: canvas_size.x, height: canvas_size.y }
             } else {
                 if capabilities.max_image_extent.width == 0 || capabilities.max_image_extent.height == 0 {
-                    return Err(vk::Result::SUCCESS);
+                    return Ok(None);
                 }
                 let width = std::cmp::max(capabilities.min_image_extent.width, std::cmp::min(capabilities.max_image_extent.width, canvas_size.x));
                 let height = std::cmp::max(capabilities.min_image_extent.height, std::cmp::min(capabilities.max_image_extent.height, canvas_size.y));
                 vk::Extent2D{ width, height }
             };
 
-            let image_count = if capabilities.max_image_count == 0 {
-                std::cmp::max(capabilities.min_image_count, 3)
-            } else {
-                std::cmp::max(capabilities.min_image_count, std::cmp::min(capabilities.max_image_count, 3))
-            };
+            let image_count = surface_capabilities.optimal_image_count(3);
 
-            let composite_alpha =
-            if capabilities.supported_composite_alpha.contains(vk::CompositeAlphaFlagsKHR::OPAQUE) {
-                vk::CompositeAlphaFlagsKHR::OPAQUE
-            } else if capabilities.supported_composite_alpha.contains(vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED) {
-                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
-            } else if capabilities.supported_composite_alpha.contains(vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED) {
-                vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+            let (compositor_hint, extra_image_usage, exclusive_fullscreen, msaa_samples) = {
+                let mut guard = self.share.guarded.lock().unwrap();
+                guard.msaa_changed = false;
+                guard.compositor_hint_changed = false;
+                guard.extra_image_usage_changed = false;
+                guard.exclusive_fullscreen_changed = false;
+                (guard.compositor_hint, guard.extra_image_usage, guard.exclusive_fullscreen, guard.msaa_samples)
+            };
+            let full_screen_monitor = if exclusive_fullscreen {
+                self.get_full_screen_exclusive_monitor()
             } else {
-                vk::CompositeAlphaFlagsKHR::INHERIT
+                None
             };
+            let composite_alpha = select_composite_alpha(compositor_hint, capabilities.supported_composite_alpha, &self.share.log_target);
+
+            let surface_format = self.select_format(&surface_capabilities.formats);
 
-            let supported_surface_formats = self.get_supported_surface_formats(surface)?;
-            let surface_format = self.select_format(&supported_surface_formats);
+            let present_mode = self.select_present_mode(&surface_capabilities);
+
+            log::debug!(target: &self.share.log_target, "Creating swapchain with {:?} {:?}.", image_extent, surface_format);
+
+            let format_properties = unsafe {
+                self.share.agnaji.instance.get_instance().get_physical_device_format_properties(self.share.agnaji.device.get_physical_device(), surface_format.format)
+            };
+            let image_usage = select_image_usage(extra_image_usage, capabilities.supported_usage_flags, format_properties.optimal_tiling_features, &self.share.log_target);
 
-            let present_mode = self.select_present_mode(surface)?;
+            let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::builder()
+                .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+            let mut full_screen_exclusive_win32_info = vk::SurfaceFullScreenExclusiveWin32InfoEXT::builder();
 
-            let create_info = vk::SwapchainCreateInfoKHR::builder()
+            let mut create_info = vk::SwapchainCreateInfoKHR::builder()
                 .surface(surface)
                 .min_image_count(image_count)
                 .image_format(surface_format.format)
                 .image_color_space(surface_format.color_space)
                 .image_extent(image_extent)
                 .image_array_layers(1)
-                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+                .image_usage(image_usage)
                 .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .pre_transform(capabilities.current_transform)
                 .composite_alpha(composite_alpha)
                 .present_mode(present_mode)
                 .clipped(true);
+            if let Some(monitor) = full_screen_monitor {
+                full_screen_exclusive_win32_info = full_screen_exclusive_win32_info.hmonitor(monitor);
+                create_info = create_info
+                    .push_next(&mut full_screen_exclusive_info)
+                    .push_next(&mut full_screen_exclusive_win32_info);
+            }
 
             let swapchain = unsafe {
                 self.share.agnaji.device.get_swapchain_khr().unwrap().create_swapchain(&create_info, None)
-            }?;
+            }.with_details("create swapchain", || format!("{:?} {:?} {:?}, Output: {:?}", image_extent, surface_format, present_mode, self.share.name))?;
+            self.share.agnaji.device.add_breadcrumb(BreadcrumbKind::SwapchainCreated, format!("Created swapchain with {:?} {:?}. (Output: {:?})", image_extent, surface_format, self.share.name));
 
-            Ok(Swapchain::new(swapchain, &self.share.agnaji.device).map_err(|err| {
+            Swapchain::new(swapchain, &self.share.agnaji.device, image_extent, surface_format.format, image_usage, full_screen_monitor.is_some()).map(|swapchain| Some((swapchain, msaa_samples))).map_err(|err| {
                 unsafe {
                     self.share.agnaji.device.get_swapchain_khr().unwrap().destroy_swapchain(swapchain, None);
                 }
                 err
-            })?)
+            })
+        }
+    }
+
+    /// The images rendered to before the result is written to a swapchain image. Recreated
+    /// together with the swapchain.
+    struct FrameAttachments {
+        /// The extent of the swapchain and all attachments.
+        extent: vk::Extent2D,
+        /// The multisampled color image which is resolved into the swapchain image. [`None`] if
+        /// msaa is disabled.
+        msaa_color: Option<AttachmentImage>,
+        /// Uses the same sample count as the color image rendered to.
+        depth: AttachmentImage,
+        /// Draws into the msaa color image if msaa is enabled or the swapchain images otherwise.
+        background: BackgroundPipeline,
+    }
+
+    /// The render graph resources of the attachments of a single frame.
+    #[derive(Copy, Clone)]
+    struct FrameTargets {
+        /// The msaa color image if msaa is enabled or the swapchain image otherwise.
+        color_image: vk::Image,
+        color: ResourceId,
+        /// False if the color image does not support transfer destination usage.
+        can_clear_color: bool,
+        depth_image: vk::Image,
+        depth: ResourceId,
+    }
+
+    impl FrameTargets {
+        /// Adds a pass clearing the color image to `clear_color` if set and the depth image if
+        /// `clear_depth` is true. No pass is added if nothing has to be cleared.
+        fn add_clear_pass<'a>(&self, graph: &mut RenderGraph<'a>, device: &'a MainDeviceContext, name: &str, clear_color: Option<Color>, clear_depth: bool) {
+            let clear_color = clear_color.filter(|_| self.can_clear_color);
+            let mut writes = Vec::with_capacity(2);
+            if clear_color.is_some() {
+                writes.push(ResourceAccess::image(self.color, vk::PipelineStageFlags2KHR::CLEAR, vk::AccessFlags2KHR::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+            }
+            if clear_depth {
+                writes.push(ResourceAccess::image(self.depth, vk::PipelineStageFlags2KHR::CLEAR, vk::AccessFlags2KHR::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+            }
+            if writes.is_empty() {
+                return;
+            }
+
+            let targets = *self;
+            graph.add_pass(name, &[], &writes, move |cmd| {
+                let vk_device = device.get_device();
+                unsafe {
+                    if let Some(color) = clear_color {
+                        let value = vk::ClearColorValue { float32: [color.r, color.g, color.b, color.a] };
+                        vk_device.cmd_clear_color_image(cmd, targets.color_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &value, std::slice::from_ref(&subresource_range(vk::ImageAspectFlags::COLOR)));
+                    }
+                    if clear_depth {
+                        let value = vk::ClearDepthStencilValue { depth: CLEAR_DEPTH, stencil: 0 };
+                        vk_device.cmd_clear_depth_stencil_image(cmd, targets.depth_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &value, std::slice::from_ref(&subresource_range(vk::ImageAspectFlags::DEPTH)));
+                    }
+                }
+            });
+        }
+    }
+
+    /// The clears recorded before a camera renders.
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    struct CameraPass {
+        camera: ComponentId,
+        /// The color the color image is cleared to or [`None`] if it is kept.
+        clear_color: Option<Color>,
+        clear_depth: bool,
+        /// Drawn instead of clearing the color image. Only set if the color image is cleared.
+        background: Option<Background>,
+    }
+
+    /// Returns the id, clear mode and render order of all cameras of `scene` in creation order.
+    fn get_camera_settings(scene: &dyn Scene) -> Vec<(ComponentId, CameraClearMode, i32)> {
+        scene.iter_cameras().filter_map(|camera| {
+            let id = camera.get_component_id();
+            camera.as_any().downcast_ref::<VulkanCameraComponent>().map(|camera| {
+                let properties = camera.get_properties();
+                (id, properties.clear_mode, properties.render_order)
+            })
+        }).collect()
+    }
+
+    /// Orders `cameras` by their render order and resolves their clear modes. Cameras with the
+    /// same render order keep the order of `cameras`.
+    ///
+    /// [`CameraClearMode::ClearAll`] clears color to `output_clear_color` or [`Color::BLACK`] if
+    /// the output does not clear. [`CameraClearMode::InheritFromOutput`] clears color and depth
+    /// only if the output clears. The scene `background` is drawn by every camera clearing color.
+    fn plan_camera_passes(cameras: &[(ComponentId, CameraClearMode, i32)], output_clear_color: Option<Color>, background: Option<Background>) -> Vec<CameraPass> {
+        let mut cameras = cameras.to_vec();
+        cameras.sort_by_key(|(_, _, render_order)| *render_order);
+
+        cameras.into_iter().map(|(camera, clear_mode, _)| {
+            let (clear_color, clear_depth) = match clear_mode {
+                CameraClearMode::ClearAll => (Some(output_clear_color.unwrap_or(Color::BLACK)), true),
+                CameraClearMode::ClearDepthOnly => (None, true),
+                CameraClearMode::NoClear => (None, false),
+                CameraClearMode::InheritFromOutput => (output_clear_color, output_clear_color.is_some()),
+            };
+            CameraPass {
+                camera,
+                clear_color,
+                clear_depth,
+                background: background.filter(|_| clear_color.is_some()),
+            }
+        }).collect()
+    }
+
+    /// Returns the range covering the single mip level and array layer of a attachment.
+    fn subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        }
+    }
+
+    /// A image in device local memory used as a attachment by a [`SurfaceOutputWorker`].
+    struct AttachmentImage {
+        device: Arc<MainDeviceContext>,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        /// Freed after the image has been destroyed in drop.
+        _allocation: VkMemoryAllocation,
+    }
+
+    impl AttachmentImage {
+        fn new(agnaji: &AgnajiVulkan, extent: vk::Extent2D, format: vk::Format, samples: vk::SampleCountFlags, usage: vk::ImageUsageFlags, aspect_mask: vk::ImageAspectFlags, name: &str) -> Result<Self, vk::Result> {
+            let device = agnaji.device.clone();
+            let vk_device = device.get_device();
+
+            let create_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(samples)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED);
+            let image = unsafe { vk_device.create_image(&create_info, None) }?;
+
+            let allocation = agnaji.get_memory_allocator().allocate_resource(MemoryResource::Image(image), vk::MemoryPropertyFlags::DEVICE_LOCAL, ResourceTiling::Optimal)
+                .inspect_err(|_| unsafe { vk_device.destroy_image(image, None) })?;
+            device.set_object_name(image, name);
+
+            Ok(Self {
+                device,
+                image,
+                subresource_range: subresource_range(aspect_mask),
+                _allocation: allocation,
+            })
+        }
+    }
+
+    impl Drop for AttachmentImage {
+        fn drop(&mut self) {
+            unsafe {
+                self.device.get_device().destroy_image(self.image, None);
+            }
+        }
+    }
+
+    /// Exponential backoff used by the [`SurfaceOutputWorker`] to retry surface and swapchain
+    /// creation. The n-th consecutive failure waits `base_wait * 2^n` capped at `max_wait`.
+    struct BackoffState {
+        err_count: u32,
+        base_wait: Duration,
+        max_wait: Duration,
+    }
+
+    impl BackoffState {
+        fn new(base_wait: Duration, max_wait: Duration) -> Self {
+            Self {
+                err_count: 0,
+                base_wait,
+                max_wait,
+            }
+        }
+
+        /// Updates the wait limits without resetting the error count.
+        fn set_limits(&mut self, base_wait: Duration, max_wait: Duration) {
+            self.base_wait = base_wait;
+            self.max_wait = max_wait;
+        }
+
+        /// Records a failure and returns how long to wait before retrying.
+        fn next_wait(&mut self) -> Duration {
+            let wait = 2u32.checked_pow(self.err_count)
+                .and_then(|factor| self.base_wait.checked_mul(factor))
+                .map_or(self.max_wait, |wait| wait.min(self.max_wait));
+            self.err_count = self.err_count.saturating_add(1);
+
+            wait
+        }
+
+        fn reset(&mut self) {
+            self.err_count = 0;
+        }
+    }
+
+    /// Command buffers used to record the frames rendered to a swapchain. Every frame in flight
+    /// has its own command buffer and fence so recording can start while previous frames are still
+    /// executing.
+    struct FrameCommands<'a> {
+        device: &'a MainDeviceContext,
+        command_pool: vk::CommandPool,
+        frames: Box<[(vk::CommandBuffer, vk::Fence)]>,
+        next_frame: usize,
+    }
+
+    impl<'a> FrameCommands<'a> {
+        fn new(device: &'a MainDeviceContext, frame_count: usize) -> Result<Self, vk::Result> {
+            let vk_device = device.get_device();
+
+            let pool_create_info = vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(device.get_main_queue().get_queue_family());
+            let command_pool = unsafe { vk_device.create_command_pool(&pool_create_info, None) }?;
+
+            // From here on drop cleans up everything created so far
+            let mut frame_commands = Self {
+                device,
+                command_pool,
+                frames: Box::new([]),
+                next_frame: 0,
+            };
+
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(frame_count as u32);
+            let command_buffers = unsafe { vk_device.allocate_command_buffers(&allocate_info) }?;
+
+            let fence_create_info = vk::FenceCreateInfo::builder()
+                .flags(vk::FenceCreateFlags::SIGNALED);
+            let mut frames = Vec::with_capacity(frame_count);
+            for cmd in command_buffers {
+                match unsafe { vk_device.create_fence(&fence_create_info, None) } {
+                    Ok(fence) => frames.push((cmd, fence)),
+                    Err(err) => {
+                        frame_commands.frames = frames.into_boxed_slice();
+                        return Err(err);
+                    }
+                }
+            }
+            frame_commands.frames = frames.into_boxed_slice();
+
+            Ok(frame_commands)
+        }
+
+        /// Waits until the next command buffer is no longer in use and calls `f` to record it.
+        /// Returns the command buffer and the fence which must be signaled by its submission.
+        fn record<F>(&mut self, f: F) -> Result<(vk::CommandBuffer, vk::Fence), vk::Result> where F: FnOnce(vk::CommandBuffer) {
+            let (cmd, fence) = self.frames[self.next_frame];
+            self.next_frame = (self.next_frame + 1) % self.frames.len();
+
+            let vk_device = self.device.get_device();
+            unsafe {
+                vk_device.wait_for_fences(std::slice::from_ref(&fence), true, u64::MAX)?;
+                vk_device.reset_fences(std::slice::from_ref(&fence))?;
+                vk_device.reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty())?;
+
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+                vk_device.begin_command_buffer(cmd, &begin_info)?;
+                f(cmd);
+                vk_device.end_command_buffer(cmd)?;
+            }
+
+            Ok((cmd, fence))
+        }
+    }
+
+    impl<'a> Drop for FrameCommands<'a> {
+        fn drop(&mut self) {
+            let vk_device = self.device.get_device();
+            unsafe {
+                // If the device has been lost waiting fails but all resources must still be destroyed
+                let fences: Vec<_> = self.frames.iter().map(|(_, fence)| *fence).collect();
+                if !fences.is_empty() {
+                    if let Err(err) = vk_device.wait_for_fences(&fences, true, u64::MAX) {
+                        log::error!("Failed to wait for frame fences: {:?}", err);
+                    }
+                }
+                for fence in fences {
+                    vk_device.destroy_fence(fence, None);
+                }
+                // Also frees all command buffers
+                vk_device.destroy_command_pool(self.command_pool, None);
+            }
+        }
+    }
+
+    /// The capabilities of a surface as used to create a swapchain.
+    pub struct SurfaceCapabilities {
+        pub capabilities: vk::SurfaceCapabilitiesKHR,
+        pub formats: SurfaceFormatList,
+        pub present_modes: Vec<vk::PresentModeKHR>,
+    }
+
+    impl SurfaceCapabilities {
+        pub fn supports_present_mode(&self, mode: vk::PresentModeKHR) -> bool {
+            self.present_modes.contains(&mode)
+        }
+
+        /// Returns the `preferred` image count clamped to the range supported by the surface.
+        pub fn optimal_image_count(&self, preferred: u32) -> u32 {
+            let min = self.capabilities.min_image_count;
+            let max = self.capabilities.max_image_count;
+
+            // A max of 0 means there is no limit
+            if max == 0 {
+                std::cmp::max(min, preferred)
+            } else {
+                std::cmp::max(min, std::cmp::min(max, preferred))
+            }
         }
     }
 
@@ -407,6 +1678,12 @@ mod surface {
         by_format: HashMap<vk::Format, Vec<usize>>,
     }
 
+    impl std::fmt::Debug for SurfaceFormatList {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_list().entries(self.surface_formats.iter()).finish()
+        }
+    }
+
     type ByIter<'a> = Map<Zip<Iter<'a, usize>, Repeat<&'a SurfaceFormatList>>, fn((&'a usize, &'a SurfaceFormatList)) -> &'a SurfaceFormat>;
 
     impl SurfaceFormatList {
@@ -494,9 +1771,196 @@ mod surface {
             data.1.surface_formats.get(*data.0).unwrap()
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn capabilities(min_image_count: u32, max_image_count: u32) -> SurfaceCapabilities {
+            SurfaceCapabilities {
+                capabilities: vk::SurfaceCapabilitiesKHR {
+                    min_image_count,
+                    max_image_count,
+                    ..Default::default()
+                },
+                formats: SurfaceFormatList::from_surface_formats(std::iter::empty()),
+                present_modes: vec![vk::PresentModeKHR::FIFO],
+            }
+        }
+
+        #[test]
+        fn optimal_image_count() {
+            assert_eq!(capabilities(2, 8).optimal_image_count(3), 3);
+            assert_eq!(capabilities(4, 8).optimal_image_count(3), 4);
+            assert_eq!(capabilities(1, 2).optimal_image_count(3), 2);
+            assert_eq!(capabilities(2, 0).optimal_image_count(5), 5);
+        }
+
+        #[test]
+        fn surface_format_list_debug() {
+            let list = SurfaceFormatList::from_surface_formats([
+                SurfaceFormat { color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR, format: vk::Format::B8G8R8A8_SRGB },
+            ].into_iter());
+            assert_eq!(format!("{:?}", list), "[SurfaceFormat { color_space: SRGB_NONLINEAR, format: B8G8R8A8_SRGB }]");
+        }
+
+        #[test]
+        fn camera_passes() {
+            let first = ComponentId::new();
+            let second = ComponentId::new();
+            let red = Color::new(1.0, 0.0, 0.0, 1.0);
+
+            // Sorted by render order. The second camera keeps the color of the first one.
+            let passes = plan_camera_passes(&[(second, CameraClearMode::NoClear, 1), (first, CameraClearMode::ClearAll, 0)], Some(red), None);
+            assert_eq!(passes, vec![
+                CameraPass { camera: first, clear_color: Some(red), clear_depth: true, background: None },
+                CameraPass { camera: second, clear_color: None, clear_depth: false, background: None },
+            ]);
+
+            // Equal render orders keep creation order
+            let passes = plan_camera_passes(&[(first, CameraClearMode::ClearAll, 0), (second, CameraClearMode::ClearDepthOnly, 0)], None, None);
+            assert_eq!(passes, vec![
+                CameraPass { camera: first, clear_color: Some(Color::BLACK), clear_depth: true, background: None },
+                CameraPass { camera: second, clear_color: None, clear_depth: true, background: None },
+            ]);
+        }
+
+        #[test]
+        fn camera_pass_background() {
+            let first = ComponentId::new();
+            let second = ComponentId::new();
+            let background = Background::Gradient { top: Color::BLACK, bottom: Color::new(1.0, 1.0, 1.0, 1.0) };
+
+            // Only cameras clearing color draw the background
+            let passes = plan_camera_passes(&[(first, CameraClearMode::ClearAll, 0), (second, CameraClearMode::ClearDepthOnly, 1)], None, Some(background));
+            assert_eq!(passes, vec![
+                CameraPass { camera: first, clear_color: Some(Color::BLACK), clear_depth: true, background: Some(background) },
+                CameraPass { camera: second, clear_color: None, clear_depth: true, background: None },
+            ]);
+        }
+
+        #[test]
+        fn camera_pass_inherits_output_clear() {
+            let camera = ComponentId::new();
+            let red = Color::new(1.0, 0.0, 0.0, 1.0);
+
+            let passes = plan_camera_passes(&[(camera, CameraClearMode::InheritFromOutput, 0)], Some(red), None);
+            assert_eq!(passes, vec![CameraPass { camera, clear_color: Some(red), clear_depth: true, background: None }]);
+
+            let passes = plan_camera_passes(&[(camera, CameraClearMode::InheritFromOutput, 0)], None, None);
+            assert_eq!(passes, vec![CameraPass { camera, clear_color: None, clear_depth: false, background: None }]);
+        }
+
+        #[test]
+        fn frames_presented_counter_names() {
+            let a = SurfaceOutputId::new();
+            let b = SurfaceOutputId::new();
+            assert_eq!(frames_presented_counter_name(a, Some("main")), "output.main.frames_presented");
+            assert_ne!(frames_presented_counter_name(a, None), frames_presented_counter_name(b, None));
+            assert_eq!(frames_presented_counter_name(a, None), format!("output.unnamed-{}.frames_presented", a.get_raw()));
+        }
+
+        #[test]
+        fn extent_packing() {
+            assert_eq!(unpack_extent(0), None);
+            let packed = pack_extent(vk::Extent2D { width: 1920, height: 1080 });
+            assert_eq!(unpack_extent(packed), Some(Vec2u32::new(1920, 1080)));
+            let packed = pack_extent(vk::Extent2D { width: u32::MAX, height: 1 });
+            assert_eq!(unpack_extent(packed), Some(Vec2u32::new(u32::MAX, 1)));
+        }
+
+        #[test]
+        fn one_shot_signal() {
+            let signal = Arc::new(OneShotSignal::new());
+            assert!(!signal.wait_timeout(Duration::from_millis(10)));
+            assert!(!signal.is_set());
+
+            let waiter = {
+                let signal = signal.clone();
+                std::thread::spawn(move || signal.wait_timeout(Duration::from_secs(5)))
+            };
+            signal.signal();
+            assert!(waiter.join().unwrap());
+            assert!(signal.wait_timeout(Duration::ZERO));
+            assert!(signal.is_set());
+        }
+
+        #[test]
+        fn msaa_samples_clamp() {
+            let supported = vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_2 | vk::SampleCountFlags::TYPE_4;
+            assert_eq!(MsaaSamples::X8.clamp_to(supported), MsaaSamples::X4);
+            assert_eq!(MsaaSamples::X2.clamp_to(supported), MsaaSamples::X2);
+            assert_eq!(MsaaSamples::X4.clamp_to(vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_8), MsaaSamples::None_);
+            assert_eq!(MsaaSamples::X8.clamp_to(vk::SampleCountFlags::empty()), MsaaSamples::None_);
+
+            assert_eq!(MsaaSamples::supported_modes(supported), vec![MsaaSamples::None_, MsaaSamples::X2, MsaaSamples::X4]);
+        }
+
+        #[test]
+        fn compositor_hint() {
+            let all = vk::CompositeAlphaFlagsKHR::OPAQUE | vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED;
+            assert_eq!(CompositorHint::Opaque.to_composite_alpha(all), Some(vk::CompositeAlphaFlagsKHR::OPAQUE));
+            assert_eq!(CompositorHint::Transparent.to_composite_alpha(all), Some(vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED));
+            assert_eq!(CompositorHint::Blur.to_composite_alpha(all), Some(vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED));
+            assert_eq!(CompositorHint::Transparent.to_composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE), None);
+
+            // Unsupported hints fall back to opaque
+            assert_eq!(select_composite_alpha(CompositorHint::Transparent, vk::CompositeAlphaFlagsKHR::OPAQUE, OUTPUT_LOG_TARGET), vk::CompositeAlphaFlagsKHR::OPAQUE);
+            assert_eq!(select_composite_alpha(CompositorHint::Opaque, vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED, OUTPUT_LOG_TARGET), vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED);
+            assert_eq!(select_composite_alpha(CompositorHint::Blur, vk::CompositeAlphaFlagsKHR::INHERIT, OUTPUT_LOG_TARGET), vk::CompositeAlphaFlagsKHR::INHERIT);
+        }
+
+        #[test]
+        fn image_usage() {
+            let supported = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::STORAGE;
+            let features = vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::STORAGE_IMAGE;
+            let default = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST;
+
+            assert_eq!(select_image_usage(vk::ImageUsageFlags::empty(), supported, features, OUTPUT_LOG_TARGET), default);
+            assert_eq!(select_image_usage(vk::ImageUsageFlags::STORAGE, supported, features, OUTPUT_LOG_TARGET), default | vk::ImageUsageFlags::STORAGE);
+
+            // Unsupported flags are dropped
+            assert_eq!(select_image_usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED, supported, features, OUTPUT_LOG_TARGET), default | vk::ImageUsageFlags::STORAGE);
+            assert_eq!(select_image_usage(vk::ImageUsageFlags::STORAGE, supported, vk::FormatFeatureFlags::COLOR_ATTACHMENT, OUTPUT_LOG_TARGET), default);
+            assert_eq!(select_image_usage(vk::ImageUsageFlags::STORAGE, vk::ImageUsageFlags::COLOR_ATTACHMENT, features, OUTPUT_LOG_TARGET), vk::ImageUsageFlags::COLOR_ATTACHMENT);
+        }
+
+        #[test]
+        fn backoff() {
+            let mut backoff = BackoffState::new(Duration::from_millis(10), Duration::from_millis(50));
+            assert_eq!(backoff.next_wait(), Duration::from_millis(10));
+            assert_eq!(backoff.next_wait(), Duration::from_millis(20));
+            assert_eq!(backoff.next_wait(), Duration::from_millis(40));
+            assert_eq!(backoff.next_wait(), Duration::from_millis(50));
+            for _ in 0..100 {
+                assert_eq!(backoff.next_wait(), Duration::from_millis(50));
+            }
+
+            backoff.set_limits(Duration::from_millis(1), Duration::from_millis(200));
+            assert_eq!(backoff.next_wait(), Duration::from_millis(200));
+
+            backoff.reset();
+            assert_eq!(backoff.next_wait(), Duration::from_millis(1));
+            assert_eq!(backoff.next_wait(), Duration::from_millis(2));
+        }
+
+        #[test]
+        fn supports_present_mode() {
+            let capabilities = capabilities(2, 0);
+            assert!(capabilities.supports_present_mode(vk::PresentModeKHR::FIFO));
+            assert!(!capabilities.supports_present_mode(vk::PresentModeKHR::MAILBOX));
+        }
+    }
 }
 
 pub use surface::SurfaceOutput;
+pub use surface::SurfaceOutputId;
 pub use surface::SurfaceFormatSelectionFn;
+pub use surface::DeviceLostFn;
+pub use surface::SwapchainRecreatedFn;
+pub use surface::SwapchainDestroyedFn;
 pub use surface::SurfaceFormat;
-pub use surface::SurfaceFormatList;
\ No newline at end of file
+pub use surface::SurfaceFormatList;
+pub use surface::SurfaceCapabilities;
+pub use surface::MsaaSamples;
+pub use surface::CompositorHint;
\ No newline at end of file
@@ -0,0 +1,162 @@
+//! Sharing of pipeline layouts between pipelines with the same descriptor and push constant
+//! configuration.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+use ash::vk::Handle;
+
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+
+/// Normalized representation of a pipeline layout configuration.
+///
+/// The order of descriptor set layouts defines the set indices and is therefore preserved. Push
+/// constant ranges have no meaningful order and are sorted and deduplicated so that equivalent
+/// configurations map to the same key.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct PipelineLayoutKey {
+    set_layouts: Box<[u64]>,
+    push_constant_ranges: Box<[(u32, u32, u32)]>,
+}
+
+impl PipelineLayoutKey {
+    fn new(descriptor_set_layouts: &[vk::DescriptorSetLayout], push_constant_ranges: &[vk::PushConstantRange]) -> Self {
+        let set_layouts = descriptor_set_layouts.iter().map(|layout| layout.as_raw()).collect();
+
+        let mut ranges: Vec<_> = push_constant_ranges.iter().map(|range| {
+            (range.stage_flags.as_raw(), range.offset, range.size)
+        }).collect();
+        ranges.sort_unstable();
+        ranges.dedup();
+
+        Self {
+            set_layouts,
+            push_constant_ranges: ranges.into_boxed_slice(),
+        }
+    }
+
+    fn get_push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.push_constant_ranges.iter().map(|(stage_flags, offset, size)| {
+            vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::from_raw(*stage_flags),
+                offset: *offset,
+                size: *size,
+            }
+        }).collect()
+    }
+}
+
+/// Creates pipeline layouts on demand and returns the same handle for equivalent configurations.
+///
+/// All layouts are owned by the cache and destroyed when it is dropped. Any pipeline using a
+/// layout from the cache must therefore not outlive it.
+pub struct PipelineLayoutCache {
+    device: Arc<MainDeviceContext>,
+    layouts: Mutex<HashMap<PipelineLayoutKey, vk::PipelineLayout>>,
+}
+
+impl PipelineLayoutCache {
+    pub fn new(device: Arc<MainDeviceContext>) -> Self {
+        Self {
+            device,
+            layouts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a pipeline layout for the provided configuration creating it if no equivalent
+    /// layout exists yet.
+    ///
+    /// # Panics
+    /// If creation of the pipeline layout fails.
+    pub fn get_or_create(&self, descriptor_set_layouts: &[vk::DescriptorSetLayout], push_constant_ranges: &[vk::PushConstantRange]) -> vk::PipelineLayout {
+        let key = PipelineLayoutKey::new(descriptor_set_layouts, push_constant_ranges);
+
+        let mut guard = self.layouts.lock().unwrap();
+        if let Some(layout) = guard.get(&key) {
+            return *layout;
+        }
+
+        let ranges = key.get_push_constant_ranges();
+        let create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts)
+            .push_constant_ranges(&ranges);
+
+        let layout = match unsafe {
+            self.device.get_device().create_pipeline_layout(&create_info, None)
+        } {
+            Ok(layout) => layout,
+            Err(err) => {
+                drop(guard);
+                panic!("Failed to create pipeline layout: {:?}", err);
+            }
+        };
+
+        guard.insert(key, layout);
+        layout
+    }
+
+    /// Returns the number of distinct pipeline layouts in the cache.
+    pub fn get_layout_count(&self) -> usize {
+        self.layouts.lock().unwrap().len()
+    }
+}
+
+impl Drop for PipelineLayoutCache {
+    fn drop(&mut self) {
+        let layouts = self.layouts.get_mut().unwrap();
+        for (_, layout) in layouts.drain() {
+            unsafe {
+                self.device.get_device().destroy_pipeline_layout(layout, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(stage_flags: vk::ShaderStageFlags, offset: u32, size: u32) -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags,
+            offset,
+            size,
+        }
+    }
+
+    #[test]
+    fn key_normalization() {
+        let set_a = vk::DescriptorSetLayout::from_raw(1);
+        let set_b = vk::DescriptorSetLayout::from_raw(2);
+        let vertex = range(vk::ShaderStageFlags::VERTEX, 0, 16);
+        let fragment = range(vk::ShaderStageFlags::FRAGMENT, 16, 16);
+
+        // Push constant range order does not matter
+        assert_eq!(
+            PipelineLayoutKey::new(&[set_a, set_b], &[vertex, fragment]),
+            PipelineLayoutKey::new(&[set_a, set_b], &[fragment, vertex])
+        );
+        assert_eq!(
+            PipelineLayoutKey::new(&[set_a], &[vertex, fragment, vertex]),
+            PipelineLayoutKey::new(&[set_a], &[fragment, vertex])
+        );
+
+        // Set layout order defines the set indices
+        assert_ne!(
+            PipelineLayoutKey::new(&[set_a, set_b], &[]),
+            PipelineLayoutKey::new(&[set_b, set_a], &[])
+        );
+        assert_ne!(
+            PipelineLayoutKey::new(&[set_a], &[vertex]),
+            PipelineLayoutKey::new(&[set_a], &[range(vk::ShaderStageFlags::VERTEX, 0, 32)])
+        );
+
+        let key = PipelineLayoutKey::new(&[], &[fragment, vertex]);
+        let ranges = key.get_push_constant_ranges();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].stage_flags, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(ranges[1].stage_flags, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(ranges[1].offset, 16);
+    }
+}
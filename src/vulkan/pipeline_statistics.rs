@@ -0,0 +1,155 @@
+//! GPU pipeline statistics queries used to count the work done by the graphics pipeline.
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::debug::PipelineStatistics;
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+
+/// The statistics collected by a [`GpuPipelineStatisticsQuery`]. Vulkan writes the results in the
+/// order of the flag bits which [`PipelineStatistics::from_results`] depends on.
+const STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw() |
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw() |
+    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw() |
+    vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw() |
+    vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw() |
+    vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+);
+
+/// The number of values written by a query using [`STATISTICS_FLAGS`].
+const STATISTICS_COUNT: usize = 6;
+
+/// Wrapper around a vulkan pipeline statistics query pool holding a single query.
+///
+/// Before the query can be started the pool must be reset by calling
+/// [`GpuPipelineStatisticsQuery::reset`]. The query may only be started once per reset.
+pub struct GpuPipelineStatisticsQuery {
+    device: Arc<MainDeviceContext>,
+    query_pool: vk::QueryPool,
+    recorded: bool,
+    active: bool,
+}
+
+impl GpuPipelineStatisticsQuery {
+    /// Creates a new query.
+    ///
+    /// Returns [`vk::Result::ERROR_FEATURE_NOT_PRESENT`] if the device does not support pipeline
+    /// statistics queries.
+    pub fn new(device: Arc<MainDeviceContext>) -> Result<Self, vk::Result> {
+        if !device.supports_pipeline_statistics_query() {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(1)
+            .pipeline_statistics(STATISTICS_FLAGS);
+
+        let query_pool = unsafe {
+            device.get_device().create_query_pool(&create_info, None)
+        }.inspect_err(|err| {
+            log::error!("Failed to create pipeline statistics query pool: {:?}", err);
+        })?;
+
+        Ok(Self {
+            device,
+            query_pool,
+            recorded: false,
+            active: false,
+        })
+    }
+
+    /// Records a reset of the query into `cmd`.
+    ///
+    /// The reset must be executed by the gpu before the query is started again.
+    ///
+    /// # Panics
+    /// If the query is currently active.
+    pub fn reset(&mut self, cmd: vk::CommandBuffer) {
+        if self.active {
+            panic!("GpuPipelineStatisticsQuery reset while active");
+        }
+
+        unsafe {
+            self.device.get_device().cmd_reset_query_pool(cmd, self.query_pool, 0, 1);
+        }
+        self.recorded = false;
+    }
+
+    /// Starts the query in `cmd`. Must be recorded outside of a render pass or in the same
+    /// subpass as [`GpuPipelineStatisticsQuery::end`].
+    ///
+    /// # Panics
+    /// If the query has already been started since the last reset.
+    pub fn begin(&mut self, cmd: vk::CommandBuffer) {
+        if self.recorded || self.active {
+            panic!("GpuPipelineStatisticsQuery started twice without a reset");
+        }
+
+        unsafe {
+            self.device.get_device().cmd_begin_query(cmd, self.query_pool, 0, vk::QueryControlFlags::empty());
+        }
+        self.active = true;
+    }
+
+    /// Ends the query in `cmd`.
+    ///
+    /// # Panics
+    /// If the query is not active.
+    pub fn end(&mut self, cmd: vk::CommandBuffer) {
+        if !self.active {
+            panic!("GpuPipelineStatisticsQuery ended without being active");
+        }
+
+        unsafe {
+            self.device.get_device().cmd_end_query(cmd, self.query_pool, 0);
+        }
+        self.active = false;
+        self.recorded = true;
+    }
+
+    /// Returns true if the query is currently active.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Reads the statistics recorded since the last reset without blocking. Returns [`None`] if
+    /// the query has not been recorded or the results are not available yet.
+    pub fn try_read(&self) -> Result<Option<PipelineStatistics>, vk::Result> {
+        if !self.recorded {
+            return Ok(None);
+        }
+
+        // The element type determines the size of the data of one query
+        let mut results = [[0u64; STATISTICS_COUNT]];
+        let result = unsafe {
+            self.device.get_device().get_query_pool_results(
+                self.query_pool,
+                0,
+                1,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64
+            )
+        };
+
+        match result {
+            Ok(_) => Ok(Some(PipelineStatistics::from_results(&results[0]))),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_handle(&self) -> vk::QueryPool {
+        self.query_pool
+    }
+}
+
+impl Drop for GpuPipelineStatisticsQuery {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.get_device().destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! Minimal SPIR-V reflection used to determine the descriptor set layouts required by a shader.
+//!
+//! Only the instructions needed to determine descriptor bindings are parsed. Everything else is
+//! skipped.
+
+use std::collections::HashMap;
+
+use ash::vk;
+
+pub(in crate::vulkan) const SPIRV_MAGIC: u32 = 0x07230203;
+const HEADER_WORDS: usize = 5;
+
+const OP_ENTRY_POINT: u16 = 15;
+const OP_TYPE_IMAGE: u16 = 25;
+const OP_TYPE_SAMPLER: u16 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u16 = 27;
+const OP_TYPE_ARRAY: u16 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u16 = 29;
+const OP_TYPE_STRUCT: u16 = 30;
+const OP_TYPE_POINTER: u16 = 32;
+const OP_CONSTANT: u16 = 43;
+const OP_VARIABLE: u16 = 59;
+const OP_DECORATE: u16 = 71;
+const OP_TYPE_ACCELERATION_STRUCTURE: u16 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SpvError {
+    /// The module does not start with the SPIR-V magic number.
+    InvalidMagic,
+
+    /// The module ended in the middle of the header or an instruction.
+    UnexpectedEnd,
+
+    /// The instruction starting at the word offset has a word count of 0.
+    InvalidInstruction(usize),
+
+    /// A descriptor variable references a type id which is not defined or cannot be used as a
+    /// descriptor.
+    UnsupportedType(u32),
+}
+
+/// A descriptor binding used by a shader.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub stage_flags: vk::ShaderStageFlags,
+    /// The number of descriptors in the binding. Runtime sized arrays have a count of 0.
+    pub count: u32,
+}
+
+/// The descriptor bindings used by one or more shader stages.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SpvReflection {
+    /// Sorted by set and binding.
+    bindings: Vec<DescriptorBinding>,
+}
+
+impl SpvReflection {
+    /// Parses a SPIR-V module and extracts all descriptor bindings. The stage flags of every
+    /// binding are the stages of all entry points in the module.
+    pub fn from_spirv(words: &[u32]) -> Result<Self, SpvError> {
+        if words.len() < HEADER_WORDS {
+            return Err(SpvError::UnexpectedEnd);
+        }
+
+        // Modules may be stored with the opposite endianness
+        let swapped;
+        let words = if words[0] == SPIRV_MAGIC {
+            words
+        } else if words[0] == SPIRV_MAGIC.swap_bytes() {
+            swapped = words.iter().map(|word| word.swap_bytes()).collect::<Vec<_>>();
+            &swapped
+        } else {
+            return Err(SpvError::InvalidMagic);
+        };
+
+        let mut module = ParsedModule::default();
+        let mut offset = HEADER_WORDS;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            let opcode = (words[offset] & 0xFFFF) as u16;
+            if word_count == 0 {
+                return Err(SpvError::InvalidInstruction(offset));
+            }
+
+            let instruction = words.get(offset..(offset + word_count)).ok_or(SpvError::UnexpectedEnd)?;
+            module.process_instruction(opcode, &instruction[1..])?;
+
+            offset += word_count;
+        }
+
+        module.into_reflection()
+    }
+
+    /// Returns all bindings sorted by set and binding.
+    pub fn get_bindings(&self) -> &[DescriptorBinding] {
+        &self.bindings
+    }
+
+    /// Returns all bindings of a set sorted by binding.
+    pub fn get_bindings_for_set(&self, set: u32) -> &[DescriptorBinding] {
+        let start = self.bindings.partition_point(|binding| binding.set < set);
+        let end = self.bindings.partition_point(|binding| binding.set <= set);
+        &self.bindings[start..end]
+    }
+
+    /// Returns the highest set index used or [`None`] if there are no bindings.
+    pub fn get_max_set(&self) -> Option<u32> {
+        self.bindings.last().map(|binding| binding.set)
+    }
+
+    /// Creates the descriptor set layout bindings for a set.
+    pub fn get_layout_bindings_for_set(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.get_bindings_for_set(set).iter().map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding.binding)
+                .descriptor_type(binding.descriptor_type)
+                .descriptor_count(binding.count)
+                .stage_flags(binding.stage_flags)
+                .build()
+        }).collect()
+    }
+
+    fn from_unsorted(mut bindings: Vec<DescriptorBinding>) -> Self {
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        Self {
+            bindings,
+        }
+    }
+}
+
+/// Combines the bindings of two shader stages. Bindings used by both stages are merged into one
+/// binding used by the stages of both.
+///
+/// If both stages use the same binding with different descriptor types the vertex binding is kept
+/// and a warning is logged.
+pub fn merge_reflection(vert: &SpvReflection, frag: &SpvReflection) -> SpvReflection {
+    let mut merged: HashMap<(u32, u32), DescriptorBinding> = HashMap::new();
+    for binding in vert.bindings.iter().chain(frag.bindings.iter()) {
+        if let Some(existing) = merged.get_mut(&(binding.set, binding.binding)) {
+            if existing.descriptor_type != binding.descriptor_type {
+                log::warn!("Descriptor type mismatch for set {} binding {}: {:?} and {:?}. Keeping {:?}", binding.set, binding.binding, existing.descriptor_type, binding.descriptor_type, existing.descriptor_type);
+                continue;
+            }
+            existing.stage_flags |= binding.stage_flags;
+            existing.count = std::cmp::max(existing.count, binding.count);
+        } else {
+            merged.insert((binding.set, binding.binding), *binding);
+        }
+    }
+
+    SpvReflection::from_unsorted(merged.into_values().collect())
+}
+
+#[derive(Copy, Clone, Debug)]
+enum SpvType {
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Struct,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct ParsedModule {
+    stage_flags: vk::ShaderStageFlags,
+    types: HashMap<u32, SpvType>,
+    constants: HashMap<u32, u32>,
+    sets: HashMap<u32, u32>,
+    bindings: HashMap<u32, u32>,
+    blocks: HashMap<u32, u32>,
+    /// (pointer type id, variable id, storage class)
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl ParsedModule {
+    fn process_instruction(&mut self, opcode: u16, operands: &[u32]) -> Result<(), SpvError> {
+        let operand = |index: usize| operands.get(index).copied().ok_or(SpvError::UnexpectedEnd);
+
+        match opcode {
+            OP_ENTRY_POINT => {
+                self.stage_flags |= Self::execution_model_to_stage(operand(0)?);
+            }
+            OP_TYPE_IMAGE => {
+                self.types.insert(operand(0)?, SpvType::Image { dim: operand(2)?, sampled: operand(6)? });
+            }
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, SpvType::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0)?, SpvType::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                self.types.insert(operand(0)?, SpvType::Array { element: operand(1)?, length: operand(2)? });
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                self.types.insert(operand(0)?, SpvType::RuntimeArray { element: operand(1)? });
+            }
+            OP_TYPE_STRUCT => {
+                self.types.insert(operand(0)?, SpvType::Struct);
+            }
+            OP_TYPE_POINTER => {
+                self.types.insert(operand(0)?, SpvType::Pointer { pointee: operand(2)? });
+            }
+            OP_TYPE_ACCELERATION_STRUCTURE => {
+                self.types.insert(operand(0)?, SpvType::AccelerationStructure);
+            }
+            OP_CONSTANT => {
+                // Only the low word is needed for array lengths
+                self.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(0)?, operand(1)?, operand(2)?));
+            }
+            OP_DECORATE => {
+                let target = operand(0)?;
+                match operand(1)? {
+                    DECORATION_DESCRIPTOR_SET => {
+                        self.sets.insert(target, operand(2)?);
+                    }
+                    DECORATION_BINDING => {
+                        self.bindings.insert(target, operand(2)?);
+                    }
+                    decoration @ (DECORATION_BLOCK | DECORATION_BUFFER_BLOCK) => {
+                        self.blocks.insert(target, decoration);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn into_reflection(self) -> Result<SpvReflection, SpvError> {
+        let mut bindings = Vec::new();
+
+        for (type_id, id, storage_class) in self.variables.iter().copied() {
+            if !matches!(storage_class, STORAGE_CLASS_UNIFORM_CONSTANT | STORAGE_CLASS_UNIFORM | STORAGE_CLASS_STORAGE_BUFFER) {
+                continue;
+            }
+            let (set, binding) = match (self.sets.get(&id), self.bindings.get(&id)) {
+                (Some(set), Some(binding)) => (*set, *binding),
+                _ => continue,
+            };
+
+            let pointee = match self.types.get(&type_id) {
+                Some(SpvType::Pointer { pointee }) => *pointee,
+                _ => return Err(SpvError::UnsupportedType(type_id)),
+            };
+            let (element, count) = self.resolve_array(pointee)?;
+            let descriptor_type = self.resolve_descriptor_type(element, storage_class)?;
+
+            bindings.push(DescriptorBinding {
+                set,
+                binding,
+                descriptor_type,
+                stage_flags: self.stage_flags,
+                count,
+            });
+        }
+
+        Ok(SpvReflection::from_unsorted(bindings))
+    }
+
+    /// Returns the element type and descriptor count of a possibly arrayed type.
+    fn resolve_array(&self, type_id: u32) -> Result<(u32, u32), SpvError> {
+        match self.types.get(&type_id) {
+            Some(SpvType::Array { element, length }) => {
+                let length = *self.constants.get(length).ok_or(SpvError::UnsupportedType(type_id))?;
+                Ok((*element, length))
+            }
+            Some(SpvType::RuntimeArray { element }) => Ok((*element, 0)),
+            _ => Ok((type_id, 1)),
+        }
+    }
+
+    fn resolve_descriptor_type(&self, type_id: u32, storage_class: u32) -> Result<vk::DescriptorType, SpvError> {
+        let descriptor_type = match self.types.get(&type_id) {
+            Some(SpvType::Sampler) => vk::DescriptorType::SAMPLER,
+            Some(SpvType::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Some(SpvType::Image { dim: DIM_SUBPASS_DATA, .. }) => vk::DescriptorType::INPUT_ATTACHMENT,
+            Some(SpvType::Image { dim: DIM_BUFFER, sampled: 2 }) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            Some(SpvType::Image { dim: DIM_BUFFER, .. }) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            Some(SpvType::Image { sampled: 2, .. }) => vk::DescriptorType::STORAGE_IMAGE,
+            Some(SpvType::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+            Some(SpvType::AccelerationStructure) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            Some(SpvType::Struct) if storage_class == STORAGE_CLASS_STORAGE_BUFFER => vk::DescriptorType::STORAGE_BUFFER,
+            Some(SpvType::Struct) => match self.blocks.get(&type_id) {
+                Some(&DECORATION_BUFFER_BLOCK) => vk::DescriptorType::STORAGE_BUFFER,
+                _ => vk::DescriptorType::UNIFORM_BUFFER,
+            },
+            _ => return Err(SpvError::UnsupportedType(type_id)),
+        };
+
+        Ok(descriptor_type)
+    }
+
+    fn execution_model_to_stage(execution_model: u32) -> vk::ShaderStageFlags {
+        match execution_model {
+            0 => vk::ShaderStageFlags::VERTEX,
+            1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            3 => vk::ShaderStageFlags::GEOMETRY,
+            4 => vk::ShaderStageFlags::FRAGMENT,
+            5 => vk::ShaderStageFlags::COMPUTE,
+            _ => vk::ShaderStageFlags::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(words: &mut Vec<u32>, opcode: u16, operands: &[u32]) {
+        words.push((((operands.len() + 1) as u32) << 16) | (opcode as u32));
+        words.extend_from_slice(operands);
+    }
+
+    /// Assembles a module equivalent to a shader declaring
+    /// ```glsl
+    /// layout(set = 0, binding = 1) uniform Ubo { float value; };
+    /// layout(set = 0, binding = 0) buffer Ssbo { float values; };
+    /// layout(set = 1, binding = 0) uniform sampler2D textures[4];
+    /// ```
+    fn assemble_module(execution_model: u32) -> Vec<u32> {
+        let mut words = vec![SPIRV_MAGIC, 0x00010500, 0, 32, 0];
+
+        // "main\0" padded to 2 words
+        instruction(&mut words, OP_ENTRY_POINT, &[execution_model, 20, u32::from_le_bytes(*b"main"), 0]);
+
+        instruction(&mut words, OP_DECORATE, &[2, DECORATION_BLOCK]);
+        instruction(&mut words, OP_DECORATE, &[4, DECORATION_DESCRIPTOR_SET, 0]);
+        instruction(&mut words, OP_DECORATE, &[4, DECORATION_BINDING, 1]);
+        instruction(&mut words, OP_DECORATE, &[11, DECORATION_DESCRIPTOR_SET, 1]);
+        instruction(&mut words, OP_DECORATE, &[11, DECORATION_BINDING, 0]);
+        instruction(&mut words, OP_DECORATE, &[12, DECORATION_BLOCK]);
+        instruction(&mut words, OP_DECORATE, &[14, DECORATION_DESCRIPTOR_SET, 0]);
+        instruction(&mut words, OP_DECORATE, &[14, DECORATION_BINDING, 0]);
+
+        // OpTypeFloat %1 32
+        instruction(&mut words, 22, &[1, 32]);
+        instruction(&mut words, OP_TYPE_STRUCT, &[2, 1]);
+        instruction(&mut words, OP_TYPE_POINTER, &[3, STORAGE_CLASS_UNIFORM, 2]);
+        instruction(&mut words, OP_VARIABLE, &[3, 4, STORAGE_CLASS_UNIFORM]);
+
+        instruction(&mut words, OP_TYPE_IMAGE, &[5, 1, 1, 0, 0, 0, 1, 0]);
+        instruction(&mut words, OP_TYPE_SAMPLED_IMAGE, &[6, 5]);
+        // OpTypeInt %7 32 0
+        instruction(&mut words, 21, &[7, 32, 0]);
+        instruction(&mut words, OP_CONSTANT, &[7, 8, 4]);
+        instruction(&mut words, OP_TYPE_ARRAY, &[9, 6, 8]);
+        instruction(&mut words, OP_TYPE_POINTER, &[10, STORAGE_CLASS_UNIFORM_CONSTANT, 9]);
+        instruction(&mut words, OP_VARIABLE, &[10, 11, STORAGE_CLASS_UNIFORM_CONSTANT]);
+
+        instruction(&mut words, OP_TYPE_STRUCT, &[12, 1]);
+        instruction(&mut words, OP_TYPE_POINTER, &[13, STORAGE_CLASS_STORAGE_BUFFER, 12]);
+        instruction(&mut words, OP_VARIABLE, &[13, 14, STORAGE_CLASS_STORAGE_BUFFER]);
+
+        words
+    }
+
+    #[test]
+    fn extract_bindings() {
+        let reflection = SpvReflection::from_spirv(&assemble_module(4)).unwrap();
+
+        assert_eq!(reflection.get_bindings_for_set(0), &[
+            DescriptorBinding { set: 0, binding: 0, descriptor_type: vk::DescriptorType::STORAGE_BUFFER, stage_flags: vk::ShaderStageFlags::FRAGMENT, count: 1 },
+            DescriptorBinding { set: 0, binding: 1, descriptor_type: vk::DescriptorType::UNIFORM_BUFFER, stage_flags: vk::ShaderStageFlags::FRAGMENT, count: 1 },
+        ]);
+        assert_eq!(reflection.get_bindings_for_set(1), &[
+            DescriptorBinding { set: 1, binding: 0, descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, stage_flags: vk::ShaderStageFlags::FRAGMENT, count: 4 },
+        ]);
+        assert!(reflection.get_bindings_for_set(2).is_empty());
+        assert_eq!(reflection.get_max_set(), Some(1));
+
+        // Byte swapped modules must produce the same result
+        let swapped: Vec<_> = assemble_module(4).into_iter().map(u32::swap_bytes).collect();
+        assert_eq!(SpvReflection::from_spirv(&swapped).unwrap(), reflection);
+    }
+
+    #[test]
+    fn invalid_modules() {
+        assert_eq!(SpvReflection::from_spirv(&[0; 5]), Err(SpvError::InvalidMagic));
+        assert_eq!(SpvReflection::from_spirv(&[SPIRV_MAGIC]), Err(SpvError::UnexpectedEnd));
+
+        let mut truncated = assemble_module(0);
+        truncated.pop();
+        assert_eq!(SpvReflection::from_spirv(&truncated), Err(SpvError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn merge_stages() {
+        let vert = SpvReflection::from_spirv(&assemble_module(0)).unwrap();
+        let frag = SpvReflection::from_spirv(&assemble_module(4)).unwrap();
+
+        let merged = merge_reflection(&vert, &frag);
+        assert_eq!(merged.get_bindings().len(), 3);
+        for binding in merged.get_bindings() {
+            assert_eq!(binding.stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        }
+    }
+}
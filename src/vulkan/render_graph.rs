@@ -0,0 +1,610 @@
+//! A render graph which orders passes by their resource accesses and inserts the barriers needed
+//! between them.
+//!
+//! Resources are registered with [`RenderGraph::register_buffer`] and
+//! [`RenderGraph::register_image`]. Every pass then declares which resources it reads and writes
+//! and in which pipeline stages, access types and (for images) layouts this happens. Compiling the
+//! graph sorts the passes by their data dependencies and computes the minimal set of
+//! `VK_KHR_synchronization2` barriers required before each pass.
+//!
+//! Accesses to the same resource keep the order in which their passes were added. Passes which do
+//! not depend on each other keep their relative order too.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use ash::vk;
+
+use crate::debug::{CmdLabelScope, GpuProfiler};
+use crate::vulkan::device::MainDeviceContext;
+
+/// The color of the debug labels wrapping each pass.
+const PASS_LABEL_COLOR: [f32; 4] = [0.2, 0.6, 1.0, 1.0];
+
+/// Identifies a resource registered to a [`RenderGraph`].
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ResourceId {
+    index: u32,
+}
+
+impl ResourceId {
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// Identifies a pass added to a [`RenderGraph`].
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct PassId {
+    index: u32,
+}
+
+impl PassId {
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// Describes how a pass accesses a resource.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ResourceAccess {
+    pub resource: ResourceId,
+    pub stage: vk::PipelineStageFlags2KHR,
+    pub access: vk::AccessFlags2KHR,
+    /// The layout the image must be in during the pass. Ignored for buffers.
+    pub layout: vk::ImageLayout,
+}
+
+impl ResourceAccess {
+    pub fn buffer(resource: ResourceId, stage: vk::PipelineStageFlags2KHR, access: vk::AccessFlags2KHR) -> Self {
+        Self {
+            resource,
+            stage,
+            access,
+            layout: vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    pub fn image(resource: ResourceId, stage: vk::PipelineStageFlags2KHR, access: vk::AccessFlags2KHR, layout: vk::ImageLayout) -> Self {
+        Self {
+            resource,
+            stage,
+            access,
+            layout,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Resource {
+    Buffer {
+        buffer: vk::Buffer,
+        offset: u64,
+        size: u64,
+    },
+    Image {
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        initial_layout: vk::ImageLayout,
+    },
+}
+
+type RecordFn<'a> = dyn FnOnce(vk::CommandBuffer) + 'a;
+
+struct Pass<'a> {
+    name: String,
+    reads: Vec<ResourceAccess>,
+    writes: Vec<ResourceAccess>,
+    record: Box<RecordFn<'a>>,
+}
+
+/// A pass in execution order together with the barriers recorded before it.
+struct CompiledPass {
+    pass: usize,
+    buffer_barriers: Vec<vk::BufferMemoryBarrier2KHR>,
+    image_barriers: Vec<vk::ImageMemoryBarrier2KHR>,
+}
+
+/// The synchronization state of a resource while walking the passes in execution order.
+#[derive(Copy, Clone)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    /// The stages and accesses of the last write.
+    write_stage: vk::PipelineStageFlags2KHR,
+    write_access: vk::AccessFlags2KHR,
+    /// The stages and accesses which have already been synchronized with the last write.
+    visible_stage: vk::PipelineStageFlags2KHR,
+    visible_access: vk::AccessFlags2KHR,
+    /// The stages which read the resource since the last write.
+    read_stage: vk::PipelineStageFlags2KHR,
+}
+
+impl ResourceState {
+    fn new(layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            write_stage: vk::PipelineStageFlags2KHR::NONE,
+            write_access: vk::AccessFlags2KHR::NONE,
+            visible_stage: vk::PipelineStageFlags2KHR::NONE,
+            visible_access: vk::AccessFlags2KHR::NONE,
+            read_stage: vk::PipelineStageFlags2KHR::NONE,
+        }
+    }
+}
+
+/// The combined access of a single pass to a resource.
+struct PassUsage {
+    stage: vk::PipelineStageFlags2KHR,
+    access: vk::AccessFlags2KHR,
+    layout: vk::ImageLayout,
+    is_write: bool,
+}
+
+/// A single frame worth of passes.
+///
+/// A graph is built, compiled and executed once. The recording closures may borrow data for the
+/// lifetime `'a`.
+pub struct RenderGraph<'a> {
+    resources: Vec<Resource>,
+    passes: Vec<Pass<'a>>,
+    compiled: Option<Vec<CompiledPass>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+            compiled: None,
+        }
+    }
+
+    /// Registers the range of `size` bytes starting at `offset` of `buffer`. The buffer is
+    /// assumed to not be in use by the gpu when the graph starts executing.
+    pub fn register_buffer(&mut self, buffer: vk::Buffer, offset: u64, size: u64) -> ResourceId {
+        self.push_resource(Resource::Buffer {
+            buffer,
+            offset,
+            size,
+        })
+    }
+
+    /// Registers `subresource_range` of `image` which is in `initial_layout` when the graph starts
+    /// executing.
+    ///
+    /// The first layout transition of the image waits for all previously submitted commands in
+    /// every stage. This makes it safe to wait on a semaphore (for example the acquire semaphore of
+    /// a swapchain image) with [`vk::PipelineStageFlags2KHR::ALL_COMMANDS`] before executing the
+    /// graph.
+    pub fn register_image(&mut self, image: vk::Image, subresource_range: vk::ImageSubresourceRange, initial_layout: vk::ImageLayout) -> ResourceId {
+        self.push_resource(Resource::Image {
+            image,
+            subresource_range,
+            initial_layout,
+        })
+    }
+
+    /// Adds a pass which accesses the resources in `reads` and `writes`. `record` is called with
+    /// the command buffer passed to [`RenderGraph::execute`] once all barriers of the pass have
+    /// been recorded.
+    ///
+    /// # Panics
+    /// If a resource was not registered to this graph, if the graph has already been compiled or
+    /// if the pass accesses an image in different layouts.
+    pub fn add_pass<F>(&mut self, name: &str, reads: &[ResourceAccess], writes: &[ResourceAccess], record: F) -> PassId where F: FnOnce(vk::CommandBuffer) + 'a {
+        assert!(self.compiled.is_none(), "Cannot add pass {} to a compiled render graph", name);
+
+        for access in reads.iter().chain(writes) {
+            assert!((access.resource.index as usize) < self.resources.len(), "Unknown resource {:?} in pass {}", access.resource, name);
+            if let Resource::Image { .. } = self.resources[access.resource.index as usize] {
+                let conflict = reads.iter().chain(writes).any(|other| other.resource == access.resource && other.layout != access.layout);
+                assert!(!conflict, "Pass {} accesses image {:?} in different layouts", name, access.resource);
+            }
+        }
+
+        let index = self.passes.len() as u32;
+        self.passes.push(Pass {
+            name: String::from(name),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+
+        PassId {
+            index,
+        }
+    }
+
+    pub fn get_pass_name(&self, pass: PassId) -> &str {
+        &self.passes[pass.index as usize].name
+    }
+
+    /// Returns the passes in execution order or [`None`] if the graph has not been compiled yet.
+    pub fn get_execution_order(&self) -> Option<Vec<PassId>> {
+        self.compiled.as_ref().map(|compiled| compiled.iter().map(|pass| PassId { index: pass.pass as u32 }).collect())
+    }
+
+    /// Sorts the passes and computes the barriers between them. Does nothing if the graph has
+    /// already been compiled.
+    pub fn compile(&mut self) {
+        if self.compiled.is_some() {
+            return;
+        }
+
+        let order = self.sort_passes();
+
+        let mut states: Vec<_> = self.resources.iter().map(|resource| match resource {
+            Resource::Buffer { .. } => ResourceState::new(vk::ImageLayout::UNDEFINED),
+            Resource::Image { initial_layout, .. } => ResourceState::new(*initial_layout),
+        }).collect();
+
+        let compiled = order.into_iter().map(|pass| {
+            let mut compiled = CompiledPass {
+                pass,
+                buffer_barriers: Vec::new(),
+                image_barriers: Vec::new(),
+            };
+            for (resource, usage) in self.collect_usages(pass) {
+                self.process_usage(resource, &usage, &mut states[resource], &mut compiled);
+            }
+            compiled
+        }).collect();
+
+        self.compiled = Some(compiled);
+    }
+
+    /// Compiles the graph if necessary and records all passes and barriers into `cmd`. Each pass
+    /// including its barriers is wrapped in a debug label with the name of the pass.
+    pub fn execute(self, device: &MainDeviceContext, cmd: vk::CommandBuffer) {
+        self.execute_internal(device, cmd, None);
+    }
+
+    /// Like [`RenderGraph::execute`] but additionally measures each pass including its barriers as
+    /// a phase of `profiler` named after the pass. A frame must be recorded by `profiler`.
+    pub fn execute_profiled(self, device: &MainDeviceContext, cmd: vk::CommandBuffer, profiler: &mut GpuProfiler) {
+        self.execute_internal(device, cmd, Some(profiler));
+    }
+
+    fn execute_internal(mut self, device: &MainDeviceContext, cmd: vk::CommandBuffer, mut profiler: Option<&mut GpuProfiler>) {
+        self.compile();
+
+        let (names, mut records): (Vec<_>, Vec<_>) = self.passes.into_iter().map(|pass| (pass.name, Some(pass.record))).unzip();
+        for compiled in self.compiled.unwrap() {
+            let _label = CmdLabelScope::begin(device.get_debug_utils(), cmd, &names[compiled.pass], PASS_LABEL_COLOR);
+            device.cmd_checkpoint(cmd, &names[compiled.pass]);
+            if let Some(profiler) = profiler.as_deref_mut() {
+                profiler.begin_phase(cmd, &names[compiled.pass]);
+            }
+            if !compiled.buffer_barriers.is_empty() || !compiled.image_barriers.is_empty() {
+                let dependency_info = vk::DependencyInfoKHR::builder()
+                    .buffer_memory_barriers(&compiled.buffer_barriers)
+                    .image_memory_barriers(&compiled.image_barriers);
+                unsafe {
+                    device.get_khr_synchronization_2().cmd_pipeline_barrier2(cmd, &dependency_info);
+                }
+            }
+
+            (records[compiled.pass].take().unwrap())(cmd);
+
+            if let Some(profiler) = profiler.as_deref_mut() {
+                profiler.end_phase(cmd);
+            }
+        }
+    }
+
+    fn push_resource(&mut self, resource: Resource) -> ResourceId {
+        assert!(self.compiled.is_none(), "Cannot register resources to a compiled render graph");
+
+        let index = self.resources.len() as u32;
+        self.resources.push(resource);
+
+        ResourceId {
+            index,
+        }
+    }
+
+    /// Topologically sorts the passes. A pass depends on the last pass writing a resource it
+    /// accesses and a writing pass additionally depends on all passes reading the resource since
+    /// that write. Ready passes are emitted in the order they were added.
+    fn sort_passes(&self) -> Vec<usize> {
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        let mut dependency_counts = vec![0usize; self.passes.len()];
+        let mut add_dependency = |from: usize, to: usize| {
+            if from != to && !dependents[from].contains(&to) {
+                dependents[from].push(to);
+                dependency_counts[to] += 1;
+            }
+        };
+
+        let mut last_writers: Vec<Option<usize>> = vec![None; self.resources.len()];
+        let mut readers: Vec<Vec<usize>> = vec![Vec::new(); self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for access in &pass.reads {
+                let resource = access.resource.index as usize;
+                if let Some(writer) = last_writers[resource] {
+                    add_dependency(writer, index);
+                }
+                readers[resource].push(index);
+            }
+            for access in &pass.writes {
+                let resource = access.resource.index as usize;
+                if let Some(writer) = last_writers[resource] {
+                    add_dependency(writer, index);
+                }
+                for reader in readers[resource].drain(..) {
+                    add_dependency(reader, index);
+                }
+                last_writers[resource] = Some(index);
+            }
+        }
+
+        let mut ready: BinaryHeap<_> = dependency_counts.iter().enumerate()
+            .filter(|(_, count)| **count == 0)
+            .map(|(index, _)| Reverse(index))
+            .collect();
+        let mut order = Vec::with_capacity(self.passes.len());
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for dependent in &dependents[index] {
+                dependency_counts[*dependent] -= 1;
+                if dependency_counts[*dependent] == 0 {
+                    ready.push(Reverse(*dependent));
+                }
+            }
+        }
+
+        // Dependencies always point to later passes so there can be no cycles
+        debug_assert_eq!(order.len(), self.passes.len());
+        order
+    }
+
+    /// Merges all accesses of a pass to the same resource.
+    fn collect_usages(&self, pass: usize) -> Vec<(usize, PassUsage)> {
+        let pass = &self.passes[pass];
+        let mut usages: Vec<(usize, PassUsage)> = Vec::new();
+        let accesses = pass.reads.iter().map(|access| (access, false))
+            .chain(pass.writes.iter().map(|access| (access, true)));
+        for (access, is_write) in accesses {
+            let resource = access.resource.index as usize;
+            if let Some((_, usage)) = usages.iter_mut().find(|(other, _)| *other == resource) {
+                usage.stage |= access.stage;
+                usage.access |= access.access;
+                usage.is_write |= is_write;
+            } else {
+                usages.push((resource, PassUsage {
+                    stage: access.stage,
+                    access: access.access,
+                    layout: access.layout,
+                    is_write,
+                }));
+            }
+        }
+        usages
+    }
+
+    /// Emits the barrier required before `usage` (if any) and updates the state of the resource.
+    fn process_usage(&self, resource: usize, usage: &PassUsage, state: &mut ResourceState, compiled: &mut CompiledPass) {
+        let is_image = matches!(self.resources[resource], Resource::Image { .. });
+        let layout_change = is_image && state.layout != usage.layout;
+
+        if usage.is_write || layout_change {
+            // Layout transitions are writes too so they need to wait for all previous accesses
+            let mut src_stage = state.write_stage | state.read_stage;
+            if src_stage.is_empty() && !layout_change {
+                // First access to the resource
+                self.update_written(state, usage);
+                return;
+            }
+            if src_stage.is_empty() {
+                src_stage = vk::PipelineStageFlags2KHR::ALL_COMMANDS;
+            }
+
+            self.push_barrier(resource, src_stage, state.write_access, state.layout, usage, compiled);
+            state.layout = usage.layout;
+            self.update_written(state, usage);
+        } else {
+            let visible = state.visible_stage.contains(usage.stage) && state.visible_access.contains(usage.access);
+            if !state.write_stage.is_empty() && !visible {
+                self.push_barrier(resource, state.write_stage, state.write_access, state.layout, usage, compiled);
+                state.visible_stage |= usage.stage;
+                state.visible_access |= usage.access;
+            }
+            state.read_stage |= usage.stage;
+        }
+    }
+
+    fn update_written(&self, state: &mut ResourceState, usage: &PassUsage) {
+        state.write_stage = usage.stage;
+        state.visible_stage = usage.stage;
+        state.visible_access = usage.access;
+        if usage.is_write {
+            state.write_access = usage.access;
+            state.read_stage = vk::PipelineStageFlags2KHR::NONE;
+        } else {
+            // A pure layout transition makes no memory writes of its own and the pass reads the
+            // resource afterwards
+            state.write_access = vk::AccessFlags2KHR::NONE;
+            state.read_stage = usage.stage;
+        }
+    }
+
+    fn push_barrier(&self, resource: usize, src_stage: vk::PipelineStageFlags2KHR, src_access: vk::AccessFlags2KHR, old_layout: vk::ImageLayout, usage: &PassUsage, compiled: &mut CompiledPass) {
+        match self.resources[resource] {
+            Resource::Buffer { buffer, offset, size } => {
+                compiled.buffer_barriers.push(vk::BufferMemoryBarrier2KHR::builder()
+                    .src_stage_mask(src_stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(usage.stage)
+                    .dst_access_mask(usage.access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer)
+                    .offset(offset)
+                    .size(size)
+                    .build()
+                );
+            }
+            Resource::Image { image, subresource_range, .. } => {
+                compiled.image_barriers.push(vk::ImageMemoryBarrier2KHR::builder()
+                    .src_stage_mask(src_stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(usage.stage)
+                    .dst_access_mask(usage.access)
+                    .old_layout(old_layout)
+                    .new_layout(usage.layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(subresource_range)
+                    .build()
+                );
+            }
+        }
+    }
+}
+
+impl<'a> Default for RenderGraph<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> std::fmt::Debug for RenderGraph<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderGraph")
+            .field("resource_count", &self.resources.len())
+            .field("passes", &self.passes.iter().map(|pass| pass.name.as_str()).collect::<Vec<_>>())
+            .field("compiled", &self.compiled.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    type Stage = vk::PipelineStageFlags2KHR;
+    type Access = vk::AccessFlags2KHR;
+
+    fn color_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        }
+    }
+
+    fn barrier_counts(graph: &RenderGraph) -> Vec<(usize, usize)> {
+        graph.compiled.as_ref().unwrap().iter()
+            .map(|pass| (pass.buffer_barriers.len(), pass.image_barriers.len()))
+            .collect()
+    }
+
+    #[test]
+    fn buffer_barriers() {
+        let mut graph = RenderGraph::new();
+        let buffer = graph.register_buffer(vk::Buffer::from_raw(1), 0, 256);
+
+        let write = ResourceAccess::buffer(buffer, Stage::COPY, Access::TRANSFER_WRITE);
+        let vertex_read = ResourceAccess::buffer(buffer, Stage::VERTEX_SHADER, Access::SHADER_STORAGE_READ);
+        let fragment_read = ResourceAccess::buffer(buffer, Stage::FRAGMENT_SHADER, Access::SHADER_STORAGE_READ);
+
+        graph.add_pass("upload", &[], &[write], |_| {});
+        graph.add_pass("vertex", &[vertex_read], &[], |_| {});
+        // Already visible to the vertex shader
+        graph.add_pass("vertex 2", &[vertex_read], &[], |_| {});
+        graph.add_pass("fragment", &[fragment_read], &[], |_| {});
+        // Write after read
+        graph.add_pass("upload 2", &[], &[write], |_| {});
+        graph.compile();
+
+        assert_eq!(barrier_counts(&graph), vec![(0, 0), (1, 0), (0, 0), (1, 0), (1, 0)]);
+
+        let compiled = graph.compiled.as_ref().unwrap();
+        let raw = compiled[1].buffer_barriers[0];
+        assert_eq!((raw.src_stage_mask, raw.src_access_mask), (Stage::COPY, Access::TRANSFER_WRITE));
+        assert_eq!((raw.dst_stage_mask, raw.dst_access_mask), (Stage::VERTEX_SHADER, Access::SHADER_STORAGE_READ));
+        assert_eq!((raw.offset, raw.size), (0, 256));
+
+        let war = compiled[4].buffer_barriers[0];
+        assert_eq!(war.src_stage_mask, Stage::COPY | Stage::VERTEX_SHADER | Stage::FRAGMENT_SHADER);
+        assert_eq!(war.src_access_mask, Access::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn image_layout_transitions() {
+        let mut graph = RenderGraph::new();
+        let image = graph.register_image(vk::Image::from_raw(1), color_range(), vk::ImageLayout::UNDEFINED);
+
+        graph.add_pass("clear", &[], &[ResourceAccess::image(image, Stage::CLEAR, Access::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL)], |_| {});
+        graph.add_pass("sample", &[ResourceAccess::image(image, Stage::FRAGMENT_SHADER, Access::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)], &[], |_| {});
+        graph.add_pass("sample 2", &[ResourceAccess::image(image, Stage::FRAGMENT_SHADER, Access::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)], &[], |_| {});
+        graph.add_pass("present", &[ResourceAccess::image(image, Stage::NONE, Access::NONE, vk::ImageLayout::PRESENT_SRC_KHR)], &[], |_| {});
+        graph.compile();
+
+        assert_eq!(barrier_counts(&graph), vec![(0, 1), (0, 1), (0, 0), (0, 1)]);
+
+        let compiled = graph.compiled.as_ref().unwrap();
+        let initial = compiled[0].image_barriers[0];
+        assert_eq!(initial.src_stage_mask, Stage::ALL_COMMANDS);
+        assert_eq!((initial.old_layout, initial.new_layout), (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+
+        let sample = compiled[1].image_barriers[0];
+        assert_eq!((sample.src_stage_mask, sample.src_access_mask), (Stage::CLEAR, Access::TRANSFER_WRITE));
+        assert_eq!((sample.old_layout, sample.new_layout), (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+
+        let present = compiled[3].image_barriers[0];
+        assert_eq!((present.src_stage_mask, present.src_access_mask), (Stage::FRAGMENT_SHADER, Access::NONE));
+        assert_eq!(present.new_layout, vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    #[test]
+    fn read_write_same_pass() {
+        let mut graph = RenderGraph::new();
+        let buffer = graph.register_buffer(vk::Buffer::from_raw(1), 64, 64);
+
+        let access = ResourceAccess::buffer(buffer, Stage::COMPUTE_SHADER, Access::SHADER_STORAGE_READ);
+        let write = ResourceAccess::buffer(buffer, Stage::COMPUTE_SHADER, Access::SHADER_STORAGE_WRITE);
+        graph.add_pass("first", &[access], &[write], |_| {});
+        graph.add_pass("second", &[access], &[write], |_| {});
+        graph.compile();
+
+        assert_eq!(barrier_counts(&graph), vec![(0, 0), (1, 0)]);
+        let barrier = graph.compiled.as_ref().unwrap()[1].buffer_barriers[0];
+        assert_eq!(barrier.src_access_mask, Access::SHADER_STORAGE_READ | Access::SHADER_STORAGE_WRITE);
+    }
+
+    #[test]
+    fn execution_order() {
+        let mut graph = RenderGraph::new();
+        let a = graph.register_buffer(vk::Buffer::from_raw(1), 0, 16);
+        let b = graph.register_buffer(vk::Buffer::from_raw(2), 0, 16);
+
+        let write_a = graph.add_pass("write a", &[], &[ResourceAccess::buffer(a, Stage::COPY, Access::TRANSFER_WRITE)], |_| {});
+        let write_b = graph.add_pass("write b", &[], &[ResourceAccess::buffer(b, Stage::COPY, Access::TRANSFER_WRITE)], |_| {});
+        let read_a = graph.add_pass("read a", &[ResourceAccess::buffer(a, Stage::COPY, Access::TRANSFER_READ)], &[], |_| {});
+        assert_eq!(graph.get_execution_order(), None);
+        assert_eq!(graph.get_pass_name(read_a), "read a");
+
+        graph.compile();
+        assert_eq!(graph.get_execution_order(), Some(vec![write_a, write_b, read_a]));
+        assert_eq!(barrier_counts(&graph), vec![(0, 0), (0, 0), (1, 0)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn conflicting_layouts() {
+        let mut graph = RenderGraph::new();
+        let image = graph.register_image(vk::Image::from_raw(1), color_range(), vk::ImageLayout::UNDEFINED);
+        graph.add_pass(
+            "conflict",
+            &[ResourceAccess::image(image, Stage::FRAGMENT_SHADER, Access::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)],
+            &[ResourceAccess::image(image, Stage::CLEAR, Access::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL)],
+            |_| {}
+        );
+    }
+}
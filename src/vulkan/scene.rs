@@ -1,25 +1,799 @@
 use std::any::Any;
-use std::sync::Arc;
-use crate::scene::{Scene, SceneId, SceneUpdate};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::debug::{Counter, Gauge, Statistics};
+use crate::scene::{BackgroundColorComponent, BackgroundProperties, CameraClearMode, CameraComponent, CameraProperties, ChangeReceiver, Color, ComponentId, ComponentType, ModifiedField, Scene, SceneChange, SceneChangeBroadcast, SceneComponent, SceneData, SceneError, SceneId, SceneStatistics, SceneUpdate, SceneUpdateError, SerializedComponent};
+use crate::utils::trace_span;
+
+/// The maximum number of commit durations stored for [`Scene::average_commit_duration`].
+const MAX_COMMIT_HISTORY: usize = 256;
 
 pub struct VulkanScene {
+    weak: Weak<Self>,
+    id: SceneId,
+    guarded: Mutex<SceneGuarded>,
+    commit_timings: Mutex<CommitTimings>,
+    changes: SceneChangeBroadcast,
+    /// Shared by all scenes of the same [`crate::vulkan::AgnajiVulkan`].
+    live_components: Gauge,
+    commits: Counter,
+}
+
+impl VulkanScene {
+    pub(in crate::vulkan) fn new(statistics: &Statistics) -> Arc<Self> {
+        Arc::new_cyclic(|weak| {
+            Self {
+                weak: weak.clone(),
+                id: SceneId::new(),
+                guarded: Mutex::new(SceneGuarded {
+                    update_active: false,
+                    components: HashMap::new(),
+                    cameras: HashMap::new(),
+                    background: None,
+                }),
+                commit_timings: Mutex::new(CommitTimings::new()),
+                changes: SceneChangeBroadcast::new(),
+                live_components: statistics.register_gauge("scene.live_components"),
+                commits: statistics.register_counter("scene.commits"),
+            }
+        })
+    }
+
+    /// Records the duration of a scene update commit. Must be called by the scene update at the
+    /// end of its drop.
+    fn record_commit_duration(&self, duration: Duration) {
+        self.commit_timings.lock().unwrap().record(duration);
+    }
+
+    fn register_component(&self, id: ComponentId, component: Weak<dyn SceneComponent>, component_type: ComponentType) {
+        self.guarded.lock().unwrap().components.insert(id, component);
+        self.changes.broadcast(SceneChange::ComponentAdded(id, component_type));
+    }
+
+    fn remove_component(&self, id: ComponentId) {
+        let mut guard = self.guarded.lock().unwrap();
+        guard.components.remove(&id);
+        guard.cameras.remove(&id);
+        drop(guard);
+
+        self.changes.broadcast(SceneChange::ComponentRemoved(id));
+    }
+
+    /// Returns the content of the [`BackgroundColorComponent`] of this scene if it has one.
+    pub fn get_background(&self) -> Option<Background> {
+        let guard = self.guarded.lock().unwrap();
+        let component = guard.background.and_then(|id| guard.components.get(&id)).and_then(Weak::upgrade);
+        drop(guard);
+
+        component.and_then(|component| {
+            component.as_any().downcast_ref::<VulkanBackgroundColorComponent>().map(VulkanBackgroundColorComponent::get_background)
+        })
+    }
 
+    /// Panics if `update` is not an update of this scene.
+    fn validate_update(&self, update: &dyn SceneUpdate) {
+        if update.get_scene_id() != self.id {
+            panic!("Used scene update of scene {:?} to modify scene {:?}", update.get_scene_id(), self.id);
+        }
+    }
 }
 
 impl Scene for VulkanScene {
     fn get_scene_id(&self) -> SceneId {
-        todo!()
+        self.id
     }
 
     fn begin_update(&self) -> Result<Box<dyn SceneUpdate>, ()> {
-        todo!()
+        let mut guard = self.guarded.lock().unwrap();
+        if guard.update_active {
+            return Err(());
+        }
+        guard.update_active = true;
+        drop(guard);
+
+        Ok(Box::new(VulkanSceneUpdate {
+            scene: self.weak.upgrade().unwrap(),
+        }))
+    }
+
+    fn last_commit_duration(&self) -> Option<Duration> {
+        self.commit_timings.lock().unwrap().last()
+    }
+
+    fn average_commit_duration(&self, window: usize) -> Option<Duration> {
+        self.commit_timings.lock().unwrap().average(window)
+    }
+
+    fn clear(&self) -> Result<(), SceneUpdateError> {
+        let update = self.begin_update().map_err(|_| SceneUpdateError::UpdateInProgress)?;
+        update.destroy_all_components();
+        drop(update);
+
+        Ok(())
+    }
+
+    fn get_component_by_id(&self, id: ComponentId) -> Option<Arc<dyn SceneComponent>> {
+        self.guarded.lock().unwrap().components.get(&id).and_then(Weak::upgrade)
+    }
+
+    fn get_statistics(&self) -> SceneStatistics {
+        SceneStatistics {
+            component_count: self.guarded.lock().unwrap().components.len(),
+        }
+    }
+
+    fn iter_cameras(&self) -> Box<dyn Iterator<Item=Arc<dyn CameraComponent>> + Send> {
+        let mut cameras: Vec<_> = self.guarded.lock().unwrap().cameras.values().filter_map(Weak::upgrade).collect();
+        cameras.sort_by_key(|camera| camera.get_component_id());
+
+        Box::new(cameras.into_iter().map(|camera| camera as Arc<dyn CameraComponent>))
+    }
+
+    fn camera_count(&self) -> usize {
+        self.guarded.lock().unwrap().cameras.len()
+    }
+
+    fn subscribe_changes(&self) -> ChangeReceiver {
+        self.changes.subscribe()
+    }
+
+    fn serialize(&self) -> Result<SceneData, SceneError> {
+        let guard = self.guarded.lock().unwrap();
+        if guard.update_active {
+            return Err(SceneError::UpdateInProgress);
+        }
+        let mut components: Vec<_> = guard.components.iter()
+            .filter_map(|(id, component)| component.upgrade().map(|component| (*id, component)))
+            .collect();
+        drop(guard);
+
+        // Ids are assigned in creation order
+        components.sort_by_key(|(id, _)| *id);
+
+        let components = components.into_iter().map(|(id, component)| {
+            let id = id.get_raw();
+            let any = component.as_any();
+            if let Some(camera) = any.downcast_ref::<VulkanCameraComponent>() {
+                SerializedComponent::new(ComponentType::Camera, id, None, &camera.get_properties())
+            } else if let Some(background) = any.downcast_ref::<VulkanBackgroundColorComponent>() {
+                SerializedComponent::new(ComponentType::BackgroundColor, id, None, &BackgroundProperties::from(background.get_background()))
+            } else {
+                Err(SceneError::UnsupportedComponent(id))
+            }
+        }).collect::<Result<_, _>>()?;
+
+        Ok(SceneData::new(components))
+    }
+
+    fn as_any(&self) -> &(dyn Any + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
+        self
+    }
+}
+
+struct SceneGuarded {
+    update_active: bool,
+    /// All live components. Components remove themselves when destroyed or dropped.
+    components: HashMap<ComponentId, Weak<dyn SceneComponent>>,
+    /// All cameras of `components` to avoid downcasting when only cameras are needed.
+    cameras: HashMap<ComponentId, Weak<VulkanCameraComponent>>,
+    /// The id of the current [`BackgroundColorComponent`] if any. Only valid while the id is
+    /// also in `components`.
+    background: Option<ComponentId>,
+}
+
+pub struct VulkanSceneUpdate {
+    scene: Arc<VulkanScene>,
+}
+
+impl VulkanSceneUpdate {
+    fn register<C>(&self, component: &Arc<C>, component_type: ComponentType) where C: SceneComponent + 'static {
+        let weak: Weak<dyn SceneComponent> = Arc::downgrade(component) as Weak<C>;
+        self.scene.register_component(component.get_component_id(), weak, component_type);
+    }
+}
+
+impl SceneUpdate for VulkanSceneUpdate {
+    fn get_scene_id(&self) -> SceneId {
+        self.scene.id
+    }
+
+    fn create_camera_component(&self) -> Arc<dyn CameraComponent> {
+        let component = Arc::new(VulkanCameraComponent {
+            base: ComponentBase::new(self.scene.clone()),
+            settings: Mutex::new(CameraSettings {
+                clear_mode: CameraClearMode::ClearAll,
+                render_order: 0,
+            }),
+        });
+        self.scene.guarded.lock().unwrap().cameras.insert(component.get_component_id(), Arc::downgrade(&component));
+        self.register(&component, ComponentType::Camera);
+
+        component
+    }
+
+    fn destroy_all_components(&self) {
+        // The components remove themselves from the scene so we must not hold the lock
+        let mut guard = self.scene.guarded.lock().unwrap();
+        let components = std::mem::take(&mut guard.components);
+        guard.cameras.clear();
+        drop(guard);
+        for component in components.values().filter_map(Weak::upgrade) {
+            component.destroy(self);
+        }
+    }
+
+    fn create_background_color(&self) -> Result<Arc<dyn BackgroundColorComponent>, SceneUpdateError> {
+        let mut guard = self.scene.guarded.lock().unwrap();
+        if let Some(background) = guard.background {
+            if guard.components.get(&background).map(|c| c.strong_count() != 0).unwrap_or(false) {
+                return Err(SceneUpdateError::AlreadyExists);
+            }
+        }
+
+        let component = Arc::new(VulkanBackgroundColorComponent {
+            base: ComponentBase::new(self.scene.clone()),
+            background: Mutex::new(Background::Color(Color::BLACK)),
+        });
+        guard.background = Some(component.get_component_id());
+        drop(guard);
+
+        self.register(&component, ComponentType::BackgroundColor);
+
+        Ok(component)
+    }
+
+    fn as_any(&self) -> &(dyn Any + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_any_box(self: Box<Self>) -> Box<dyn Any + Send + Sync + 'static> {
+        self
+    }
+}
+
+impl Drop for VulkanSceneUpdate {
+    fn drop(&mut self) {
+        trace_span!("scene_update_commit", scene = ?self.scene.id);
+        let start = Instant::now();
+
+        self.scene.guarded.lock().unwrap().update_active = false;
+
+        self.scene.record_commit_duration(start.elapsed());
+        self.scene.commits.increment();
+    }
+}
+
+/// State shared by all component implementations.
+struct ComponentBase {
+    id: ComponentId,
+    scene: Arc<VulkanScene>,
+    destroyed: AtomicBool,
+}
+
+impl ComponentBase {
+    fn new(scene: Arc<VulkanScene>) -> Self {
+        scene.live_components.add(1);
+        Self {
+            id: ComponentId::new(),
+            scene,
+            destroyed: AtomicBool::new(false),
+        }
+    }
+
+    fn destroy(&self, update: &dyn SceneUpdate) {
+        self.scene.validate_update(update);
+        if !self.destroyed.swap(true, Ordering::SeqCst) {
+            self.scene.live_components.sub(1);
+            self.scene.remove_component(self.id);
+        }
+    }
+
+    fn notify_modified(&self, field: ModifiedField) {
+        self.scene.changes.broadcast(SceneChange::ComponentModified(self.id, field));
+    }
+
+    /// Validates the update and returns true if the component has not been destroyed.
+    fn is_alive(&self, update: &dyn SceneUpdate) -> bool {
+        self.scene.validate_update(update);
+        if self.destroyed.load(Ordering::SeqCst) {
+            log::warn!("Attempted to modify destroyed component {:?}", self.id);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+impl Drop for ComponentBase {
+    fn drop(&mut self) {
+        // Dropped components are destroyed. The scene lock is never held while a component may
+        // be dropped so removing the component here cannot deadlock.
+        if !self.destroyed.load(Ordering::SeqCst) {
+            self.scene.live_components.sub(1);
+            self.scene.remove_component(self.id);
+        }
+    }
+}
+
+pub struct VulkanCameraComponent {
+    base: ComponentBase,
+    settings: Mutex<CameraSettings>,
+}
+
+impl VulkanCameraComponent {
+    pub fn get_clear_mode(&self) -> CameraClearMode {
+        self.settings.lock().unwrap().clear_mode
+    }
+
+    pub fn get_render_order(&self) -> i32 {
+        self.settings.lock().unwrap().render_order
+    }
+
+    pub fn get_properties(&self) -> CameraProperties {
+        let settings = self.settings.lock().unwrap();
+        CameraProperties {
+            clear_mode: settings.clear_mode,
+            render_order: settings.render_order,
+        }
+    }
+}
+
+struct CameraSettings {
+    clear_mode: CameraClearMode,
+    render_order: i32,
+}
+
+impl SceneComponent for VulkanCameraComponent {
+    fn get_component_id(&self) -> ComponentId {
+        self.base.id
+    }
+
+    fn get_scene(&self) -> Arc<dyn Scene> {
+        self.base.scene.clone()
+    }
+
+    fn destroy(&self, update: &dyn SceneUpdate) {
+        self.base.destroy(update)
+    }
+
+    fn as_any(&self) -> &(dyn Any + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
+        self
+    }
+}
+
+impl CameraComponent for VulkanCameraComponent {
+    fn set_clear_mode(&self, update: &dyn SceneUpdate, mode: CameraClearMode) {
+        if self.base.is_alive(update) {
+            self.settings.lock().unwrap().clear_mode = mode;
+            self.base.notify_modified(ModifiedField::ClearMode);
+        }
+    }
+
+    fn set_render_order(&self, update: &dyn SceneUpdate, render_order: i32) {
+        if self.base.is_alive(update) {
+            self.settings.lock().unwrap().render_order = render_order;
+            self.base.notify_modified(ModifiedField::RenderOrder);
+        }
+    }
+}
+
+/// The content of a [`BackgroundColorComponent`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Background {
+    Color(Color),
+    Gradient {
+        top: Color,
+        bottom: Color,
+    },
+}
+
+impl From<Background> for BackgroundProperties {
+    fn from(background: Background) -> Self {
+        match background {
+            Background::Color(color) => BackgroundProperties::Color(color),
+            Background::Gradient { top, bottom } => BackgroundProperties::Gradient { top, bottom },
+        }
+    }
+}
+
+pub struct VulkanBackgroundColorComponent {
+    base: ComponentBase,
+    background: Mutex<Background>,
+}
+
+impl VulkanBackgroundColorComponent {
+    pub fn get_background(&self) -> Background {
+        *self.background.lock().unwrap()
+    }
+}
+
+impl SceneComponent for VulkanBackgroundColorComponent {
+    fn get_component_id(&self) -> ComponentId {
+        self.base.id
+    }
+
+    fn get_scene(&self) -> Arc<dyn Scene> {
+        self.base.scene.clone()
+    }
+
+    fn destroy(&self, update: &dyn SceneUpdate) {
+        self.base.destroy(update)
     }
 
     fn as_any(&self) -> &(dyn Any + Send + Sync + 'static) {
-        todo!()
+        self
     }
 
     fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
-        todo!()
+        self
+    }
+}
+
+impl BackgroundColorComponent for VulkanBackgroundColorComponent {
+    fn set_color(&self, update: &dyn SceneUpdate, color: Color) {
+        if self.base.is_alive(update) {
+            *self.background.lock().unwrap() = Background::Color(color);
+            self.base.notify_modified(ModifiedField::Background);
+        }
+    }
+
+    fn set_gradient(&self, update: &dyn SceneUpdate, top: Color, bottom: Color) {
+        if self.base.is_alive(update) {
+            *self.background.lock().unwrap() = Background::Gradient { top, bottom };
+            self.base.notify_modified(ModifiedField::Background);
+        }
+    }
+}
+
+/// Circular buffer of the durations of the most recent scene update commits.
+///
+/// A commit is measured from the start of the drop of a [`SceneUpdate`] to its end.
+struct CommitTimings {
+    history: VecDeque<Duration>,
+}
+
+impl CommitTimings {
+    fn new() -> Self {
+        Self {
+            history: VecDeque::with_capacity(MAX_COMMIT_HISTORY),
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        if self.history.len() >= MAX_COMMIT_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(duration);
+    }
+
+    fn last(&self) -> Option<Duration> {
+        self.history.back().copied()
+    }
+
+    /// Returns the average of the last `window` durations. If fewer durations have been recorded
+    /// all of them are used. Returns [`None`] if no durations have been recorded or `window` is
+    /// 0.
+    fn average(&self, window: usize) -> Option<Duration> {
+        let count = std::cmp::min(window, self.history.len());
+        if count == 0 {
+            return None;
+        }
+
+        let sum: Duration = self.history.iter().rev().take(count).sum();
+        Some(sum / count as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn iter_cameras() {
+        let scene = VulkanScene::new(&Statistics::new());
+        let update = scene.begin_update().unwrap();
+        let first = update.create_camera_component();
+        let second = update.create_camera_component();
+        let third = update.create_camera_component();
+        let _background = update.create_background_color().unwrap();
+        assert_eq!(scene.camera_count(), 3);
+
+        let ids: Vec<_> = scene.iter_cameras().map(|camera| camera.get_component_id()).collect();
+        assert_eq!(ids, vec![first.get_component_id(), second.get_component_id(), third.get_component_id()]);
+
+        // The iterator is a snapshot
+        let iter = scene.iter_cameras();
+        second.destroy(update.as_ref());
+        drop(third);
+        assert_eq!(iter.count(), 3);
+
+        assert_eq!(scene.camera_count(), 1);
+        let ids: Vec<_> = scene.iter_cameras().map(|camera| camera.get_component_id()).collect();
+        assert_eq!(ids, vec![first.get_component_id()]);
+
+        update.destroy_all_components();
+        assert_eq!(scene.camera_count(), 0);
+        assert_eq!(scene.iter_cameras().count(), 0);
+    }
+
+    #[test]
+    fn commit_timings() {
+        let mut timings = CommitTimings::new();
+        assert_eq!(timings.last(), None);
+        assert_eq!(timings.average(4), None);
+
+        for _ in 0..10 {
+            let start = Instant::now();
+            std::thread::sleep(Duration::from_millis(1));
+            timings.record(start.elapsed());
+        }
+        assert!(timings.last().unwrap() > Duration::ZERO);
+        assert!(timings.average(10).unwrap() > Duration::ZERO);
+        assert_eq!(timings.average(0), None);
+
+        let mut timings = CommitTimings::new();
+        for millis in 1..=4 {
+            timings.record(Duration::from_millis(millis));
+        }
+        assert_eq!(timings.last(), Some(Duration::from_millis(4)));
+        assert_eq!(timings.average(2), Some(Duration::from_micros(3500)));
+        assert_eq!(timings.average(100), Some(Duration::from_micros(2500)));
+    }
+
+    #[test]
+    fn commit_timings_history_is_bounded() {
+        let mut timings = CommitTimings::new();
+        for _ in 0..MAX_COMMIT_HISTORY {
+            timings.record(Duration::from_millis(10));
+        }
+        timings.record(Duration::from_millis(10 + MAX_COMMIT_HISTORY as u64));
+
+        assert_eq!(timings.history.len(), MAX_COMMIT_HISTORY);
+        assert_eq!(timings.average(MAX_COMMIT_HISTORY), Some(Duration::from_millis(11)));
+    }
+
+    #[test]
+    fn clear_removes_all_components() {
+        let scene = VulkanScene::new(&Statistics::new());
+
+        let update = scene.begin_update().unwrap();
+        let components: Vec<_> = (0..100).map(|_| update.create_camera_component()).collect();
+        assert!(scene.begin_update().is_err());
+        assert_eq!(scene.clear(), Err(SceneUpdateError::UpdateInProgress));
+        drop(update);
+
+        assert_eq!(scene.get_statistics().component_count, 100);
+        assert!(scene.get_component_by_id(components[0].get_component_id()).is_some());
+
+        scene.clear().unwrap();
+        assert_eq!(scene.get_statistics().component_count, 0);
+        for component in components.iter() {
+            assert!(scene.get_component_by_id(component.get_component_id()).is_none());
+        }
+        assert!(scene.last_commit_duration().is_some());
+    }
+
+    #[test]
+    fn single_background() {
+        let scene = VulkanScene::new(&Statistics::new());
+        let update = scene.begin_update().unwrap();
+
+        let background = update.create_background_color().unwrap();
+        assert_eq!(update.create_background_color().err(), Some(SceneUpdateError::AlreadyExists));
+
+        let color = Color::new(0.5, 0.25, 1.0, 1.0);
+        background.set_color(update.as_ref(), color);
+        let vulkan_background = background.clone().as_any_arc().downcast::<VulkanBackgroundColorComponent>().unwrap();
+        assert_eq!(vulkan_background.get_background(), Background::Color(color));
+        assert_eq!(scene.get_background(), Some(Background::Color(color)));
+
+        background.destroy(update.as_ref());
+        assert_eq!(scene.get_background(), None);
+        assert!(update.create_background_color().is_ok());
+    }
+
+    #[test]
+    fn camera_settings() {
+        let scene = VulkanScene::new(&Statistics::new());
+        let update = scene.begin_update().unwrap();
+
+        let first = update.create_camera_component();
+        let second = update.create_camera_component();
+        second.set_clear_mode(update.as_ref(), CameraClearMode::NoClear);
+        second.set_render_order(update.as_ref(), 1);
+        first.set_render_order(update.as_ref(), -1);
+
+        let mut cameras: Vec<_> = [second, first].into_iter()
+            .map(|camera| camera.as_any_arc().downcast::<VulkanCameraComponent>().unwrap())
+            .collect();
+        cameras.sort_by_key(|camera| camera.get_render_order());
+        assert_eq!(cameras[0].get_clear_mode(), CameraClearMode::ClearAll);
+        assert_eq!(cameras[1].get_clear_mode(), CameraClearMode::NoClear);
+        assert_eq!(cameras[1].get_render_order(), 1);
+
+        // Modifying a destroyed camera has no effect
+        cameras[1].destroy(update.as_ref());
+        cameras[1].set_clear_mode(update.as_ref(), CameraClearMode::ClearDepthOnly);
+        assert_eq!(cameras[1].get_clear_mode(), CameraClearMode::NoClear);
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let scene = VulkanScene::new(&Statistics::new());
+        let update = scene.begin_update().unwrap();
+        let first = update.create_camera_component();
+        first.set_clear_mode(update.as_ref(), CameraClearMode::ClearDepthOnly);
+        first.set_render_order(update.as_ref(), 2);
+        let _second = update.create_camera_component();
+        let background = update.create_background_color().unwrap();
+        let (top, bottom) = (Color::new(1.0, 0.5, 0.0, 1.0), Color::new(0.0, 0.0, 0.25, 1.0));
+        background.set_gradient(update.as_ref(), top, bottom);
+        assert_eq!(scene.serialize(), Err(SceneError::UpdateInProgress));
+        drop(update);
+
+        let data = scene.serialize().unwrap();
+        assert_eq!(data.version, SceneData::VERSION);
+        let types: Vec<_> = data.components.iter().map(|component| component.component_type).collect();
+        assert_eq!(types, vec![ComponentType::Camera, ComponentType::Camera, ComponentType::BackgroundColor]);
+
+        let json = serde_json::to_string(&data).unwrap();
+        let data: SceneData = serde_json::from_str(&json).unwrap();
+
+        let loaded = VulkanScene::new(&Statistics::new());
+        let update = loaded.begin_update().unwrap();
+        let components = data.load_into(update.as_ref()).unwrap();
+        drop(update);
+        assert_eq!(components.len(), 3);
+        assert_eq!(loaded.get_statistics().component_count, 3);
+
+        let reloaded = loaded.serialize().unwrap();
+        assert_eq!(reloaded.components.len(), 3);
+        for (original, reloaded) in data.components.iter().zip(&reloaded.components) {
+            assert_eq!(original.component_type, reloaded.component_type);
+            assert_eq!(original.properties, reloaded.properties);
+            assert_ne!(original.id, reloaded.id);
+        }
+        assert_eq!(reloaded.components[0].get_properties::<CameraProperties>().unwrap(), CameraProperties {
+            clear_mode: CameraClearMode::ClearDepthOnly,
+            render_order: 2,
+        });
+        assert_eq!(reloaded.components[2].get_properties::<BackgroundProperties>().unwrap(), BackgroundProperties::Gradient { top, bottom });
+    }
+
+    /// A component type the scene does not know how to serialize.
+    struct UnknownComponent {
+        id: ComponentId,
+        scene: Arc<VulkanScene>,
     }
-}
\ No newline at end of file
+
+    impl SceneComponent for UnknownComponent {
+        fn get_component_id(&self) -> ComponentId {
+            self.id
+        }
+
+        fn get_scene(&self) -> Arc<dyn Scene> {
+            self.scene.clone()
+        }
+
+        fn destroy(&self, _: &dyn SceneUpdate) {
+        }
+
+        fn as_any(&self) -> &(dyn Any + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    #[test]
+    fn serialize_unknown_component() {
+        let scene = VulkanScene::new(&Statistics::new());
+        let component = Arc::new(UnknownComponent {
+            id: ComponentId::new(),
+            scene: scene.clone(),
+        });
+        let weak: Weak<dyn SceneComponent> = Arc::downgrade(&component) as Weak<UnknownComponent>;
+        scene.register_component(component.id, weak, ComponentType::Camera);
+
+        assert_eq!(scene.serialize(), Err(SceneError::UnsupportedComponent(component.id.get_raw())));
+    }
+
+    #[test]
+    fn load_invalid_data() {
+        let camera = |id, parent| SerializedComponent::new(ComponentType::Camera, id, parent, &CameraProperties {
+            clear_mode: CameraClearMode::ClearAll,
+            render_order: 0,
+        }).unwrap();
+        let background = |id| SerializedComponent::new(ComponentType::BackgroundColor, id, None, &BackgroundProperties::Color(Color::BLACK)).unwrap();
+
+        let scene = VulkanScene::new(&Statistics::new());
+        let update = scene.begin_update().unwrap();
+        let load = |data: SceneData| data.load_into(update.as_ref());
+
+        let mut data = SceneData::new(vec![camera(1, None)]);
+        data.version = 0;
+        assert_eq!(load(data).err(), Some(SceneError::UnsupportedVersion(0)));
+        assert_eq!(load(SceneData::new(vec![camera(1, None), camera(1, None)])).err(), Some(SceneError::DuplicateComponentId(1)));
+        assert_eq!(load(SceneData::new(vec![camera(1, Some(3))])).err(), Some(SceneError::UnknownParent { id: 1, parent: 3 }));
+
+        let mut invalid = camera(2, None);
+        invalid.properties = String::from("{}");
+        assert!(matches!(load(SceneData::new(vec![camera(1, None), invalid])), Err(SceneError::InvalidProperties { id: 2, .. })));
+
+        // Nothing is created if the data is invalid
+        assert_eq!(scene.get_statistics().component_count, 0);
+
+        assert_eq!(load(SceneData::new(vec![background(1), background(2)])).err(), Some(SceneError::Update(SceneUpdateError::AlreadyExists)));
+    }
+
+    #[test]
+    fn change_notifications() {
+        let scene = VulkanScene::new(&Statistics::new());
+        let receiver = scene.subscribe_changes();
+        drop(scene.subscribe_changes());
+
+        let update = scene.begin_update().unwrap();
+        let camera = update.create_camera_component();
+        camera.set_clear_mode(update.as_ref(), CameraClearMode::NoClear);
+        let background = update.create_background_color().unwrap();
+        background.set_color(update.as_ref(), Color::BLACK);
+        camera.destroy(update.as_ref());
+        // Destroyed components are not modified and not removed again
+        camera.set_render_order(update.as_ref(), 1);
+        camera.destroy(update.as_ref());
+        drop(update);
+
+        let camera_id = camera.get_component_id();
+        let background_id = background.get_component_id();
+        assert_eq!(receiver.try_recv(), Some(SceneChange::ComponentAdded(camera_id, ComponentType::Camera)));
+        assert_eq!(receiver.try_recv(), Some(SceneChange::ComponentModified(camera_id, ModifiedField::ClearMode)));
+        assert_eq!(receiver.try_recv(), Some(SceneChange::ComponentAdded(background_id, ComponentType::BackgroundColor)));
+        assert_eq!(receiver.try_recv(), Some(SceneChange::ComponentModified(background_id, ModifiedField::Background)));
+        assert_eq!(receiver.try_recv(), Some(SceneChange::ComponentRemoved(camera_id)));
+        assert_eq!(receiver.try_recv(), None);
+
+        let waiter = std::thread::spawn(move || receiver.wait_change());
+        scene.clear().unwrap();
+        assert_eq!(waiter.join().unwrap(), Some(SceneChange::ComponentRemoved(background_id)));
+    }
+
+    #[test]
+    fn live_component_statistics() {
+        let statistics = Statistics::new();
+        let scene = VulkanScene::new(&statistics);
+        let other = VulkanScene::new(&statistics);
+
+        let update = scene.begin_update().unwrap();
+        let camera = update.create_camera_component();
+        let dropped = update.create_camera_component();
+        let _background = update.create_background_color().unwrap();
+        drop(update);
+        drop(other.begin_update().unwrap().create_camera_component());
+        assert_eq!(statistics.get("scene.live_components"), Some(3));
+        assert_eq!(statistics.get("scene.commits"), Some(2));
+
+        drop(dropped);
+        assert_eq!(scene.get_statistics().component_count, 2);
+        assert_eq!(scene.camera_count(), 1);
+        camera.destroy(scene.begin_update().unwrap().as_ref());
+        assert_eq!(statistics.get("scene.live_components"), Some(1));
+
+        scene.clear().unwrap();
+        assert_eq!(statistics.get("scene.live_components"), Some(0));
+    }
+}
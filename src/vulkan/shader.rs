@@ -0,0 +1,137 @@
+//! Loading of SPIR-V shader modules.
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+use crate::vulkan::reflection::SPIRV_MAGIC;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ShaderError {
+    /// The length in bytes of the SPIR-V code is not a non zero multiple of 4.
+    InvalidLength(usize),
+
+    /// The code does not start with the SPIR-V magic number in native byte order.
+    InvalidMagic,
+
+    Vulkan(vk::Result),
+}
+
+impl From<vk::Result> for ShaderError {
+    fn from(result: vk::Result) -> Self {
+        ShaderError::Vulkan(result)
+    }
+}
+
+/// Validated SPIR-V code in native byte order.
+///
+/// Usually created from embedded bytes using [`include_spirv!`](crate::include_spirv).
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CompiledSpirv(Vec<u32>);
+
+impl CompiledSpirv {
+    /// Converts the bytes into SPIR-V words. The bytes do not need to be 4 byte aligned.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ShaderError> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(4) {
+            return Err(ShaderError::InvalidLength(bytes.len()));
+        }
+
+        // Byte slices are not guaranteed to be aligned so we must copy
+        let words: Vec<u32> = bytes.chunks_exact(4)
+            .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        if words[0] != SPIRV_MAGIC {
+            return Err(ShaderError::InvalidMagic);
+        }
+
+        Ok(Self(words))
+    }
+
+    pub fn get_words(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+/// Embeds a SPIR-V file at compile time and evaluates to a [`CompiledSpirv`]. The path is
+/// resolved the same way as [`include_bytes!`].
+///
+/// # Panics
+/// If the file does not contain valid SPIR-V in native byte order.
+#[macro_export]
+macro_rules! include_spirv {
+    ($path:expr) => {
+        match $crate::vulkan::shader::CompiledSpirv::from_bytes(include_bytes!($path)) {
+            Ok(spirv) => spirv,
+            Err(err) => panic!("Embedded SPIR-V file {} is invalid: {:?}", $path, err),
+        }
+    };
+}
+
+pub use crate::include_spirv;
+
+pub struct ShaderModule {
+    device: Arc<MainDeviceContext>,
+    handle: vk::ShaderModule,
+}
+
+impl ShaderModule {
+    pub fn from_compiled(device: Arc<MainDeviceContext>, spirv: &CompiledSpirv) -> Result<Self, ShaderError> {
+        let create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(spirv.get_words());
+
+        let handle = unsafe {
+            device.get_device().create_shader_module(&create_info, None)
+        }?;
+
+        Ok(Self {
+            device,
+            handle,
+        })
+    }
+
+    /// Creates a shader module from SPIR-V bytes loaded at runtime.
+    pub fn from_bytes(device: Arc<MainDeviceContext>, bytes: &[u8]) -> Result<Self, ShaderError> {
+        Self::from_compiled(device, &CompiledSpirv::from_bytes(bytes)?)
+    }
+
+    pub fn get_handle(&self) -> vk::ShaderModule {
+        self.handle
+    }
+}
+
+impl Drop for ShaderModule {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.get_device().destroy_shader_module(self.handle, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_spirv() {
+        let spirv = include_spirv!("../../tests/shaders/minimal.comp.spv");
+        assert_eq!(spirv.get_words()[0], SPIRV_MAGIC);
+        assert_eq!(spirv.get_words().len(), include_bytes!("../../tests/shaders/minimal.comp.spv").len() / 4);
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        assert_eq!(CompiledSpirv::from_bytes(&[]), Err(ShaderError::InvalidLength(0)));
+        assert_eq!(CompiledSpirv::from_bytes(&[0; 7]), Err(ShaderError::InvalidLength(7)));
+        assert_eq!(CompiledSpirv::from_bytes(&[0; 8]), Err(ShaderError::InvalidMagic));
+
+        let swapped = SPIRV_MAGIC.swap_bytes().to_ne_bytes();
+        assert_eq!(CompiledSpirv::from_bytes(&swapped), Err(ShaderError::InvalidMagic));
+
+        // Unaligned slices must be accepted
+        let mut bytes = vec![0u8];
+        bytes.extend_from_slice(&SPIRV_MAGIC.to_ne_bytes());
+        assert_eq!(CompiledSpirv::from_bytes(&bytes[1..]).unwrap().get_words(), &[SPIRV_MAGIC]);
+    }
+}
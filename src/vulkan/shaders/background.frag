@@ -0,0 +1,16 @@
+#version 450
+
+// Vertical gradient between two colors. A solid color uses the same color for both.
+
+layout(push_constant) uniform Background {
+    vec4 top;
+    vec4 bottom;
+} background;
+
+layout(location = 0) in float in_t;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = background.top + (background.bottom - background.top) * in_t;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// Full-screen triangle generated from the vertex index. Draw with 3 vertices and no vertex buffer.
+
+layout(location = 0) out float out_t;
+
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+
+    // 0 at the top edge and 1 at the bottom edge of the viewport
+    out_t = uv.y;
+}
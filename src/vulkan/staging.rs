@@ -0,0 +1,303 @@
+//! Uploads of data to device buffers through host visible staging memory.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+use crate::vulkan::memory::{BufferArenaError, FrameRingError, MappedBuffer, RingState};
+
+/// The alignment of every staging range.
+const STAGING_ALIGNMENT: u64 = 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum StagingError {
+    Memory(BufferArenaError),
+
+    /// Staging space could not be reserved even though no copies are pending.
+    Ring(FrameRingError),
+
+    Vulkan(vk::Result),
+}
+
+impl From<vk::Result> for StagingError {
+    fn from(result: vk::Result) -> Self {
+        StagingError::Vulkan(result)
+    }
+}
+
+impl From<BufferArenaError> for StagingError {
+    fn from(err: BufferArenaError) -> Self {
+        StagingError::Memory(err)
+    }
+}
+
+/// Copies data into device buffers using a fixed size host visible staging buffer.
+///
+/// Copies are recorded into a command buffer which is submitted to the main queue by
+/// [`StagingManager::flush`] or once the staging buffer runs out of space. Every submission
+/// signals a new value of a timeline semaphore and staging space is recycled once that value has
+/// been reached. Uploads larger than the staging buffer are split across multiple submissions.
+///
+/// The main queue is used to avoid queue family ownership transfers. Every submission ends with
+/// a memory barrier so later submissions to the main queue observe the uploaded data once the
+/// returned timeline value has been reached.
+pub struct StagingManager {
+    device: Arc<MainDeviceContext>,
+    buffer: MappedBuffer,
+    coherent: bool,
+    ring: RingState,
+    command_pool: vk::CommandPool,
+    semaphore: vk::Semaphore,
+    /// The timeline value signaled by the next submission.
+    next_value: u64,
+    recording: Option<vk::CommandBuffer>,
+    in_flight: VecDeque<(u64, vk::CommandBuffer)>,
+    free_command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl StagingManager {
+    /// Creates a new staging manager with a staging buffer of `staging_size` bytes.
+    ///
+    /// # Panics
+    /// If `staging_size` is not a power of 2 or smaller than 1KiB.
+    pub fn new(device: Arc<MainDeviceContext>, staging_size: u64) -> Result<Self, StagingError> {
+        assert!(staging_size.is_power_of_two() && staging_size >= 1024, "Invalid staging size {}", staging_size);
+
+        let buffer = MappedBuffer::new(&device, staging_size, vk::BufferUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::HOST_VISIBLE, vk::MemoryPropertyFlags::HOST_COHERENT, Some("agnaji staging buffer"))?;
+        let coherent = buffer.coherent;
+
+        let vk_device = device.get_device();
+        let pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(device.get_main_queue().get_queue_family());
+        let command_pool = match unsafe { vk_device.create_command_pool(&pool_create_info, None) } {
+            Ok(command_pool) => command_pool,
+            Err(err) => {
+                unsafe { buffer.destroy(&device) };
+                return Err(err.into());
+            }
+        };
+
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore_create_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut type_info);
+        let semaphore = match unsafe { vk_device.create_semaphore(&semaphore_create_info, None) } {
+            Ok(semaphore) => semaphore,
+            Err(err) => {
+                unsafe {
+                    vk_device.destroy_command_pool(command_pool, None);
+                    buffer.destroy(&device);
+                }
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            device,
+            buffer,
+            coherent,
+            ring: RingState::new(staging_size, STAGING_ALIGNMENT),
+            command_pool,
+            semaphore,
+            next_value: 1,
+            recording: None,
+            in_flight: VecDeque::new(),
+            free_command_buffers: Vec::new(),
+        })
+    }
+
+    /// Records a copy of `data` into `dst` at `dst_offset`. Returns the timeline value of the
+    /// submission that will contain the last part of the copy. The copy is only guaranteed to be
+    /// submitted after calling [`StagingManager::flush`].
+    ///
+    /// The `dst` buffer must have been created with [`vk::BufferUsageFlags::TRANSFER_DST`].
+    pub fn upload_buffer(&mut self, data: &[u8], dst: vk::Buffer, dst_offset: u64) -> Result<u64, StagingError> {
+        let mut remaining = data;
+        let mut dst_offset = dst_offset;
+
+        while !remaining.is_empty() {
+            let chunk_size = (remaining.len() as u64).min(self.ring.get_size());
+            let (src_offset, cmd) = self.allocate_staging(chunk_size)?;
+
+            let (chunk, rest) = remaining.split_at(chunk_size as usize);
+            unsafe {
+                std::ptr::copy_nonoverlapping(chunk.as_ptr(), self.buffer.mapped.add(src_offset as usize), chunk.len());
+            }
+
+            let region = vk::BufferCopy {
+                src_offset,
+                dst_offset,
+                size: chunk_size,
+            };
+            unsafe {
+                self.device.get_device().cmd_copy_buffer(cmd, self.buffer.buffer, dst, std::slice::from_ref(&region));
+            }
+
+            remaining = rest;
+            dst_offset += chunk_size;
+        }
+
+        Ok(self.next_value)
+    }
+
+    /// Submits all recorded copies. Returns the timeline value signaled once all copies recorded
+    /// so far have completed.
+    pub fn flush(&mut self) -> Result<u64, StagingError> {
+        let cmd = match self.recording.take() {
+            Some(cmd) => cmd,
+            None => return Ok(self.next_value - 1),
+        };
+        let vk_device = self.device.get_device();
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE);
+        unsafe {
+            vk_device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                std::slice::from_ref(&barrier),
+                &[],
+                &[]
+            );
+            vk_device.end_command_buffer(cmd)?;
+        }
+
+        if !self.coherent {
+            let range = vk::MappedMemoryRange::builder()
+                .memory(self.buffer.memory)
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            unsafe { vk_device.flush_mapped_memory_ranges(std::slice::from_ref(&range)) }?;
+        }
+
+        let value = self.next_value;
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .signal_semaphore_values(std::slice::from_ref(&value));
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(std::slice::from_ref(&cmd))
+            .signal_semaphores(std::slice::from_ref(&self.semaphore))
+            .push_next(&mut timeline_info);
+
+        let queue = self.device.get_main_queue().lock().unwrap();
+        unsafe { vk_device.queue_submit(*queue, std::slice::from_ref(&submit_info), vk::Fence::null()) }?;
+        drop(queue);
+
+        self.ring.end_frame(value);
+        self.in_flight.push_back((value, cmd));
+        self.next_value += 1;
+
+        Ok(value)
+    }
+
+    /// Blocks until the timeline reaches `value` and recycles all completed staging space.
+    pub fn wait(&mut self, value: u64) -> Result<(), StagingError> {
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(std::slice::from_ref(&self.semaphore))
+            .values(std::slice::from_ref(&value));
+        unsafe { self.device.get_device().wait_semaphores(&wait_info, u64::MAX) }?;
+
+        self.recycle(value);
+        Ok(())
+    }
+
+    /// Recycles the staging space of all completed submissions without blocking.
+    pub fn poll(&mut self) -> Result<u64, StagingError> {
+        let completed = unsafe { self.device.get_device().get_semaphore_counter_value(self.semaphore) }?;
+        self.recycle(completed);
+        Ok(completed)
+    }
+
+    /// Returns the timeline semaphore signaled by the submissions.
+    pub fn get_semaphore(&self) -> vk::Semaphore {
+        self.semaphore
+    }
+
+    fn recycle(&mut self, completed: u64) {
+        self.ring.recycle(completed);
+        while let Some((value, cmd)) = self.in_flight.front().copied() {
+            if value > completed {
+                break;
+            }
+            self.free_command_buffers.push(cmd);
+            self.in_flight.pop_front();
+        }
+    }
+
+    /// Allocates staging space submitting and waiting for previous copies if necessary. Returns
+    /// the offset of the space and the command buffer the copy must be recorded into.
+    ///
+    /// The command buffer is acquired before the space is reserved so every reservation is
+    /// released by the submission of the recording command buffer.
+    fn allocate_staging(&mut self, size: u64) -> Result<(u64, vk::CommandBuffer), StagingError> {
+        loop {
+            let cmd = self.get_recording_command_buffer()?;
+            match self.ring.allocate(size) {
+                Ok(offset) => return Ok((offset, cmd)),
+                Err(err @ FrameRingError::OutOfSpace { .. }) => {
+                    // The recorded copies may be holding the space
+                    self.flush()?;
+
+                    // The ring is empty once everything completed so the allocation must succeed
+                    match self.in_flight.front().copied() {
+                        Some((oldest, _)) => self.wait(oldest)?,
+                        None => return Err(StagingError::Ring(err)),
+                    }
+                }
+                Err(FrameRingError::EmptyPush) => unreachable!(),
+            }
+        }
+    }
+
+    fn get_recording_command_buffer(&mut self) -> Result<vk::CommandBuffer, StagingError> {
+        if let Some(cmd) = self.recording {
+            return Ok(cmd);
+        }
+        let vk_device = self.device.get_device();
+
+        let cmd = match self.free_command_buffers.pop() {
+            Some(cmd) => {
+                unsafe { vk_device.reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty()) }?;
+                cmd
+            }
+            None => {
+                let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(self.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1);
+                unsafe { vk_device.allocate_command_buffers(&allocate_info) }?[0]
+            }
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe { vk_device.begin_command_buffer(cmd, &begin_info) }?;
+
+        self.recording = Some(cmd);
+        Ok(cmd)
+    }
+}
+
+impl Drop for StagingManager {
+    fn drop(&mut self) {
+        if let Some((last, _)) = self.in_flight.back().copied() {
+            if let Err(err) = self.wait(last) {
+                log::error!("Failed to wait for staging submissions: {:?}", err);
+            }
+        }
+
+        unsafe {
+            let vk_device = self.device.get_device();
+            vk_device.destroy_semaphore(self.semaphore, None);
+            // Also frees all command buffers including a potentially unsubmitted one
+            vk_device.destroy_command_pool(self.command_pool, None);
+            self.buffer.destroy(&self.device);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::ffi::CString;
 use std::marker::PhantomData;
+use std::time::Duration;
 
 use ash::vk;
 use static_assertions::assert_impl_all;
@@ -9,6 +10,23 @@ use crate::prelude::*;
 
 define_counting_id_type!(pub, SurfaceProviderId);
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum SurfaceCreateError {
+    /// The canvas is currently not available because the application is suspended. Once the
+    /// application resumes surface creation may succeed again.
+    Suspended,
+    Vulkan(vk::Result),
+}
+
+impl From<vk::Result> for SurfaceCreateError {
+    fn from(result: vk::Result) -> Self {
+        Self::Vulkan(result)
+    }
+}
+
+/// Called after the surface has been destroyed.
+pub type SurfaceDropHook<'a> = Box<dyn FnOnce() + Send + Sync + 'a>;
+
 /// Provides a api to create and use vulkan surfaces associated with some canvas (for example a
 /// window).
 pub trait VulkanSurfaceProvider: Send {
@@ -16,12 +34,45 @@ pub trait VulkanSurfaceProvider: Send {
     ///
     /// # Safety
     /// Calling this function while a surface already exists in undefined behaviour.
-    unsafe fn create_surface<'a, 'b>(&'a self, instance: &'b crate::vulkan::InstanceContext) -> Result<Surface<'a, 'b>, vk::Result>;
+    unsafe fn create_surface<'a, 'b>(&'a self, instance: &'b crate::vulkan::InstanceContext) -> Result<Surface<'a, 'b>, SurfaceCreateError>;
 
     /// Returns the size of the canvas in pixels backing the surface (for example the window size)
     /// or [`None`] if that is currently undefined. If [`None`] is returned the renderer may not
     /// be able to create a swapchain so during normal use this function should return a valid size.
     fn get_canvas_size(&self) -> Option<Vec2u32>;
+
+    /// Returns true if the canvas is currently fully hidden from view (for example a window
+    /// covered by other windows). Used by outputs to pause rendering if requested.
+    ///
+    /// The default implementation always returns false.
+    fn is_occluded(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the canvas is about to become unavailable (for example because the
+    /// application is being suspended) and any surface must be destroyed as soon as possible.
+    ///
+    /// The default implementation always returns false.
+    fn should_release_surface(&self) -> bool {
+        false
+    }
+
+    /// Blocks until a redraw of the canvas has been requested or the timeout elapsed. Returns
+    /// true if a redraw has been requested. Used by outputs with on demand rendering enabled.
+    ///
+    /// The default implementation always returns true immediately.
+    fn wait_redraw_requested(&self, timeout: Duration) -> bool {
+        let _ = timeout;
+        true
+    }
+
+    /// Returns the win32 monitor the canvas is currently displayed on. Required for exclusive
+    /// full screen mode on windows (see [`crate::vulkan::output::SurfaceOutput::request_exclusive_fullscreen`]).
+    ///
+    /// The default implementation always returns [`None`].
+    fn get_win32_monitor(&self) -> Option<vk::HMONITOR> {
+        None
+    }
 }
 
 /// Wrapper of a vulkan surface.
@@ -31,6 +82,7 @@ pub trait VulkanSurfaceProvider: Send {
 pub struct Surface<'a, 'b> {
     instance: &'b crate::vulkan::InstanceContext,
     surface: vk::SurfaceKHR,
+    drop_hook: Option<SurfaceDropHook<'a>>,
 
     #[allow(unused)]
     _phantom: PhantomData<&'a ()>
@@ -39,6 +91,12 @@ pub struct Surface<'a, 'b> {
 impl<'a, 'b> Surface<'a, 'b> {
     /// Creates a new instance of this struct for the provided surface.
     pub fn new(instance: &'b crate::vulkan::InstanceContext, surface: vk::SurfaceKHR) -> Self {
+        Self::new_with_drop_hook(instance, surface, None)
+    }
+
+    /// Creates a new instance of this struct for the provided surface. If a `drop_hook` is
+    /// provided it is called after the surface has been destroyed.
+    pub fn new_with_drop_hook(instance: &'b crate::vulkan::InstanceContext, surface: vk::SurfaceKHR, drop_hook: Option<SurfaceDropHook<'a>>) -> Self {
         if instance.get_khr_surface().is_none() {
             panic!("Called Surface::new with instance that does not have the VK_KHR_surface extension enabled");
         }
@@ -49,6 +107,7 @@ impl<'a, 'b> Surface<'a, 'b> {
         Self {
             instance,
             surface,
+            drop_hook,
             _phantom: PhantomData,
         }
     }
@@ -68,6 +127,9 @@ impl<'a, 'b> Drop for Surface<'a, 'b> {
         unsafe {
             self.instance.get_khr_surface().unwrap().destroy_surface(self.surface, None);
         }
+        if let Some(drop_hook) = self.drop_hook.take() {
+            drop_hook();
+        }
     }
 }
 
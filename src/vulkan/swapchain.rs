@@ -2,7 +2,10 @@ use std::time::{Duration, Instant};
 
 use ash::vk;
 
+use crate::utils::trace_span;
 use crate::vulkan::device::{DeviceProvider, DeviceQueue, MainDeviceContext, SwapchainProvider};
+use crate::vulkan::error::{VkError, VkResultExt};
+use crate::vulkan::instance::DebugSuppressionGuard;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 #[must_use]
@@ -11,20 +14,33 @@ pub enum NextImageResult {
     MustRecreate,
     Suboptimal,
     Timeout,
+    /// The device has been lost. The swapchain and device must be recreated.
+    DeviceLost,
     VulkanError(vk::Result),
 }
 
 impl From<vk::Result> for NextImageResult {
     fn from(result: vk::Result) -> Self {
-        Self::VulkanError(result)
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => Self::DeviceLost,
+            vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT => Self::MustRecreate,
+            _ => Self::VulkanError(result),
+        }
     }
 }
 
 pub struct Swapchain<'a> {
     device: &'a ash::Device,
     swapchain_khr: &'a ash::extensions::khr::Swapchain,
+    /// Only set if the swapchain has been created with application controlled exclusive full
+    /// screen mode.
+    ext_full_screen_exclusive: Option<&'a ash::extensions::ext::FullScreenExclusive>,
+    full_screen_exclusive_acquired: bool,
 
     swapchain: vk::SwapchainKHR,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    image_usage: vk::ImageUsageFlags,
     images: Box<[SwapchainImage]>,
 
     acquire_fence: vk::Fence,
@@ -34,20 +50,28 @@ pub struct Swapchain<'a> {
 }
 
 impl<'a> Swapchain<'a> {
-    pub fn new(swapchain: vk::SwapchainKHR, device: &'a MainDeviceContext) -> Result<Self, vk::Result> {
+    /// Wraps `swapchain`. If `full_screen_exclusive` is true the swapchain must have been created
+    /// with [`vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED`].
+    pub fn new(swapchain: vk::SwapchainKHR, device: &'a MainDeviceContext, extent: vk::Extent2D, format: vk::Format, image_usage: vk::ImageUsageFlags, full_screen_exclusive: bool) -> Result<Self, VkError> {
         let swapchain_khr = device.get_swapchain_khr().unwrap();
+        let ext_full_screen_exclusive = if full_screen_exclusive {
+            device.get_ext_full_screen_exclusive()
+        } else {
+            None
+        };
+        let debug_utils = device.get_debug_utils();
         let device = device.get_device();
 
         let images_raw = unsafe {
             swapchain_khr.get_swapchain_images(swapchain)
-        }?;
+        }.context("get swapchain images")?;
 
         let fence_create_info = vk::FenceCreateInfo::builder()
             .flags(vk::FenceCreateFlags::SIGNALED);
 
         let acquire_fence = unsafe {
             device.create_fence(&fence_create_info, None)
-        }?;
+        }.context("create swapchain acquire fence")?;
 
         let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
         let mut acquire_semaphores = Vec::with_capacity(images_raw.len());
@@ -62,13 +86,13 @@ impl<'a> Swapchain<'a> {
                     };
                     err
                 }
-            })?;
+            }).context("create swapchain acquire semaphore")?;
             acquire_semaphores.push(semaphore);
         }
 
         let mut images: Vec<SwapchainImage> = Vec::with_capacity(images_raw.len());
-        for image in images_raw.into_iter() {
-            let image = SwapchainImage::new(image, device).map_err(|err| {
+        for (index, image) in images_raw.into_iter().enumerate() {
+            let image = SwapchainImage::new(image, index as u32, device).map_err(|err| {
                 unsafe {
                     device.destroy_fence(acquire_fence, None);
                     for semaphore in &acquire_semaphores {
@@ -83,10 +107,24 @@ impl<'a> Swapchain<'a> {
             images.push(image);
         }
 
+        if debug_utils.is_enabled() {
+            let handle = device.handle();
+            for (index, (image, acquire_semaphore)) in images.iter().zip(acquire_semaphores.iter()).enumerate() {
+                debug_utils.set_image_name(handle, image.image, &format!("agnaji swapchain image {}", index));
+                debug_utils.set_semaphore_name(handle, image.present_semaphore, &format!("agnaji swapchain present semaphore {}", index));
+                debug_utils.set_semaphore_name(handle, *acquire_semaphore, &format!("agnaji swapchain acquire semaphore {}", index));
+            }
+        }
+
         Ok(Self {
             device,
             swapchain_khr,
+            ext_full_screen_exclusive,
+            full_screen_exclusive_acquired: false,
             swapchain,
+            extent,
+            format,
+            image_usage,
             images: images.into_boxed_slice(),
             acquire_fence,
             acquire_semaphores: acquire_semaphores.into_boxed_slice(),
@@ -94,43 +132,86 @@ impl<'a> Swapchain<'a> {
         })
     }
 
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn get_format(&self) -> vk::Format {
+        self.format
+    }
+
+    /// Returns the usage flags the swapchain images have been created with.
+    pub fn get_image_usage(&self) -> vk::ImageUsageFlags {
+        self.image_usage
+    }
+
+    pub fn get_image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Returns all images of the swapchain ordered by their index.
+    pub fn get_images(&self) -> &[SwapchainImage] {
+        &self.images
+    }
+
+    /// Acquires exclusive full screen access if the swapchain has been created with application
+    /// controlled exclusive full screen mode. The access is released when the swapchain is
+    /// dropped. Does nothing if the access is already held or the swapchain has not been created
+    /// for exclusive full screen mode.
+    pub fn acquire_full_screen_exclusive(&mut self) -> Result<(), VkError> {
+        if let Some(ext) = self.ext_full_screen_exclusive.filter(|_| !self.full_screen_exclusive_acquired) {
+            unsafe {
+                ext.acquire_full_screen_exclusive_mode(self.swapchain)
+            }.context("acquire exclusive fullscreen")?;
+            self.full_screen_exclusive_acquired = true;
+        }
+
+        Ok(())
+    }
+
     /// Attempts to acquire a image and calls the provided closure with it.
     pub fn with_next_image<'b, F>(&mut self, timeout: Duration, f: F) -> NextImageResult where
         F: FnOnce(&SwapchainImage, vk::Semaphore) -> Option<&'b DeviceQueue> {
 
-        let start_instant = Instant::now();
-        if let Err(result) = unsafe {
-            self.device.wait_for_fences(std::slice::from_ref(&self.acquire_fence), true, timeout.as_nanos() as u64)
-        } {
-            return match result {
-                vk::Result::TIMEOUT => NextImageResult::Timeout,
-                _ => NextImageResult::VulkanError(result),
+        let (index, acquire_semaphore) = {
+            trace_span!("acquire_image");
+
+            let start_instant = Instant::now();
+            if let Err(result) = unsafe {
+                self.device.wait_for_fences(std::slice::from_ref(&self.acquire_fence), true, timeout_to_nanos(timeout))
+            } {
+                return match result {
+                    vk::Result::TIMEOUT => NextImageResult::Timeout,
+                    _ => NextImageResult::from(result),
+                }
             }
-        }
 
-        if let Err(result) = unsafe {
-            self.device.reset_fences(std::slice::from_ref(&self.acquire_fence))
-        } {
-            return NextImageResult::VulkanError(result);
-        }
+            if let Err(result) = unsafe {
+                self.device.reset_fences(std::slice::from_ref(&self.acquire_fence))
+            } {
+                return NextImageResult::from(result);
+            }
+
+            let acquire_semaphore = self.acquire_semaphores[self.next_acquire_semaphore];
+
+            let timeout = timeout_to_nanos(remaining_timeout(timeout, start_instant.elapsed()));
+            let (index, _) = match unsafe {
+                self.swapchain_khr.acquire_next_image(self.swapchain, timeout, acquire_semaphore, self.acquire_fence)
+            } {
+                Ok(ok) => ok,
+                Err(vk::Result::TIMEOUT) => return NextImageResult::Timeout,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return NextImageResult::MustRecreate,
+                Err(result) => return NextImageResult::from(result),
+            };
+            self.next_acquire_semaphore = (self.next_acquire_semaphore + 1) % self.acquire_semaphores.len();
 
-        let acquire_semaphore = self.acquire_semaphores[self.next_acquire_semaphore];
-
-        let timeout = timeout - (Instant::now() - start_instant);
-        let timeout = timeout.as_nanos() as u64;
-        let (index, _) = match unsafe {
-            self.swapchain_khr.acquire_next_image(self.swapchain, timeout, acquire_semaphore, self.acquire_fence)
-        } {
-            Ok(ok) => ok,
-            Err(vk::Result::TIMEOUT) => return NextImageResult::Timeout,
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return NextImageResult::MustRecreate,
-            Err(result) => return NextImageResult::VulkanError(result),
+            (index, acquire_semaphore)
         };
-        self.next_acquire_semaphore = (self.next_acquire_semaphore + 1) % self.acquire_semaphores.len();
 
         let image = &self.images[index as usize];
 
         if let Some(queue) = f(image, acquire_semaphore) {
+            trace_span!("present");
             let present_info = vk::PresentInfoKHR::builder()
                 .wait_semaphores(std::slice::from_ref(&image.present_semaphore))
                 .swapchains(std::slice::from_ref(&self.swapchain))
@@ -146,7 +227,7 @@ impl<'a> Swapchain<'a> {
                 Ok(false) => NextImageResult::Ok,
                 Ok(true) => NextImageResult::Suboptimal,
                 Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => NextImageResult::MustRecreate,
-                Err(result) => NextImageResult::VulkanError(result),
+                Err(result) => NextImageResult::from(result),
             }
         } else {
             NextImageResult::MustRecreate
@@ -157,8 +238,17 @@ impl<'a> Swapchain<'a> {
 impl<'a> Drop for Swapchain<'a> {
     fn drop(&mut self) {
         unsafe {
-            self.device.device_wait_idle().unwrap();
-            self.device.wait_for_fences(std::slice::from_ref(&self.acquire_fence), true, u64::MAX).unwrap();
+            // The validation layers report images acquired but never presented as having an
+            // invalid layout during teardown
+            let suppression = DebugSuppressionGuard::new("UNASSIGNED-CoreValidation-DrawState-InvalidImageLayout");
+
+            // If the device has been lost waiting fails but all resources must still be destroyed
+            if let Err(err) = self.device.device_wait_idle() {
+                log::error!("Failed to wait for device idle while destroying swapchain: {:?}", err);
+            } else if let Err(err) = self.device.wait_for_fences(std::slice::from_ref(&self.acquire_fence), true, u64::MAX) {
+                log::error!("Failed to wait for acquire fence while destroying swapchain: {:?}", err);
+            }
+            drop(suppression);
 
             for image in self.images.iter() {
                 image.destroy(self.device);
@@ -168,28 +258,47 @@ impl<'a> Drop for Swapchain<'a> {
             }
             self.device.destroy_fence(self.acquire_fence, None);
 
+            if let Some(ext) = self.ext_full_screen_exclusive.filter(|_| self.full_screen_exclusive_acquired) {
+                if let Err(err) = ext.release_full_screen_exclusive_mode(self.swapchain) {
+                    log::warn!("Failed to release exclusive full screen mode: {:?}", err);
+                }
+            }
             self.swapchain_khr.destroy_swapchain(self.swapchain, None);
         }
     }
 }
 
+impl<'a> std::fmt::Debug for Swapchain<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Swapchain")
+            .field("image_count", &self.images.len())
+            .field("extent", &self.extent)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
 pub struct SwapchainImage {
     /// The swapchain image.
     pub image: vk::Image,
 
+    /// The index of the image in the swapchain.
+    pub index: u32,
+
     /// Semaphore signaled when rendering is done and the image can be presented.
     pub present_semaphore: vk::Semaphore,
 }
 
 impl SwapchainImage {
-    fn new(image: vk::Image, device: &ash::Device) -> Result<Self, vk::Result> {
+    fn new(image: vk::Image, index: u32, device: &ash::Device) -> Result<Self, VkError> {
         let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
         let present_semaphore = unsafe {
             device.create_semaphore(&semaphore_create_info, None)
-        }?;
+        }.with_details("create swapchain present semaphore", || format!("Image: {}", index))?;
 
         Ok(Self {
             image,
+            index,
             present_semaphore,
         })
     }
@@ -199,4 +308,43 @@ impl SwapchainImage {
             device.destroy_semaphore(self.present_semaphore, None)
         };
     }
-}
\ No newline at end of file
+}
+
+/// Converts a timeout to the nanoseconds expected by vulkan. Durations too large to be
+/// represented saturate to [`u64::MAX`] which vulkan treats as an infinite timeout.
+fn timeout_to_nanos(timeout: Duration) -> u64 {
+    timeout.as_nanos().min(u64::MAX as u128) as u64
+}
+
+/// Returns the time left of `timeout` after `elapsed` has passed or [`Duration::ZERO`] if the
+/// timeout has already expired.
+fn remaining_timeout(timeout: Duration, elapsed: Duration) -> Duration {
+    timeout.checked_sub(elapsed).unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_conversion() {
+        assert_eq!(timeout_to_nanos(Duration::from_millis(0)), 0);
+        assert_eq!(timeout_to_nanos(Duration::from_millis(3)), 3_000_000);
+        assert_eq!(timeout_to_nanos(Duration::MAX), u64::MAX);
+        assert_eq!(timeout_to_nanos(Duration::from_nanos(u64::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn next_image_result_from_error() {
+        assert_eq!(NextImageResult::from(vk::Result::ERROR_DEVICE_LOST), NextImageResult::DeviceLost);
+        assert_eq!(NextImageResult::from(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT), NextImageResult::MustRecreate);
+        assert_eq!(NextImageResult::from(vk::Result::ERROR_OUT_OF_HOST_MEMORY), NextImageResult::VulkanError(vk::Result::ERROR_OUT_OF_HOST_MEMORY));
+    }
+
+    #[test]
+    fn remaining_timeout_saturates() {
+        assert_eq!(remaining_timeout(Duration::from_millis(0), Duration::from_millis(5)), Duration::ZERO);
+        assert_eq!(remaining_timeout(Duration::from_millis(10), Duration::from_millis(4)), Duration::from_millis(6));
+        assert_eq!(remaining_timeout(Duration::MAX, Duration::from_secs(1)), Duration::MAX - Duration::from_secs(1));
+    }
+}
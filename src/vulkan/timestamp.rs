@@ -0,0 +1,163 @@
+//! GPU timestamp queries used to measure the duration of GPU work.
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::device::{DeviceProvider, MainDeviceContext};
+
+/// Identifies a timestamp recorded into a [`GpuTimestampPool`]. The handle is the index of the
+/// timestamp in the pool and hence also the index into the vector returned by
+/// [`GpuTimestampPool::read_timestamps`].
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct TimestampHandle {
+    index: u32,
+}
+
+impl TimestampHandle {
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// Wrapper around a vulkan timestamp query pool.
+///
+/// Before any timestamps can be recorded the pool must be reset by calling
+/// [`GpuTimestampPool::reset`]. Timestamps are then allocated sequentially from the start of the
+/// pool until the pool is reset again.
+pub struct GpuTimestampPool {
+    device: Arc<MainDeviceContext>,
+    query_pool: vk::QueryPool,
+    capacity: u32,
+    next_query: u32,
+}
+
+impl GpuTimestampPool {
+    /// Creates a new pool able to hold `capacity` timestamps.
+    pub fn new(device: Arc<MainDeviceContext>, capacity: u32) -> Result<Self, vk::Result> {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(capacity);
+
+        let query_pool = unsafe {
+            device.get_device().create_query_pool(&create_info, None)
+        }.inspect_err(|err| {
+            log::error!("Failed to create timestamp query pool: {:?}", err);
+        })?;
+
+        Ok(Self {
+            device,
+            query_pool,
+            capacity,
+            next_query: 0,
+        })
+    }
+
+    /// Records a reset of all queries in this pool into `cmd`. Any previously returned
+    /// [`TimestampHandle`] is invalidated.
+    ///
+    /// The reset must be executed by the gpu before any timestamps recorded afterwards.
+    pub fn reset(&mut self, cmd: vk::CommandBuffer) {
+        unsafe {
+            self.device.get_device().cmd_reset_query_pool(cmd, self.query_pool, 0, self.capacity);
+        }
+        self.next_query = 0;
+    }
+
+    /// Records a timestamp write into `cmd` which will be written once all previous commands have
+    /// reached `pipeline_stage`.
+    ///
+    /// # Panics
+    /// If the pool is full.
+    pub fn record_timestamp(&mut self, cmd: vk::CommandBuffer, pipeline_stage: vk::PipelineStageFlags2KHR) -> TimestampHandle {
+        if self.next_query >= self.capacity {
+            panic!("GpuTimestampPool is full (capacity: {})", self.capacity);
+        }
+
+        let index = self.next_query;
+        self.next_query += 1;
+
+        unsafe {
+            self.device.get_khr_synchronization_2().cmd_write_timestamp2(cmd, pipeline_stage, self.query_pool, index);
+        }
+
+        TimestampHandle {
+            index
+        }
+    }
+
+    /// Reads all timestamps recorded since the last reset. The result is indexed by
+    /// [`TimestampHandle::get_index`].
+    ///
+    /// Blocks until all timestamps are available so the command buffers containing the
+    /// timestamps must have been submitted before calling this function.
+    pub fn read_timestamps(&self) -> Result<Vec<u64>, vk::Result> {
+        let mut timestamps = vec![0u64; self.next_query as usize];
+        if self.next_query == 0 {
+            return Ok(timestamps);
+        }
+
+        unsafe {
+            self.device.get_device().get_query_pool_results(
+                self.query_pool,
+                0,
+                self.next_query,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT
+            )
+        }?;
+
+        Ok(timestamps)
+    }
+
+    /// Like [`GpuTimestampPool::read_timestamps`] but never blocks. Returns [`None`] if any
+    /// timestamp is not available yet.
+    pub fn try_read_timestamps(&self) -> Result<Option<Vec<u64>>, vk::Result> {
+        let mut timestamps = vec![0u64; self.next_query as usize];
+        if self.next_query == 0 {
+            return Ok(Some(timestamps));
+        }
+
+        let result = unsafe {
+            self.device.get_device().get_query_pool_results(
+                self.query_pool,
+                0,
+                self.next_query,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64
+            )
+        };
+
+        match result {
+            Ok(_) => Ok(Some(timestamps)),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the number of timestamps recorded since the last reset.
+    pub fn get_recorded_count(&self) -> u32 {
+        self.next_query
+    }
+
+    /// Returns the number of nanoseconds per timestamp tick.
+    pub fn nanoseconds_per_tick(&self, device: &MainDeviceContext) -> f64 {
+        device.get_limits().timestamp_period as f64
+    }
+
+    pub fn get_capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn get_handle(&self) -> vk::QueryPool {
+        self.query_pool
+    }
+}
+
+impl Drop for GpuTimestampPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.get_device().destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
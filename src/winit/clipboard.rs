@@ -0,0 +1,263 @@
+use std::sync::Mutex;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+
+
+/// How long a clipboard request waits for the clipboard thread to respond before giving up.
+///
+/// On X11 reading the clipboard requires a round trip to the current selection owner which may
+/// never respond. The timeout prevents such a owner from blocking the caller forever.
+pub const CLIPBOARD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Replaces [`CLIPBOARD_TIMEOUT`] for requests made on the event loop thread (for example from a
+/// user event handler) so that a unresponsive selection owner cannot stall the event loop.
+pub const EVENT_LOOP_CLIPBOARD_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum ClipboardError {
+    /// The platform does not provide a clipboard or it could not be accessed.
+    Unavailable,
+
+    /// The request did not complete within [`CLIPBOARD_TIMEOUT`] or
+    /// [`EVENT_LOOP_CLIPBOARD_TIMEOUT`] if made on the event loop thread.
+    Timeout,
+
+    /// Some other platform error occurred.
+    Platform(String),
+}
+
+impl From<arboard::Error> for ClipboardError {
+    fn from(error: arboard::Error) -> Self {
+        match error {
+            arboard::Error::ClipboardNotSupported => Self::Unavailable,
+            error => Self::Platform(error.to_string()),
+        }
+    }
+}
+
+/// Returns how long a clipboard request waits for a response.
+pub(in crate::winit) fn request_timeout(on_event_loop_thread: bool) -> Duration {
+    if on_event_loop_thread {
+        EVENT_LOOP_CLIPBOARD_TIMEOUT
+    } else {
+        CLIPBOARD_TIMEOUT
+    }
+}
+
+enum ClipboardRequest {
+    GetText(Sender<Result<Option<String>, ClipboardError>>),
+    SetText(String, Sender<Result<(), ClipboardError>>),
+}
+
+impl std::fmt::Debug for ClipboardRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClipboardRequest::GetText(_) => f.write_str("GetText"),
+            ClipboardRequest::SetText(text, _) => f.debug_tuple("SetText").field(&text.len()).finish(),
+        }
+    }
+}
+
+/// The platform clipboard used by a [`ClipboardThread`]. Only replaced in tests.
+trait PlatformClipboard {
+    fn get_text(&mut self) -> Result<Option<String>, ClipboardError>;
+
+    fn set_text(&mut self, text: String) -> Result<(), ClipboardError>;
+}
+
+impl PlatformClipboard for arboard::Clipboard {
+    fn get_text(&mut self) -> Result<Option<String>, ClipboardError> {
+        match arboard::Clipboard::get_text(self) {
+            Ok(text) => Ok(Some(text)),
+            Err(arboard::Error::ContentNotAvailable) => Ok(None),
+            Err(err) => Err(ClipboardError::from(err)),
+        }
+    }
+
+    fn set_text(&mut self, text: String) -> Result<(), ClipboardError> {
+        arboard::Clipboard::set_text(self, text).map_err(ClipboardError::from)
+    }
+}
+
+type CreateClipboardFn = dyn FnMut() -> Result<Box<dyn PlatformClipboard>, ClipboardError> + Send;
+
+/// Owns the platform clipboard on a dedicated thread so that clipboard operations, which may
+/// block for a long time, never stall the event loop.
+///
+/// The clipboard is kept alive until the [`ClipboardThread`] is dropped since on some platforms
+/// (X11) the clipboard contents are served by the owning process and would be lost if it was
+/// dropped.
+pub(in crate::winit) struct ClipboardThread {
+    sender: Mutex<Sender<ClipboardRequest>>,
+    log_target: String,
+}
+
+impl ClipboardThread {
+    pub(in crate::winit) fn new(log_target: String) -> Self {
+        let thread_log_target = log_target.clone();
+        Self::with_clipboard_fn(log_target, Box::new(move || {
+            match arboard::Clipboard::new() {
+                Ok(clipboard) => Ok(Box::new(clipboard) as Box<dyn PlatformClipboard>),
+                Err(err) => {
+                    log::error!(target: &thread_log_target, "Failed to access platform clipboard: {:?}", err);
+                    Err(ClipboardError::from(err))
+                }
+            }
+        }))
+    }
+
+    /// Spawns the clipboard thread. The clipboard is created lazily by calling `create_fn` on
+    /// the clipboard thread until it succeeds.
+    fn with_clipboard_fn(log_target: String, mut create_fn: Box<CreateClipboardFn>) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel::<ClipboardRequest>();
+
+        let thread_log_target = log_target.clone();
+        std::thread::Builder::new().name(String::from("agnaji-clipboard")).spawn(move || {
+            let mut clipboard = None;
+            // Exits once the ClipboardThread has been dropped
+            for request in receiver {
+                log::trace!(target: &thread_log_target, "Processing clipboard request: {:?}", request);
+                let clipboard = match &mut clipboard {
+                    Some(clipboard) => Ok(clipboard),
+                    None => create_fn().map(|created| clipboard.insert(created)),
+                };
+
+                // The requester may have timed out in which case nobody is listening anymore
+                match request {
+                    ClipboardRequest::GetText(reply) => {
+                        let _ = reply.send(clipboard.and_then(|clipboard| clipboard.get_text()));
+                    }
+                    ClipboardRequest::SetText(text, reply) => {
+                        let _ = reply.send(clipboard.and_then(|clipboard| clipboard.set_text(text)));
+                    }
+                }
+            }
+        }).expect("Failed to spawn clipboard thread");
+
+        Self {
+            sender: Mutex::new(sender),
+            log_target,
+        }
+    }
+
+    pub(in crate::winit) fn get_text(&self, timeout: Duration) -> Result<Option<String>, ClipboardError> {
+        let (send, recv) = std::sync::mpsc::channel();
+        self.submit(ClipboardRequest::GetText(send), recv, timeout)
+    }
+
+    pub(in crate::winit) fn set_text(&self, text: String, timeout: Duration) -> Result<(), ClipboardError> {
+        let (send, recv) = std::sync::mpsc::channel();
+        self.submit(ClipboardRequest::SetText(text, send), recv, timeout)
+    }
+
+    fn submit<R>(&self, request: ClipboardRequest, reply: std::sync::mpsc::Receiver<Result<R, ClipboardError>>, timeout: Duration) -> Result<R, ClipboardError> {
+        let kind = format!("{:?}", request);
+        self.sender.lock().unwrap().send(request).map_err(|_| {
+            log::error!(target: &self.log_target, "Clipboard thread is not running");
+            ClipboardError::Unavailable
+        })?;
+
+        reply.recv_timeout(timeout).map_err(|err| match err {
+            RecvTimeoutError::Timeout => {
+                log::warn!(target: &self.log_target, "Clipboard request {} timed out", kind);
+                ClipboardError::Timeout
+            }
+            RecvTimeoutError::Disconnected => ClipboardError::Unavailable,
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread::ThreadId;
+
+    use crate::winit::worker::EVENT_LOOP_LOG_TARGET;
+
+    use super::*;
+
+    /// In memory clipboard recording the threads it is accessed from.
+    struct TestClipboard {
+        text: Option<String>,
+        delay: Duration,
+        threads: Arc<Mutex<Vec<ThreadId>>>,
+    }
+
+    impl PlatformClipboard for TestClipboard {
+        fn get_text(&mut self) -> Result<Option<String>, ClipboardError> {
+            self.threads.lock().unwrap().push(std::thread::current().id());
+            std::thread::sleep(self.delay);
+            Ok(self.text.clone())
+        }
+
+        fn set_text(&mut self, text: String) -> Result<(), ClipboardError> {
+            self.threads.lock().unwrap().push(std::thread::current().id());
+            std::thread::sleep(self.delay);
+            self.text = Some(text);
+            Ok(())
+        }
+    }
+
+    fn test_thread(delay: Duration) -> (ClipboardThread, Arc<Mutex<Vec<ThreadId>>>) {
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let threads_clone = threads.clone();
+        let thread = ClipboardThread::with_clipboard_fn(String::from(EVENT_LOOP_LOG_TARGET), Box::new(move || {
+            Ok(Box::new(TestClipboard {
+                text: None,
+                delay,
+                threads: threads_clone.clone(),
+            }))
+        }));
+        (thread, threads)
+    }
+
+    #[test]
+    fn runs_on_clipboard_thread() {
+        let (thread, threads) = test_thread(Duration::ZERO);
+        assert_eq!(thread.get_text(CLIPBOARD_TIMEOUT), Ok(None));
+        assert_eq!(thread.set_text(String::from("agnaji"), CLIPBOARD_TIMEOUT), Ok(()));
+        assert_eq!(thread.get_text(CLIPBOARD_TIMEOUT), Ok(Some(String::from("agnaji"))));
+
+        // All requests are processed by the same thread which is not the calling thread
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 3);
+        assert!(threads.iter().all(|id| *id == threads[0]));
+        assert_ne!(threads[0], std::thread::current().id());
+    }
+
+    #[test]
+    fn slow_clipboard_times_out() {
+        let (thread, _) = test_thread(Duration::from_millis(200));
+        assert_eq!(thread.get_text(Duration::from_millis(10)), Err(ClipboardError::Timeout));
+
+        // The thread keeps serving requests after a timeout
+        assert_eq!(thread.set_text(String::from("late"), Duration::from_secs(5)), Ok(()));
+        assert_eq!(thread.get_text(Duration::from_secs(5)), Ok(Some(String::from("late"))));
+    }
+
+    #[test]
+    fn clipboard_creation_is_retried() {
+        let mut attempts = 0;
+        let thread = ClipboardThread::with_clipboard_fn(String::from(EVENT_LOOP_LOG_TARGET), Box::new(move || {
+            attempts += 1;
+            if attempts == 1 {
+                return Err(ClipboardError::Unavailable);
+            }
+            Ok(Box::new(TestClipboard {
+                text: Some(String::from("second")),
+                delay: Duration::ZERO,
+                threads: Arc::new(Mutex::new(Vec::new())),
+            }))
+        }));
+
+        assert_eq!(thread.get_text(CLIPBOARD_TIMEOUT), Err(ClipboardError::Unavailable));
+        assert_eq!(thread.get_text(CLIPBOARD_TIMEOUT), Ok(Some(String::from("second"))));
+    }
+
+    #[test]
+    fn event_loop_thread_timeout() {
+        assert_eq!(request_timeout(false), CLIPBOARD_TIMEOUT);
+        assert_eq!(request_timeout(true), EVENT_LOOP_CLIPBOARD_TIMEOUT);
+        assert!(EVENT_LOOP_CLIPBOARD_TIMEOUT < CLIPBOARD_TIMEOUT);
+    }
+}
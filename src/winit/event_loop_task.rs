@@ -0,0 +1,145 @@
+use std::any::Any;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Condvar, Mutex};
+
+use winit::event_loop::EventLoopWindowTarget;
+
+use crate::winit::AgnajiEvent;
+
+/// Returns true if the task panicked.
+type EventLoopTaskFn = dyn FnOnce(&EventLoopWindowTarget<AgnajiEvent>) -> bool + Send;
+
+/// A closure sent to the event loop by [`crate::winit::WinitBackend::run_on_event_loop_thread`].
+pub(in crate::winit) struct EventLoopTask(Box<EventLoopTaskFn>);
+
+impl EventLoopTask {
+    /// Creates a task calling `f` and sending its return value to the returned [`TaskReply`]. If
+    /// `f` panics the panic is caught and sent to the [`TaskReply`] instead.
+    pub(in crate::winit) fn new<R, F>(f: F) -> (Self, Arc<TaskReply<R>>) where R: Send + 'static, F: FnOnce(&EventLoopWindowTarget<AgnajiEvent>) -> R + Send + 'static {
+        let reply = Arc::new(TaskReply::new());
+        let sender = TaskReplySender(reply.clone());
+
+        (Self(Box::new(move |window_target| {
+            // The waiting thread resumes the panic so no state observed by it is left broken
+            let result = catch_unwind(AssertUnwindSafe(|| f(window_target)));
+            let panicked = result.is_err();
+            sender.send(result);
+            panicked
+        })), reply)
+    }
+
+    /// Runs the task. Returns true if the task panicked. The panic has already been sent to the
+    /// waiting thread in that case.
+    pub(in crate::winit) fn run(self, window_target: &EventLoopWindowTarget<AgnajiEvent>) -> bool {
+        (self.0)(window_target)
+    }
+}
+
+impl std::fmt::Debug for EventLoopTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventLoopTask")
+    }
+}
+
+enum TaskReplyState<R> {
+    Pending,
+    Done(R),
+    /// The task panicked. Contains the panic payload.
+    Panicked(Box<dyn Any + Send>),
+    /// The task has been dropped without running. This happens if the event loop exits.
+    Dropped,
+}
+
+/// Transfers the return value of a [`EventLoopTask`] back to the waiting thread.
+pub(in crate::winit) struct TaskReply<R> {
+    state: Mutex<TaskReplyState<R>>,
+    condvar: Condvar,
+}
+
+impl<R> TaskReply<R> {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(TaskReplyState::Pending),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Blocks until the task has run and returns its return value or the payload of its panic.
+    /// Returns [`None`] if the task has been dropped without running.
+    pub(in crate::winit) fn wait(&self) -> Option<std::thread::Result<R>> {
+        let mut guard = self.state.lock().unwrap();
+        loop {
+            match std::mem::replace(&mut *guard, TaskReplyState::Dropped) {
+                TaskReplyState::Pending => {
+                    *guard = TaskReplyState::Pending;
+                    guard = self.condvar.wait(guard).unwrap();
+                }
+                TaskReplyState::Done(value) => return Some(Ok(value)),
+                TaskReplyState::Panicked(payload) => return Some(Err(payload)),
+                TaskReplyState::Dropped => return None,
+            }
+        }
+    }
+
+    fn complete(&self, state: TaskReplyState<R>) {
+        let mut guard = self.state.lock().unwrap();
+        if matches!(*guard, TaskReplyState::Pending) {
+            *guard = state;
+            self.condvar.notify_all();
+        }
+    }
+}
+
+/// Owned by the task closure. Marks the reply as dropped if the closure is dropped without
+/// running so the waiting thread never blocks forever.
+struct TaskReplySender<R>(Arc<TaskReply<R>>);
+
+impl<R> TaskReplySender<R> {
+    fn send(&self, result: std::thread::Result<R>) {
+        self.0.complete(match result {
+            Ok(value) => TaskReplyState::Done(value),
+            Err(payload) => TaskReplyState::Panicked(payload),
+        });
+    }
+}
+
+impl<R> Drop for TaskReplySender<R> {
+    fn drop(&mut self) {
+        self.0.complete(TaskReplyState::Dropped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_dropped_task() {
+        let (task, reply) = EventLoopTask::new(|_| 42u32);
+        let waiter = std::thread::spawn(move || reply.wait());
+        drop(task);
+        assert!(waiter.join().unwrap().is_none());
+    }
+
+    #[test]
+    fn reply_send() {
+        let reply = Arc::new(TaskReply::new());
+        let sender = TaskReplySender(reply.clone());
+        let waiter = std::thread::spawn(move || reply.wait());
+        sender.send(Ok(String::from("done")));
+        drop(sender);
+        assert_eq!(waiter.join().unwrap().map(Result::unwrap), Some(String::from("done")));
+    }
+
+    #[test]
+    fn reply_panic() {
+        let reply = Arc::new(TaskReply::<u32>::new());
+        let sender = TaskReplySender(reply.clone());
+        let waiter = std::thread::spawn(move || reply.wait());
+        sender.send(catch_unwind(|| panic!("task failed")));
+        drop(sender);
+
+        let payload = waiter.join().unwrap().unwrap().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"task failed"));
+    }
+}
@@ -0,0 +1,482 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::prelude::*;
+
+/// The maximum number of input events queued per window. If the application does not poll events
+/// fast enough the oldest events are dropped.
+const MAX_QUEUED_EVENTS: usize = 4096;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled,
+}
+
+impl From<winit::event::TouchPhase> for TouchPhase {
+    fn from(phase: winit::event::TouchPhase) -> Self {
+        match phase {
+            winit::event::TouchPhase::Started => Self::Started,
+            winit::event::TouchPhase::Moved => Self::Moved,
+            winit::event::TouchPhase::Ended => Self::Ended,
+            winit::event::TouchPhase::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16),
+}
+
+impl From<winit::event::MouseButton> for MouseButton {
+    fn from(button: winit::event::MouseButton) -> Self {
+        match button {
+            winit::event::MouseButton::Left => Self::Left,
+            winit::event::MouseButton::Right => Self::Right,
+            winit::event::MouseButton::Middle => Self::Middle,
+            winit::event::MouseButton::Other(other) => Self::Other(other),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ButtonState {
+    Pressed,
+    Released,
+}
+
+impl From<winit::event::ElementState> for ButtonState {
+    fn from(state: winit::event::ElementState) -> Self {
+        match state {
+            winit::event::ElementState::Pressed => Self::Pressed,
+            winit::event::ElementState::Released => Self::Released,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ScrollDelta {
+    /// Scroll amount in lines (or rows and columns). Typically reported by mouse wheels.
+    LineDelta(Vec2f32),
+
+    /// Scroll amount in physical pixels. Typically reported by touchpads supporting smooth
+    /// scrolling.
+    PixelDelta(Vec2f64),
+}
+
+impl From<winit::event::MouseScrollDelta> for ScrollDelta {
+    fn from(delta: winit::event::MouseScrollDelta) -> Self {
+        match delta {
+            winit::event::MouseScrollDelta::LineDelta(x, y) => Self::LineDelta(Vec2f32::new(x, y)),
+            winit::event::MouseScrollDelta::PixelDelta(position) => Self::PixelDelta(Vec2f64::new(position.x, position.y)),
+        }
+    }
+}
+
+/// The accumulated scroll amount since it was last reset. Line and pixel deltas are accumulated
+/// independently.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ScrollTotal {
+    pub lines: Vec2f32,
+    pub pixels: Vec2f64,
+}
+
+impl Default for ScrollTotal {
+    fn default() -> Self {
+        Self {
+            lines: Vec2f32::zeros(),
+            pixels: Vec2f64::zeros(),
+        }
+    }
+}
+
+/// State of the modifier keys.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    /// The windows key on windows or the command key on macos.
+    pub logo: bool,
+}
+
+impl From<winit::event::ModifiersState> for Modifiers {
+    fn from(state: winit::event::ModifiersState) -> Self {
+        Self {
+            shift: state.shift(),
+            ctrl: state.ctrl(),
+            alt: state.alt(),
+            logo: state.logo(),
+        }
+    }
+}
+
+/// A input event received by a window. All positions are in physical pixels relative to the top
+/// left corner of the window.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InputEvent {
+    /// A touch event. The `id` is unique and stable for the duration of a single touch (i.e.
+    /// from [`TouchPhase::Started`] until [`TouchPhase::Ended`] or [`TouchPhase::Cancelled`]).
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        position: Vec2f64,
+    },
+    CursorMoved {
+        position: Vec2f64,
+    },
+    MouseInput {
+        button: MouseButton,
+        state: ButtonState,
+        /// The modifier state at the time the event was received.
+        modifiers: Modifiers,
+    },
+    /// A scroll event. The phase is [`TouchPhase::Moved`] on platforms and devices which do
+    /// not report phases.
+    MouseWheel {
+        delta: ScrollDelta,
+        phase: TouchPhase,
+        modifiers: Modifiers,
+    },
+    /// A key has been pressed or released. The scancode is the platform dependent physical key
+    /// code.
+    KeyboardInput {
+        scancode: u32,
+        state: ButtonState,
+        modifiers: Modifiers,
+    },
+    /// The window has been moved. The position is the new outer position of the window in
+    /// physical pixels relative to the top left corner of the desktop.
+    Moved {
+        position: Vec2i32,
+    },
+    FocusGained,
+    /// The window lost focus. Any buttons and keys still pressed at this point are reported as
+    /// released before this event since their release would otherwise never be received. The
+    /// modifier state is reset.
+    FocusLost,
+}
+
+/// Queue of input events of a single window.
+pub(in crate::winit) struct InputQueue {
+    events: VecDeque<InputEvent>,
+
+    /// If true the first touch is additionally reported as left mouse button events.
+    emulate_mouse_from_touch: bool,
+
+    /// The touch currently used for mouse emulation.
+    emulated_touch: Option<u64>,
+
+    /// All mouse buttons for which a press but no release has been queued.
+    pressed_buttons: HashSet<MouseButton>,
+
+    /// The scancodes of all keys for which a press but no release has been queued.
+    pressed_keys: HashSet<u32>,
+
+    modifiers: Modifiers,
+
+    scroll_total: ScrollTotal,
+}
+
+impl InputQueue {
+    pub(in crate::winit) fn new() -> Self {
+        Self {
+            events: VecDeque::with_capacity(64),
+            emulate_mouse_from_touch: false,
+            emulated_touch: None,
+            pressed_buttons: HashSet::new(),
+            pressed_keys: HashSet::new(),
+            modifiers: Modifiers::default(),
+            scroll_total: ScrollTotal::default(),
+        }
+    }
+
+    pub(in crate::winit) fn set_emulate_mouse_from_touch(&mut self, emulate: bool) {
+        self.emulate_mouse_from_touch = emulate;
+        if !emulate {
+            self.emulated_touch = None;
+        }
+    }
+
+    pub(in crate::winit) fn push(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::MouseInput { button, state, .. } => match state {
+                ButtonState::Pressed => self.pressed_buttons.insert(button),
+                ButtonState::Released => self.pressed_buttons.remove(&button),
+            },
+            InputEvent::KeyboardInput { scancode, state, .. } => match state {
+                ButtonState::Pressed => self.pressed_keys.insert(scancode),
+                ButtonState::Released => self.pressed_keys.remove(&scancode),
+            },
+            _ => false,
+        };
+
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub(in crate::winit) fn push_mouse_input(&mut self, button: MouseButton, state: ButtonState) {
+        self.push(InputEvent::MouseInput { button, state, modifiers: self.modifiers });
+    }
+
+    pub(in crate::winit) fn push_keyboard_input(&mut self, scancode: u32, state: ButtonState) {
+        self.push(InputEvent::KeyboardInput { scancode, state, modifiers: self.modifiers });
+    }
+
+    pub(in crate::winit) fn push_mouse_wheel(&mut self, delta: ScrollDelta, phase: TouchPhase) {
+        match delta {
+            ScrollDelta::LineDelta(lines) => self.scroll_total.lines += lines,
+            ScrollDelta::PixelDelta(pixels) => self.scroll_total.pixels += pixels,
+        }
+        self.push(InputEvent::MouseWheel { delta, phase, modifiers: self.modifiers });
+    }
+
+    /// Returns the scroll amount accumulated since the last call to this function.
+    pub(in crate::winit) fn take_scroll_total(&mut self) -> ScrollTotal {
+        std::mem::take(&mut self.scroll_total)
+    }
+
+    pub(in crate::winit) fn get_modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub(in crate::winit) fn set_modifiers(&mut self, modifiers: Modifiers) {
+        self.modifiers = modifiers;
+    }
+
+    pub(in crate::winit) fn push_touch(&mut self, id: u64, phase: TouchPhase, position: Vec2f64) {
+        self.push(InputEvent::Touch { id, phase, position });
+
+        if self.emulate_mouse_from_touch {
+            self.emulate_mouse(id, phase, position);
+        }
+    }
+
+    pub(in crate::winit) fn push_focus(&mut self, focused: bool) {
+        if focused {
+            self.push(InputEvent::FocusGained);
+        } else {
+            // Sort to make the order deterministic
+            let mut pressed: Vec<_> = self.pressed_buttons.iter().copied().collect();
+            pressed.sort_by_key(|button| match button {
+                MouseButton::Left => 0,
+                MouseButton::Right => 1,
+                MouseButton::Middle => 2,
+                MouseButton::Other(other) => 3 + *other as u32,
+            });
+            for button in pressed {
+                self.push_mouse_input(button, ButtonState::Released);
+            }
+            let mut pressed: Vec<_> = self.pressed_keys.iter().copied().collect();
+            pressed.sort_unstable();
+            for scancode in pressed {
+                self.push_keyboard_input(scancode, ButtonState::Released);
+            }
+            self.emulated_touch = None;
+            self.modifiers = Modifiers::default();
+
+            self.push(InputEvent::FocusLost);
+        }
+    }
+
+    pub(in crate::winit) fn drain(&mut self) -> Vec<InputEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Only the first touch started while no other touch is being emulated is converted into
+    /// mouse events. Any concurrent touches are ignored for emulation.
+    fn emulate_mouse(&mut self, id: u64, phase: TouchPhase, position: Vec2f64) {
+        match phase {
+            TouchPhase::Started => {
+                if self.emulated_touch.is_none() {
+                    self.emulated_touch = Some(id);
+                    self.push(InputEvent::CursorMoved { position });
+                    self.push_mouse_input(MouseButton::Left, ButtonState::Pressed);
+                }
+            }
+            TouchPhase::Moved => {
+                if self.emulated_touch == Some(id) {
+                    self.push(InputEvent::CursorMoved { position });
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if self.emulated_touch == Some(id) {
+                    self.emulated_touch = None;
+                    if phase == TouchPhase::Ended {
+                        self.push(InputEvent::CursorMoved { position });
+                    }
+                    self.push_mouse_input(MouseButton::Left, ButtonState::Released);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: f64) -> InputEvent {
+        InputEvent::Touch { id, phase, position: Vec2f64::new(x, 0.0) }
+    }
+
+    fn mouse(button: MouseButton, state: ButtonState, modifiers: Modifiers) -> InputEvent {
+        InputEvent::MouseInput { button, state, modifiers }
+    }
+
+    #[test]
+    fn touch_without_emulation() {
+        let mut queue = InputQueue::new();
+        queue.push_touch(1, TouchPhase::Started, Vec2f64::new(1.0, 0.0));
+        queue.push_touch(1, TouchPhase::Moved, Vec2f64::new(2.0, 0.0));
+        queue.push_touch(1, TouchPhase::Ended, Vec2f64::new(3.0, 0.0));
+
+        assert_eq!(queue.drain(), vec![
+            touch(1, TouchPhase::Started, 1.0),
+            touch(1, TouchPhase::Moved, 2.0),
+            touch(1, TouchPhase::Ended, 3.0),
+        ]);
+        assert!(queue.drain().is_empty());
+    }
+
+    #[test]
+    fn emulate_mouse_from_first_touch() {
+        let mut queue = InputQueue::new();
+        queue.set_emulate_mouse_from_touch(true);
+
+        queue.push_touch(1, TouchPhase::Started, Vec2f64::new(1.0, 0.0));
+        queue.push_touch(2, TouchPhase::Started, Vec2f64::new(5.0, 0.0));
+        queue.push_touch(2, TouchPhase::Moved, Vec2f64::new(6.0, 0.0));
+        queue.push_touch(1, TouchPhase::Moved, Vec2f64::new(2.0, 0.0));
+        queue.push_touch(1, TouchPhase::Ended, Vec2f64::new(3.0, 0.0));
+        queue.push_touch(2, TouchPhase::Cancelled, Vec2f64::new(6.0, 0.0));
+
+        let pressed = mouse(MouseButton::Left, ButtonState::Pressed, Modifiers::default());
+        let released = mouse(MouseButton::Left, ButtonState::Released, Modifiers::default());
+        let cursor = |x: f64| InputEvent::CursorMoved { position: Vec2f64::new(x, 0.0) };
+
+        assert_eq!(queue.drain(), vec![
+            touch(1, TouchPhase::Started, 1.0),
+            cursor(1.0),
+            pressed,
+            touch(2, TouchPhase::Started, 5.0),
+            touch(2, TouchPhase::Moved, 6.0),
+            touch(1, TouchPhase::Moved, 2.0),
+            cursor(2.0),
+            touch(1, TouchPhase::Ended, 3.0),
+            cursor(3.0),
+            released,
+            touch(2, TouchPhase::Cancelled, 6.0),
+        ]);
+    }
+
+    #[test]
+    fn focus_lost_releases_pressed_buttons() {
+        let mut queue = InputQueue::new();
+        queue.push_focus(true);
+        queue.push_mouse_input(MouseButton::Right, ButtonState::Pressed);
+        queue.push_mouse_input(MouseButton::Left, ButtonState::Pressed);
+        queue.push_mouse_input(MouseButton::Right, ButtonState::Released);
+        queue.push_mouse_input(MouseButton::Middle, ButtonState::Pressed);
+        queue.drain();
+
+        queue.push_focus(false);
+        assert_eq!(queue.drain(), vec![
+            mouse(MouseButton::Left, ButtonState::Released, Modifiers::default()),
+            mouse(MouseButton::Middle, ButtonState::Released, Modifiers::default()),
+            InputEvent::FocusLost,
+        ]);
+
+        queue.push_focus(false);
+        assert_eq!(queue.drain(), vec![InputEvent::FocusLost]);
+    }
+
+    #[test]
+    fn focus_lost_releases_pressed_keys() {
+        let key = |scancode: u32, state: ButtonState| InputEvent::KeyboardInput { scancode, state, modifiers: Modifiers::default() };
+
+        let mut queue = InputQueue::new();
+        queue.push_focus(true);
+        queue.push_keyboard_input(42, ButtonState::Pressed);
+        queue.push_keyboard_input(17, ButtonState::Pressed);
+        queue.push_keyboard_input(30, ButtonState::Pressed);
+        queue.push_keyboard_input(17, ButtonState::Released);
+        queue.push_mouse_input(MouseButton::Left, ButtonState::Pressed);
+        queue.drain();
+
+        queue.push_focus(false);
+        assert_eq!(queue.drain(), vec![
+            mouse(MouseButton::Left, ButtonState::Released, Modifiers::default()),
+            key(30, ButtonState::Released),
+            key(42, ButtonState::Released),
+            InputEvent::FocusLost,
+        ]);
+
+        queue.push_focus(false);
+        assert_eq!(queue.drain(), vec![InputEvent::FocusLost]);
+    }
+
+    #[test]
+    fn mouse_wheel_accumulates_scroll_total() {
+        let mut queue = InputQueue::new();
+        queue.push_mouse_wheel(ScrollDelta::LineDelta(Vec2f32::new(0.0, 1.0)), TouchPhase::Moved);
+        queue.push(InputEvent::CursorMoved { position: Vec2f64::new(1.0, 0.0) });
+        queue.push_mouse_wheel(ScrollDelta::PixelDelta(Vec2f64::new(2.0, 3.0)), TouchPhase::Started);
+        queue.push_mouse_wheel(ScrollDelta::LineDelta(Vec2f32::new(1.0, 2.0)), TouchPhase::Moved);
+
+        assert_eq!(queue.take_scroll_total(), ScrollTotal {
+            lines: Vec2f32::new(1.0, 3.0),
+            pixels: Vec2f64::new(2.0, 3.0),
+        });
+        assert_eq!(queue.take_scroll_total(), ScrollTotal::default());
+
+        let wheel = |delta, phase| InputEvent::MouseWheel { delta, phase, modifiers: Modifiers::default() };
+        assert_eq!(queue.drain(), vec![
+            wheel(ScrollDelta::LineDelta(Vec2f32::new(0.0, 1.0)), TouchPhase::Moved),
+            InputEvent::CursorMoved { position: Vec2f64::new(1.0, 0.0) },
+            wheel(ScrollDelta::PixelDelta(Vec2f64::new(2.0, 3.0)), TouchPhase::Started),
+            wheel(ScrollDelta::LineDelta(Vec2f32::new(1.0, 2.0)), TouchPhase::Moved),
+        ]);
+    }
+
+    #[test]
+    fn modifiers_are_attached_and_reset_on_focus_loss() {
+        let ctrl = Modifiers { ctrl: true, ..Default::default() };
+
+        let mut queue = InputQueue::new();
+        queue.push_focus(true);
+        queue.set_modifiers(ctrl);
+        queue.push_mouse_input(MouseButton::Left, ButtonState::Pressed);
+        queue.push_focus(false);
+
+        assert_eq!(queue.get_modifiers(), Modifiers::default());
+        assert_eq!(queue.drain(), vec![
+            InputEvent::FocusGained,
+            mouse(MouseButton::Left, ButtonState::Pressed, ctrl),
+            mouse(MouseButton::Left, ButtonState::Released, ctrl),
+            InputEvent::FocusLost,
+        ]);
+    }
+
+    #[test]
+    fn keyboard_input_carries_modifiers() {
+        let shift = Modifiers { shift: true, ..Default::default() };
+
+        let mut queue = InputQueue::new();
+        queue.push_keyboard_input(30, ButtonState::Pressed);
+        queue.set_modifiers(shift);
+        queue.push_keyboard_input(30, ButtonState::Released);
+
+        assert_eq!(queue.drain(), vec![
+            InputEvent::KeyboardInput { scancode: 30, state: ButtonState::Pressed, modifiers: Modifiers::default() },
+            InputEvent::KeyboardInput { scancode: 30, state: ButtonState::Released, modifiers: shift },
+        ]);
+    }
+}
@@ -1,68 +1,334 @@
 mod worker;
 mod window;
 mod vulkan;
+mod clipboard;
+mod input;
+mod suspend;
+mod theme;
+mod user_event;
+mod event_loop_task;
 
+use std::any::Any;
 use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::mpsc::Sender;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::ThreadId;
+use std::time::Duration;
 use static_assertions::assert_impl_all;
-use winit::event_loop::EventLoopProxy;
+use winit::event_loop::{EventLoopClosed, EventLoopProxy, EventLoopWindowTarget};
 
+use crate::debug::log_target;
 use crate::prelude::*;
-use crate::winit::worker::WindowChannel;
+use crate::winit::clipboard::ClipboardThread;
+use crate::winit::event_loop_task::EventLoopTask;
+use crate::winit::suspend::SuspendState;
+use crate::winit::theme::ThemeBroadcast;
+use crate::winit::user_event::UserEventDispatcher;
+use crate::winit::worker::{EVENT_LOOP_LOG_TARGET, WindowChannel};
 
-pub use crate::winit::window::Window;
+pub use crate::winit::window::{Color, Window, WindowError, WindowOptions, WindowOptionsBuilder, WINDOW_REQUEST_TIMEOUT};
+pub use crate::winit::clipboard::{ClipboardError, CLIPBOARD_TIMEOUT, EVENT_LOOP_CLIPBOARD_TIMEOUT};
+pub use crate::winit::input::{ButtonState, InputEvent, Modifiers, MouseButton, ScrollDelta, ScrollTotal, TouchPhase};
+pub use crate::winit::suspend::SuspendListener;
+pub use crate::winit::theme::{Theme, ThemeReceiver};
+pub use crate::winit::user_event::{DEFAULT_USER_EVENT_THRESHOLD, UserEventHandler};
 
 const DEFAULT_LOG_TARGET: &'static str = "agnaji::winit";
 
+type QuitHandler = Box<dyn FnOnce() + Send>;
+
 pub struct WinitBackend {
     event_loop_proxy: Mutex<EventLoopProxy<AgnajiEvent>>,
     quit_requested: AtomicBool,
+    quit_handlers: Mutex<Option<Vec<QuitHandler>>>,
+    engine_thread_panicked: AtomicBool,
     window_channel: WindowChannel,
+    suspend: SuspendState,
+    focused_window: Mutex<Option<Weak<Window>>>,
+    themes: ThemeBroadcast,
+    user_events: UserEventDispatcher,
+    clipboard: ClipboardThread,
+    event_loop_thread: ThreadId,
+    label: Option<String>,
+    /// [`DEFAULT_LOG_TARGET`] with the label appended.
+    log_target: String,
+    /// [`EVENT_LOOP_LOG_TARGET`] with the label appended.
+    event_loop_log_target: String,
 }
 
 impl WinitBackend {
-    fn new(event_loop_proxy: EventLoopProxy<AgnajiEvent>) -> Self {
+    fn new(event_loop_proxy: EventLoopProxy<AgnajiEvent>, label: Option<String>) -> Self {
+        let backend_log_target = log_target(DEFAULT_LOG_TARGET, label.as_deref());
+
         Self {
             event_loop_proxy: Mutex::new(event_loop_proxy),
             quit_requested: AtomicBool::new(false),
+            quit_handlers: Mutex::new(Some(Vec::new())),
+            engine_thread_panicked: AtomicBool::new(false),
             window_channel: WindowChannel::new(),
+            suspend: SuspendState::new(cfg!(target_os = "android")),
+            focused_window: Mutex::new(None),
+            themes: ThemeBroadcast::new(),
+            user_events: UserEventDispatcher::new(),
+            clipboard: ClipboardThread::new(backend_log_target.clone()),
+            // The backend is created on the event loop thread
+            event_loop_thread: std::thread::current().id(),
+            log_target: backend_log_target,
+            event_loop_log_target: log_target(EVENT_LOOP_LOG_TARGET, label.as_deref()),
+            label,
         }
     }
 
+    /// Returns the label passed to [`run_with_label`].
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Requests the application to quit.
+    ///
+    /// All registered quit handlers are called on the calling thread before the event loop is
+    /// notified. Only the first call has any effect.
     pub fn quit(&self) {
         if self.quit_requested.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
-            self.push_event(AgnajiEvent::Quit);
-            log::debug!(target: DEFAULT_LOG_TARGET, "Submitted quit request");
+            let handlers = self.quit_handlers.lock().unwrap().take().unwrap();
+            log::debug!(target: &self.log_target, "Running {} quit handlers", handlers.len());
+            for handler in handlers {
+                handler();
+            }
+
+            // The event loop may already have exited for example if quit is called by a lingering
+            // thread during shutdown. There is nothing left to quit in that case.
+            if self.push_event(BackendEvent::Quit).is_err() {
+                log::debug!(target: &self.log_target, "Event loop already closed while submitting quit request");
+            }
+            self.suspend.signal_quit();
+            log::debug!(target: &self.log_target, "Submitted quit request");
         } else {
-            log::debug!(target: DEFAULT_LOG_TARGET, "Quit request inhibited. (Already submitted request before)");
+            log::debug!(target: &self.log_target, "Quit request inhibited. (Already submitted request before)");
         }
     }
 
+    /// Registers a handler which is called once when [`WinitBackend::quit`] is called or the
+    /// main application thread panics. Handlers are called in registration order.
+    ///
+    /// If a quit has already been requested the handler is called immediately.
+    pub fn add_quit_handler(&self, handler: QuitHandler) {
+        let mut guard = self.quit_handlers.lock().unwrap();
+        if let Some(handlers) = guard.as_mut() {
+            handlers.push(handler);
+        } else {
+            drop(guard);
+            handler();
+        }
+    }
+
+    /// Creates a window with the default [`WindowOptions`]. If `initial_size` is [`None`] the
+    /// default size is used.
     pub fn create_window(&self, title: String, initial_size: Option<Vec2u32>) -> Result<Arc<Window>, String> {
+        let mut options = WindowOptions::default().with_title(title);
+        if let Some(initial_size) = initial_size {
+            options = options.with_initial_size(initial_size);
+        }
+        self.create_window_with_options(options)
+    }
+
+    pub fn create_window_with_options(&self, options: WindowOptions) -> Result<Arc<Window>, String> {
         let id = self.window_channel.allocate_id();
 
-        log::debug!(target: DEFAULT_LOG_TARGET, "Submitted window creation request: {:?} size: {:?} (RequestID: {})", &title, initial_size, id);
-        self.push_event(AgnajiEvent::CreateWindow {
+        log::debug!(target: &self.log_target, "Submitted window creation request: {:?} (RequestID: {})", &options, id);
+        self.push_event(BackendEvent::CreateWindow {
             id,
-            title,
-            initial_size,
-        });
+            options,
+        }).map_err(|_| {
+            log::debug!(target: &self.log_target, "Window creation request failed because the event loop has been closed. RequestID: {}", id);
+            String::from("Event loop has been closed")
+        })?;
+
+        self.window_channel.wait_ready(id, &self.log_target)
+    }
+
+    /// Returns the current text contents of the clipboard or [`None`] if the clipboard is empty
+    /// or does not contain text.
+    ///
+    /// The request is processed on a dedicated clipboard thread. If it does not complete within
+    /// [`CLIPBOARD_TIMEOUT`] [`ClipboardError::Timeout`] is returned. On the event loop thread
+    /// [`EVENT_LOOP_CLIPBOARD_TIMEOUT`] is used instead.
+    pub fn clipboard_get_text(&self) -> Result<Option<String>, ClipboardError> {
+        self.clipboard.get_text(clipboard::request_timeout(self.is_event_loop_thread()))
+    }
+
+    /// Replaces the contents of the clipboard with `text`.
+    ///
+    /// The request is processed on a dedicated clipboard thread. If it does not complete within
+    /// [`CLIPBOARD_TIMEOUT`] [`ClipboardError::Timeout`] is returned. On the event loop thread
+    /// [`EVENT_LOOP_CLIPBOARD_TIMEOUT`] is used instead.
+    pub fn clipboard_set_text(&self, text: &str) -> Result<(), ClipboardError> {
+        self.clipboard.set_text(String::from(text), clipboard::request_timeout(self.is_event_loop_thread()))
+    }
+
+    /// Returns the window which currently has keyboard focus if any.
+    pub fn focused_window(&self) -> Option<Arc<Window>> {
+        self.focused_window.lock().unwrap().as_ref().and_then(Weak::upgrade)
+    }
+
+    /// Returns a receiver which is sent the new theme whenever the operating system theme
+    /// changes.
+    pub fn subscribe_theme_changes(&self) -> ThemeReceiver {
+        self.themes.subscribe()
+    }
+
+    fn event_loop_signal_theme_changed(&self, theme: Theme) {
+        self.themes.broadcast(theme);
+    }
+
+    /// Sends a custom event to the event loop which is passed to the handler set using
+    /// [`WinitBackend::set_user_event_handler`]. If no handler is set the event is dropped.
+    pub fn post_user_event(&self, event: Box<dyn Any + Send>) {
+        if self.push_event(BackendEvent::UserEvent(event)).is_err() {
+            log::debug!(target: &self.log_target, "Event loop closed. Ignoring user event");
+        }
+    }
+
+    /// Runs `f` on the event loop thread and blocks until it returns. Can be used for winit
+    /// operations which must run on the event loop thread and have no dedicated function.
+    ///
+    /// No other events are processed while `f` runs so it should return quickly.
+    ///
+    /// # Panics
+    /// If called from the event loop thread (for example from a user event handler) since that
+    /// would deadlock or if the event loop exits before running `f`. If `f` panics the panic is
+    /// caught on the event loop thread, which keeps running, and resumed on the calling thread.
+    pub fn run_on_event_loop_thread<R, F>(&self, f: F) -> R where R: Send + 'static, F: FnOnce(&EventLoopWindowTarget<AgnajiEvent>) -> R + Send + 'static {
+        if self.is_event_loop_thread() {
+            panic!("WinitBackend::run_on_event_loop_thread called from the event loop thread");
+        }
+
+        let (task, reply) = EventLoopTask::new(f);
+        if self.push_event(BackendEvent::RunOnEventLoopThread(task)).is_err() {
+            panic!("WinitBackend::run_on_event_loop_thread called after the event loop has been closed");
+        }
+
+        match reply.wait().expect("Event loop exited before running the task") {
+            Ok(value) => value,
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+
+    /// Sets the handler called on the event loop thread for every event posted using
+    /// [`WinitBackend::post_user_event`].
+    ///
+    /// The handler must not block since no other events are processed while it runs. If it takes
+    /// longer than the user event threshold (see [`WinitBackend::set_user_event_threshold`]) a
+    /// warning is logged.
+    pub fn set_user_event_handler(&self, handler: UserEventHandler) {
+        self.user_events.set_handler(handler);
+    }
+
+    /// Sets how long the user event handler may run before a warning is logged. Defaults to
+    /// [`DEFAULT_USER_EVENT_THRESHOLD`].
+    pub fn set_user_event_threshold(&self, threshold: Duration) {
+        self.user_events.set_threshold(threshold);
+    }
+
+    pub fn get_user_event_threshold(&self) -> Duration {
+        self.user_events.get_threshold()
+    }
+
+    fn event_loop_dispatch_user_event(&self, event: Box<dyn Any + Send>) {
+        self.user_events.dispatch(event, &self.event_loop_log_target);
+    }
+
+    fn event_loop_signal_focus_change(&self, window: &Arc<Window>, focused: bool) {
+        let mut guard = self.focused_window.lock().unwrap();
+        if focused {
+            *guard = Some(Arc::downgrade(window));
+        } else if guard.as_ref().map(|current| std::ptr::eq(current.as_ptr(), Arc::as_ptr(window))).unwrap_or(false) {
+            *guard = None;
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspend.is_suspended()
+    }
 
-        self.window_channel.wait_ready(id).map_err(|err| {
-            err.to_string()
-        })
+    /// Blocks until the application is not suspended.
+    ///
+    /// This function never returns if the application is suspended while quitting. Threads which
+    /// need to notice a quit request should use [`WinitBackend::wait_resumed_or_quit`] instead.
+    pub fn wait_resumed(&self) {
+        self.suspend.wait_resumed()
     }
 
-    fn push_event(&self, event: AgnajiEvent) {
-        let result = self.event_loop_proxy.lock().unwrap().send_event(event);
-        // Make sure we panic outside the mutex
-        result.unwrap();
+    /// Blocks until the application is not suspended or the timeout elapsed. Returns true if the
+    /// application is not suspended.
+    pub fn wait_resumed_timeout(&self, timeout: Duration) -> bool {
+        self.suspend.wait_resumed_timeout(timeout)
     }
+
+    /// Blocks until the application is not suspended or a quit has been requested. Returns true
+    /// if the application is not suspended.
+    pub fn wait_resumed_or_quit(&self) -> bool {
+        self.suspend.wait_resumed_or_quit()
+    }
+
+    /// Registers a listener which is notified whenever the application is suspended or resumed.
+    /// The listener is automatically removed once it is dropped.
+    pub fn add_suspend_listener(&self, listener: Weak<dyn SuspendListener>) {
+        self.suspend.add_listener(listener)
+    }
+
+    /// See [`SuspendState::with_client_api_guard_inc`].
+    fn with_client_api_guard_inc<R, E, F>(&self, f: F) -> Option<Result<R, E>> where F: FnOnce() -> Result<R, E> {
+        self.suspend.with_client_api_guard_inc(f)
+    }
+
+    /// See [`SuspendState::dec_client_api_count`].
+    fn dec_client_api_count(&self) {
+        self.suspend.dec_client_api_count()
+    }
+
+    fn event_loop_signal_suspended(&self) {
+        log::debug!(target: &self.log_target, "Application suspended");
+        self.suspend.signal_suspended(&self.event_loop_log_target);
+    }
+
+    fn event_loop_signal_resumed(&self) {
+        log::debug!(target: &self.log_target, "Application resumed");
+        self.suspend.signal_resumed();
+    }
+
+    /// Returns true if called on the thread running the event loop.
+    fn is_event_loop_thread(&self) -> bool {
+        std::thread::current().id() == self.event_loop_thread
+    }
+
+    /// Sends an event to the event loop. Fails if the event loop has already exited.
+    fn push_event(&self, event: BackendEvent) -> Result<(), EventLoopClosed<AgnajiEvent>> {
+        self.event_loop_proxy.lock().unwrap().send_event(AgnajiEvent(event))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum RunError {
+    /// The main application thread (running the `post_init` function) panicked.
+    EngineThreadPanicked,
 }
 
-pub fn run<F>(post_init: F) where F: FnOnce(Arc<WinitBackend>) + Send + UnwindSafe + 'static {
-    worker::run(post_init)
+/// Runs the winit event loop on the current thread and the `post_init` function on a new main
+/// application thread. This function returns once the event loop has quit.
+///
+/// On platforms where the winit event loop cannot return (ios, web) this function never returns
+/// and the process exit code is set to 1 if [`RunError::EngineThreadPanicked`] would be
+/// returned.
+pub fn run<F>(post_init: F) -> Result<(), RunError> where F: FnOnce(Arc<WinitBackend>) + Send + UnwindSafe + 'static {
+    worker::run(None, post_init)
+}
+
+/// Equivalent to [`run`] but appends `label` to the log targets of the backend (for example
+/// `agnaji::winit[editor]`). Used to tell apart the logs of multiple backends in one process.
+pub fn run_with_label<S, F>(label: S, post_init: F) -> Result<(), RunError> where S: Into<String>, F: FnOnce(Arc<WinitBackend>) + Send + UnwindSafe + 'static {
+    worker::run(Some(label.into()), post_init)
 }
 
 // Required because condvar
@@ -73,12 +339,38 @@ impl RefUnwindSafe for WinitBackend {
 
 assert_impl_all!(WinitBackend: Send, Sync);
 
+/// The user event type of the winit event loop. Only needed to name the
+/// [`EventLoopWindowTarget`] passed to [`WinitBackend::run_on_event_loop_thread`].
 #[derive(Debug)]
-enum AgnajiEvent {
+pub struct AgnajiEvent(BackendEvent);
+
+#[derive(Debug)]
+enum BackendEvent {
     CreateWindow {
         id: u64,
-        title: String,
-        initial_size: Option<Vec2u32>,
+        options: WindowOptions,
+    },
+    SetDecorated {
+        window: Arc<Window>,
+        decorated: bool,
+    },
+    SetTitlebarColor {
+        window: Arc<Window>,
+        color: Color,
+    },
+    SetOuterPosition {
+        window: Arc<Window>,
+        position: Vec2i32,
+        reply: Sender<Result<(), WindowError>>,
+    },
+    RequestRedraw {
+        window: Arc<Window>,
+    },
+    SetTheme {
+        window: Arc<Window>,
+        theme: Theme,
     },
+    UserEvent(Box<dyn Any + Send>),
+    RunOnEventLoopThread(EventLoopTask),
     Quit,
 }
@@ -0,0 +1,289 @@
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+
+/// Receives notifications when the application is suspended or resumed.
+///
+/// The callbacks are called from the event loop thread and must not block for extended periods
+/// of time.
+pub trait SuspendListener: Send + Sync {
+    fn on_suspended(&self);
+
+    fn on_resumed(&self);
+}
+
+/// Tracks the suspended state of the application.
+///
+/// Some platforms (android) destroy the native window when the application is suspended. All
+/// client api objects (for example vulkan surfaces) using the window must be destroyed before the
+/// event loop returns from processing the suspend event. To support this every live client api
+/// object is counted using [`SuspendState::with_client_api_guard_inc`] and
+/// [`SuspendState::dec_client_api_count`]. When suspending, the event loop blocks until the count
+/// reaches 0.
+///
+/// # Locking order
+/// All state transitions happen while holding the internal mutex and waiters check the state
+/// while holding the same mutex before atomically releasing it in [`Condvar::wait`]. Since a
+/// transition has to acquire the mutex it can only happen either before the check (in which case
+/// the waiter sees the new state) or after the waiter has started waiting (in which case the
+/// waiter is notified). No transition can be missed.
+///
+/// Listeners are called after the mutex has been released so that they may call any function of
+/// this struct.
+pub(in crate::winit) struct SuspendState {
+    guarded: Mutex<SuspendStateGuarded>,
+    condvar: Condvar,
+}
+
+impl SuspendState {
+    /// Creates a new suspend state. On android the application must start suspended since no
+    /// window is provided before the first resume event.
+    pub(in crate::winit) fn new(suspended: bool) -> Self {
+        Self {
+            guarded: Mutex::new(SuspendStateGuarded {
+                suspended,
+                quit_requested: false,
+                client_api_count: 0,
+                listeners: Vec::new(),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub(in crate::winit) fn is_suspended(&self) -> bool {
+        self.guarded.lock().unwrap().suspended
+    }
+
+    pub(in crate::winit) fn add_listener(&self, listener: Weak<dyn SuspendListener>) {
+        self.guarded.lock().unwrap().listeners.push(listener);
+    }
+
+    /// Marks the application as suspended, notifies all listeners and then blocks until all
+    /// client api objects have been released. Log messages are written to `log_target`.
+    pub(in crate::winit) fn signal_suspended(&self, log_target: &str) {
+        let listeners = self.set_suspended(true);
+        for listener in listeners {
+            listener.on_suspended();
+        }
+
+        let guard = self.guarded.lock().unwrap();
+        if guard.client_api_count != 0 {
+            log::debug!(target: log_target, "Waiting for {} client api objects to be released", guard.client_api_count);
+        }
+        let _guard = self.condvar.wait_while(guard, |guarded| guarded.client_api_count != 0).unwrap();
+    }
+
+    /// Calls `f` and if it returns [`Ok`] increments the client api count. The state is locked
+    /// while `f` runs so no suspend can happen concurrently.
+    ///
+    /// If the application is suspended `f` is not called and [`None`] is returned.
+    pub(in crate::winit) fn with_client_api_guard_inc<R, E, F>(&self, f: F) -> Option<Result<R, E>> where F: FnOnce() -> Result<R, E> {
+        let mut guard = self.guarded.lock().unwrap();
+        if guard.suspended {
+            return None;
+        }
+
+        let result = f();
+        if result.is_ok() {
+            guard.client_api_count += 1;
+        }
+        Some(result)
+    }
+
+    /// Decrements the client api count. Must be called once for every successful call of
+    /// [`SuspendState::with_client_api_guard_inc`] after the client api object has been
+    /// destroyed.
+    pub(in crate::winit) fn dec_client_api_count(&self) {
+        let mut guard = self.guarded.lock().unwrap();
+        guard.client_api_count = guard.client_api_count.checked_sub(1).expect("Client api count underflow");
+        drop(guard);
+
+        self.condvar.notify_all();
+    }
+
+    pub(in crate::winit) fn signal_resumed(&self) {
+        let listeners = self.set_suspended(false);
+        for listener in listeners {
+            listener.on_resumed();
+        }
+    }
+
+    pub(in crate::winit) fn signal_quit(&self) {
+        let mut guard = self.guarded.lock().unwrap();
+        guard.quit_requested = true;
+        drop(guard);
+
+        self.condvar.notify_all();
+    }
+
+    /// Blocks until the application is not suspended.
+    pub(in crate::winit) fn wait_resumed(&self) {
+        let guard = self.guarded.lock().unwrap();
+        let _guard = self.condvar.wait_while(guard, |guarded| guarded.suspended).unwrap();
+    }
+
+    /// Blocks until the application is not suspended or the timeout elapsed. Returns true if the
+    /// application is not suspended.
+    pub(in crate::winit) fn wait_resumed_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        let mut guard = self.guarded.lock().unwrap();
+        while guard.suspended {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            guard = self.condvar.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+
+    /// Blocks until the application is not suspended or a quit has been requested. Returns true
+    /// if the application is not suspended and false if a quit has been requested while
+    /// suspended.
+    pub(in crate::winit) fn wait_resumed_or_quit(&self) -> bool {
+        let guard = self.guarded.lock().unwrap();
+        let guard = self.condvar.wait_while(guard, |guarded| guarded.suspended && !guarded.quit_requested).unwrap();
+
+        !guard.suspended
+    }
+
+    /// Updates the state and returns all live listeners. Dead listeners are removed.
+    fn set_suspended(&self, suspended: bool) -> Vec<Arc<dyn SuspendListener>> {
+        let mut guard = self.guarded.lock().unwrap();
+        guard.suspended = suspended;
+
+        let mut listeners = Vec::with_capacity(guard.listeners.len());
+        guard.listeners.retain(|listener| {
+            if let Some(listener) = listener.upgrade() {
+                listeners.push(listener);
+                true
+            } else {
+                false
+            }
+        });
+        drop(guard);
+
+        self.condvar.notify_all();
+        listeners
+    }
+}
+
+struct SuspendStateGuarded {
+    suspended: bool,
+    quit_requested: bool,
+    client_api_count: usize,
+    listeners: Vec<Weak<dyn SuspendListener>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::winit::worker::EVENT_LOOP_LOG_TARGET;
+
+    use super::*;
+
+    #[test]
+    fn wait_resumed_timeout() {
+        let state = SuspendState::new(true);
+        assert!(!state.wait_resumed_timeout(Duration::from_millis(10)));
+
+        state.signal_resumed();
+        assert!(state.wait_resumed_timeout(Duration::from_millis(10)));
+        assert!(state.wait_resumed_or_quit());
+    }
+
+    #[test]
+    fn wait_resumed_or_quit() {
+        let state = Arc::new(SuspendState::new(true));
+
+        let waiter = {
+            let state = state.clone();
+            std::thread::spawn(move || state.wait_resumed_or_quit())
+        };
+        state.signal_quit();
+
+        assert!(!waiter.join().unwrap());
+    }
+
+    #[test]
+    fn no_missed_transitions() {
+        // Resume concurrently with the waiters to exercise the window between check and wait
+        for _ in 0..100 {
+            let state = Arc::new(SuspendState::new(true));
+
+            let waiters: Vec<_> = (0..4).map(|_| {
+                let state = state.clone();
+                std::thread::spawn(move || state.wait_resumed())
+            }).collect();
+            state.signal_resumed();
+
+            for waiter in waiters {
+                waiter.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn suspend_waits_for_client_api_objects() {
+        let state = Arc::new(SuspendState::new(false));
+        assert_eq!(state.with_client_api_guard_inc(|| Ok::<_, ()>(())), Some(Ok(())));
+        assert_eq!(state.with_client_api_guard_inc(|| Err::<(), _>(())), Some(Err(())));
+
+        let (send, recv) = std::sync::mpsc::channel();
+        let event_loop = {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                state.signal_suspended(EVENT_LOOP_LOG_TARGET);
+                send.send(()).unwrap();
+            })
+        };
+
+        // The simulated surface is still alive so the suspend must block
+        assert!(recv.recv_timeout(Duration::from_millis(50)).is_err());
+        assert!(state.is_suspended());
+        assert_eq!(state.with_client_api_guard_inc(|| Ok::<_, ()>(())), None);
+
+        state.dec_client_api_count();
+        recv.recv_timeout(Duration::from_secs(5)).unwrap();
+        event_loop.join().unwrap();
+    }
+
+    struct CountingListener {
+        suspended: AtomicU32,
+        resumed: AtomicU32,
+    }
+
+    impl SuspendListener for CountingListener {
+        fn on_suspended(&self) {
+            self.suspended.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_resumed(&self) {
+            self.resumed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn listeners() {
+        let state = SuspendState::new(true);
+        let listener = Arc::new(CountingListener {
+            suspended: AtomicU32::new(0),
+            resumed: AtomicU32::new(0),
+        });
+        let weak = Arc::downgrade(&listener);
+        state.add_listener(weak);
+
+        state.signal_resumed();
+        state.signal_suspended(EVENT_LOOP_LOG_TARGET);
+        state.signal_resumed();
+        assert_eq!(listener.suspended.load(Ordering::SeqCst), 1);
+        assert_eq!(listener.resumed.load(Ordering::SeqCst), 2);
+
+        drop(listener);
+        state.signal_suspended(EVENT_LOOP_LOG_TARGET);
+        assert!(state.guarded.lock().unwrap().listeners.is_empty());
+    }
+}
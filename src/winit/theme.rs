@@ -0,0 +1,111 @@
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Theme {
+    Light,
+    Dark,
+    /// Follow the theme of the operating system. Also used as the current theme if the platform
+    /// does not report it.
+    System,
+}
+
+impl From<winit::window::Theme> for Theme {
+    fn from(theme: winit::window::Theme) -> Self {
+        match theme {
+            winit::window::Theme::Light => Self::Light,
+            winit::window::Theme::Dark => Self::Dark,
+        }
+    }
+}
+
+/// Receives the new theme whenever the operating system theme changes. Created by
+/// [`crate::winit::WinitBackend::subscribe_theme_changes`].
+pub struct ThemeReceiver {
+    receiver: Receiver<Theme>,
+}
+
+impl ThemeReceiver {
+    /// Returns the next theme change if one is available without blocking.
+    pub fn try_recv(&self) -> Option<Theme> {
+        match self.receiver.try_recv() {
+            Ok(theme) => Some(theme),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Blocks until the next theme change or the timeout elapsed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Theme> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(theme) => Some(theme),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+/// Distributes theme changes to all [`ThemeReceiver`]s.
+///
+/// Theme change events are reported per window so the same change is usually reported multiple
+/// times. Only changes to a different theme than the last one are forwarded.
+pub(in crate::winit) struct ThemeBroadcast {
+    guarded: Mutex<ThemeBroadcastGuarded>,
+}
+
+impl ThemeBroadcast {
+    pub(in crate::winit) fn new() -> Self {
+        Self {
+            guarded: Mutex::new(ThemeBroadcastGuarded {
+                last_theme: None,
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    pub(in crate::winit) fn subscribe(&self) -> ThemeReceiver {
+        let (send, receiver) = std::sync::mpsc::channel();
+        self.guarded.lock().unwrap().subscribers.push(send);
+
+        ThemeReceiver {
+            receiver,
+        }
+    }
+
+    pub(in crate::winit) fn broadcast(&self, theme: Theme) {
+        let mut guard = self.guarded.lock().unwrap();
+        if guard.last_theme == Some(theme) {
+            return;
+        }
+        guard.last_theme = Some(theme);
+
+        // Dropped receivers are removed
+        guard.subscribers.retain(|subscriber| subscriber.send(theme).is_ok());
+    }
+}
+
+struct ThemeBroadcastGuarded {
+    last_theme: Option<Theme>,
+    subscribers: Vec<Sender<Theme>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_deduplicates() {
+        let broadcast = ThemeBroadcast::new();
+        let receiver = broadcast.subscribe();
+        let dropped = broadcast.subscribe();
+        drop(dropped);
+
+        broadcast.broadcast(Theme::Dark);
+        broadcast.broadcast(Theme::Dark);
+        broadcast.broadcast(Theme::Light);
+
+        assert_eq!(receiver.try_recv(), Some(Theme::Dark));
+        assert_eq!(receiver.try_recv(), Some(Theme::Light));
+        assert_eq!(receiver.try_recv(), None);
+        assert_eq!(broadcast.guarded.lock().unwrap().subscribers.len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// Called on the event loop thread for every event posted using
+/// [`crate::winit::WinitBackend::post_user_event`].
+pub type UserEventHandler = Arc<dyn Fn(&Box<dyn Any + Send>) + Send + Sync>;
+
+/// How long a [`UserEventHandler`] may block the event loop by default before a warning is
+/// logged.
+pub const DEFAULT_USER_EVENT_THRESHOLD: Duration = Duration::from_millis(16);
+
+/// Dispatches user events to the registered [`UserEventHandler`].
+pub(in crate::winit) struct UserEventDispatcher {
+    guarded: Mutex<UserEventDispatcherGuarded>,
+}
+
+impl UserEventDispatcher {
+    pub(in crate::winit) fn new() -> Self {
+        Self {
+            guarded: Mutex::new(UserEventDispatcherGuarded {
+                handler: None,
+                threshold: DEFAULT_USER_EVENT_THRESHOLD,
+            }),
+        }
+    }
+
+    pub(in crate::winit) fn set_handler(&self, handler: UserEventHandler) {
+        self.guarded.lock().unwrap().handler = Some(handler);
+    }
+
+    pub(in crate::winit) fn set_threshold(&self, threshold: Duration) {
+        self.guarded.lock().unwrap().threshold = threshold;
+    }
+
+    pub(in crate::winit) fn get_threshold(&self) -> Duration {
+        self.guarded.lock().unwrap().threshold
+    }
+
+    /// Calls the handler with the event. Returns false if no handler has been set in which case
+    /// the event is dropped.
+    ///
+    /// The handler is called without holding the internal lock so it may replace itself. Log
+    /// messages are written to `log_target`.
+    pub(in crate::winit) fn dispatch(&self, event: Box<dyn Any + Send>, log_target: &str) -> bool {
+        let (handler, threshold) = {
+            let guard = self.guarded.lock().unwrap();
+            (guard.handler.clone(), guard.threshold)
+        };
+
+        if let Some(handler) = handler {
+            let start = Instant::now();
+            handler(&event);
+            let elapsed = start.elapsed();
+
+            if elapsed > threshold {
+                log::warn!(target: log_target, "User event handler blocked the event loop for {:?} (Threshold: {:?})", elapsed, threshold);
+            }
+            true
+        } else {
+            log::debug!(target: log_target, "No user event handler set. Dropping user event");
+            false
+        }
+    }
+}
+
+struct UserEventDispatcherGuarded {
+    handler: Option<UserEventHandler>,
+    threshold: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::winit::worker::EVENT_LOOP_LOG_TARGET;
+
+    use super::*;
+
+    #[test]
+    fn dispatch() {
+        let dispatcher = UserEventDispatcher::new();
+        assert!(!dispatcher.dispatch(Box::new(1u32), EVENT_LOOP_LOG_TARGET));
+        assert_eq!(dispatcher.get_threshold(), DEFAULT_USER_EVENT_THRESHOLD);
+
+        let sum = Arc::new(AtomicU32::new(0));
+        let sum_clone = sum.clone();
+        dispatcher.set_handler(Arc::new(move |event| {
+            if let Some(value) = event.downcast_ref::<u32>() {
+                sum_clone.fetch_add(*value, Ordering::SeqCst);
+            }
+        }));
+
+        assert!(dispatcher.dispatch(Box::new(3u32), EVENT_LOOP_LOG_TARGET));
+        assert!(dispatcher.dispatch(Box::new(String::from("ignored")), EVENT_LOOP_LOG_TARGET));
+        assert!(dispatcher.dispatch(Box::new(4u32), EVENT_LOOP_LOG_TARGET));
+        assert_eq!(sum.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    fn slow_handler() {
+        let dispatcher = UserEventDispatcher::new();
+        dispatcher.set_threshold(Duration::ZERO);
+        dispatcher.set_handler(Arc::new(|_| std::thread::sleep(Duration::from_millis(1))));
+
+        // Exceeding the threshold only logs a warning
+        assert!(dispatcher.dispatch(Box::new(()), EVENT_LOOP_LOG_TARGET));
+    }
+}
@@ -1,10 +1,10 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use ash::vk;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 
 use crate::vulkan::InstanceContext;
-use crate::vulkan::surface::{Surface, VulkanSurfaceProvider};
+use crate::vulkan::surface::{Surface, SurfaceCreateError, VulkanSurfaceProvider};
 use crate::winit::window::Window;
 
 use crate::prelude::*;
@@ -22,20 +22,43 @@ impl WinitVulkanSurfaceProvider {
 }
 
 impl VulkanSurfaceProvider for WinitVulkanSurfaceProvider {
-    unsafe fn create_surface<'a, 'b>(&'a self, instance: &'b InstanceContext) -> Result<Surface<'a, 'b>, vk::Result> {
-        let surface = unsafe {
+    unsafe fn create_surface<'a, 'b>(&'a self, instance: &'b InstanceContext) -> Result<Surface<'a, 'b>, SurfaceCreateError> {
+        let backend = self.window.get_backend();
+
+        let surface = backend.with_client_api_guard_inc(|| unsafe {
             ash_window::create_surface(
                 instance.get_entry(),
                 instance.get_instance(),
                 self.window.get_window().raw_display_handle(),
                 self.window.get_window().raw_window_handle(),
                 None)
-        }?;
+        }).ok_or(SurfaceCreateError::Suspended)??;
 
-        Ok(Surface::new(instance, surface))
+        Ok(Surface::new_with_drop_hook(instance, surface, Some(Box::new(move || {
+            backend.dec_client_api_count();
+        }))))
     }
 
     fn get_canvas_size(&self) -> Option<Vec2u32> {
         Some(self.window.get_current_size())
     }
+
+    fn is_occluded(&self) -> bool {
+        self.window.is_occluded()
+    }
+
+    fn should_release_surface(&self) -> bool {
+        self.window.get_backend().is_suspended()
+    }
+
+    fn wait_redraw_requested(&self, timeout: Duration) -> bool {
+        self.window.wait_redraw_requested(timeout)
+    }
+
+    #[cfg(windows)]
+    fn get_win32_monitor(&self) -> Option<ash::vk::HMONITOR> {
+        use winit::platform::windows::MonitorHandleExtWindows;
+
+        self.window.get_window().current_monitor().map(|monitor| monitor.hmonitor() as ash::vk::HMONITOR)
+    }
 }
\ No newline at end of file
@@ -1,26 +1,193 @@
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+use winit::dpi::PhysicalPosition;
 use winit::window::Window as WinitWindow;
 
 use crate::prelude::*;
 use crate::vulkan::surface::VulkanSurfaceProvider;
+use crate::winit::theme::Theme;
+use crate::winit::input::{ButtonState, InputEvent, InputQueue, Modifiers, MouseButton, ScrollDelta, ScrollTotal, TouchPhase};
 use crate::winit::vulkan::WinitVulkanSurfaceProvider;
-use crate::winit::WinitBackend;
+use crate::winit::{BackendEvent, WinitBackend};
+
+/// How long a blocking window request like [`Window::set_outer_position`] waits for the event loop
+/// to process it before giving up.
+pub const WINDOW_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A 8 bit per channel srgb color.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum WindowError {
+    /// The operation is not supported by the platform (for example setting the window position
+    /// on wayland).
+    NotSupported,
+
+    /// The event loop has exited before the request could be processed.
+    EventLoopClosed,
+
+    /// The event loop did not process the request within [`WINDOW_REQUEST_TIMEOUT`].
+    Timeout,
+}
+
+/// Options used to create a window using [`WinitBackend::create_window_with_options`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WindowOptions {
+    pub title: String,
+
+    /// The initial inner size of the window in physical pixels.
+    pub initial_size: Vec2u32,
+    pub resizable: bool,
+    pub decorated: bool,
+    pub transparent: bool,
+    pub always_on_top: bool,
+
+    /// The initial outer position of the window. If [`None`] the platform picks a position.
+    pub position: Option<Vec2i32>,
+}
+
+impl WindowOptions {
+    pub fn builder() -> WindowOptionsBuilder {
+        WindowOptionsBuilder::new()
+    }
+
+    pub fn with_title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_initial_size(mut self, initial_size: Vec2u32) -> Self {
+        self.initial_size = initial_size;
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn with_decorated(mut self, decorated: bool) -> Self {
+        self.decorated = decorated;
+        self
+    }
+
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    pub fn with_always_on_top(mut self, always_on_top: bool) -> Self {
+        self.always_on_top = always_on_top;
+        self
+    }
+
+    pub fn with_position(mut self, position: Option<Vec2i32>) -> Self {
+        self.position = position;
+        self
+    }
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        Self {
+            title: String::from("Agnaji Window"),
+            initial_size: Vec2u32::new(800, 600),
+            resizable: true,
+            decorated: true,
+            transparent: false,
+            always_on_top: false,
+            position: None,
+        }
+    }
+}
+
+/// Builds a [`WindowOptions`] starting from [`WindowOptions::default`].
+#[derive(Clone, Debug, Default)]
+pub struct WindowOptionsBuilder {
+    options: WindowOptions,
+}
+
+impl WindowOptionsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.options = self.options.with_title(title);
+        self
+    }
+
+    pub fn initial_size(mut self, initial_size: Vec2u32) -> Self {
+        self.options = self.options.with_initial_size(initial_size);
+        self
+    }
+
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.options = self.options.with_resizable(resizable);
+        self
+    }
+
+    pub fn decorated(mut self, decorated: bool) -> Self {
+        self.options = self.options.with_decorated(decorated);
+        self
+    }
+
+    pub fn transparent(mut self, transparent: bool) -> Self {
+        self.options = self.options.with_transparent(transparent);
+        self
+    }
+
+    pub fn always_on_top(mut self, always_on_top: bool) -> Self {
+        self.options = self.options.with_always_on_top(always_on_top);
+        self
+    }
+
+    pub fn position(mut self, position: Vec2i32) -> Self {
+        self.options = self.options.with_position(Some(position));
+        self
+    }
+
+    pub fn build(self) -> WindowOptions {
+        self.options
+    }
+}
 
 pub struct Window {
     backend: Arc<WinitBackend>,
     window: WinitWindow,
     close_requested: AtomicBool,
     state: Mutex<WindowState>,
+    input: Mutex<InputQueue>,
+    redraw: RedrawSignal,
+    /// Notified whenever the focus state changes.
+    focus_condvar: Condvar,
 }
 
 impl Window {
-    pub(in crate::winit) fn new(backend: Arc<WinitBackend>, window: WinitWindow, initial_size: Vec2u32) -> Self {
+    pub(in crate::winit) fn new(backend: Arc<WinitBackend>, window: WinitWindow, initial_size: Vec2u32, decorated: bool) -> Self {
+        let scale_factor = window.scale_factor();
+        let theme = Self::query_theme(&window);
         Self {
             backend,
             window,
             close_requested: AtomicBool::new(false),
-            state: Mutex::new(WindowState::new(initial_size)),
+            state: Mutex::new(WindowState::new(initial_size, scale_factor, decorated, theme)),
+            input: Mutex::new(InputQueue::new()),
+            redraw: RedrawSignal::new(),
+            focus_condvar: Condvar::new(),
         }
     }
 
@@ -36,10 +203,174 @@ impl Window {
         self.close_requested.load(Ordering::SeqCst)
     }
 
+    /// Returns true if the window currently has keyboard focus.
+    pub fn is_focused(&self) -> bool {
+        self.state.lock().unwrap().focused
+    }
+
+    /// Blocks until the window has keyboard focus or the timeout elapsed. Returns true if the
+    /// window has focus.
+    pub fn wait_focused(&self, timeout: Duration) -> bool {
+        let guard = self.state.lock().unwrap();
+        self.focus_condvar.wait_timeout_while(guard, timeout, |state| !state.focused).unwrap().0.focused
+    }
+
+    /// Returns true if the window is currently fully hidden from view. Not all platforms report
+    /// occlusion in which case this always returns false.
+    pub fn is_occluded(&self) -> bool {
+        self.state.lock().unwrap().occluded
+    }
+
     pub fn get_current_size(&self) -> Vec2u32 {
         self.state.lock().unwrap().size
     }
 
+    /// Returns the factor used to convert from logical to physical pixels.
+    pub fn get_scale_factor(&self) -> f64 {
+        self.state.lock().unwrap().scale_factor
+    }
+
+    /// Returns the current size of the window in logical pixels.
+    pub fn get_logical_size(&self) -> Vec2f64 {
+        self.state.lock().unwrap().logical_size()
+    }
+
+    /// Converts a vector in physical pixels to logical pixels using the current scale factor.
+    pub fn physical_to_logical(&self, p: Vec2u32) -> Vec2f64 {
+        self.state.lock().unwrap().physical_to_logical(p)
+    }
+
+    /// Enables or disables the window decorations (title bar, borders etc.).
+    ///
+    /// The request is processed asynchronously on the event loop thread however
+    /// [`Window::is_decorated`] reflects the new state immediately.
+    pub fn set_decorated(self: &Arc<Self>, decorated: bool) {
+        self.state.lock().unwrap().decorated = decorated;
+        if self.backend.push_event(BackendEvent::SetDecorated {
+            window: self.clone(),
+            decorated,
+        }).is_err() {
+            log::debug!(target: &self.backend.log_target, "Event loop closed. Ignoring set decorated request");
+        }
+    }
+
+    pub fn is_decorated(&self) -> bool {
+        self.state.lock().unwrap().decorated
+    }
+
+    /// Returns true if [`Window::set_titlebar_color`] is supported on the current platform.
+    /// Currently this is only the case on windows (requires windows 11).
+    pub const fn supports_titlebar_color() -> bool {
+        cfg!(target_os = "windows")
+    }
+
+    /// Sets the background color of the title bar.
+    ///
+    /// The request is processed asynchronously on the event loop thread. If the platform does not
+    /// support custom title bar colors (see [`Window::supports_titlebar_color`]) the request is
+    /// ignored.
+    pub fn set_titlebar_color(self: &Arc<Self>, color: Color) {
+        if self.backend.push_event(BackendEvent::SetTitlebarColor {
+            window: self.clone(),
+            color,
+        }).is_err() {
+            log::debug!(target: &self.backend.log_target, "Event loop closed. Ignoring set titlebar color request");
+        }
+    }
+
+    /// Sets the preferred theme of the window decorations.
+    ///
+    /// The request is processed asynchronously on the event loop thread. Currently this is only
+    /// supported on windows. On other platforms the request is ignored.
+    pub fn set_preferred_theme(self: &Arc<Self>, theme: Theme) {
+        if self.backend.push_event(BackendEvent::SetTheme {
+            window: self.clone(),
+            theme,
+        }).is_err() {
+            log::debug!(target: &self.backend.log_target, "Event loop closed. Ignoring set theme request");
+        }
+    }
+
+    /// Returns the theme last reported by the platform. If the platform does not report themes
+    /// [`Theme::System`] is returned.
+    pub fn get_current_theme(&self) -> Theme {
+        self.state.lock().unwrap().current_theme
+    }
+
+    /// Returns the position of the top left corner of the window including decorations in
+    /// physical pixels relative to the top left corner of the desktop.
+    pub fn get_outer_position(&self) -> Result<Vec2i32, WindowError> {
+        self.window.outer_position().map(|position| {
+            Vec2i32::new(position.x, position.y)
+        }).map_err(|_| WindowError::NotSupported)
+    }
+
+    /// Moves the window such that its top left corner including decorations is at `position`.
+    ///
+    /// The request is processed on the event loop thread and this function blocks until it has
+    /// been processed or [`WINDOW_REQUEST_TIMEOUT`] elapsed. If called on the event loop thread
+    /// the position is applied directly. If the platform does not support positioning windows
+    /// [`WindowError::NotSupported`] is returned.
+    pub fn set_outer_position(self: &Arc<Self>, position: Vec2i32) -> Result<(), WindowError> {
+        apply_or_submit(
+            self.backend.is_event_loop_thread(),
+            || self.apply_outer_position(position),
+            |reply| self.backend.push_event(BackendEvent::SetOuterPosition {
+                window: self.clone(),
+                position,
+                reply,
+            }).map_err(|_| WindowError::EventLoopClosed),
+            WINDOW_REQUEST_TIMEOUT
+        ).inspect_err(|err| {
+            if *err == WindowError::Timeout {
+                log::warn!(target: &self.backend.log_target, "Set outer position request timed out");
+            }
+        })
+    }
+
+    /// Requests the window to be redrawn. Once the platform is ready for a new frame any thread
+    /// blocked in [`Window::wait_redraw_requested`] is woken up.
+    ///
+    /// The request is processed asynchronously on the event loop thread. Multiple requests before
+    /// the next redraw are merged into one.
+    pub fn request_redraw(self: &Arc<Self>) {
+        if self.backend.push_event(BackendEvent::RequestRedraw {
+            window: self.clone(),
+        }).is_err() {
+            log::debug!(target: &self.backend.log_target, "Event loop closed. Ignoring redraw request");
+        }
+    }
+
+    /// Blocks until a redraw has been requested or the timeout elapsed. Returns true if a redraw
+    /// has been requested in which case the pending request is consumed.
+    pub fn wait_redraw_requested(&self, timeout: Duration) -> bool {
+        self.redraw.wait_timeout(timeout)
+    }
+
+    /// Returns all input events received since the last call to this function in the order they
+    /// were received.
+    pub fn poll_input_events(&self) -> Vec<InputEvent> {
+        self.input.lock().unwrap().drain()
+    }
+
+    /// Returns the scroll amount accumulated since the last call to this function. Intended to be
+    /// called once per frame by immediate mode ui systems.
+    pub fn take_scroll_total(&self) -> ScrollTotal {
+        self.input.lock().unwrap().take_scroll_total()
+    }
+
+    /// Returns the current state of the modifier keys as last reported to this window.
+    pub fn get_modifiers(&self) -> Modifiers {
+        self.input.lock().unwrap().get_modifiers()
+    }
+
+    /// If enabled the first touch is additionally reported as left mouse button and cursor events
+    /// so applications only handling mouse input can be used on touch devices. Disabled by
+    /// default.
+    pub fn set_emulate_mouse_from_touch(&self, emulate: bool) {
+        self.input.lock().unwrap().set_emulate_mouse_from_touch(emulate);
+    }
+
     pub fn as_vulkan_surface_provider(self: &Arc<Self>) -> Box<dyn VulkanSurfaceProvider> {
         Box::new(WinitVulkanSurfaceProvider::new(self.clone()))
     }
@@ -55,16 +386,335 @@ impl Window {
     pub(in crate::winit) fn on_resize(&self, new_size: Vec2u32) {
         self.state.lock().unwrap().size = new_size;
     }
+
+    pub(in crate::winit) fn on_scale_factor_changed(&self, scale_factor: f64, new_size: Vec2u32) {
+        let mut guard = self.state.lock().unwrap();
+        guard.scale_factor = scale_factor;
+        guard.size = new_size;
+    }
+
+    /// Must only be called on the event loop thread.
+    pub(in crate::winit) fn apply_decorated(&self, decorated: bool) {
+        self.window.set_decorations(decorated);
+    }
+
+    /// Must only be called on the event loop thread.
+    #[cfg(target_os = "windows")]
+    pub(in crate::winit) fn apply_titlebar_color(&self, color: Color) {
+        use windows_sys::Win32::Graphics::Dwm::{DwmSetWindowAttribute, DWMWA_CAPTION_COLOR};
+        use winit::platform::windows::WindowExtWindows;
+
+        // COLORREF is laid out as 0x00BBGGRR
+        let color_ref: u32 = (color.r as u32) | ((color.g as u32) << 8) | ((color.b as u32) << 16);
+        let result = unsafe {
+            DwmSetWindowAttribute(
+                self.window.hwnd(),
+                DWMWA_CAPTION_COLOR,
+                &color_ref as *const u32 as *const std::ffi::c_void,
+                std::mem::size_of::<u32>() as u32
+            )
+        };
+        if result != 0 {
+            // Fails on windows versions before windows 11
+            log::warn!(target: &self.backend.event_loop_log_target, "Failed to set titlebar color: HRESULT {:#X}", result);
+        }
+    }
+
+    /// Must only be called on the event loop thread.
+    #[cfg(not(target_os = "windows"))]
+    pub(in crate::winit) fn apply_titlebar_color(&self, _color: Color) {
+        log::warn!(target: &self.backend.event_loop_log_target, "Custom titlebar colors are not supported on this platform. Ignoring request");
+    }
+
+    /// Must only be called on the event loop thread.
+    #[cfg(target_os = "windows")]
+    pub(in crate::winit) fn apply_preferred_theme(&self, theme: Theme) {
+        use windows_sys::Win32::Graphics::Dwm::{DwmSetWindowAttribute, DWMWA_USE_IMMERSIVE_DARK_MODE};
+        use winit::platform::windows::WindowExtWindows;
+
+        let dark = match theme {
+            Theme::Light => false,
+            Theme::Dark => true,
+            Theme::System => self.window.theme() == winit::window::Theme::Dark,
+        };
+        let value: i32 = dark as i32;
+        let result = unsafe {
+            DwmSetWindowAttribute(
+                self.window.hwnd(),
+                DWMWA_USE_IMMERSIVE_DARK_MODE,
+                &value as *const i32 as *const std::ffi::c_void,
+                std::mem::size_of::<i32>() as u32
+            )
+        };
+        if result != 0 {
+            log::warn!(target: &self.backend.event_loop_log_target, "Failed to set window theme: HRESULT {:#X}", result);
+        }
+    }
+
+    /// Must only be called on the event loop thread.
+    #[cfg(not(target_os = "windows"))]
+    pub(in crate::winit) fn apply_preferred_theme(&self, _theme: Theme) {
+        log::warn!(target: &self.backend.event_loop_log_target, "Setting the window theme is not supported on this platform. Ignoring request");
+    }
+
+    pub(in crate::winit) fn on_theme_changed(&self, theme: Theme) {
+        self.state.lock().unwrap().current_theme = theme;
+    }
+
+    #[cfg(target_os = "windows")]
+    fn query_theme(window: &WinitWindow) -> Theme {
+        use winit::platform::windows::WindowExtWindows;
+        window.theme().into()
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn query_theme(_window: &WinitWindow) -> Theme {
+        Theme::System
+    }
+
+    pub(in crate::winit) fn on_focus_change(&self, focused: bool) {
+        self.state.lock().unwrap().focused = focused;
+        self.focus_condvar.notify_all();
+        self.input.lock().unwrap().push_focus(focused);
+    }
+
+    pub(in crate::winit) fn on_occluded(&self, occluded: bool) {
+        self.state.lock().unwrap().occluded = occluded;
+    }
+
+    pub(in crate::winit) fn on_moved(&self, position: Vec2i32) {
+        self.input.lock().unwrap().push(InputEvent::Moved { position });
+    }
+
+    /// Must only be called on the event loop thread.
+    pub(in crate::winit) fn apply_outer_position(&self, position: Vec2i32) -> Result<(), WindowError> {
+        // Winit silently ignores the request on platforms which do not support it. Since the
+        // same platforms also do not report the position we use that to detect support.
+        self.window.outer_position().map_err(|_| WindowError::NotSupported)?;
+        self.window.set_outer_position(PhysicalPosition::new(position.x, position.y));
+        Ok(())
+    }
+
+    /// Must only be called on the event loop thread.
+    pub(in crate::winit) fn apply_request_redraw(&self) {
+        self.window.request_redraw();
+    }
+
+    pub(in crate::winit) fn on_redraw_requested(&self) {
+        self.redraw.signal();
+    }
+
+    pub(in crate::winit) fn on_touch(&self, id: u64, phase: TouchPhase, position: Vec2f64) {
+        self.input.lock().unwrap().push_touch(id, phase, position);
+    }
+
+    pub(in crate::winit) fn on_cursor_moved(&self, position: Vec2f64) {
+        self.input.lock().unwrap().push(InputEvent::CursorMoved { position });
+    }
+
+    pub(in crate::winit) fn on_mouse_wheel(&self, delta: ScrollDelta, phase: TouchPhase) {
+        self.input.lock().unwrap().push_mouse_wheel(delta, phase);
+    }
+
+    pub(in crate::winit) fn on_modifiers_changed(&self, modifiers: Modifiers) {
+        self.input.lock().unwrap().set_modifiers(modifiers);
+    }
+
+    pub(in crate::winit) fn on_keyboard_input(&self, scancode: u32, state: ButtonState) {
+        self.input.lock().unwrap().push_keyboard_input(scancode, state);
+    }
+
+    pub(in crate::winit) fn on_mouse_input(&self, button: MouseButton, state: ButtonState) {
+        self.input.lock().unwrap().push_mouse_input(button, state);
+    }
+}
+
+impl std::fmt::Debug for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Window").field("id", &self.window.id()).finish()
+    }
+}
+
+/// Tracks pending redraw requests of a window.
+///
+/// The pending flag is only set while holding the mutex so a waiter checking the flag under the
+/// same mutex can never miss a notification.
+struct RedrawSignal {
+    pending: AtomicBool,
+    mutex: Mutex<()>,
+    condvar: Condvar,
+}
+
+impl RedrawSignal {
+    fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+            mutex: Mutex::new(()),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn signal(&self) {
+        let guard = self.mutex.lock().unwrap();
+        self.pending.store(true, Ordering::SeqCst);
+        drop(guard);
+
+        self.condvar.notify_all();
+    }
+
+    /// Waits until the signal is set or the timeout elapsed and clears the signal. Returns true
+    /// if the signal was set.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let guard = self.mutex.lock().unwrap();
+        let _guard = self.condvar.wait_timeout_while(guard, timeout, |_| !self.pending.load(Ordering::SeqCst)).unwrap();
+
+        self.pending.swap(false, Ordering::SeqCst)
+    }
 }
 
 struct WindowState {
     size: Vec2u32,
+    scale_factor: f64,
+    decorated: bool,
+    focused: bool,
+    occluded: bool,
+    current_theme: Theme,
 }
 
 impl WindowState {
-    fn new(initial_size: Vec2u32) -> Self {
+    fn new(initial_size: Vec2u32, scale_factor: f64, decorated: bool, current_theme: Theme) -> Self {
         Self {
-            size: initial_size
+            size: initial_size,
+            scale_factor,
+            decorated,
+            focused: false,
+            occluded: false,
+            current_theme,
         }
     }
+
+    fn logical_size(&self) -> Vec2f64 {
+        self.physical_to_logical(self.size)
+    }
+
+    fn physical_to_logical(&self, p: Vec2u32) -> Vec2f64 {
+        p.cast::<f64>() / self.scale_factor
+    }
+}
+
+/// Runs a blocking window request. On the event loop thread `apply` is called directly since
+/// waiting for the event loop would deadlock. On any other thread `submit` must send the request
+/// to the event loop and the reply is awaited for at most `timeout`.
+fn apply_or_submit<T, A, S>(on_event_loop_thread: bool, apply: A, submit: S, timeout: Duration) -> Result<T, WindowError>
+    where A: FnOnce() -> Result<T, WindowError>, S: FnOnce(Sender<Result<T, WindowError>>) -> Result<(), WindowError> {
+
+    if on_event_loop_thread {
+        return apply();
+    }
+
+    let (send, recv) = std::sync::mpsc::channel();
+    submit(send)?;
+
+    recv.recv_timeout(timeout).map_err(|err| match err {
+        RecvTimeoutError::Timeout => WindowError::Timeout,
+        RecvTimeoutError::Disconnected => WindowError::EventLoopClosed,
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_or_submit_on_event_loop_thread() {
+        // The request must be applied without waiting for the event loop
+        let result = apply_or_submit(true, || Ok(1u32), |_| panic!("Request submitted on the event loop thread"), Duration::ZERO);
+        assert_eq!(result, Ok(1));
+    }
+
+    #[test]
+    fn apply_or_submit_on_other_thread() {
+        let result = apply_or_submit(false, || panic!("Request applied outside of the event loop thread"), |reply| {
+            // Simulates the event loop processing the request
+            std::thread::spawn(move || reply.send(Ok(2u32)).unwrap());
+            Ok(())
+        }, Duration::from_secs(5));
+        assert_eq!(result, Ok(2));
+
+        let result = apply_or_submit::<u32, _, _>(false, || unreachable!(), |_| Err(WindowError::EventLoopClosed), Duration::from_secs(5));
+        assert_eq!(result, Err(WindowError::EventLoopClosed));
+
+        // The event loop dropped the request without processing it
+        let result = apply_or_submit::<u32, _, _>(false, || unreachable!(), |reply| {
+            drop(reply);
+            Ok(())
+        }, Duration::from_secs(5));
+        assert_eq!(result, Err(WindowError::EventLoopClosed));
+
+        // The event loop never responds
+        let mut pending = None;
+        let result = apply_or_submit::<u32, _, _>(false, || unreachable!(), |reply| {
+            pending = Some(reply);
+            Ok(())
+        }, Duration::from_millis(10));
+        assert_eq!(result, Err(WindowError::Timeout));
+    }
+
+    #[test]
+    fn window_options() {
+        let options = WindowOptions::default();
+        assert_eq!(options.title, "Agnaji Window");
+        assert_eq!(options.initial_size, Vec2u32::new(800, 600));
+        assert!(options.resizable && options.decorated);
+        assert!(!options.transparent && !options.always_on_top);
+        assert_eq!(options.position, None);
+
+        assert_eq!(WindowOptions::builder().build(), options);
+
+        let built = WindowOptions::builder()
+            .title("Test")
+            .initial_size(Vec2u32::new(100, 200))
+            .resizable(false)
+            .transparent(true)
+            .position(Vec2i32::new(-10, 20))
+            .build();
+        let expected = options.with_title("Test")
+            .with_initial_size(Vec2u32::new(100, 200))
+            .with_resizable(false)
+            .with_transparent(true)
+            .with_position(Some(Vec2i32::new(-10, 20)));
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn logical_size() {
+        let mut state = WindowState::new(Vec2u32::new(1600, 900), 2.0, true, Theme::System);
+        assert_eq!(state.logical_size(), Vec2f64::new(800.0, 450.0));
+
+        state.scale_factor = 1.5;
+        assert_eq!(state.logical_size(), Vec2f64::new(1600.0 / 1.5, 600.0));
+        assert_eq!(state.physical_to_logical(Vec2u32::new(3, 0)), Vec2f64::new(2.0, 0.0));
+
+        state.scale_factor = 1.0;
+        assert_eq!(state.logical_size(), Vec2f64::new(1600.0, 900.0));
+    }
+
+    #[test]
+    fn redraw_signal() {
+        let signal = Arc::new(RedrawSignal::new());
+        assert!(!signal.wait_timeout(Duration::from_millis(10)));
+
+        // Multiple requests are merged into one redraw
+        signal.signal();
+        signal.signal();
+        assert!(signal.wait_timeout(Duration::from_millis(10)));
+        assert!(!signal.wait_timeout(Duration::from_millis(10)));
+
+        let waiter = {
+            let signal = signal.clone();
+            std::thread::spawn(move || signal.wait_timeout(Duration::from_secs(5)))
+        };
+        signal.signal();
+        assert!(waiter.join().unwrap());
+    }
 }
\ No newline at end of file
@@ -1,137 +1,279 @@
 use std::collections::HashMap;
 use std::panic::{catch_unwind, UnwindSafe};
 use std::sync::{Arc, Condvar, Mutex, Weak};
-use winit::dpi::PhysicalSize;
+use std::sync::atomic::Ordering;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::error::OsError;
 use winit::event::{Event, WindowEvent};
-use winit::event_loop::{ControlFlow, EventLoop, EventLoopBuilder};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopWindowTarget};
 use winit::window::{WindowBuilder, WindowId};
-use crate::prelude::Vec2u32;
-use crate::winit::{AgnajiEvent, DEFAULT_LOG_TARGET, WinitBackend};
+use crate::prelude::{Vec2f64, Vec2i32, Vec2u32};
+use crate::winit::{AgnajiEvent, BackendEvent, RunError, WinitBackend};
 use crate::winit::window::Window;
 
 pub(in crate::winit) const EVENT_LOOP_LOG_TARGET: &'static str = "agnaji::winit::EventLoop";
 
-pub(in crate::winit) fn run<F>(post_init: F) where F: FnOnce(Arc<WinitBackend>) + Send + UnwindSafe + 'static {
-    let event_loop: EventLoop<AgnajiEvent> = EventLoopBuilder::with_user_event().build();
+pub(in crate::winit) fn run<F>(label: Option<String>, post_init: F) -> Result<(), RunError> where F: FnOnce(Arc<WinitBackend>) + Send + UnwindSafe + 'static {
+    #[allow(unused_mut)]
+    let mut event_loop: EventLoop<AgnajiEvent> = EventLoopBuilder::with_user_event().build();
 
     let backend = Arc::new(WinitBackend::new(
-        event_loop.create_proxy()
+        event_loop.create_proxy(),
+        label
     ));
+    let log_target = backend.event_loop_log_target.clone();
 
     let backend_clone = backend.clone();
     let mut engine_thread = Some(std::thread::spawn(move || {
-        log::debug!(target: EVENT_LOOP_LOG_TARGET, "Starting main application thread");
+        log::debug!(target: &backend_clone.event_loop_log_target, "Starting main application thread");
         let backend = backend_clone.clone();
-        if let Err(_) = catch_unwind(move || {
+        if catch_unwind(move || {
             post_init(backend_clone)
-        }) {
-            log::error!(target: EVENT_LOOP_LOG_TARGET, "Main application thread panicked. Quitting winit backend");
+        }).is_err() {
+            log::error!(target: &backend.event_loop_log_target, "Main application thread panicked. Quitting winit backend");
+            backend.engine_thread_panicked.store(true, Ordering::SeqCst);
         };
         backend.quit();
     }));
 
+    let result_backend = backend.clone();
+
     let mut window_table: HashMap<WindowId, Weak<Window>> = HashMap::new();
 
-    log::debug!(target: EVENT_LOOP_LOG_TARGET, "Starting winit event loop");
-    event_loop.run(move |event, window_target, control_flow| {
+    log::debug!(target: &log_target, "Starting winit event loop");
+    let event_handler = move |event: Event<AgnajiEvent>, window_target: &EventLoopWindowTarget<AgnajiEvent>, control_flow: &mut ControlFlow| {
         *control_flow = ControlFlow::Wait;
 
-        log::trace!(target: EVENT_LOOP_LOG_TARGET, "Processing winit event: {:?}", event);
+        log::trace!(target: &log_target, "Processing winit event: {:?}", event);
         match event {
             Event::NewEvents(_) => {}
             Event::WindowEvent { window_id, event } => {
                 match event {
                     WindowEvent::Resized(new_size) => {
-                        if let Some(window) = window_table.get(&window_id).map(Weak::upgrade).flatten() {
+                        if let Some(window) = find_window(&window_table, window_id) {
                             window.on_resize(Vec2u32::new(new_size.width, new_size.height));
                         }
                     }
-                    WindowEvent::Moved(_) => {}
+                    WindowEvent::Moved(position) => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_moved(Vec2i32::new(position.x, position.y));
+                        }
+                    }
                     WindowEvent::CloseRequested => {
-                        log::debug!(target: EVENT_LOOP_LOG_TARGET, "Window {:?} close requested", &window_id);
-                        if let Some(window) = window_table.get(&window_id).map(Weak::upgrade).flatten() {
+                        log::debug!(target: &log_target, "Window {:?} close requested", &window_id);
+                        if let Some(window) = find_window(&window_table, window_id) {
                             window.on_close_requested();
                         }
                     }
                     WindowEvent::Destroyed => {
-                        log::debug!(target: EVENT_LOOP_LOG_TARGET, "Window {:?} destroyed", &window_id);
+                        log::debug!(target: &log_target, "Window {:?} destroyed", &window_id);
                         window_table.remove(&window_id);
                     }
                     WindowEvent::DroppedFile(_) => {}
                     WindowEvent::HoveredFile(_) => {}
                     WindowEvent::HoveredFileCancelled => {}
                     WindowEvent::ReceivedCharacter(_) => {}
-                    WindowEvent::Focused(_) => {}
-                    WindowEvent::KeyboardInput { .. } => {}
-                    WindowEvent::ModifiersChanged(_) => {}
+                    WindowEvent::Focused(focused) => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            backend.event_loop_signal_focus_change(&window, focused);
+                            window.on_focus_change(focused);
+                        }
+                    }
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_keyboard_input(input.scancode, input.state.into());
+                        }
+                    }
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_modifiers_changed(modifiers.into());
+                        }
+                    }
                     WindowEvent::Ime(_) => {}
-                    WindowEvent::CursorMoved { .. } => {}
+                    WindowEvent::CursorMoved { position, .. } => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_cursor_moved(Vec2f64::new(position.x, position.y));
+                        }
+                    }
                     WindowEvent::CursorEntered { .. } => {}
                     WindowEvent::CursorLeft { .. } => {}
-                    WindowEvent::MouseWheel { .. } => {}
-                    WindowEvent::MouseInput { .. } => {}
+                    WindowEvent::MouseWheel { delta, phase, .. } => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_mouse_wheel(delta.into(), phase.into());
+                        }
+                    }
+                    WindowEvent::MouseInput { state, button, .. } => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_mouse_input(button.into(), state.into());
+                        }
+                    }
                     WindowEvent::TouchpadPressure { .. } => {}
                     WindowEvent::AxisMotion { .. } => {}
-                    WindowEvent::Touch(_) => {}
-                    WindowEvent::ScaleFactorChanged { .. } => {}
-                    WindowEvent::ThemeChanged(_) => {}
-                    WindowEvent::Occluded(_) => {}
+                    WindowEvent::Touch(touch) => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_touch(touch.id, touch.phase.into(), Vec2f64::new(touch.location.x, touch.location.y));
+                        }
+                    }
+                    WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_scale_factor_changed(scale_factor, Vec2u32::new(new_inner_size.width, new_inner_size.height));
+                        }
+                    }
+                    WindowEvent::ThemeChanged(theme) => {
+                        log::debug!(target: &log_target, "Window {:?} theme changed: {:?}", &window_id, theme);
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_theme_changed(theme.into());
+                        }
+                        backend.event_loop_signal_theme_changed(theme.into());
+                    }
+                    WindowEvent::Occluded(occluded) => {
+                        log::debug!(target: &log_target, "Window {:?} occluded: {:?}", &window_id, occluded);
+                        if let Some(window) = find_window(&window_table, window_id) {
+                            window.on_occluded(occluded);
+                        }
+                    }
                 }
             }
             Event::DeviceEvent { .. } => {}
-            Event::UserEvent(event) => {
+            Event::UserEvent(AgnajiEvent(event)) => {
                 match event {
-                    AgnajiEvent::CreateWindow {
-                        id, title, initial_size
+                    BackendEvent::CreateWindow {
+                        id, options
                     } => {
-                        log::debug!(target: EVENT_LOOP_LOG_TARGET, "Received create window request: {:?} size: {:?} (RequestID: {})", title, initial_size, id);
-                        let size = if let Some(initial_size) = initial_size {
-                            initial_size
-                        } else {
-                            Vec2u32::new(800, 600)
-                        };
-
-                        let window = WindowBuilder::new()
-                            .with_title(title)
+                        log::debug!(target: &log_target, "Received create window request: {:?} (RequestID: {})", options, id);
+                        let size = options.initial_size;
+
+                        let mut builder = WindowBuilder::new()
+                            .with_title(options.title)
                             .with_inner_size(PhysicalSize::new(size.x, size.y))
-                            .build(&window_target);
+                            .with_resizable(options.resizable)
+                            .with_decorations(options.decorated)
+                            .with_transparent(options.transparent)
+                            .with_always_on_top(options.always_on_top);
+                        if let Some(position) = options.position {
+                            builder = builder.with_position(PhysicalPosition::new(position.x, position.y));
+                        }
+                        let window = builder.build(&window_target);
 
                         match window {
                             Ok(window) => {
                                 let window_id = window.id();
-                                log::debug!(target: EVENT_LOOP_LOG_TARGET, "Window creation successful. Id: {:?}", window_id);
+                                log::debug!(target: &log_target, "Window creation successful. Id: {:?}", window_id);
 
-                                let window = Arc::new(Window::new(backend.clone(), window, size));
+                                let window = Arc::new(Window::new(backend.clone(), window, size, options.decorated));
                                 window_table.insert(window_id, Arc::downgrade(&window));
 
                                 backend.window_channel.push(id, Ok(window));
                             },
                             Err(error) => {
-                                log::error!(target: EVENT_LOOP_LOG_TARGET, "Failed to create window: {:?}", &error);
+                                log::error!(target: &log_target, "Failed to create window: {:?}", &error);
                                 backend.window_channel.push(id, Err(error));
                             }
                         }
                     }
-                    AgnajiEvent::Quit => {
-                        *control_flow = ControlFlow::ExitWithCode(0);
-                        log::debug!(target: EVENT_LOOP_LOG_TARGET, "Received quit order");
+                    BackendEvent::SetDecorated { window, decorated } => {
+                        log::trace!(target: &log_target, "Received set decorated request: {:?}", decorated);
+                        window.apply_decorated(decorated);
+                    }
+                    BackendEvent::SetTitlebarColor { window, color } => {
+                        log::trace!(target: &log_target, "Received set titlebar color request: {:?}", color);
+                        window.apply_titlebar_color(color);
+                    }
+                    BackendEvent::SetOuterPosition { window, position, reply } => {
+                        log::trace!(target: &log_target, "Received set outer position request: {:?}", position);
+                        // The requester may have given up in which case nobody is listening anymore
+                        let _ = reply.send(window.apply_outer_position(position));
+                    }
+                    BackendEvent::RequestRedraw { window } => {
+                        window.apply_request_redraw();
+                    }
+                    BackendEvent::SetTheme { window, theme } => {
+                        log::trace!(target: &log_target, "Received set theme request: {:?}", theme);
+                        window.apply_preferred_theme(theme);
+                    }
+                    BackendEvent::UserEvent(event) => {
+                        log::trace!(target: &log_target, "Received user event");
+                        backend.event_loop_dispatch_user_event(event);
+                    }
+                    BackendEvent::RunOnEventLoopThread(task) => {
+                        log::trace!(target: &log_target, "Running task on event loop thread");
+                        if task.run(window_target) {
+                            log::error!(target: &log_target, "Task run on the event loop thread panicked");
+                        }
+                    }
+                    BackendEvent::Quit => {
+                        let exit_code = if backend.engine_thread_panicked.load(Ordering::SeqCst) { 1 } else { 0 };
+                        *control_flow = ControlFlow::ExitWithCode(exit_code);
+                        log::debug!(target: &log_target, "Received quit order");
+
+                        // Any window creation requests submitted after this point will never be
+                        // processed so we must wake up the waiting threads
+                        backend.window_channel.close();
                     }
                 }
             }
             Event::Suspended => {
+                backend.event_loop_signal_suspended();
             }
             Event::Resumed => {
+                backend.event_loop_signal_resumed();
             }
             Event::MainEventsCleared => {}
-            Event::RedrawRequested(_) => {}
+            Event::RedrawRequested(window_id) => {
+                if let Some(window) = find_window(&window_table, window_id) {
+                    window.on_redraw_requested();
+                }
+            }
             Event::RedrawEventsCleared => {}
             Event::LoopDestroyed => {
-                log::debug!(target: EVENT_LOOP_LOG_TARGET, "Event loop destroyed");
+                log::debug!(target: &log_target, "Event loop destroyed");
                 engine_thread.take().unwrap().join().unwrap();
             }
         }
-    });
+    };
+
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "android",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    {
+        use winit::platform::run_return::EventLoopExtRunReturn;
+        event_loop.run_return(event_handler);
+
+        if result_backend.engine_thread_panicked.load(Ordering::SeqCst) {
+            Err(RunError::EngineThreadPanicked)
+        } else {
+            Ok(())
+        }
+    }
+
+    // On all other platforms the event loop never returns. The exit code set when quitting is
+    // used to indicate errors instead.
+    #[cfg(not(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "android",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    {
+        drop(result_backend);
+        event_loop.run(event_handler)
+    }
+}
+
+/// Returns the window with the provided id if it is still alive.
+fn find_window(window_table: &HashMap<WindowId, Weak<Window>>, id: WindowId) -> Option<Arc<Window>> {
+    window_table.get(&id)?.upgrade()
 }
 
 pub(in crate::winit) struct WindowChannel {
@@ -145,6 +287,7 @@ impl WindowChannel {
             guarded: Mutex::new(WindowChannelGuarded {
                 next_id: 1,
                 available_windows: Vec::with_capacity(4),
+                closed: false,
             }),
             condvar: Condvar::new(),
         }
@@ -159,7 +302,9 @@ impl WindowChannel {
         id
     }
 
-    pub(in crate::winit) fn wait_ready(&self, id: u64) -> Result<Arc<Window>, OsError> {
+    /// Blocks until the window creation request `id` has been fulfilled. Log messages are
+    /// written to `log_target`.
+    pub(in crate::winit) fn wait_ready(&self, id: u64, log_target: &str) -> Result<Arc<Window>, String> {
         let mut guard = self.guarded.lock().unwrap();
         loop {
             let mut found = None;
@@ -171,15 +316,30 @@ impl WindowChannel {
             }
 
             if let Some(index) = found {
-                log::debug!(target: DEFAULT_LOG_TARGET, "Window creation request fulfilled. RequestID: {}", id);
-                return guard.available_windows.swap_remove(index).1;
+                log::debug!(target: log_target, "Window creation request fulfilled. RequestID: {}", id);
+                return guard.available_windows.swap_remove(index).1.map_err(|err| err.to_string());
+            }
+
+            if guard.closed {
+                log::debug!(target: log_target, "Window creation request failed because the event loop has been closed. RequestID: {}", id);
+                return Err(String::from("Event loop has been closed"));
             }
 
-            log::debug!(target: DEFAULT_LOG_TARGET, "Waiting for window creation request fulfillment. RequestID: {}", id);
+            log::debug!(target: log_target, "Waiting for window creation request fulfillment. RequestID: {}", id);
             guard = self.condvar.wait(guard).unwrap();
         }
     }
 
+    /// Marks the channel as closed. Any requests which have not been fulfilled at this point will
+    /// return an error.
+    fn close(&self) {
+        let mut guard = self.guarded.lock().unwrap();
+        guard.closed = true;
+        drop(guard);
+
+        self.condvar.notify_all();
+    }
+
     fn push(&self, id: u64, window: Result<Arc<Window>, OsError>) {
         let mut guard = self.guarded.lock().unwrap();
         guard.available_windows.push((id, window));
@@ -192,4 +352,5 @@ impl WindowChannel {
 struct WindowChannelGuarded {
     next_id: u64,
     available_windows: Vec<(u64, Result<Arc<Window>, OsError>)>,
+    closed: bool,
 }
\ No newline at end of file
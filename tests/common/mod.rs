@@ -1,3 +1,4 @@
 pub fn pre_init() {
-    pretty_env_logger::init();
-}
\ No newline at end of file
+    // Multiple tests in the same binary call this so the logger may already be set
+    let _ = pretty_env_logger::try_init();
+}
@@ -0,0 +1,84 @@
+extern crate agnaji;
+
+use std::sync::Mutex;
+
+use ash::vk;
+
+use agnaji::vulkan::device::DeviceProvider;
+
+/// Records all messages of the validation layers.
+struct ValidationLogger {
+    messages: Mutex<Vec<String>>,
+}
+
+impl log::Log for ValidationLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target() == "agnaji::Vulkan"
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.messages.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+    }
+}
+
+static LOGGER: ValidationLogger = ValidationLogger {
+    messages: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn object_names_in_validation_messages() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+    if !device.get_instance().is_validation_enabled() || !device.is_object_naming_enabled() {
+        return;
+    }
+    let vk_device = device.get_device();
+
+    // A buffer without memory and without transfer dst usage cannot be filled
+    let buffer_create_info = vk::BufferCreateInfo::builder()
+        .size(256)
+        .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = unsafe { vk_device.create_buffer(&buffer_create_info, None) }.unwrap();
+    device.get_debug_utils().set_buffer_name(vk_device.handle(), buffer, "debug utils test buffer");
+
+    let pool_create_info = vk::CommandPoolCreateInfo::builder()
+        .queue_family_index(device.get_main_queue().get_queue_family());
+    let command_pool = unsafe { vk_device.create_command_pool(&pool_create_info, None) }.unwrap();
+
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = unsafe { vk_device.allocate_command_buffers(&allocate_info) }.unwrap()[0];
+
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    unsafe {
+        vk_device.begin_command_buffer(cmd, &begin_info).unwrap();
+        vk_device.cmd_fill_buffer(cmd, buffer, 0, vk::WHOLE_SIZE, 0);
+        vk_device.end_command_buffer(cmd).unwrap();
+
+        vk_device.destroy_command_pool(command_pool, None);
+        vk_device.destroy_buffer(buffer, None);
+    }
+
+    let messages = LOGGER.messages.lock().unwrap();
+    assert!(messages.iter().any(|message| message.contains("debug utils test buffer")), "No validation message contains the buffer name: {:?}", messages);
+}
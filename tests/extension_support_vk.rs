@@ -0,0 +1,35 @@
+extern crate agnaji;
+
+mod common;
+
+use agnaji::vulkan::device::SwapchainProvider;
+
+#[test]
+fn headless_extension_support() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+
+    // Swapchains require VK_KHR_surface which is never enabled for headless instances
+    assert!(!agnaji.is_instance_extension_enabled(ash::extensions::khr::Surface::name()));
+    assert!(!agnaji.is_device_extension_enabled(ash::extensions::khr::Swapchain::name()));
+    assert!(agnaji.get_device().get_swapchain_khr().is_none());
+
+    // Required by every device
+    assert!(agnaji.is_device_extension_enabled(ash::extensions::khr::Synchronization2::name()));
+
+    for extension in agnaji.get_enabled_instance_extensions() {
+        assert!(agnaji.is_instance_extension_enabled(extension));
+    }
+    for extension in agnaji.get_enabled_device_extensions() {
+        assert!(agnaji.is_device_extension_enabled(extension));
+    }
+}
@@ -2,11 +2,19 @@ extern crate agnaji;
 
 mod common;
 
+use ash::vk;
+
+use agnaji::debug::ValidationCapture;
+use agnaji::vulkan::instance::DebugConfig;
+
 #[test]
 fn run_test() {
     common::pre_init();
 
-    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new(None, true);
+    let capture = ValidationCapture::new(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING);
+
+    // Installed before the instance is created so instance and device creation are checked too
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_with_debug_handler(std::iter::empty(), DebugConfig::default(), capture.create_handler());
     let device_reports = initializer.generate_device_reports().unwrap();
 
     let mut selected = None;
@@ -19,6 +27,12 @@ fn run_test() {
     }
 
     if let Some(selected) = selected {
-        let (_agnaji, _) = initializer.build(selected).unwrap();
+        let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+        drop(agnaji);
+    } else {
+        drop(initializer);
     }
-}
\ No newline at end of file
+    drop(device_reports);
+
+    capture.assert_no_errors();
+}
@@ -0,0 +1,135 @@
+extern crate agnaji;
+
+mod common;
+
+use ash::vk;
+
+use agnaji::vulkan::device::DeviceProvider;
+use agnaji::vulkan::memory::{MapError, ResourceTiling};
+
+#[test]
+fn map_host_visible_memory() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let allocator = agnaji.get_memory_allocator();
+
+    let requirements = vk::MemoryRequirements {
+        size: 1024,
+        alignment: 16,
+        memory_type_bits: u32::MAX,
+    };
+
+    let mut allocation = allocator.allocate(requirements, vk::MemoryPropertyFlags::HOST_VISIBLE, ResourceTiling::Linear).unwrap();
+    assert!(allocation.is_mapped());
+    {
+        let mut mapped = allocation.map().unwrap();
+        assert_eq!(mapped.as_slice().len(), 1024);
+        mapped.write(0, &0x12345678u32);
+        mapped.write_slice(16, &[1.0f32, 2.0, 3.0]);
+        mapped.flush().unwrap();
+        assert_eq!(mapped.read::<u32>(0), 0x12345678);
+        assert_eq!(mapped.read::<f32>(20), 2.0);
+    }
+
+    // Separate allocations are mapped as well
+    let config = allocator.get_heap_manager().get_config(allocation.get_memory_type());
+    let large = vk::MemoryRequirements {
+        size: config.page_size + 1,
+        memory_type_bits: 1 << allocation.get_memory_type(),
+        ..requirements
+    };
+    let mut large = allocator.allocate(large, vk::MemoryPropertyFlags::empty(), ResourceTiling::Linear).unwrap();
+    assert!(large.is_separate());
+    large.map().unwrap().write(config.page_size - 3, &7u32);
+    drop(large);
+    drop(allocation);
+
+    // Device local only memory cannot be mapped
+    let device = agnaji.get_device();
+    let properties = unsafe {
+        device.get_instance().get_instance().get_physical_device_memory_properties(device.get_physical_device())
+    };
+    let device_only_bits = properties.memory_types[0..(properties.memory_type_count as usize)].iter()
+        .enumerate()
+        .filter(|(_, memory_type)| !memory_type.property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE))
+        .fold(0, |bits, (index, _)| bits | (1 << index));
+    if device_only_bits != 0 {
+        let requirements = vk::MemoryRequirements {
+            memory_type_bits: device_only_bits,
+            ..requirements
+        };
+        let mut allocation = allocator.allocate(requirements, vk::MemoryPropertyFlags::empty(), ResourceTiling::Linear).unwrap();
+        assert!(!allocation.is_mapped());
+        assert_eq!(allocation.map().err(), Some(MapError::NotHostVisible));
+    }
+}
+
+#[test]
+fn map_adjacent_allocations() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let allocator = agnaji.get_memory_allocator();
+
+    // Prefer non coherent memory since only there flushes and invalidations can clobber neighbours
+    let device = agnaji.get_device();
+    let properties = unsafe {
+        device.get_instance().get_instance().get_physical_device_memory_properties(device.get_physical_device())
+    };
+    let non_coherent_bits = properties.memory_types[0..(properties.memory_type_count as usize)].iter()
+        .enumerate()
+        .filter(|(_, memory_type)| memory_type.property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) && !memory_type.property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT))
+        .fold(0, |bits, (index, _)| bits | (1 << index));
+    let requirements = vk::MemoryRequirements {
+        size: 20,
+        alignment: 4,
+        memory_type_bits: if non_coherent_bits != 0 { non_coherent_bits } else { u32::MAX },
+    };
+
+    let mut first = allocator.allocate(requirements, vk::MemoryPropertyFlags::HOST_VISIBLE, ResourceTiling::Linear).unwrap();
+    let mut second = allocator.allocate(requirements, vk::MemoryPropertyFlags::HOST_VISIBLE, ResourceTiling::Linear).unwrap();
+    if first.memory == second.memory {
+        let (low, high) = if first.offset < second.offset { (&first, &second) } else { (&second, &first) };
+        assert!(low.offset + low.get_size() <= high.offset);
+        if non_coherent_bits != 0 {
+            let atom_size = device.get_limits().non_coherent_atom_size;
+            assert_eq!(high.offset % atom_size, 0);
+            assert_eq!(low.get_size() % atom_size, 0);
+        }
+    }
+
+    {
+        let mut first_mapped = first.map().unwrap();
+        first_mapped.write(0, &1u32);
+
+        // Mapping and invalidating the second allocation must not discard the unflushed write
+        // to the first allocation
+        let mut second_mapped = second.map().unwrap();
+        second_mapped.invalidate().unwrap();
+        second_mapped.write(16, &3u32);
+
+        // Both slices are flushed when dropped
+        drop(second_mapped);
+        drop(first_mapped);
+    }
+
+    assert_eq!(first.map().unwrap().read::<u32>(0), 1);
+    assert_eq!(second.map().unwrap().read::<u32>(16), 3);
+}
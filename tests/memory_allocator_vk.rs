@@ -0,0 +1,149 @@
+extern crate agnaji;
+
+mod common;
+
+use ash::vk;
+
+use agnaji::vulkan::device::DeviceProvider;
+use agnaji::vulkan::memory::{MemoryResource, ResourceMemoryRequirements, ResourceTiling};
+
+#[test]
+fn suballocate_device_memory() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+    let vk_device = device.get_device();
+    let allocator = agnaji.get_memory_allocator();
+
+    let create_info = vk::BufferCreateInfo::builder()
+        .size(4096)
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    let mut resources = Vec::new();
+    for _ in 0..64 {
+        let buffer = unsafe { vk_device.create_buffer(&create_info, None) }.unwrap();
+        let requirements = unsafe { vk_device.get_buffer_memory_requirements(buffer) };
+
+        let allocation = allocator.allocate(requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL, ResourceTiling::Linear).unwrap();
+        assert_eq!(allocation.offset % requirements.alignment, 0);
+        assert!(allocation.get_size() >= requirements.size);
+        unsafe { vk_device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) }.unwrap();
+
+        resources.push((buffer, allocation));
+    }
+    assert_eq!(allocator.get_allocation_count(), 64);
+
+    // All buffers fit into a single page
+    assert!(resources.iter().all(|(_, allocation)| allocation.memory == resources[0].1.memory));
+
+    let statistics = allocator.get_heap_statistics();
+    let memory_type = resources[0].1.get_memory_type();
+    assert_eq!(statistics[memory_type as usize].pooled_allocation_count, 64);
+    assert_eq!(statistics[memory_type as usize].page_count, 1);
+
+    // Large allocations bypass the pages
+    let config = allocator.get_heap_manager().get_config(memory_type);
+    let large = vk::MemoryRequirements {
+        size: config.page_size + 1,
+        alignment: 1,
+        memory_type_bits: 1 << memory_type,
+    };
+    let large = allocator.allocate(large, vk::MemoryPropertyFlags::empty(), ResourceTiling::Linear).unwrap();
+    assert!(large.is_separate());
+    assert_eq!(allocator.get_heap_statistics()[memory_type as usize].separate_bytes, config.page_size + 1);
+    drop(large);
+
+    for (buffer, allocation) in resources.drain(..) {
+        unsafe { vk_device.destroy_buffer(buffer, None) };
+        drop(allocation);
+    }
+    assert_eq!(allocator.get_allocation_count(), 0);
+}
+
+#[test]
+fn separate_linear_and_optimal_resources() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+    let allocator = agnaji.get_memory_allocator();
+    let granularity = device.get_limits().buffer_image_granularity;
+
+    let requirements = vk::MemoryRequirements {
+        size: 256,
+        alignment: 16,
+        memory_type_bits: u32::MAX,
+    };
+    let linear = allocator.allocate(requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL, ResourceTiling::Linear).unwrap();
+    let optimal = allocator.allocate(requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL, ResourceTiling::Optimal).unwrap();
+    assert_eq!(linear.get_memory_type(), optimal.get_memory_type());
+
+    if granularity > 1 {
+        // Different pages are different device memory objects where the granularity does not apply
+        assert_ne!(linear.memory, optimal.memory);
+        assert_eq!(allocator.get_heap_statistics()[linear.get_memory_type() as usize].page_count, 2);
+    } else {
+        assert_eq!(linear.memory, optimal.memory);
+    }
+}
+
+#[test]
+fn allocate_resource_memory() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+    let vk_device = device.get_device();
+    let allocator = agnaji.get_memory_allocator();
+
+    let create_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(vk::Format::R8G8B8A8_UNORM)
+        .extent(vk::Extent3D { width: 1024, height: 1024, depth: 1 })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { vk_device.create_image(&create_info, None) }.unwrap();
+
+    let requirements = ResourceMemoryRequirements::query(&device, MemoryResource::Image(image));
+    let allocation = allocator.allocate_resource(MemoryResource::Image(image), vk::MemoryPropertyFlags::DEVICE_LOCAL, ResourceTiling::Optimal).unwrap();
+    assert!(allocation.get_size() >= requirements.requirements.size);
+    if requirements.requires_dedicated_allocation {
+        assert!(allocation.is_dedicated());
+    }
+    assert_eq!(allocator.get_allocation_count(), 1);
+
+    unsafe { vk_device.destroy_image(image, None) };
+    drop(allocation);
+    assert_eq!(allocator.get_allocation_count(), 0);
+}
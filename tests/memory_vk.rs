@@ -0,0 +1,51 @@
+extern crate agnaji;
+
+mod common;
+
+use agnaji::vulkan::memory::{BufferArena, BufferUsageClass};
+
+#[test]
+fn suballocate_buffers() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+
+    let arena = BufferArena::new(device.clone(), 1 << 16).unwrap();
+
+    let mut slices = Vec::new();
+    for usage_class in BufferUsageClass::ALL {
+        let alignment = usage_class.get_offset_alignment(device.get_limits());
+
+        // Enough slices to require more than one buffer
+        for _ in 0..300 {
+            let slice = arena.allocate(usage_class, 200).unwrap();
+            assert_eq!(slice.get_offset() % alignment, 0);
+            assert!(slice.get_offset() + slice.get_size() <= arena.get_buffer_size());
+            assert_ne!(slice.get_device_address(), 0);
+
+            unsafe { slice.get_mapped_ptr().write_bytes(0xAB, slice.get_size() as usize) };
+            slices.push(slice);
+        }
+    }
+    assert_eq!(arena.get_slice_count(), 600);
+    assert!(arena.allocate(BufferUsageClass::Uniform, arena.get_buffer_size() + 1).is_err());
+
+    slices.clear();
+    assert_eq!(arena.get_slice_count(), 0);
+
+    let statistics = device.get_memory_statistics().snapshot();
+    assert!(statistics.pool_allocation_count >= 2);
+    assert!(statistics.pool_bytes >= 2 * arena.get_buffer_size());
+
+    drop(arena);
+    assert_eq!(device.get_memory_statistics().snapshot().pool_bytes, 0);
+}
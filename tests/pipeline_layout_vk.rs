@@ -0,0 +1,41 @@
+extern crate agnaji;
+
+mod common;
+
+use ash::vk;
+
+#[test]
+fn shared_pipeline_layouts() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let cache = agnaji.get_pipeline_layout_cache();
+
+    let vertex = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX,
+        offset: 0,
+        size: 16,
+    };
+    let fragment = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 16,
+        size: 16,
+    };
+
+    let empty = cache.get_or_create(&[], &[]);
+    assert_ne!(empty, vk::PipelineLayout::null());
+    assert_eq!(cache.get_or_create(&[], &[]), empty);
+
+    let push = cache.get_or_create(&[], &[vertex, fragment]);
+    assert_ne!(push, empty);
+    assert_eq!(cache.get_or_create(&[], &[fragment, vertex]), push);
+    assert_eq!(cache.get_layout_count(), 2);
+}
@@ -0,0 +1,31 @@
+extern crate agnaji;
+
+mod common;
+
+use ash::vk;
+
+use agnaji::include_spirv;
+use agnaji::vulkan::shader::ShaderModule;
+
+#[test]
+fn create_embedded_shader_module() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+
+    let spirv = include_spirv!("shaders/minimal.comp.spv");
+    let module = ShaderModule::from_compiled(device.clone(), &spirv).unwrap();
+    assert_ne!(module.get_handle(), vk::ShaderModule::null());
+
+    let module = ShaderModule::from_bytes(device, include_bytes!("shaders/minimal.comp.spv")).unwrap();
+    assert_ne!(module.get_handle(), vk::ShaderModule::null());
+}
@@ -0,0 +1,40 @@
+extern crate agnaji;
+
+mod common;
+
+use agnaji::vulkan::memory::{BufferArena, BufferUsageClass};
+use agnaji::vulkan::staging::StagingManager;
+
+#[test]
+fn chunked_upload() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+
+    let arena = BufferArena::new(device.clone(), 1 << 16).unwrap();
+    let mut staging = StagingManager::new(device, 4096).unwrap();
+
+    // Larger than the staging buffer so the upload must be split across submissions
+    let data: Vec<u8> = (0..40000u32).map(|i| (i % 251) as u8).collect();
+    let dst = arena.allocate(BufferUsageClass::Storage, data.len() as u64).unwrap();
+
+    staging.upload_buffer(&data, dst.get_buffer(), dst.get_offset()).unwrap();
+    let value = staging.flush().unwrap();
+    assert!(value > 1);
+    staging.wait(value).unwrap();
+
+    let uploaded = unsafe { std::slice::from_raw_parts(dst.get_mapped_ptr(), data.len()) };
+    assert_eq!(uploaded, data.as_slice());
+    assert_eq!(staging.poll().unwrap(), value);
+
+    drop(dst);
+}
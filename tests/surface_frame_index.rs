@@ -0,0 +1,84 @@
+//! The winit event loop must run on the main thread so this test uses a custom harness.
+
+extern crate agnaji;
+
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use raw_window_handle::HasRawDisplayHandle;
+
+use agnaji::vulkan::init::{AgnajiVulkanInitializer, DeviceSelection};
+use agnaji::vulkan::output::SurfaceOutput;
+
+/// Polls the frame index of `surface` until it differs from `previous` or the timeout elapsed.
+fn wait_frame_index_change(surface: &SurfaceOutput, previous: u64, timeout: Duration) -> u64 {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let index = surface.get_frame_index();
+        if index != previous || Instant::now() >= deadline {
+            return index;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn main() {
+    common::pre_init();
+
+    #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        println!("No display available. Skipping surface frame index test");
+        return;
+    }
+
+    let (send, recv) = channel();
+    agnaji::winit::run(move |backend| {
+        let test = std::thread::spawn(move || {
+            let window = backend.create_window(String::from("Frame Index Test"), None).unwrap();
+
+            let required_extensions: Vec<_> = ash_window::enumerate_required_extensions(window.get_window().raw_display_handle()).unwrap()
+                .iter().map(|ext| CString::from(unsafe { CStr::from_ptr(*ext) })).collect();
+            let mut initializer = AgnajiVulkanInitializer::new(required_extensions.into_iter(), true);
+            initializer.register_surface(window.as_vulkan_surface_provider(), Some("main")).unwrap();
+
+            let device_reports = initializer.generate_device_reports().unwrap();
+            let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+                Some(selected) => selected,
+                None => {
+                    println!("No suitable device. Skipping surface frame index test");
+                    backend.quit();
+                    return;
+                }
+            };
+            let (_agnaji, mut surfaces) = initializer.build(DeviceSelection::Report(selected)).unwrap();
+            let surface = surfaces.remove(0).1;
+
+            surface.set_on_demand_rendering(true);
+            assert!(surface.wait_for_first_frame(Duration::from_secs(10)));
+
+            // Let redraws requested by the platform settle
+            let mut index = surface.get_frame_index();
+            while wait_frame_index_change(&surface, index, Duration::from_millis(500)) != index {
+                index = surface.get_frame_index();
+            }
+            assert!(index >= 1);
+
+            for _ in 0..3 {
+                window.request_redraw();
+                let next = wait_frame_index_change(&surface, index, Duration::from_secs(5));
+                assert_eq!(next, index + 1);
+                index = next;
+            }
+            assert_eq!(surface.get_swapchain_image_index(), None);
+
+            drop(surface);
+            backend.quit();
+        });
+        send.send(test).unwrap();
+    }).unwrap();
+
+    recv.recv().unwrap().join().unwrap();
+}
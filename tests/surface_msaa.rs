@@ -0,0 +1,93 @@
+//! The winit event loop must run on the main thread so this test uses a custom harness.
+
+extern crate agnaji;
+
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use raw_window_handle::HasRawDisplayHandle;
+
+use agnaji::Agnaji;
+use agnaji::output::OutputTarget;
+use agnaji::scene::{CameraClearMode, Color};
+use agnaji::vulkan::init::{AgnajiVulkanInitializer, DeviceSelection};
+use agnaji::vulkan::output::{MsaaSamples, SurfaceOutput};
+
+/// Polls the frame index of `surface` until it reached `target` or the timeout elapsed.
+fn wait_frame_index(surface: &SurfaceOutput, target: u64, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while surface.get_frame_index() < target {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    true
+}
+
+fn main() {
+    common::pre_init();
+
+    #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        println!("No display available. Skipping surface msaa test");
+        return;
+    }
+
+    let (send, recv) = channel();
+    agnaji::winit::run(move |backend| {
+        let test = std::thread::spawn(move || {
+            let window = backend.create_window(String::from("Msaa Test"), None).unwrap();
+
+            let required_extensions: Vec<_> = ash_window::enumerate_required_extensions(window.get_window().raw_display_handle()).unwrap()
+                .iter().map(|ext| CString::from(unsafe { CStr::from_ptr(*ext) })).collect();
+            let mut initializer = AgnajiVulkanInitializer::new(required_extensions.into_iter(), true);
+            initializer.register_surface(window.as_vulkan_surface_provider(), Some("main")).unwrap();
+
+            let device_reports = initializer.generate_device_reports().unwrap();
+            let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+                Some(selected) => selected,
+                None => {
+                    println!("No suitable device. Skipping surface msaa test");
+                    backend.quit();
+                    return;
+                }
+            };
+            let (agnaji, mut surfaces) = initializer.build(DeviceSelection::Report(selected)).unwrap();
+            let surface = surfaces.remove(0).1;
+            assert!(surface.wait_for_first_frame(Duration::from_secs(10)));
+
+            // Two cameras where only the first one clears and draws the background
+            let scene = agnaji.create_scene();
+            let update = scene.begin_update().unwrap();
+            let background = update.create_background_color().unwrap();
+            background.set_gradient(update.as_ref(), Color::new(0.2, 0.4, 0.8, 1.0), Color::new(0.9, 0.9, 1.0, 1.0));
+            let first = update.create_camera_component();
+            let second = update.create_camera_component();
+            second.set_clear_mode(update.as_ref(), CameraClearMode::NoClear);
+            second.set_render_order(update.as_ref(), 1);
+            drop(update);
+            surface.set_source_camera(Some(first.clone()));
+
+            // Every change recreates the swapchain and the attachments. Frames must keep being
+            // presented with every sample count.
+            for samples in MsaaSamples::ALL.into_iter().chain([MsaaSamples::None_]) {
+                surface.set_msaa_samples(samples);
+                assert!(surface.get_msaa_samples() <= samples);
+
+                let target = surface.get_frame_index() + 3;
+                assert!(wait_frame_index(&surface, target, Duration::from_secs(5)), "No frames presented with {:?}", samples);
+            }
+
+            surface.set_source_camera(None);
+            drop(surface);
+            backend.quit();
+        });
+        send.send(test).unwrap();
+    }).unwrap();
+
+    recv.recv().unwrap().join().unwrap();
+}
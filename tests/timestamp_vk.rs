@@ -0,0 +1,147 @@
+extern crate agnaji;
+
+mod common;
+
+use ash::vk;
+
+use agnaji::vulkan::device::DeviceProvider;
+use agnaji::debug::GpuProfiler;
+use agnaji::vulkan::timestamp::GpuTimestampPool;
+
+#[test]
+fn record_and_read_timestamps() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+    let vk_device = device.get_device();
+
+    let mut pool = GpuTimestampPool::new(device.clone(), 2).unwrap();
+
+    let pool_create_info = vk::CommandPoolCreateInfo::builder()
+        .queue_family_index(device.get_main_queue().get_queue_family());
+    let command_pool = unsafe { vk_device.create_command_pool(&pool_create_info, None) }.unwrap();
+
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = unsafe { vk_device.allocate_command_buffers(&allocate_info) }.unwrap()[0];
+
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    unsafe { vk_device.begin_command_buffer(cmd, &begin_info) }.unwrap();
+
+    pool.reset(cmd);
+    let first = pool.record_timestamp(cmd, vk::PipelineStageFlags2KHR::TOP_OF_PIPE);
+    let second = pool.record_timestamp(cmd, vk::PipelineStageFlags2KHR::BOTTOM_OF_PIPE);
+
+    unsafe { vk_device.end_command_buffer(cmd) }.unwrap();
+
+    let fence = unsafe { vk_device.create_fence(&vk::FenceCreateInfo::builder(), None) }.unwrap();
+    let submit_info = vk::SubmitInfo::builder()
+        .command_buffers(std::slice::from_ref(&cmd));
+    unsafe {
+        let queue = device.get_main_queue().lock().unwrap();
+        vk_device.queue_submit(*queue, std::slice::from_ref(&submit_info), fence).unwrap();
+        drop(queue);
+        vk_device.wait_for_fences(std::slice::from_ref(&fence), true, u64::MAX).unwrap();
+    }
+
+    let timestamps = pool.read_timestamps().unwrap();
+    assert_eq!(timestamps.len(), 2);
+    assert!(timestamps[second.get_index() as usize] >= timestamps[first.get_index() as usize]);
+    assert!(pool.nanoseconds_per_tick(&device) > 0.0);
+
+    unsafe {
+        vk_device.destroy_fence(fence, None);
+        vk_device.destroy_command_pool(command_pool, None);
+    }
+}
+
+#[test]
+fn profiler_resolves_previous_frame() {
+    common::pre_init();
+
+    let mut initializer = agnaji::vulkan::init::AgnajiVulkanInitializer::new_headless(true);
+    let device_reports = initializer.generate_device_reports().unwrap();
+
+    let selected = match device_reports.iter().find(|report| report.is_suitable()) {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (agnaji, _) = initializer.build(agnaji::vulkan::init::DeviceSelection::Report(selected)).unwrap();
+    let device = agnaji.get_device().clone();
+    let vk_device = device.get_device();
+
+    let mut profiler = match GpuProfiler::new(device.clone(), 1, 4) {
+        Some(profiler) => profiler,
+        None => return,
+    };
+
+    let pool_create_info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(device.get_main_queue().get_queue_family());
+    let command_pool = unsafe { vk_device.create_command_pool(&pool_create_info, None) }.unwrap();
+
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = unsafe { vk_device.allocate_command_buffers(&allocate_info) }.unwrap()[0];
+    let fence = unsafe { vk_device.create_fence(&vk::FenceCreateInfo::builder(), None) }.unwrap();
+
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    let mut statistics = Vec::new();
+    for _ in 0..2 {
+        unsafe { vk_device.begin_command_buffer(cmd, &begin_info) }.unwrap();
+        statistics.push(profiler.begin_frame(cmd));
+        profiler.begin_phase(cmd, "outer");
+        profiler.begin_pipeline_statistics(cmd);
+        profiler.begin_phase(cmd, "inner");
+        profiler.end_phase(cmd);
+        // Does not fit into the pool anymore
+        profiler.begin_phase(cmd, "skipped");
+        profiler.end_phase(cmd);
+        profiler.end_pipeline_statistics(cmd);
+        profiler.end_phase(cmd);
+        profiler.end_frame();
+        unsafe { vk_device.end_command_buffer(cmd) }.unwrap();
+
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(std::slice::from_ref(&cmd));
+        unsafe {
+            let queue = device.get_main_queue().lock().unwrap();
+            vk_device.queue_submit(*queue, std::slice::from_ref(&submit_info), fence).unwrap();
+            drop(queue);
+            vk_device.wait_for_fences(std::slice::from_ref(&fence), true, u64::MAX).unwrap();
+            vk_device.reset_fences(std::slice::from_ref(&fence)).unwrap();
+        }
+    }
+
+    assert_eq!(statistics[0], None);
+    let resolved = statistics[1].as_ref().unwrap();
+    let names: Vec<_> = resolved.phases.iter().map(|phase| phase.name.as_str()).collect();
+    assert_eq!(names, vec!["outer", "inner"]);
+    assert!(resolved.get_phase_duration("outer").unwrap() >= resolved.get_phase_duration("inner").unwrap());
+    // No draws have been recorded
+    match device.supports_pipeline_statistics_query() {
+        true => assert_eq!(resolved.pipeline, Some(Default::default())),
+        false => assert_eq!(resolved.pipeline, None),
+    }
+
+    unsafe {
+        vk_device.destroy_fence(fence, None);
+        vk_device.destroy_command_pool(command_pool, None);
+    }
+}
@@ -0,0 +1,40 @@
+//! The winit event loop must run on the main thread so this test uses a custom harness.
+
+extern crate agnaji;
+
+mod common;
+
+use std::sync::mpsc::channel;
+
+fn main() {
+    common::pre_init();
+
+    #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        println!("No display available. Skipping winit shutdown test");
+        return;
+    }
+
+    let (send, recv) = channel();
+    agnaji::winit::run(move |backend| {
+        let monitor_count = backend.run_on_event_loop_thread(|window_target| window_target.available_monitors().count());
+        println!("Event loop reports {} monitors", monitor_count);
+
+        // Keeps creating windows while and after the event loop quits. Must never panic
+        let lingering = std::thread::spawn(move || {
+            let mut created = 0u32;
+            while backend.create_window(String::from("Shutdown Test"), None).is_ok() {
+                created += 1;
+            }
+
+            // The event loop is gone so every further request must fail as well
+            assert!(backend.create_window(String::from("Shutdown Test"), None).is_err());
+            backend.quit();
+            created
+        });
+        send.send(lingering).unwrap();
+    }).unwrap();
+
+    let created = recv.recv().unwrap().join().unwrap();
+    println!("Created {} windows before the event loop closed", created);
+}
@@ -0,0 +1,41 @@
+//! The winit event loop must run on the main thread so this test uses a custom harness.
+
+extern crate agnaji;
+
+mod common;
+
+use std::sync::mpsc::channel;
+
+use agnaji::prelude::Vec2i32;
+use agnaji::winit::WindowError;
+
+fn main() {
+    common::pre_init();
+
+    #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        println!("No display available. Skipping winit window position test");
+        return;
+    }
+
+    let (send, recv) = channel();
+    agnaji::winit::run(move |backend| {
+        let window = backend.create_window(String::from("Window Position Test"), None).unwrap();
+
+        // Submitted to the event loop from the main application thread
+        let from_application = window.set_outer_position(Vec2i32::new(50, 60));
+
+        // Applied directly since the event loop cannot wait for itself. Must not deadlock
+        let window_clone = window.clone();
+        let from_event_loop = backend.run_on_event_loop_thread(move |_| window_clone.set_outer_position(Vec2i32::new(70, 80)));
+
+        send.send((from_application, from_event_loop)).unwrap();
+        backend.quit();
+    }).unwrap();
+
+    let (from_application, from_event_loop) = recv.recv().unwrap();
+    for result in [from_application, from_event_loop] {
+        // Wayland does not support positioning windows
+        assert!(matches!(result, Ok(()) | Err(WindowError::NotSupported)), "Unexpected result {:?}", result);
+    }
+}